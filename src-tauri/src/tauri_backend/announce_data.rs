@@ -0,0 +1,306 @@
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::Cursor;
+
+pub(crate) fn extract_peer_capabilities(peer: &serde_json::Map<String, Value>) -> Vec<String> {
+    for key in ["capabilities", "caps", "announce_capabilities"] {
+        if let Some(caps) = extract_capabilities_from_json(peer.get(key)) {
+            return caps;
+        }
+    }
+
+    for bytes in peer_app_data(peer) {
+        if let Some(caps) = decode_capabilities_from_announce_app_data(&bytes) {
+            return caps;
+        }
+    }
+
+    Vec::new()
+}
+
+/// The name a peer announces, preferring the runtime's decoded `name` and
+/// falling back to the display name carried in the announce app data.
+pub(crate) fn extract_peer_display_name(peer: &serde_json::Map<String, Value>) -> Option<String> {
+    for key in ["name", "display_name"] {
+        let name = peer
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+        if let Some(name) = name {
+            return Some(name.to_string());
+        }
+    }
+    peer_app_data(peer).find_map(|bytes| decode_display_name_from_announce_app_data(&bytes))
+}
+
+/// LXMF announces carry `[display_name, stamp_cost, ...]` as msgpack; older
+/// peers send the bare UTF-8 name instead.
+pub(crate) fn decode_display_name_from_announce_app_data(app_data: &[u8]) -> Option<String> {
+    let mut cursor = Cursor::new(app_data);
+    let raw = match rmpv::decode::read_value(&mut cursor) {
+        Ok(rmpv::Value::Array(entries)) => match entries.into_iter().next()? {
+            rmpv::Value::Binary(bytes) => String::from_utf8(bytes).ok()?,
+            rmpv::Value::String(text) => text.into_str()?,
+            rmpv::Value::Array(values) => String::from_utf8(rmpv_array_to_bytes(&values)?).ok()?,
+            _ => return None,
+        },
+        _ => String::from_utf8(app_data.to_vec()).ok()?,
+    };
+    let name = raw.trim();
+    if name.is_empty() || name.chars().any(char::is_control) {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+fn peer_app_data(peer: &serde_json::Map<String, Value>) -> impl Iterator<Item = Vec<u8>> + '_ {
+    [
+        "app_data_hex",
+        "announce_app_data_hex",
+        "app_data",
+        "announce_app_data",
+    ]
+    .into_iter()
+    .filter_map(|key| peer.get(key).and_then(extract_bytes))
+}
+
+fn extract_capabilities_from_json(value: Option<&Value>) -> Option<Vec<String>> {
+    let value = value?;
+    if let Some(array) = value.as_array() {
+        return Some(normalize_capabilities_iter(
+            array.iter().filter_map(Value::as_str),
+        ));
+    }
+    let object = value.as_object()?;
+    if let Some(array) = object.get("caps").and_then(Value::as_array) {
+        return Some(normalize_capabilities_iter(
+            array.iter().filter_map(Value::as_str),
+        ));
+    }
+    if let Some(array) = object.get("capabilities").and_then(Value::as_array) {
+        return Some(normalize_capabilities_iter(
+            array.iter().filter_map(Value::as_str),
+        ));
+    }
+    None
+}
+
+fn extract_bytes(value: &Value) -> Option<Vec<u8>> {
+    if let Some(text) = value.as_str() {
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            return None;
+        }
+        let normalized = trimmed
+            .strip_prefix("0x")
+            .or_else(|| trimmed.strip_prefix("0X"))
+            .unwrap_or(trimmed);
+        if normalized.len() % 2 == 0 {
+            if let Ok(decoded) = hex::decode(normalized) {
+                if !decoded.is_empty() {
+                    return Some(decoded);
+                }
+            }
+        }
+        return None;
+    }
+
+    let array = value.as_array()?;
+    let mut output = Vec::with_capacity(array.len());
+    for entry in array {
+        let number = entry.as_u64()?;
+        if number > u8::MAX as u64 {
+            return None;
+        }
+        output.push(number as u8);
+    }
+    if output.is_empty() {
+        None
+    } else {
+        Some(output)
+    }
+}
+
+pub(crate) fn decode_capabilities_from_announce_app_data(app_data: &[u8]) -> Option<Vec<String>> {
+    let mut cursor = Cursor::new(app_data);
+    let value = rmpv::decode::read_value(&mut cursor).ok()?;
+    let entries = match value {
+        rmpv::Value::Array(values) => values,
+        _ => return None,
+    };
+    if entries.len() < 3 {
+        return None;
+    }
+    decode_capabilities_from_app_data_entry(&entries[2])
+}
+
+fn decode_capabilities_from_app_data_entry(entry: &rmpv::Value) -> Option<Vec<String>> {
+    match entry {
+        rmpv::Value::Map(map) => decode_capabilities_from_map_entry(map),
+        rmpv::Value::Binary(bytes) => decode_capabilities_from_payload(bytes),
+        rmpv::Value::Array(values) => {
+            if let Some(bytes) = rmpv_array_to_bytes(values) {
+                return decode_capabilities_from_payload(&bytes);
+            }
+            None
+        }
+        rmpv::Value::String(text) => {
+            let raw = text.as_str().unwrap_or_default().trim();
+            if raw.is_empty() {
+                return None;
+            }
+            if let Ok(decoded) = hex::decode(raw) {
+                return decode_capabilities_from_payload(&decoded);
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+fn decode_capabilities_from_map_entry(map: &[(rmpv::Value, rmpv::Value)]) -> Option<Vec<String>> {
+    for (key, value) in map {
+        let Some(key_name) = key.as_str() else {
+            continue;
+        };
+        if key_name == "caps" {
+            if let rmpv::Value::Array(values) = value {
+                return Some(normalize_capabilities_iter(
+                    values.iter().filter_map(rmpv::Value::as_str),
+                ));
+            }
+        }
+        if key_name == "capabilities" {
+            if let rmpv::Value::Array(values) = value {
+                return Some(normalize_capabilities_iter(
+                    values.iter().filter_map(rmpv::Value::as_str),
+                ));
+            }
+        }
+    }
+    None
+}
+
+fn rmpv_array_to_bytes(values: &[rmpv::Value]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(values.len());
+    for value in values {
+        let number = value.as_u64()?;
+        if number > u8::MAX as u64 {
+            return None;
+        }
+        bytes.push(number as u8);
+    }
+    Some(bytes)
+}
+
+fn decode_capabilities_from_payload(raw: &[u8]) -> Option<Vec<String>> {
+    if raw.is_empty() {
+        return None;
+    }
+
+    if let Ok(decoded) = serde_cbor::from_slice::<Value>(raw) {
+        if let Some(caps) = extract_capabilities_from_json(Some(&decoded)) {
+            return Some(caps);
+        }
+    }
+
+    if let Ok(decoded) = rmp_serde::from_slice::<Value>(raw) {
+        if let Some(caps) = extract_capabilities_from_json(Some(&decoded)) {
+            return Some(caps);
+        }
+    }
+
+    None
+}
+
+fn normalize_capabilities_iter<'a>(values: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    for entry in values {
+        let normalized = entry.trim().to_ascii_lowercase();
+        if normalized.is_empty() || !seen.insert(normalized.clone()) {
+            continue;
+        }
+        out.push(normalized);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn decode_capabilities_from_msgpack_announce_payload() {
+        let capability_payload = rmp_serde::to_vec(&json!({
+            "app": "rch",
+            "schema": 1,
+            "caps": ["topic_broker", "attachments"],
+        }))
+        .expect("encode capability payload");
+        let announce_app_data = rmp_serde::to_vec(&(b"RCH".to_vec(), 1u32, capability_payload))
+            .expect("encode announce app-data");
+
+        let caps = decode_capabilities_from_announce_app_data(&announce_app_data)
+            .expect("decode caps from app-data");
+        assert_eq!(caps, vec!["topic_broker", "attachments"]);
+    }
+
+    #[test]
+    fn decode_capabilities_from_cbor_announce_payload() {
+        let capability_payload = serde_cbor::to_vec(&json!({
+            "app": "rch",
+            "schema": 1,
+            "caps": ["telemetry_relay", "attachments"],
+        }))
+        .expect("encode cbor capability payload");
+        let announce_app_data = rmp_serde::to_vec(&(b"RCH".to_vec(), 1u32, capability_payload))
+            .expect("encode announce app-data");
+
+        let caps = decode_capabilities_from_announce_app_data(&announce_app_data)
+            .expect("decode caps from app-data");
+        assert_eq!(caps, vec!["telemetry_relay", "attachments"]);
+    }
+
+    #[test]
+    fn extract_peer_capabilities_from_hex_app_data() {
+        let capability_payload = rmp_serde::to_vec(&json!({
+            "app": "rch",
+            "schema": 1,
+            "caps": ["group_chat"],
+        }))
+        .expect("encode capability payload");
+        let announce_app_data = rmp_serde::to_vec(&(b"RCH".to_vec(), 1u32, capability_payload))
+            .expect("encode announce app-data");
+
+        let mut peer = serde_json::Map::new();
+        peer.insert("peer".into(), Value::String("abc123".into()));
+        peer.insert(
+            "app_data_hex".into(),
+            Value::String(hex::encode(announce_app_data)),
+        );
+
+        let caps = extract_peer_capabilities(&peer);
+        assert_eq!(caps, vec!["group_chat"]);
+    }
+
+    #[test]
+    fn decode_display_name_from_msgpack_and_legacy_app_data() {
+        let announce_app_data =
+            rmp_serde::to_vec(&(b"Hub Node".to_vec(), 1u32)).expect("encode announce app-data");
+        assert_eq!(
+            decode_display_name_from_announce_app_data(&announce_app_data).as_deref(),
+            Some("Hub Node")
+        );
+        assert_eq!(
+            decode_display_name_from_announce_app_data(b"Base Camp").as_deref(),
+            Some("Base Camp")
+        );
+        assert_eq!(
+            decode_display_name_from_announce_app_data(&[0xff, 0x00]),
+            None
+        );
+    }
+}
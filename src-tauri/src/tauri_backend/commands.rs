@@ -2,13 +2,17 @@ use super::actor::{
     clean_required_arg, parse_command_entries, rpc_actor_call, ActorCommand, RuntimeActor,
 };
 use super::attachment_handles::AttachmentHandleManager;
-use super::index_store::IndexStore;
+use super::index_store::{
+    delete_fallback_body, edit_fallback_body, IndexStore, IndexStores, OutboxResend, OutboxRoute,
+    QueuedSend, QueuedSendEntry,
+};
 use super::selector::{
     clean_arg, default_profile, default_rpc, default_transport, RuntimeSelector,
 };
 use super::{
-    current_system_appearance, DesktopShellPreferencePatch, DesktopShellState, EventPumpControl,
-    DEFAULT_EVENT_PUMP_INTERVAL_MS, TRAY_ACTION_CHANNEL,
+    current_system_appearance, spawn_index_backfill, DesktopShellPreferencePatch,
+    DesktopShellState, EventPumpControl, DEFAULT_EVENT_PUMP_INTERVAL_MS, SEND_QUEUE_CHANNEL,
+    TRAY_ACTION_CHANNEL,
 };
 use base64::Engine as _;
 use lxmf::cli::profile::{load_profile_settings, save_profile_settings};
@@ -17,7 +21,7 @@ use lxmf::payload_fields::{decode_transport_fields_json, TRANSPORT_FIELDS_MSGPAC
 use lxmf::runtime::{SendCommandRequest, SendMessageRequest};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::collections::{BTreeMap, HashSet};
+use std::collections::BTreeMap;
 use std::io::Cursor;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -46,8 +50,12 @@ pub(crate) fn daemon_status(
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn daemon_start(
+    app: AppHandle,
     actor: State<'_, RuntimeActor>,
+    index_stores: State<'_, Arc<IndexStores>>,
+    event_pump: State<'_, EventPumpControl>,
     profile: Option<String>,
     rpc: Option<String>,
     managed: Option<bool>,
@@ -63,10 +71,45 @@ pub(crate) fn daemon_start(
 
     let selector = RuntimeSelector::load(profile, rpc)?;
     let transport = clean_arg(transport).or_else(default_transport);
-    actor.request(ActorCommand::Start {
-        selector,
+    let result = actor.request(ActorCommand::Start {
+        selector: selector.clone(),
         transport,
-    })
+    })?;
+    activate_profile_index(app, &actor, &index_stores, &event_pump, selector);
+    Ok(result)
+}
+
+// Points index queries at the started profile's database and backfills it
+// when this switched identities. A running event pump follows the switch so
+// events are polled from, and indexed into, the profile that is now active.
+fn activate_profile_index(
+    app: AppHandle,
+    actor: &RuntimeActor,
+    index_stores: &IndexStores,
+    event_pump: &EventPumpControl,
+    selector: RuntimeSelector,
+) {
+    if let Some(index_store) = index_stores.activate(&selector.profile_name) {
+        spawn_index_backfill(actor.clone(), index_store, selector.clone());
+    }
+    let (Some((profile, rpc)), Some(interval_ms)) = (
+        event_pump.current_target(),
+        event_pump.current_interval_ms(),
+    ) else {
+        return;
+    };
+    if profile == selector.profile_name && rpc == selector.profile_settings.rpc {
+        return;
+    }
+    if let Err(err) = event_pump.start(
+        app,
+        actor.clone(),
+        index_stores.for_profile(&selector.profile_name),
+        selector,
+        interval_ms,
+    ) {
+        log::warn!("event pump retarget failed: {err}");
+    }
 }
 
 #[tauri::command]
@@ -80,8 +123,12 @@ pub(crate) fn daemon_stop(
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn daemon_restart(
+    app: AppHandle,
     actor: State<'_, RuntimeActor>,
+    index_stores: State<'_, Arc<IndexStores>>,
+    event_pump: State<'_, EventPumpControl>,
     profile: Option<String>,
     rpc: Option<String>,
     managed: Option<bool>,
@@ -97,10 +144,12 @@ pub(crate) fn daemon_restart(
 
     let selector = RuntimeSelector::load(profile, rpc)?;
     let transport = clean_arg(transport).or_else(default_transport);
-    actor.request(ActorCommand::Restart {
-        selector,
+    let result = actor.request(ActorCommand::Restart {
+        selector: selector.clone(),
         transport,
-    })
+    })?;
+    activate_profile_index(app, &actor, &index_stores, &event_pump, selector);
+    Ok(result)
 }
 
 #[tauri::command]
@@ -222,6 +271,7 @@ pub(crate) fn lxmf_peer_unpeer(
 #[tauri::command]
 pub(crate) fn lxmf_list_announces(
     actor: State<'_, RuntimeActor>,
+    index_stores: State<'_, Arc<IndexStores>>,
     profile: Option<String>,
     rpc: Option<String>,
     limit: Option<usize>,
@@ -229,6 +279,7 @@ pub(crate) fn lxmf_list_announces(
     cursor: Option<String>,
 ) -> Result<Value, String> {
     let selector = RuntimeSelector::load(profile, rpc)?;
+    let index_store = index_stores.for_profile(&selector.profile_name);
     let mut params = serde_json::Map::new();
     if let Some(limit) = limit {
         params.insert("limit".to_string(), json!(limit.clamp(1, 5000)));
@@ -250,6 +301,9 @@ pub(crate) fn lxmf_list_announces(
         },
     )?;
     let announces = array_from_response(&response, "announces")?;
+    if let Err(err) = index_store.record_announces(&response) {
+        log::debug!("announce history update failed: {err}");
+    }
 
     Ok(json!({
         "announces": announces,
@@ -496,20 +550,31 @@ pub(crate) fn lxmf_set_outbound_propagation_node(
 #[tauri::command]
 pub(crate) fn lxmf_message_delivery_trace(
     actor: State<'_, RuntimeActor>,
+    index_stores: State<'_, Arc<IndexStores>>,
     profile: Option<String>,
     rpc: Option<String>,
     message_id: String,
 ) -> Result<Value, String> {
     let selector = RuntimeSelector::load(profile, rpc)?;
+    let index_store = index_stores.for_profile(&selector.profile_name);
     let message_id = clean_required_arg(message_id, "message_id")?;
-    rpc_actor_call(
+    let mut trace = rpc_actor_call(
         &actor,
         selector,
         "message_delivery_trace",
         Some(json!({
             "message_id": message_id
         })),
-    )
+    )?;
+    match index_store.merge_delivery_trace(&trace) {
+        Ok(timeline) => {
+            if let Some(object) = trace.as_object_mut() {
+                object.insert("timeline".to_string(), timeline);
+            }
+        }
+        Err(err) => log::debug!("delivery trace merge failed: {err}"),
+    }
+    Ok(trace)
 }
 
 #[tauri::command]
@@ -555,7 +620,7 @@ pub(crate) fn lxmf_poll_event(
 pub(crate) fn lxmf_start_event_pump(
     app: AppHandle,
     actor: State<'_, RuntimeActor>,
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     event_pump: State<'_, EventPumpControl>,
     profile: Option<String>,
     rpc: Option<String>,
@@ -566,7 +631,7 @@ pub(crate) fn lxmf_start_event_pump(
     event_pump.start(
         app,
         actor.inner().clone(),
-        index_store.inner().clone(),
+        index_stores.for_profile(&selector.profile_name),
         selector,
         interval_ms,
     )?;
@@ -581,7 +646,7 @@ pub(crate) fn lxmf_start_event_pump(
 pub(crate) fn lxmf_set_event_pump_policy(
     app: AppHandle,
     actor: State<'_, RuntimeActor>,
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     event_pump: State<'_, EventPumpControl>,
     profile: Option<String>,
     rpc: Option<String>,
@@ -610,7 +675,7 @@ pub(crate) fn lxmf_set_event_pump_policy(
     event_pump.start(
         app,
         actor.inner().clone(),
-        index_store.inner().clone(),
+        index_stores.for_profile(&selector.profile_name),
         selector,
         desired_interval,
     )?;
@@ -744,7 +809,9 @@ pub(crate) fn desktop_set_shell_preferences(
 #[tauri::command]
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn lxmf_send_message(
+    app: AppHandle,
     actor: State<'_, RuntimeActor>,
+    index_stores: State<'_, Arc<IndexStores>>,
     profile: Option<String>,
     rpc: Option<String>,
     destination: String,
@@ -773,41 +840,241 @@ pub(crate) fn lxmf_send_message(
         reaction_sender,
         telemetry_location,
     )?;
-    let request = SendMessageRequest {
+    let request = QueuedSend {
         id: clean_arg(id),
         source: clean_arg(source),
-        source_private_key: None,
-        destination: destination.clone(),
+        destination,
         title: clean_arg(title).unwrap_or_default(),
         content,
         fields,
         method: clean_arg(method),
         stamp_cost,
         include_ticket: include_ticket.unwrap_or(false),
-        try_propagation_on_fail: true,
     };
-    let response = actor.request(ActorCommand::SendMessage { selector, request })?;
-    let result = response.get("result").cloned().unwrap_or(Value::Null);
-    let source = response
-        .get("source")
-        .and_then(Value::as_str)
-        .unwrap_or_default()
-        .to_string();
-    let destination = response
-        .get("destination")
-        .and_then(Value::as_str)
-        .unwrap_or(destination.as_str())
-        .to_string();
-
+    send_or_queue(&app, &actor, &index_stores, selector, request)
+}
+
+// Hands the send to the runtime, which starts it for the profile if needed.
+// When the runtime cannot be reached at all, the request waits in the
+// profile's send queue for the queue worker instead.
+fn send_or_queue(
+    app: &AppHandle,
+    actor: &RuntimeActor,
+    index_stores: &IndexStores,
+    selector: RuntimeSelector,
+    request: QueuedSend,
+) -> Result<Value, String> {
+    let err = match send_resolved_message(
+        actor,
+        selector.clone(),
+        queued_send_request(request.clone()),
+    ) {
+        Ok(response) => return Ok(response),
+        Err(err) => err,
+    };
+    if classify_send_error(&err) != SendFailure::Unavailable {
+        return Err(err);
+    }
+    log::info!("runtime unavailable, queueing send: {err}");
+    let destination = request.destination.clone();
+    let entry = index_stores
+        .for_profile(&selector.profile_name)
+        .enqueue_send(
+            &selector.profile_name,
+            &selector.profile_settings.rpc,
+            request,
+        )?;
+    let _ = app.emit(SEND_QUEUE_CHANNEL, send_queue_event("queued", &entry));
     Ok(json!({
-        "result": result,
+        "result": Value::Null,
+        "queued": {
+            "queue_id": entry.queue_id,
+            "message_id": entry.message_id,
+        },
         "resolved": {
-            "source": source,
+            "source": "",
             "destination": destination,
         }
     }))
 }
 
+/// Why a send did not come back with a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SendFailure {
+    /// The request never reached a runtime: the worker is gone or the
+    /// runtime could not be started.
+    Unavailable,
+    /// The worker took the request but did not answer, so the runtime may
+    /// have accepted it.
+    Unconfirmed,
+    /// The runtime answered with an error.
+    Rejected,
+}
+
+fn classify_send_error(err: &str) -> SendFailure {
+    if err == "runtime worker did not respond" {
+        SendFailure::Unconfirmed
+    } else if err == "runtime worker unavailable"
+        || err == "runtime unavailable after start"
+        || err.starts_with("failed to start runtime for profile")
+    {
+        SendFailure::Unavailable
+    } else {
+        SendFailure::Rejected
+    }
+}
+
+fn runtime_running(actor: &RuntimeActor, selector: &RuntimeSelector) -> bool {
+    actor
+        .request(ActorCommand::Status {
+            selector: selector.clone(),
+        })
+        .ok()
+        .and_then(|status| status.get("running").and_then(Value::as_bool))
+        .unwrap_or(false)
+}
+
+// Whether the runtime already holds `message_id`, i.e. an unanswered send did
+// go through. Any trace entry counts.
+fn runtime_has_message(actor: &RuntimeActor, selector: RuntimeSelector, message_id: &str) -> bool {
+    rpc_actor_call(
+        actor,
+        selector,
+        "message_delivery_trace",
+        Some(json!({ "message_id": message_id })),
+    )
+    .ok()
+    .map(|trace| {
+        let trace = trace.get("message_delivery_trace").unwrap_or(&trace);
+        trace
+            .get("transitions")
+            .and_then(Value::as_array)
+            .is_some_and(|transitions| !transitions.is_empty())
+    })
+    .unwrap_or(false)
+}
+
+fn queued_send_request(request: QueuedSend) -> SendMessageRequest {
+    SendMessageRequest {
+        id: request.id,
+        source: request.source,
+        source_private_key: None,
+        destination: request.destination,
+        title: request.title,
+        content: request.content,
+        fields: request.fields,
+        method: request.method,
+        stamp_cost: request.stamp_cost,
+        include_ticket: request.include_ticket,
+        try_propagation_on_fail: true,
+    }
+}
+
+/// Hands due queued sends to the runtime once it reports running. A send
+/// that does not reach the runtime backs off and is retried; one the runtime
+/// rejects is marked failed. Progress is reported through `emit` as
+/// `weft://send-queue` events.
+pub(crate) fn flush_send_queue(
+    actor: &RuntimeActor,
+    index_store: &IndexStore,
+    batch_size: usize,
+    emit: &dyn Fn(Value),
+) {
+    let due = match index_store.due_queued_sends(batch_size) {
+        Ok(due) => due,
+        Err(err) => {
+            log::warn!("send queue read failed: {err}");
+            return;
+        }
+    };
+    let mut running = BTreeMap::new();
+    for entry in due {
+        let selector =
+            match RuntimeSelector::load(Some(entry.profile.clone()), Some(entry.rpc.clone())) {
+                Ok(selector) => selector,
+                Err(err) => {
+                    fail_queued_send(index_store, &entry, &err, emit);
+                    continue;
+                }
+            };
+        let is_running = *running
+            .entry((entry.profile.clone(), entry.rpc.clone()))
+            .or_insert_with(|| runtime_running(actor, &selector));
+        if !is_running {
+            continue;
+        }
+        if entry.unconfirmed && runtime_has_message(actor, selector.clone(), &entry.message_id) {
+            finish_queued_send(index_store, &entry, Value::Null, emit);
+            continue;
+        }
+        emit(send_queue_event("sending", &entry));
+        match send_resolved_message(actor, selector, queued_send_request(entry.request.clone())) {
+            Ok(response) => finish_queued_send(index_store, &entry, response, emit),
+            Err(err) => match classify_send_error(&err) {
+                SendFailure::Unavailable => {
+                    reschedule_queued_send(index_store, &entry, &err, false, emit)
+                }
+                SendFailure::Unconfirmed => {
+                    reschedule_queued_send(index_store, &entry, &err, true, emit)
+                }
+                SendFailure::Rejected => fail_queued_send(index_store, &entry, &err, emit),
+            },
+        }
+    }
+}
+
+fn finish_queued_send(
+    index_store: &IndexStore,
+    entry: &QueuedSendEntry,
+    response: Value,
+    emit: &dyn Fn(Value),
+) {
+    if let Err(err) = index_store.remove_queued_send(entry.queue_id) {
+        log::warn!("send queue cleanup failed: {err}");
+    }
+    let mut event = send_queue_event("sent", entry);
+    if let Some(object) = event.as_object_mut() {
+        object.insert("response".to_string(), response);
+    }
+    emit(event);
+}
+
+fn reschedule_queued_send(
+    index_store: &IndexStore,
+    entry: &QueuedSendEntry,
+    error: &str,
+    unconfirmed: bool,
+    emit: &dyn Fn(Value),
+) {
+    match index_store.reschedule_queued_send(entry.queue_id, error, unconfirmed) {
+        Ok(Some(entry)) if entry.failed_at_ms.is_some() => emit(send_queue_event("failed", &entry)),
+        Ok(Some(entry)) => emit(send_queue_event("retry_scheduled", &entry)),
+        Ok(None) => {}
+        Err(err) => log::warn!("send queue reschedule failed: {err}"),
+    }
+}
+
+fn fail_queued_send(
+    index_store: &IndexStore,
+    entry: &QueuedSendEntry,
+    error: &str,
+    emit: &dyn Fn(Value),
+) {
+    match index_store.fail_queued_send(entry.queue_id, error) {
+        Ok(Some(entry)) => emit(send_queue_event("failed", &entry)),
+        Ok(None) => {}
+        Err(err) => log::warn!("send queue failure update failed: {err}"),
+    }
+}
+
+fn send_queue_event(event: &str, entry: &QueuedSendEntry) -> Value {
+    let mut payload = serde_json::to_value(entry).unwrap_or_else(|_| json!({}));
+    if let Some(object) = payload.as_object_mut() {
+        object.insert("event".to_string(), json!(event));
+    }
+    payload
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct RichAttachmentInput {
     name: String,
@@ -821,7 +1088,9 @@ pub(crate) struct RichAttachmentInput {
 #[tauri::command]
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn lxmf_send_rich_message(
+    app: AppHandle,
     actor: State<'_, RuntimeActor>,
+    index_stores: State<'_, Arc<IndexStores>>,
     profile: Option<String>,
     rpc: Option<String>,
     destination: String,
@@ -850,45 +1119,26 @@ pub(crate) fn lxmf_send_rich_message(
         reaction_sender,
         telemetry_location,
     )?;
-    let request = SendMessageRequest {
+    let request = QueuedSend {
         id: clean_arg(id),
         source: clean_arg(source),
-        source_private_key: None,
-        destination: destination.clone(),
+        destination,
         title: clean_arg(title).unwrap_or_default(),
         content,
         fields,
         method: clean_arg(method),
         stamp_cost,
         include_ticket: include_ticket.unwrap_or(false),
-        try_propagation_on_fail: true,
     };
-    let response = actor.request(ActorCommand::SendMessage { selector, request })?;
-    let result = response.get("result").cloned().unwrap_or(Value::Null);
-    let source = response
-        .get("source")
-        .and_then(Value::as_str)
-        .unwrap_or_default()
-        .to_string();
-    let destination = response
-        .get("destination")
-        .and_then(Value::as_str)
-        .unwrap_or(destination.as_str())
-        .to_string();
-
-    Ok(json!({
-        "result": result,
-        "resolved": {
-            "source": source,
-            "destination": destination,
-        }
-    }))
+    send_or_queue(&app, &actor, &index_stores, selector, request)
 }
 
 #[tauri::command]
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn lxmf_send_rich_message_refs(
+    app: AppHandle,
     actor: State<'_, RuntimeActor>,
+    index_stores: State<'_, Arc<IndexStores>>,
     profile: Option<String>,
     rpc: Option<String>,
     destination: String,
@@ -907,7 +1157,9 @@ pub(crate) fn lxmf_send_rich_message_refs(
     telemetry_location: Option<Value>,
 ) -> Result<Value, String> {
     lxmf_send_rich_message(
+        app,
         actor,
+        index_stores,
         profile,
         rpc,
         destination,
@@ -927,6 +1179,223 @@ pub(crate) fn lxmf_send_rich_message_refs(
     )
 }
 
+/// Replaces the text of a message previously sent to `destination`. Clients
+/// that understand `edit_of` update the original; others show the new text
+/// as a separate message marked as an edit.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn lxmf_send_message_edit(
+    actor: State<'_, RuntimeActor>,
+    profile: Option<String>,
+    rpc: Option<String>,
+    destination: String,
+    message_id: String,
+    content: String,
+    source: Option<String>,
+    id: Option<String>,
+    method: Option<String>,
+    stamp_cost: Option<u32>,
+    include_ticket: Option<bool>,
+) -> Result<Value, String> {
+    let selector = RuntimeSelector::load(profile, rpc)?;
+    let message_id = clean_required_arg(message_id, "message_id")?;
+    let content = clean_required_arg(content, "content")?;
+    let request = SendMessageRequest {
+        id: clean_arg(id),
+        source: clean_arg(source),
+        source_private_key: None,
+        destination: clean_required_arg(destination, "destination")?,
+        title: String::new(),
+        content: edit_fallback_body(&content),
+        fields: build_amendment_fields("edit_of", message_id)?,
+        method: clean_arg(method),
+        stamp_cost,
+        include_ticket: include_ticket.unwrap_or(false),
+        try_propagation_on_fail: true,
+    };
+    send_resolved_message(&actor, selector, request)
+}
+
+/// Deletes a message previously sent to `destination` for every recipient
+/// that understands `delete_of`; others see a short deletion notice.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn lxmf_send_message_delete(
+    actor: State<'_, RuntimeActor>,
+    profile: Option<String>,
+    rpc: Option<String>,
+    destination: String,
+    message_id: String,
+    source: Option<String>,
+    id: Option<String>,
+    method: Option<String>,
+    stamp_cost: Option<u32>,
+    include_ticket: Option<bool>,
+) -> Result<Value, String> {
+    let selector = RuntimeSelector::load(profile, rpc)?;
+    let message_id = clean_required_arg(message_id, "message_id")?;
+    let request = SendMessageRequest {
+        id: clean_arg(id),
+        source: clean_arg(source),
+        source_private_key: None,
+        destination: clean_required_arg(destination, "destination")?,
+        title: String::new(),
+        content: delete_fallback_body().to_string(),
+        fields: build_amendment_fields("delete_of", message_id)?,
+        method: clean_arg(method),
+        stamp_cost,
+        include_ticket: include_ticket.unwrap_or(false),
+        try_propagation_on_fail: true,
+    };
+    send_resolved_message(&actor, selector, request)
+}
+
+fn send_resolved_message(
+    actor: &RuntimeActor,
+    selector: RuntimeSelector,
+    request: SendMessageRequest,
+) -> Result<Value, String> {
+    let destination = request.destination.clone();
+    let response = actor.request(ActorCommand::SendMessage { selector, request })?;
+    let result = response.get("result").cloned().unwrap_or(Value::Null);
+    let source = response
+        .get("source")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let destination = response
+        .get("destination")
+        .and_then(Value::as_str)
+        .unwrap_or(destination.as_str())
+        .to_string();
+
+    Ok(json!({
+        "result": result,
+        "resolved": {
+            "source": source,
+            "destination": destination,
+        }
+    }))
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_outbox_retry(
+    actor: State<'_, RuntimeActor>,
+    index_stores: State<'_, Arc<IndexStores>>,
+    profile: Option<String>,
+    rpc: Option<String>,
+    message_id: String,
+) -> Result<Value, String> {
+    let selector = RuntimeSelector::load(profile, rpc)?;
+    let message_id = clean_required_arg(message_id, "message_id")?;
+    resend_from_outbox(
+        &actor,
+        &index_stores,
+        selector,
+        &message_id,
+        OutboxRoute::Same,
+    )
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_outbox_reroute(
+    actor: State<'_, RuntimeActor>,
+    index_stores: State<'_, Arc<IndexStores>>,
+    profile: Option<String>,
+    rpc: Option<String>,
+    message_id: String,
+    method: Option<String>,
+) -> Result<Value, String> {
+    let selector = RuntimeSelector::load(profile, rpc)?;
+    let message_id = clean_required_arg(message_id, "message_id")?;
+    let route = clean_arg(method).map_or(OutboxRoute::Next, OutboxRoute::Method);
+    resend_from_outbox(&actor, &index_stores, selector, &message_id, route)
+}
+
+// Sends a stored outbound message again under its own id. The index re-queues
+// it first; if the runtime rejects the send, the attempt is recorded as failed.
+fn resend_from_outbox(
+    actor: &RuntimeActor,
+    index_stores: &IndexStores,
+    selector: RuntimeSelector,
+    message_id: &str,
+    route: OutboxRoute,
+) -> Result<Value, String> {
+    let index_store = index_stores.for_profile(&selector.profile_name);
+    let resend = index_store.begin_outbox_resend(message_id, route)?;
+    let method = resend.method.clone();
+    let result = outbox_send_request(resend)
+        .and_then(|request| send_resolved_message(actor, selector, request));
+    if let Err(err) = &result {
+        if let Err(record_err) = index_store.fail_outbox_resend(message_id, err) {
+            log::warn!("recording outbox resend failure failed: {record_err}");
+        }
+    }
+    let mut response = result?;
+    if let Some(object) = response.as_object_mut() {
+        object.insert("method".to_string(), json!(method));
+    }
+    Ok(response)
+}
+
+// Stored fields are always re-encoded for transport, so field ids go back out
+// as integers whether or not the message carries attachments.
+fn outbox_send_request(resend: OutboxResend) -> Result<SendMessageRequest, String> {
+    let mut map_entries = match resend.fields.as_ref() {
+        Some(fields) => decode_or_convert_field_map(fields)?
+            .into_iter()
+            .map(|(key, value)| (numeric_field_key(key), value))
+            .collect(),
+        None => Vec::new(),
+    };
+    if !resend.attachments.is_empty() {
+        let attachments = resend
+            .attachments
+            .into_iter()
+            .map(|(name, bytes)| encode_attachment_entry(name, bytes))
+            .collect();
+        upsert_numeric_field(
+            &mut map_entries,
+            FIELD_FILE_ATTACHMENTS,
+            rmpv::Value::Array(attachments),
+        );
+    }
+    let fields = if map_entries.is_empty() {
+        None
+    } else {
+        let encoded = rmp_serde::to_vec(&rmpv::Value::Map(map_entries))
+            .map_err(|err| format!("failed to encode message fields: {err}"))?;
+        let payload = base64::engine::general_purpose::STANDARD.encode(encoded);
+        Some(json!({
+            TRANSPORT_FIELDS_MSGPACK_B64_KEY: payload
+        }))
+    };
+    Ok(SendMessageRequest {
+        id: Some(resend.message_id),
+        source: resend.source,
+        source_private_key: None,
+        destination: resend.destination,
+        title: resend.title,
+        content: resend.content,
+        fields,
+        method: resend.method,
+        stamp_cost: resend.stamp_cost,
+        include_ticket: resend.include_ticket,
+        try_propagation_on_fail: true,
+    })
+}
+
+// Stored field maps come back from JSON with field ids as strings.
+fn numeric_field_key(key: rmpv::Value) -> rmpv::Value {
+    match key
+        .as_str()
+        .and_then(|value| value.trim().parse::<u8>().ok())
+    {
+        Some(field_id) => rmpv::Value::Integer((field_id as i64).into()),
+        None => key,
+    }
+}
+
 #[tauri::command]
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn lxmf_send_command(
@@ -1007,204 +1476,6 @@ fn array_from_response(value: &Value, field: &str) -> Result<Vec<Value>, String>
     Ok(array.clone())
 }
 
-#[cfg_attr(not(test), allow(dead_code))]
-fn extract_peer_capabilities(peer: &serde_json::Map<String, Value>) -> Vec<String> {
-    for key in ["capabilities", "caps", "announce_capabilities"] {
-        if let Some(caps) = extract_capabilities_from_json(peer.get(key)) {
-            return caps;
-        }
-    }
-
-    for key in [
-        "app_data_hex",
-        "announce_app_data_hex",
-        "app_data",
-        "announce_app_data",
-    ] {
-        let Some(raw) = peer.get(key) else {
-            continue;
-        };
-        let Some(bytes) = extract_bytes(raw) else {
-            continue;
-        };
-        if let Some(caps) = decode_capabilities_from_announce_app_data(&bytes) {
-            return caps;
-        }
-    }
-
-    Vec::new()
-}
-
-#[cfg_attr(not(test), allow(dead_code))]
-fn extract_capabilities_from_json(value: Option<&Value>) -> Option<Vec<String>> {
-    let value = value?;
-    if let Some(array) = value.as_array() {
-        return Some(normalize_capabilities_iter(
-            array.iter().filter_map(Value::as_str),
-        ));
-    }
-    let object = value.as_object()?;
-    if let Some(array) = object.get("caps").and_then(Value::as_array) {
-        return Some(normalize_capabilities_iter(
-            array.iter().filter_map(Value::as_str),
-        ));
-    }
-    if let Some(array) = object.get("capabilities").and_then(Value::as_array) {
-        return Some(normalize_capabilities_iter(
-            array.iter().filter_map(Value::as_str),
-        ));
-    }
-    None
-}
-
-#[cfg_attr(not(test), allow(dead_code))]
-fn extract_bytes(value: &Value) -> Option<Vec<u8>> {
-    if let Some(text) = value.as_str() {
-        let trimmed = text.trim();
-        if trimmed.is_empty() {
-            return None;
-        }
-        let normalized = trimmed
-            .strip_prefix("0x")
-            .or_else(|| trimmed.strip_prefix("0X"))
-            .unwrap_or(trimmed);
-        if normalized.len() % 2 == 0 {
-            if let Ok(decoded) = hex::decode(normalized) {
-                if !decoded.is_empty() {
-                    return Some(decoded);
-                }
-            }
-        }
-        return None;
-    }
-
-    let array = value.as_array()?;
-    let mut output = Vec::with_capacity(array.len());
-    for entry in array {
-        let number = entry.as_u64()?;
-        if number > u8::MAX as u64 {
-            return None;
-        }
-        output.push(number as u8);
-    }
-    if output.is_empty() {
-        None
-    } else {
-        Some(output)
-    }
-}
-
-#[cfg_attr(not(test), allow(dead_code))]
-fn decode_capabilities_from_announce_app_data(app_data: &[u8]) -> Option<Vec<String>> {
-    let mut cursor = Cursor::new(app_data);
-    let value = rmpv::decode::read_value(&mut cursor).ok()?;
-    let entries = match value {
-        rmpv::Value::Array(values) => values,
-        _ => return None,
-    };
-    if entries.len() < 3 {
-        return None;
-    }
-    decode_capabilities_from_app_data_entry(&entries[2])
-}
-
-#[cfg_attr(not(test), allow(dead_code))]
-fn decode_capabilities_from_app_data_entry(entry: &rmpv::Value) -> Option<Vec<String>> {
-    match entry {
-        rmpv::Value::Map(map) => decode_capabilities_from_map_entry(map),
-        rmpv::Value::Binary(bytes) => decode_capabilities_from_payload(bytes),
-        rmpv::Value::Array(values) => {
-            if let Some(bytes) = rmpv_array_to_bytes(values) {
-                return decode_capabilities_from_payload(&bytes);
-            }
-            None
-        }
-        rmpv::Value::String(text) => {
-            let raw = text.as_str().unwrap_or_default().trim();
-            if raw.is_empty() {
-                return None;
-            }
-            if let Ok(decoded) = hex::decode(raw) {
-                return decode_capabilities_from_payload(&decoded);
-            }
-            None
-        }
-        _ => None,
-    }
-}
-
-#[cfg_attr(not(test), allow(dead_code))]
-fn decode_capabilities_from_map_entry(map: &[(rmpv::Value, rmpv::Value)]) -> Option<Vec<String>> {
-    for (key, value) in map {
-        let Some(key_name) = key.as_str() else {
-            continue;
-        };
-        if key_name == "caps" {
-            if let rmpv::Value::Array(values) = value {
-                return Some(normalize_capabilities_iter(
-                    values.iter().filter_map(rmpv::Value::as_str),
-                ));
-            }
-        }
-        if key_name == "capabilities" {
-            if let rmpv::Value::Array(values) = value {
-                return Some(normalize_capabilities_iter(
-                    values.iter().filter_map(rmpv::Value::as_str),
-                ));
-            }
-        }
-    }
-    None
-}
-
-#[cfg_attr(not(test), allow(dead_code))]
-fn rmpv_array_to_bytes(values: &[rmpv::Value]) -> Option<Vec<u8>> {
-    let mut bytes = Vec::with_capacity(values.len());
-    for value in values {
-        let number = value.as_u64()?;
-        if number > u8::MAX as u64 {
-            return None;
-        }
-        bytes.push(number as u8);
-    }
-    Some(bytes)
-}
-
-#[cfg_attr(not(test), allow(dead_code))]
-fn decode_capabilities_from_payload(raw: &[u8]) -> Option<Vec<String>> {
-    if raw.is_empty() {
-        return None;
-    }
-
-    if let Ok(decoded) = serde_cbor::from_slice::<Value>(raw) {
-        if let Some(caps) = extract_capabilities_from_json(Some(&decoded)) {
-            return Some(caps);
-        }
-    }
-
-    if let Ok(decoded) = rmp_serde::from_slice::<Value>(raw) {
-        if let Some(caps) = extract_capabilities_from_json(Some(&decoded)) {
-            return Some(caps);
-        }
-    }
-
-    None
-}
-
-#[cfg_attr(not(test), allow(dead_code))]
-fn normalize_capabilities_iter<'a>(values: impl Iterator<Item = &'a str>) -> Vec<String> {
-    let mut seen = HashSet::new();
-    let mut out = Vec::new();
-    for entry in values {
-        let normalized = entry.trim().to_ascii_lowercase();
-        if normalized.is_empty() || !seen.insert(normalized.clone()) {
-            continue;
-        }
-        out.push(normalized);
-    }
-    out
-}
-
 const FIELD_APP_EXTENSIONS: u8 = 0x10;
 
 #[allow(clippy::too_many_arguments)]
@@ -1289,6 +1560,23 @@ fn build_app_extensions_value(
     Ok(Some(rmpv::Value::Map(entries)))
 }
 
+// Edits and deletes travel alone: the extension map holds just the one key so
+// receivers never mistake them for replies or reactions.
+fn build_amendment_fields(key: &str, message_id: String) -> Result<Option<Value>, String> {
+    let extensions = rmpv::Value::Map(vec![(
+        rmpv::Value::String(key.into()),
+        rmpv::Value::String(message_id.into()),
+    )]);
+    let mut map_entries = Vec::new();
+    upsert_numeric_field(&mut map_entries, FIELD_APP_EXTENSIONS, extensions);
+    let encoded = rmp_serde::to_vec(&rmpv::Value::Map(map_entries))
+        .map_err(|err| format!("failed to encode message fields: {err}"))?;
+    let payload = base64::engine::general_purpose::STANDARD.encode(encoded);
+    Ok(Some(json!({
+        TRANSPORT_FIELDS_MSGPACK_B64_KEY: payload
+    })))
+}
+
 fn build_telemetry_value(telemetry_location: Option<Value>) -> Result<Option<rmpv::Value>, String> {
     let Some(telemetry_location) = telemetry_location else {
         return Ok(None);
@@ -1387,10 +1675,7 @@ fn build_attachment_fields(attachments: &[RichAttachmentInput]) -> Result<Option
                 "attachments[{index}].data_base64 must not be empty"
             ));
         }
-        encoded_attachments.push(rmpv::Value::Array(vec![
-            rmpv::Value::String(name.into()),
-            rmpv::Value::Binary(bytes),
-        ]));
+        encoded_attachments.push(encode_attachment_entry(name, bytes));
     }
 
     let fields_map = rmpv::Value::Map(vec![(
@@ -1405,6 +1690,13 @@ fn build_attachment_fields(attachments: &[RichAttachmentInput]) -> Result<Option
     })))
 }
 
+fn encode_attachment_entry(name: String, bytes: Vec<u8>) -> rmpv::Value {
+    rmpv::Value::Array(vec![
+        rmpv::Value::String(name.into()),
+        rmpv::Value::Binary(bytes),
+    ])
+}
+
 fn pack_sideband_location_telemetry(
     lat: f64,
     lon: f64,
@@ -1468,60 +1760,6 @@ fn decode_attachment_bytes(value: &str) -> Result<Vec<u8>, String> {
 mod tests {
     use super::*;
 
-    #[test]
-    fn decode_capabilities_from_msgpack_announce_payload() {
-        let capability_payload = rmp_serde::to_vec(&json!({
-            "app": "rch",
-            "schema": 1,
-            "caps": ["topic_broker", "attachments"],
-        }))
-        .expect("encode capability payload");
-        let announce_app_data = rmp_serde::to_vec(&(b"RCH".to_vec(), 1u32, capability_payload))
-            .expect("encode announce app-data");
-
-        let caps = decode_capabilities_from_announce_app_data(&announce_app_data)
-            .expect("decode caps from app-data");
-        assert_eq!(caps, vec!["topic_broker", "attachments"]);
-    }
-
-    #[test]
-    fn decode_capabilities_from_cbor_announce_payload() {
-        let capability_payload = serde_cbor::to_vec(&json!({
-            "app": "rch",
-            "schema": 1,
-            "caps": ["telemetry_relay", "attachments"],
-        }))
-        .expect("encode cbor capability payload");
-        let announce_app_data = rmp_serde::to_vec(&(b"RCH".to_vec(), 1u32, capability_payload))
-            .expect("encode announce app-data");
-
-        let caps = decode_capabilities_from_announce_app_data(&announce_app_data)
-            .expect("decode caps from app-data");
-        assert_eq!(caps, vec!["telemetry_relay", "attachments"]);
-    }
-
-    #[test]
-    fn extract_peer_capabilities_from_hex_app_data() {
-        let capability_payload = rmp_serde::to_vec(&json!({
-            "app": "rch",
-            "schema": 1,
-            "caps": ["group_chat"],
-        }))
-        .expect("encode capability payload");
-        let announce_app_data = rmp_serde::to_vec(&(b"RCH".to_vec(), 1u32, capability_payload))
-            .expect("encode announce app-data");
-
-        let mut peer = serde_json::Map::new();
-        peer.insert("peer".into(), Value::String("abc123".into()));
-        peer.insert(
-            "app_data_hex".into(),
-            Value::String(hex::encode(announce_app_data)),
-        );
-
-        let caps = extract_peer_capabilities(&peer);
-        assert_eq!(caps, vec!["group_chat"]);
-    }
-
     #[test]
     fn build_attachment_fields_encodes_lxmf_field_id() {
         let fields = build_attachment_fields(&[RichAttachmentInput {
@@ -1616,4 +1854,108 @@ mod tests {
             .iter()
             .any(|(key, _)| key.as_i64() == Some(FIELD_TELEMETRY as i64)));
     }
+
+    #[test]
+    fn outbox_resend_reencodes_stored_fields_for_transport() {
+        let request = outbox_send_request(OutboxResend {
+            message_id: "msg-1".to_string(),
+            source: None,
+            destination: "peer".to_string(),
+            title: String::new(),
+            content: "report attached".to_string(),
+            fields: Some(json!({ "16": { "reply_to": "msg-0" } })),
+            attachments: vec![("report.txt".to_string(), b"report".to_vec())],
+            method: Some("propagated".to_string()),
+            stamp_cost: None,
+            include_ticket: false,
+        })
+        .expect("request");
+        assert_eq!(request.id.as_deref(), Some("msg-1"));
+        assert_eq!(request.method.as_deref(), Some("propagated"));
+
+        let decoded = lxmf::payload_fields::decode_transport_fields_json(
+            request.fields.as_ref().expect("fields"),
+        )
+        .expect("decode transport")
+        .expect("msgpack map");
+        let map = decoded.as_map().expect("map");
+        assert!(map
+            .iter()
+            .any(|(key, _)| key.as_i64() == Some(FIELD_APP_EXTENSIONS as i64)));
+        let attachments = map
+            .iter()
+            .find(|(key, _)| key.as_i64() == Some(FIELD_FILE_ATTACHMENTS as i64))
+            .and_then(|(_, value)| value.as_array())
+            .expect("attachments");
+        assert_eq!(
+            attachments[0].as_array().map(|entry| &entry[1]),
+            Some(&rmpv::Value::Binary(b"report".to_vec()))
+        );
+
+        let plain = outbox_send_request(OutboxResend {
+            message_id: "msg-2".to_string(),
+            source: None,
+            destination: "peer".to_string(),
+            title: String::new(),
+            content: "no files".to_string(),
+            fields: Some(json!({ "16": { "reply_to": "msg-0" } })),
+            attachments: Vec::new(),
+            method: None,
+            stamp_cost: None,
+            include_ticket: false,
+        })
+        .expect("plain request");
+        let decoded = lxmf::payload_fields::decode_transport_fields_json(
+            plain.fields.as_ref().expect("fields"),
+        )
+        .expect("decode transport")
+        .expect("msgpack map");
+        assert!(decoded
+            .as_map()
+            .expect("map")
+            .iter()
+            .any(|(key, _)| key.as_i64() == Some(FIELD_APP_EXTENSIONS as i64)));
+    }
+
+    #[test]
+    fn send_errors_split_unreachable_unanswered_and_rejected() {
+        assert_eq!(
+            classify_send_error("runtime worker unavailable"),
+            SendFailure::Unavailable
+        );
+        assert_eq!(
+            classify_send_error(
+                "failed to start runtime for profile 'default' rpc '127.0.0.1:4243': bind failed"
+            ),
+            SendFailure::Unavailable
+        );
+        assert_eq!(
+            classify_send_error("runtime worker did not respond"),
+            SendFailure::Unconfirmed
+        );
+        assert_eq!(
+            classify_send_error("invalid destination hash"),
+            SendFailure::Rejected
+        );
+    }
+
+    #[test]
+    fn amendment_fields_carry_only_the_target_key() {
+        let fields = build_amendment_fields("edit_of", "msg-789".to_string())
+            .expect("build fields")
+            .expect("fields");
+        let decoded = lxmf::payload_fields::decode_transport_fields_json(&fields)
+            .expect("decode transport")
+            .expect("msgpack map");
+        let extensions = decoded
+            .as_map()
+            .expect("map")
+            .iter()
+            .find(|(key, _)| key.as_i64() == Some(FIELD_APP_EXTENSIONS as i64))
+            .and_then(|(_, value)| value.as_map())
+            .expect("extensions map");
+        assert_eq!(extensions.len(), 1);
+        assert_eq!(extensions[0].0.as_str(), Some("edit_of"));
+        assert_eq!(extensions[0].1.as_str(), Some("msg-789"));
+    }
 }
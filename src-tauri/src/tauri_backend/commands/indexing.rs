@@ -1,8 +1,14 @@
 use super::super::index_store::{
-    AttachmentBlobParams, AttachmentBytesParams, FilesQueryParams, MapPointsQueryParams,
-    SearchQueryParams, ThreadMessageQueryParams, ThreadQueryParams,
+    AnnounceQueryParams, AttachmentBlobParams, AttachmentBytesParams, BackupParams,
+    ContactQueryParams, ContactUpdateParams, DeleteMessagesParams, ExportParams, FilesQueryParams,
+    IndexStore, IndexStores, MapPointsQueryParams, MessageRepliesQueryParams, OutboxQueryParams,
+    PeerPositionsQueryParams, PeerTrackQueryParams, RetentionPolicyParams, SearchQueryParams,
+    SyncSummary, ThreadFlagParams, ThreadMessageQueryParams, ThreadQueryParams,
+    ThreadReadStateParams,
 };
+use super::super::DesktopShellPreferences;
 use super::*;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
@@ -10,10 +16,19 @@ pub(crate) fn reindex_index_store_from_runtime(
     actor: &RuntimeActor,
     index_store: &IndexStore,
     selector: RuntimeSelector,
-) -> Result<(), String> {
+) -> Result<SyncSummary, String> {
     let messages = rpc_actor_call(actor, selector.clone(), "list_messages", None)?;
-    let peers = rpc_actor_call(actor, selector, "list_peers", None)?;
-    index_store.reindex_from_runtime_payloads(&messages, &peers)
+    let peers = rpc_actor_call(actor, selector.clone(), "list_peers", None)?;
+    let summary = index_store.reindex_from_runtime_payloads(&messages, &peers)?;
+    // Announce history is a convenience; a runtime that cannot list announces
+    // should not fail the message backfill.
+    match rpc_actor_call(actor, selector, "list_announces", None)
+        .and_then(|announces| index_store.record_announces(&announces))
+    {
+        Ok(count) => log::debug!("announce history backfill recorded={count}"),
+        Err(err) => log::debug!("announce history backfill skipped: {err}"),
+    }
+    Ok(summary)
 }
 
 fn now_epoch_ms() -> i64 {
@@ -60,31 +75,39 @@ fn log_index_query_latency(command: &str, started_at: Instant, result: &Result<V
 }
 
 #[tauri::command]
-pub(crate) fn lxmf_index_status(index_store: State<'_, Arc<IndexStore>>) -> Result<Value, String> {
+pub(crate) fn lxmf_index_status(
+    index_stores: State<'_, Arc<IndexStores>>,
+) -> Result<Value, String> {
     let started_at = Instant::now();
-    let status = index_store.as_ref().index_status()?;
+    let profile = index_stores.active_profile();
+    let status = index_stores.for_profile(&profile).index_status()?;
     let freshness_ms = status
         .last_sync_ms
         .map(|last_sync_ms| now_epoch_ms().saturating_sub(last_sync_ms));
     log::debug!(
-        "index_status ready={} message_count={} thread_count={} freshness_ms={} elapsed_ms={}",
+        "index_status ready={} message_count={} thread_count={} schema_version={} freshness_ms={} elapsed_ms={}",
         status.ready,
         status.message_count,
         status.thread_count,
+        status.schema_version,
         freshness_ms
             .map(|value| value.to_string())
             .unwrap_or_else(|| "unknown".to_string()),
         started_at.elapsed().as_millis(),
     );
-    serde_json::to_value(status).map_err(|err| format!("serialize index status failed: {err}"))
+    let mut value = serde_json::to_value(status)
+        .map_err(|err| format!("serialize index status failed: {err}"))?;
+    value["profile"] = json!(profile);
+    Ok(value)
 }
 
 #[tauri::command]
 pub(crate) fn get_runtime_metrics(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     event_pump: State<'_, EventPumpControl>,
     attachment_handles: State<'_, Arc<AttachmentHandleManager>>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let metrics = index_store.as_ref().runtime_metrics()?;
     let rss_bytes = current_process_rss_bytes();
@@ -120,18 +143,21 @@ pub(crate) fn get_runtime_metrics(
 
 #[tauri::command]
 pub(crate) fn lxmf_query_threads(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     query: Option<String>,
     limit: Option<usize>,
     cursor: Option<String>,
     pinned_only: Option<bool>,
+    archived: Option<bool>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store.as_ref().query_threads(ThreadQueryParams {
         query,
         limit,
         cursor,
         pinned_only,
+        archived,
     });
     log_index_query_latency("lxmf_query_threads", started_at, &result);
     result
@@ -139,18 +165,21 @@ pub(crate) fn lxmf_query_threads(
 
 #[tauri::command]
 pub(crate) fn query_threads_page(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     query: Option<String>,
     limit: Option<usize>,
     cursor: Option<String>,
     pinned_only: Option<bool>,
+    archived: Option<bool>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store.as_ref().query_threads(ThreadQueryParams {
         query,
         limit,
         cursor,
         pinned_only,
+        archived,
     });
     log_index_query_latency("query_threads_page", started_at, &result);
     result
@@ -158,12 +187,13 @@ pub(crate) fn query_threads_page(
 
 #[tauri::command]
 pub(crate) fn lxmf_query_thread_messages(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     thread_id: String,
     limit: Option<usize>,
     cursor: Option<String>,
     query: Option<String>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store
         .as_ref()
@@ -179,12 +209,13 @@ pub(crate) fn lxmf_query_thread_messages(
 
 #[tauri::command]
 pub(crate) fn query_thread_messages_page(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     thread_id: String,
     limit: Option<usize>,
     cursor: Option<String>,
     query: Option<String>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store
         .as_ref()
@@ -198,20 +229,511 @@ pub(crate) fn query_thread_messages_page(
     result
 }
 
+#[tauri::command]
+pub(crate) fn lxmf_query_message_replies(
+    index_stores: State<'_, Arc<IndexStores>>,
+    message_id: String,
+    limit: Option<usize>,
+    cursor: Option<String>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .query_message_replies(MessageRepliesQueryParams {
+            message_id,
+            limit,
+            cursor,
+        });
+    log_index_query_latency("lxmf_query_message_replies", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_query_message_revisions(
+    index_stores: State<'_, Arc<IndexStores>>,
+    message_id: String,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().query_message_revisions(&message_id);
+    log_index_query_latency("lxmf_query_message_revisions", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_mark_thread_read(
+    index_stores: State<'_, Arc<IndexStores>>,
+    thread_id: String,
+    message_id: Option<String>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .mark_thread_read(ThreadReadStateParams {
+            thread_id,
+            message_id,
+        });
+    log_index_query_latency("lxmf_mark_thread_read", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_mark_all_threads_read(
+    index_stores: State<'_, Arc<IndexStores>>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().mark_all_threads_read();
+    log_index_query_latency("lxmf_mark_all_threads_read", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_mark_thread_unread(
+    index_stores: State<'_, Arc<IndexStores>>,
+    thread_id: String,
+    message_id: Option<String>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .mark_thread_unread(ThreadReadStateParams {
+            thread_id,
+            message_id,
+        });
+    log_index_query_latency("lxmf_mark_thread_unread", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_set_thread_pinned(
+    index_stores: State<'_, Arc<IndexStores>>,
+    thread_id: String,
+    pinned: bool,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().set_thread_flags(ThreadFlagParams {
+        thread_id,
+        pinned: Some(pinned),
+        muted: None,
+        muted_until_ms: None,
+        archived: None,
+    });
+    log_index_query_latency("lxmf_set_thread_pinned", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_set_thread_muted(
+    index_stores: State<'_, Arc<IndexStores>>,
+    thread_id: String,
+    muted: bool,
+    muted_until_ms: Option<i64>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().set_thread_flags(ThreadFlagParams {
+        thread_id,
+        pinned: None,
+        muted: Some(muted),
+        muted_until_ms,
+        archived: None,
+    });
+    log_index_query_latency("lxmf_set_thread_muted", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_set_thread_archived(
+    index_stores: State<'_, Arc<IndexStores>>,
+    thread_id: String,
+    archived: bool,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().set_thread_flags(ThreadFlagParams {
+        thread_id,
+        pinned: None,
+        muted: None,
+        muted_until_ms: None,
+        archived: Some(archived),
+    });
+    log_index_query_latency("lxmf_set_thread_archived", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_message_delivery_timeline(
+    index_stores: State<'_, Arc<IndexStores>>,
+    message_id: String,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().query_delivery_timeline(&message_id);
+    log_index_query_latency("lxmf_message_delivery_timeline", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_query_outbox(
+    index_stores: State<'_, Arc<IndexStores>>,
+    states: Option<Vec<String>>,
+    reason_code: Option<String>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().query_outbox(OutboxQueryParams {
+        states,
+        reason_code,
+        limit,
+        cursor,
+    });
+    log_index_query_latency("lxmf_query_outbox", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_outbox_cancel(
+    index_stores: State<'_, Arc<IndexStores>>,
+    message_id: String,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().cancel_outbox_message(&message_id);
+    log_index_query_latency("lxmf_outbox_cancel", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_list_send_queue(
+    index_stores: State<'_, Arc<IndexStores>>,
+    profile: Option<String>,
+    rpc: Option<String>,
+) -> Result<Value, String> {
+    let selector = RuntimeSelector::load(profile, rpc)?;
+    let index_store = index_stores.for_profile(&selector.profile_name);
+    let started_at = Instant::now();
+    let result = index_store.as_ref().list_send_queue();
+    log_index_query_latency("lxmf_list_send_queue", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_discard_queued_send(
+    index_stores: State<'_, Arc<IndexStores>>,
+    profile: Option<String>,
+    rpc: Option<String>,
+    queue_id: i64,
+) -> Result<Value, String> {
+    let selector = RuntimeSelector::load(profile, rpc)?;
+    let index_store = index_stores.for_profile(&selector.profile_name);
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .remove_queued_send(queue_id)
+        .map(|discarded| json!({ "queue_id": queue_id, "discarded": discarded }));
+    log_index_query_latency("lxmf_discard_queued_send", started_at, &result);
+    result
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn lxmf_query_announces(
+    index_stores: State<'_, Arc<IndexStores>>,
+    query: Option<String>,
+    aspect: Option<String>,
+    capability: Option<String>,
+    max_hops: Option<i64>,
+    seen_within_ms: Option<i64>,
+    new_only: Option<bool>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().query_announces(AnnounceQueryParams {
+        query,
+        aspect,
+        capability,
+        max_hops,
+        seen_within_ms,
+        new_only,
+        limit,
+        cursor,
+    });
+    log_index_query_latency("lxmf_query_announces", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_mark_announces_viewed(
+    index_stores: State<'_, Arc<IndexStores>>,
+    viewed_at_ms: Option<i64>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().mark_announces_viewed(viewed_at_ms);
+    log_index_query_latency("lxmf_mark_announces_viewed", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_query_contacts(
+    index_stores: State<'_, Arc<IndexStores>>,
+    query: Option<String>,
+    favorites_only: Option<bool>,
+    trust: Option<String>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().query_contacts(ContactQueryParams {
+        query,
+        favorites_only,
+        trust,
+        limit,
+        cursor,
+    });
+    log_index_query_latency("lxmf_query_contacts", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_get_contact(
+    index_stores: State<'_, Arc<IndexStores>>,
+    peer: String,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().get_contact(&peer);
+    log_index_query_latency("lxmf_get_contact", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_update_contact(
+    index_stores: State<'_, Arc<IndexStores>>,
+    peer: String,
+    alias: Option<String>,
+    notes: Option<String>,
+    trust: Option<String>,
+    favorite: Option<bool>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().update_contact(ContactUpdateParams {
+        peer,
+        alias,
+        notes,
+        trust,
+        favorite,
+    });
+    log_index_query_latency("lxmf_update_contact", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_delete_contact(
+    index_stores: State<'_, Arc<IndexStores>>,
+    peer: String,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().delete_contact(&peer);
+    log_index_query_latency("lxmf_delete_contact", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_delete_messages(
+    index_stores: State<'_, Arc<IndexStores>>,
+    profile: Option<String>,
+    rpc: Option<String>,
+    message_ids: Option<Vec<String>>,
+    thread_id: Option<String>,
+) -> Result<Value, String> {
+    // The runtime contract has no per-message delete (only `clear_messages`),
+    // so deletion is index-only: tombstones keep the ids out of later syncs
+    // while the runtime keeps its own copy.
+    let selector = RuntimeSelector::load(profile, rpc)?;
+    let index_store = index_stores.for_profile(&selector.profile_name);
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .delete_messages(DeleteMessagesParams {
+            message_ids: message_ids.unwrap_or_default(),
+            thread_id,
+        })
+        .and_then(|summary| {
+            serde_json::to_value(summary)
+                .map_err(|err| format!("serialize delete summary failed: {err}"))
+        });
+    log_index_query_latency("lxmf_delete_messages", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_get_retention_policy(
+    index_stores: State<'_, Arc<IndexStores>>,
+    thread_id: Option<String>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().get_retention_policy(thread_id);
+    log_index_query_latency("lxmf_get_retention_policy", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_set_retention_policy(
+    index_stores: State<'_, Arc<IndexStores>>,
+    thread_id: Option<String>,
+    max_age_ms: Option<i64>,
+    max_messages: Option<i64>,
+    max_attachment_bytes: Option<i64>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .set_retention_policy(RetentionPolicyParams {
+            thread_id,
+            max_age_ms,
+            max_messages,
+            max_attachment_bytes,
+        });
+    log_index_query_latency("lxmf_set_retention_policy", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_apply_retention(
+    index_stores: State<'_, Arc<IndexStores>>,
+    dry_run: Option<bool>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .apply_retention(dry_run.unwrap_or(false))
+        .and_then(|report| {
+            serde_json::to_value(report)
+                .map_err(|err| format!("serialize retention report failed: {err}"))
+        });
+    log_index_query_latency("lxmf_apply_retention", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_export_messages(
+    index_stores: State<'_, Arc<IndexStores>>,
+    thread_id: Option<String>,
+    query: Option<String>,
+    format: String,
+    destination: String,
+    attachments: Option<String>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .export_messages(ExportParams {
+            thread_id,
+            query,
+            format,
+            destination: PathBuf::from(destination),
+            attachments,
+        })
+        .and_then(|summary| {
+            serde_json::to_value(summary)
+                .map_err(|err| format!("serialize export summary failed: {err}"))
+        });
+    log_index_query_latency("lxmf_export_messages", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_backup_index(
+    index_stores: State<'_, Arc<IndexStores>>,
+    desktop_shell: State<'_, DesktopShellState>,
+    destination: String,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = serde_json::to_value(desktop_shell.snapshot())
+        .map_err(|err| format!("serialize desktop shell prefs failed: {err}"))
+        .and_then(|prefs| {
+            index_store.as_ref().create_backup(BackupParams {
+                destination: PathBuf::from(destination),
+                desktop_shell: Some(prefs),
+            })
+        })
+        .and_then(|summary| {
+            serde_json::to_value(summary)
+                .map_err(|err| format!("serialize backup summary failed: {err}"))
+        });
+    log_index_query_latency("lxmf_backup_index", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_restore_index(
+    app: AppHandle,
+    index_stores: State<'_, Arc<IndexStores>>,
+    desktop_shell: State<'_, DesktopShellState>,
+    archive: String,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .restore_backup(Path::new(&archive))
+        .and_then(|summary| {
+            if let Some(prefs) = summary.desktop_shell.clone() {
+                let prefs: DesktopShellPreferences = serde_json::from_value(prefs)
+                    .map_err(|err| format!("parse backup desktop shell prefs failed: {err}"))?;
+                desktop_shell.apply_patch(
+                    &app,
+                    DesktopShellPreferencePatch {
+                        minimize_to_tray_on_close: Some(prefs.minimize_to_tray_on_close),
+                        start_in_tray: Some(prefs.start_in_tray),
+                        single_instance_focus: Some(prefs.single_instance_focus),
+                        notifications_muted: Some(prefs.notifications_muted),
+                    },
+                )?;
+            }
+            index_store.mark_ready();
+            serde_json::to_value(summary)
+                .map_err(|err| format!("serialize restore summary failed: {err}"))
+        });
+    log_index_query_latency("lxmf_restore_index", started_at, &result);
+    result
+}
+
 #[tauri::command]
 pub(crate) fn lxmf_search_messages(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     query: String,
     thread_id: Option<String>,
     limit: Option<usize>,
     cursor: Option<String>,
+    sort: Option<String>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store.as_ref().search_messages(SearchQueryParams {
         query,
         thread_id,
         limit,
         cursor,
+        sort,
     });
     log_index_query_latency("lxmf_search_messages", started_at, &result);
     result
@@ -219,12 +741,13 @@ pub(crate) fn lxmf_search_messages(
 
 #[tauri::command]
 pub(crate) fn lxmf_query_files(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     query: Option<String>,
     kind: Option<String>,
     limit: Option<usize>,
     cursor: Option<String>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store.as_ref().query_files(FilesQueryParams {
         query,
@@ -239,13 +762,14 @@ pub(crate) fn lxmf_query_files(
 
 #[tauri::command]
 pub(crate) fn query_files_page(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     query: Option<String>,
     kind: Option<String>,
     limit: Option<usize>,
     cursor: Option<String>,
     include_bytes: Option<bool>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store.as_ref().query_files(FilesQueryParams {
         query,
@@ -259,28 +783,87 @@ pub(crate) fn query_files_page(
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn lxmf_query_map_points(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     query: Option<String>,
     limit: Option<usize>,
     cursor: Option<String>,
+    min_lat: Option<f64>,
+    min_lon: Option<f64>,
+    max_lat: Option<f64>,
+    max_lon: Option<f64>,
+    since_ms: Option<i64>,
+    until_ms: Option<i64>,
+    peer: Option<String>,
+    direction: Option<String>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store.as_ref().query_map_points(MapPointsQueryParams {
         query,
         limit,
         cursor,
+        min_lat,
+        min_lon,
+        max_lat,
+        max_lon,
+        since_ms,
+        until_ms,
+        peer,
+        direction,
     });
     log_index_query_latency("lxmf_query_map_points", started_at, &result);
     result
 }
 
+#[tauri::command]
+pub(crate) fn lxmf_query_peer_positions(
+    index_stores: State<'_, Arc<IndexStores>>,
+    peer: Option<String>,
+    since_ms: Option<i64>,
+    stale_after_ms: Option<i64>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store
+        .as_ref()
+        .query_peer_positions(PeerPositionsQueryParams {
+            peer,
+            since_ms,
+            stale_after_ms,
+        });
+    log_index_query_latency("lxmf_query_peer_positions", started_at, &result);
+    result
+}
+
+#[tauri::command]
+pub(crate) fn lxmf_query_peer_track(
+    index_stores: State<'_, Arc<IndexStores>>,
+    peer: String,
+    since_ms: Option<i64>,
+    until_ms: Option<i64>,
+    limit: Option<usize>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let result = index_store.as_ref().query_peer_track(PeerTrackQueryParams {
+        peer,
+        since_ms,
+        until_ms,
+        limit,
+    });
+    log_index_query_latency("lxmf_query_peer_track", started_at, &result);
+    result
+}
+
 #[tauri::command]
 pub(crate) fn lxmf_get_attachment_blob(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     message_id: String,
     attachment_name: String,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store
         .as_ref()
@@ -294,9 +877,10 @@ pub(crate) fn lxmf_get_attachment_blob(
 
 #[tauri::command]
 pub(crate) fn get_attachment_bytes(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     attachment_id: String,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let result = index_store
         .as_ref()
@@ -308,11 +892,12 @@ pub(crate) fn get_attachment_bytes(
 #[tauri::command]
 pub(crate) fn open_attachment_handle(
     app: AppHandle,
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     attachment_handles: State<'_, Arc<AttachmentHandleManager>>,
     attachment_id: String,
     disposition: Option<String>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     let _ = disposition;
     let result = attachment_handles
@@ -341,21 +926,24 @@ pub(crate) fn close_attachment_handle(
 #[tauri::command]
 pub(crate) fn lxmf_force_reindex(
     actor: State<'_, RuntimeActor>,
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
     profile: Option<String>,
     rpc: Option<String>,
 ) -> Result<Value, String> {
     let started_at = Instant::now();
-    index_store.as_ref().force_reindex()?;
     let selector = RuntimeSelector::load(profile, rpc)?;
-    reindex_index_store_from_runtime(&actor, index_store.as_ref(), selector)?;
+    let index_store = index_stores.for_profile(&selector.profile_name);
+    index_store.as_ref().force_reindex()?;
+    let summary = reindex_index_store_from_runtime(&actor, index_store.as_ref(), selector)?;
     if let Ok(status) = index_store.as_ref().index_status() {
         let freshness_ms = status
             .last_sync_ms
             .map(|last_sync_ms| now_epoch_ms().saturating_sub(last_sync_ms));
         log::info!(
-            "index_reindex completed elapsed_ms={} message_count={} thread_count={} freshness_ms={}",
+            "index_reindex completed elapsed_ms={} upserted={} deleted={} message_count={} thread_count={} freshness_ms={}",
             started_at.elapsed().as_millis(),
+            summary.upserted,
+            summary.deleted,
             status.message_count,
             status.thread_count,
             freshness_ms
@@ -369,14 +957,16 @@ pub(crate) fn lxmf_force_reindex(
         );
     }
     Ok(json!({
-        "started": true
+        "started": true,
+        "sync": summary,
     }))
 }
 
 #[tauri::command]
 pub(crate) fn rebuild_thread_summaries(
-    index_store: State<'_, Arc<IndexStore>>,
+    index_stores: State<'_, Arc<IndexStores>>,
 ) -> Result<Value, String> {
+    let index_store = index_stores.active();
     let started_at = Instant::now();
     index_store.as_ref().rebuild_thread_summaries()?;
     log::info!(
@@ -385,3 +975,23 @@ pub(crate) fn rebuild_thread_summaries(
     );
     Ok(json!({ "rebuilt": true }))
 }
+
+#[tauri::command]
+pub(crate) fn lxmf_verify_and_repair_index(
+    index_stores: State<'_, Arc<IndexStores>>,
+) -> Result<Value, String> {
+    let index_store = index_stores.active();
+    let started_at = Instant::now();
+    let report = index_store.as_ref().verify_and_repair()?;
+    log::info!(
+        "index_repair elapsed_ms={} database_ok={} fts_problems={} threads_fixed={} orphan_attachments_removed={} blob_refcounts_fixed={} missing_blob_files={}",
+        started_at.elapsed().as_millis(),
+        report.database_ok,
+        report.fts_problems.len(),
+        report.threads_fixed,
+        report.orphan_attachments_removed,
+        report.blob_refcounts_fixed,
+        report.missing_blob_files
+    );
+    serde_json::to_value(report).map_err(|err| format!("serialize repair report failed: {err}"))
+}
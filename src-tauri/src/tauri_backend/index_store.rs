@@ -1,17 +1,44 @@
+use super::announce_data::{extract_peer_capabilities, extract_peer_display_name};
 use base64::Engine as _;
 use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub(crate) use amendments::{delete_fallback_body, edit_fallback_body};
+use blobs::BlobStore;
+use delivery::DeliveryState;
+use integrity::OpenFailure;
+pub(crate) use profiles::IndexStores;
+
+mod amendments;
+mod announces;
 mod attachments;
+mod backup;
+mod blobs;
+mod contacts;
+mod deletion;
+mod delivery;
+mod export;
 mod ingest;
+mod integrity;
+mod locations;
 mod maintenance;
+mod migrations;
+mod outbox;
+mod profiles;
 mod queries;
+mod reactions;
+mod read_state;
+mod replies;
+mod retention;
+mod search_query;
+mod send_queue;
+mod thread_flags;
 
 const DEFAULT_LIMIT: usize = 100;
 const MAX_LIMIT: usize = 1000;
@@ -22,6 +49,7 @@ pub(crate) struct ThreadQueryParams {
     pub limit: Option<usize>,
     pub cursor: Option<String>,
     pub pinned_only: Option<bool>,
+    pub archived: Option<bool>,
 }
 
 #[derive(Clone, Debug)]
@@ -32,12 +60,20 @@ pub(crate) struct ThreadMessageQueryParams {
     pub query: Option<String>,
 }
 
+#[derive(Clone, Debug)]
+pub(crate) struct MessageRepliesQueryParams {
+    pub message_id: String,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct SearchQueryParams {
     pub query: String,
     pub thread_id: Option<String>,
     pub limit: Option<usize>,
     pub cursor: Option<String>,
+    pub sort: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -54,6 +90,58 @@ pub(crate) struct MapPointsQueryParams {
     pub query: Option<String>,
     pub limit: Option<usize>,
     pub cursor: Option<String>,
+    pub min_lat: Option<f64>,
+    pub min_lon: Option<f64>,
+    pub max_lat: Option<f64>,
+    pub max_lon: Option<f64>,
+    pub since_ms: Option<i64>,
+    pub until_ms: Option<i64>,
+    pub peer: Option<String>,
+    pub direction: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct DeleteMessagesParams {
+    pub message_ids: Vec<String>,
+    pub thread_id: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ExportParams {
+    pub thread_id: Option<String>,
+    pub query: Option<String>,
+    pub format: String,
+    pub destination: PathBuf,
+    pub attachments: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct BackupParams {
+    pub destination: PathBuf,
+    pub desktop_shell: Option<Value>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct RetentionPolicyParams {
+    pub thread_id: Option<String>,
+    pub max_age_ms: Option<i64>,
+    pub max_messages: Option<i64>,
+    pub max_attachment_bytes: Option<i64>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PeerPositionsQueryParams {
+    pub peer: Option<String>,
+    pub since_ms: Option<i64>,
+    pub stale_after_ms: Option<i64>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PeerTrackQueryParams {
+    pub peer: String,
+    pub since_ms: Option<i64>,
+    pub until_ms: Option<i64>,
+    pub limit: Option<usize>,
 }
 
 #[derive(Clone, Debug)]
@@ -67,12 +155,130 @@ pub(crate) struct AttachmentBytesParams {
     pub attachment_id: String,
 }
 
+#[derive(Clone, Debug)]
+pub(crate) struct ContactQueryParams {
+    pub query: Option<String>,
+    pub favorites_only: Option<bool>,
+    pub trust: Option<String>,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct AnnounceQueryParams {
+    pub query: Option<String>,
+    pub aspect: Option<String>,
+    pub capability: Option<String>,
+    pub max_hops: Option<i64>,
+    pub seen_within_ms: Option<i64>,
+    pub new_only: Option<bool>,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct OutboxQueryParams {
+    pub states: Option<Vec<String>>,
+    pub reason_code: Option<String>,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+/// How an outbox resend is routed: with the stored method, with the next
+/// fallback after it (direct, then propagated, then paper), or with an
+/// explicit method.
+#[derive(Clone, Debug)]
+pub(crate) enum OutboxRoute {
+    Same,
+    Next,
+    Method(String),
+}
+
+/// Everything needed to send a stored outbound message again under its own
+/// id. `fields` is the stored field map without attachments or the `_lxmf`
+/// send settings, which are returned separately.
+#[derive(Debug)]
+pub(crate) struct OutboxResend {
+    pub message_id: String,
+    pub source: Option<String>,
+    pub destination: String,
+    pub title: String,
+    pub content: String,
+    pub fields: Option<Value>,
+    pub attachments: Vec<(String, Vec<u8>)>,
+    pub method: Option<String>,
+    pub stamp_cost: Option<u32>,
+    pub include_ticket: bool,
+}
+
+/// A send request held by the offline send queue, in the shape of the
+/// runtime's `SendMessageRequest`. `id` is always set once queued so the
+/// message can be followed before the runtime has seen it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct QueuedSend {
+    pub id: Option<String>,
+    pub source: Option<String>,
+    pub destination: String,
+    pub title: String,
+    pub content: String,
+    pub fields: Option<Value>,
+    pub method: Option<String>,
+    pub stamp_cost: Option<u32>,
+    pub include_ticket: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct QueuedSendEntry {
+    pub queue_id: i64,
+    pub message_id: String,
+    pub profile: String,
+    pub rpc: String,
+    #[serde(skip)]
+    pub request: QueuedSend,
+    pub destination: String,
+    pub title: String,
+    pub content: String,
+    pub created_at_ms: i64,
+    pub attempts: u32,
+    pub next_attempt_ms: i64,
+    pub last_error: Option<String>,
+    pub failed_at_ms: Option<i64>,
+    pub unconfirmed: bool,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ContactUpdateParams {
+    pub peer: String,
+    pub alias: Option<String>,
+    pub notes: Option<String>,
+    pub trust: Option<String>,
+    pub favorite: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ThreadFlagParams {
+    pub thread_id: String,
+    pub pinned: Option<bool>,
+    pub muted: Option<bool>,
+    pub muted_until_ms: Option<i64>,
+    pub archived: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ThreadReadStateParams {
+    pub thread_id: String,
+    pub message_id: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub(crate) struct IndexStatus {
     pub ready: bool,
     pub message_count: usize,
     pub thread_count: usize,
     pub last_sync_ms: Option<i64>,
+    pub schema_version: i64,
+    pub schema_error: Option<String>,
+    pub quarantined_path: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -84,6 +290,84 @@ pub(crate) struct RuntimeMetrics {
     pub index_last_sync_ms: Option<i64>,
 }
 
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct SyncSummary {
+    pub upserted: usize,
+    pub unchanged: usize,
+    pub deleted: usize,
+    pub threads_updated: usize,
+    pub resumed: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct DeleteSummary {
+    pub deleted: usize,
+    pub message_ids: Vec<String>,
+    pub threads_updated: Vec<String>,
+    pub threads_removed: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct ExportSummary {
+    pub format: String,
+    pub path: String,
+    pub attachments_path: Option<String>,
+    pub messages: usize,
+    pub attachments: usize,
+    pub bytes_written: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct BackupSummary {
+    pub path: String,
+    pub schema_version: i64,
+    pub created_at_ms: i64,
+    pub messages: i64,
+    pub blobs: usize,
+    pub bytes_written: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct RestoreSummary {
+    pub app_version: String,
+    pub schema_version: i64,
+    pub created_at_ms: i64,
+    pub messages: i64,
+    pub threads: i64,
+    pub blobs: usize,
+    pub desktop_shell: Option<Value>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct IndexRepairReport {
+    pub database_ok: bool,
+    pub database_problems: Vec<String>,
+    pub fts_problems: Vec<String>,
+    pub fts_rebuilt: bool,
+    pub threads_fixed: usize,
+    pub orphan_attachments_removed: usize,
+    pub blob_refcounts_fixed: usize,
+    pub blobs_removed: usize,
+    pub missing_blob_files: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct RetentionReport {
+    pub dry_run: bool,
+    pub messages: usize,
+    pub attachments: usize,
+    pub attachment_bytes: i64,
+    pub threads: Vec<RetentionThreadReport>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct RetentionThreadReport {
+    pub thread_id: String,
+    pub messages: usize,
+    pub attachments: usize,
+    pub attachment_bytes: i64,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct AttachmentBinary {
     pub mime: Option<String>,
@@ -108,6 +392,115 @@ struct IndexedThread {
     muted: bool,
     last_message_id: Option<String>,
     last_activity_ms: i64,
+    last_read_message_id: Option<String>,
+    archived: bool,
+    muted_until_ms: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct RetentionPolicy {
+    max_age_ms: Option<i64>,
+    max_messages: Option<i64>,
+    max_attachment_bytes: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ThreadFlags {
+    thread_id: String,
+    pinned: bool,
+    muted: bool,
+    muted_until_ms: Option<i64>,
+    archived: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ThreadReadState {
+    thread_id: String,
+    unread: usize,
+    last_read_message_id: Option<String>,
+    last_read_ts_ms: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Contact {
+    peer: String,
+    display_name: String,
+    alias: Option<String>,
+    announced_name: Option<String>,
+    notes: String,
+    trust: String,
+    favorite: bool,
+    capabilities: Vec<String>,
+    first_seen_ms: Option<i64>,
+    last_seen_ms: Option<i64>,
+    updated_at_ms: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct AnnounceEntry {
+    peer: String,
+    name: Option<String>,
+    name_source: Option<String>,
+    aspect: Option<String>,
+    app_data_hex: Option<String>,
+    capabilities: Vec<String>,
+    first_seen_ms: i64,
+    last_seen_ms: i64,
+    seen_count: usize,
+    hops: Option<i64>,
+    hop_history: Vec<HopSample>,
+    rssi: Option<f64>,
+    snr: Option<f64>,
+    q: Option<f64>,
+    is_new: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct HopSample {
+    ts_ms: i64,
+    hops: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct AnnounceQueryResult {
+    items: Vec<AnnounceEntry>,
+    next_cursor: Option<String>,
+    last_viewed_ms: Option<i64>,
+    new_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct PeerPosition {
+    peer: String,
+    display_name: String,
+    lat: f64,
+    lon: f64,
+    timestamp: i64,
+    age_ms: i64,
+    stale: bool,
+    message_id: String,
+    label: String,
+    heading_deg: Option<f64>,
+    speed_mps: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct PeerTrack {
+    peer: String,
+    display_name: String,
+    since_ms: i64,
+    until_ms: i64,
+    points: Vec<TrackPoint>,
+    distance_m: f64,
+    truncated: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct TrackPoint {
+    lat: f64,
+    lon: f64,
+    timestamp: i64,
+    message_id: String,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -121,6 +514,61 @@ struct IndexedMessage {
     direction: String,
     fields: Value,
     receipt_status: Option<String>,
+    reactions: Vec<ReactionSummary>,
+    reply_to: Option<QuotedMessage>,
+    reply_count: usize,
+    edited_at_ms: Option<i64>,
+    deleted_at_ms: Option<i64>,
+    delivery_state: Option<DeliveryState>,
+    delivery_reason_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DeliveryEvent {
+    state: String,
+    ts_ms: i64,
+    reason_code: Option<String>,
+    detail: Option<String>,
+    source: String,
+}
+
+#[derive(Debug, Serialize)]
+struct OutboxEntry {
+    message_id: String,
+    thread_id: String,
+    destination: String,
+    title: String,
+    preview: String,
+    ts_ms: i64,
+    state: Option<DeliveryState>,
+    reason_code: Option<String>,
+    receipt_status: Option<String>,
+    method: Option<String>,
+    next_method: Option<&'static str>,
+    has_attachments: bool,
+    retry_count: usize,
+    state_since_ms: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct OutboxQueryResult {
+    items: Vec<OutboxEntry>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct QuotedMessage {
+    message_id: String,
+    author: Option<String>,
+    first_line: Option<String>,
+    missing: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ReactionSummary {
+    emoji: String,
+    count: usize,
+    peers: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -149,6 +597,9 @@ struct IndexedMapPoint {
     source: String,
     when: String,
     direction: String,
+    message_id: String,
+    peer: String,
+    timestamp: i64,
 }
 
 #[derive(Debug, Clone)]
@@ -180,6 +631,7 @@ struct ThreadSummary {
     preview: String,
     last_message_id: String,
     last_activity_ms: i64,
+    preview_is_reaction: bool,
     unread: usize,
     pinned: bool,
     muted: bool,
@@ -192,28 +644,79 @@ struct ThreadCursorKey {
     thread_id: String,
 }
 
+#[derive(Debug, Clone, Serialize)]
+struct SearchHit {
+    #[serde(flatten)]
+    message: IndexedMessage,
+    thread_id: String,
+    thread_name: String,
+    snippet: String,
+    // [start, end) ranges into `snippet`, in UTF-16 code units.
+    highlights: Vec<[usize; 2]>,
+    score: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SearchCursorKey {
+    timestamp: i64,
+    message_id: String,
+    // Kept as text: serde_json's default float parsing is not exact, and the
+    // keyset comparison needs the bm25 score bit-for-bit.
+    score: Option<String>,
+    scan: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct MessageCursorKey {
     timestamp: i64,
     message_id: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MapPointCursorKey {
+    timestamp: i64,
+    id: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct FileCursorKey {
     created_at_ms: i64,
     sort_id: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnnounceCursorKey {
+    last_seen_ms: i64,
+    peer: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OutboxCursorKey {
+    ts_ms: i64,
+    message_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ContactCursorKey {
+    favorite: bool,
+    sort_name: String,
+    peer: String,
+}
+
 #[derive(Debug)]
 struct PeerSummary {
     peer: String,
-    name: String,
+    name: Option<String>,
+    capabilities: Option<Vec<String>>,
+    first_seen_ms: Option<i64>,
+    last_seen_ms: Option<i64>,
 }
 
 #[derive(Debug)]
 struct MessageParseResult {
     row: MessageRow,
     attachments: Vec<AttachmentEntry>,
+    sync_marker: String,
 }
 
 #[derive(Debug)]
@@ -229,32 +732,102 @@ struct MapPointMessageContext<'a> {
 
 pub(crate) struct IndexStore {
     conn: Mutex<Connection>,
+    // Absent for the in-memory fallback, which keeps payloads inline.
+    blobs: Option<BlobStore>,
     ready: AtomicBool,
+    schema_error: Option<String>,
+    // Where a corrupted database found at startup was moved to.
+    quarantined_path: Option<String>,
 }
 
 impl IndexStore {
     pub(crate) fn new(path: PathBuf) -> Result<Self, String> {
+        Self::open_checked(path).map_err(String::from)
+    }
+
+    fn open_checked(path: PathBuf) -> Result<Self, OpenFailure> {
         let parent = path
             .parent()
             .ok_or_else(|| "index database parent directory is missing".to_string())?;
         std::fs::create_dir_all(parent)
             .map_err(|err| format!("create index directory failed: {err}"))?;
 
-        let conn = Connection::open(&path).map_err(|err| format!("open index db failed: {err}"))?;
+        let mut conn =
+            Connection::open(&path).map_err(|err| format!("open index db failed: {err}"))?;
         conn.pragma_update(None, "journal_mode", "WAL")
-            .map_err(|err| format!("set journal mode failed: {err}"))?;
+            .map_err(|err| {
+                let message = format!("set journal mode failed: {err}");
+                if integrity::is_corruption(&err) {
+                    OpenFailure::Corrupt(message)
+                } else {
+                    OpenFailure::Other(message)
+                }
+            })?;
         conn.pragma_update(None, "synchronous", "NORMAL")
             .map_err(|err| format!("set synchronous mode failed: {err}"))?;
-        conn.execute_batch(SCHEMA_SQL)
-            .map_err(|err| format!("init index schema failed: {err}"))?;
-        run_schema_migrations(&conn)?;
+        integrity::check_database(&conn)?;
+        migrations::run_schema_migrations(&mut conn)?;
+
+        let blobs = BlobStore::for_index(&path);
+        let externalized = blobs::externalize_inline_attachments(&mut conn, &blobs)?;
+        if externalized > 0 {
+            // The pages the base64 payloads occupied are reclaimed by the
+            // hourly maintenance job rather than holding up startup.
+            log::info!("moved {externalized} inline attachment payloads to the blob store");
+        }
+        let orphaned = blobs::sweep_orphan_blob_files(&conn, &blobs);
+        if orphaned > 0 {
+            log::warn!("removed {orphaned} orphaned attachment blob files");
+        }
 
         Ok(Self {
             conn: Mutex::new(conn),
+            blobs: Some(blobs),
             ready: AtomicBool::new(false),
+            schema_error: None,
+            quarantined_path: None,
         })
     }
 
+    /// Opens the on-disk index, falling back to an in-memory index when the
+    /// database cannot be opened or migrated. The failure is kept so
+    /// `index_status` can surface it instead of aborting startup.
+    pub(crate) fn open_or_fallback(path: PathBuf) -> Self {
+        let err = match Self::open_checked(path.clone()) {
+            Ok(store) => return store,
+            Err(OpenFailure::Corrupt(reason)) => {
+                log::error!("index database {} is corrupted: {reason}", path.display());
+                // The fresh index is empty, so the startup backfill repopulates
+                // it from the runtime.
+                match integrity::quarantine_index(&path)
+                    .and_then(|moved| Ok((moved, Self::new(path)?)))
+                {
+                    Ok((moved, mut store)) => {
+                        log::warn!("moved corrupted index to {}", moved.display());
+                        store.quarantined_path = Some(moved.to_string_lossy().to_string());
+                        return store;
+                    }
+                    Err(err) => err,
+                }
+            }
+            Err(OpenFailure::Other(err)) => err,
+        };
+        log::error!("index store unavailable, using in-memory fallback: {err}");
+        Self::in_memory(err)
+    }
+
+    fn in_memory(schema_error: String) -> Self {
+        let mut conn = Connection::open_in_memory().expect("open in-memory index db");
+        migrations::run_schema_migrations(&mut conn).expect("migrate in-memory index db");
+        Self {
+            conn: Mutex::new(conn),
+            blobs: None,
+            ready: AtomicBool::new(false),
+            schema_error: Some(schema_error),
+            quarantined_path: None,
+        }
+    }
+
     pub(crate) fn is_ready(&self) -> bool {
         self.ready.load(Ordering::Relaxed)
     }
@@ -287,12 +860,16 @@ impl IndexStore {
             .optional()
             .map_err(|err| format!("read last_sync_ms failed: {err}"))?
             .and_then(|value| value.parse::<i64>().ok());
+        let schema_version = migrations::read_schema_version(&conn)?;
 
         Ok(IndexStatus {
             ready: self.is_ready(),
             message_count: message_count.max(0) as usize,
             thread_count: thread_count.max(0) as usize,
             last_sync_ms,
+            schema_version,
+            schema_error: self.schema_error.clone(),
+            quarantined_path: self.quarantined_path.clone(),
         })
     }
 
@@ -334,7 +911,12 @@ fn sanitize_fields_for_client(conn: &Connection, message_id: &str, fields: Value
 fn upsert_message_row(
     tx: &rusqlite::Transaction<'_>,
     parsed: &MessageParseResult,
+    blobs: Option<&BlobStore>,
 ) -> Result<(), String> {
+    let amendment = amendments::extract_amendment(&parsed.row);
+    if parsed.row.direction != "out" {
+        contacts::note_peer_seen(tx, &parsed.row.source, parsed.row.ts_ms)?;
+    }
     tx.execute(
         "
         INSERT INTO messages (
@@ -351,8 +933,15 @@ fn upsert_message_row(
           has_attachments,
           has_paper,
           fields_json,
-          updated_at_ms
-        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+          updated_at_ms,
+          sync_marker,
+          reaction_only,
+          reply_to_message_id,
+          amends_message_id,
+          amendment_kind
+        ) VALUES (
+          ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19
+        )
         ON CONFLICT(message_id) DO UPDATE SET
           thread_id = excluded.thread_id,
           direction = excluded.direction,
@@ -366,7 +955,12 @@ fn upsert_message_row(
           has_attachments = excluded.has_attachments,
           has_paper = excluded.has_paper,
           fields_json = excluded.fields_json,
-          updated_at_ms = excluded.updated_at_ms
+          updated_at_ms = excluded.updated_at_ms,
+          sync_marker = excluded.sync_marker,
+          reaction_only = excluded.reaction_only,
+          reply_to_message_id = excluded.reply_to_message_id,
+          amends_message_id = excluded.amends_message_id,
+          amendment_kind = excluded.amendment_kind
         ",
         params![
             &parsed.row.message_id,
@@ -378,15 +972,29 @@ fn upsert_message_row(
             &parsed.row.title,
             &parsed.row.body,
             &parsed.row.receipt_status,
-            derive_reason_code(parsed.row.receipt_status.as_deref()),
+            delivery::classify_status(parsed.row.receipt_status.as_deref())
+                .and_then(|(_, reason_code)| reason_code),
             if parsed.attachments.is_empty() { 0 } else { 1 },
             if has_paper_field(parsed.row.fields.as_ref()) {
                 1
             } else {
                 0
             },
-            parsed.row.fields.as_ref().map(|fields| fields.to_string()),
+            parsed
+                .row
+                .fields
+                .clone()
+                .map(|fields| blobs::strip_attachment_payloads(fields).to_string()),
             current_timestamp_ms(),
+            &parsed.sync_marker,
+            if reactions::is_reaction_only(&parsed.row) {
+                1
+            } else {
+                0
+            },
+            replies::extract_reply_to(&parsed.row),
+            amendment.as_ref().map(|(_, target)| target.as_str()),
+            amendment.as_ref().map(|(kind, _)| kind.as_str()),
         ],
     )
     .map_err(|err| format!("upsert message failed: {err}"))?;
@@ -398,6 +1006,17 @@ fn upsert_message_row(
     .map_err(|err| format!("clear attachments failed: {err}"))?;
 
     for (index, attachment) in parsed.attachments.iter().enumerate() {
+        let blob_sha256 = match (blobs, attachment.inline_base64.as_deref()) {
+            (Some(blobs), Some(inline_base64)) => {
+                blobs::store_attachment_blob(tx, blobs, inline_base64)?
+            }
+            _ => None,
+        };
+        let inline_base64 = if blob_sha256.is_some() {
+            None
+        } else {
+            attachment.inline_base64.as_deref()
+        };
         tx.execute(
             "
             INSERT INTO attachments (
@@ -407,8 +1026,9 @@ fn upsert_message_row(
               mime,
               size_bytes,
               inline_base64,
-              created_at_ms
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+              created_at_ms,
+              blob_sha256
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
             ",
             params![
                 &parsed.row.message_id,
@@ -416,76 +1036,188 @@ fn upsert_message_row(
                 &attachment.name,
                 &attachment.mime,
                 attachment.size_bytes,
-                &attachment.inline_base64,
+                inline_base64,
                 parsed.row.ts_ms,
+                blob_sha256,
             ],
         )
         .map_err(|err| format!("insert attachment failed: {err}"))?;
     }
 
+    replace_message_map_points(tx, &parsed.row)?;
+    reactions::replace_message_reaction(tx, &parsed.row)?;
+    delivery::record_row_delivery_state(tx, &parsed.row)?;
+    amendments::apply_amendments_for_row(tx, &parsed.row)
+}
+
+// Removes messages from the index and records tombstones so the next sync does
+// not bring them back. FTS rows, map points and blob references follow via
+// triggers. Returns the threads whose summaries need refreshing.
+fn delete_indexed_messages(
+    conn: &Connection,
+    message_ids: &[String],
+    reason: &str,
+) -> Result<BTreeSet<String>, String> {
+    let now_ms = current_timestamp_ms();
+    let mut touched_threads = BTreeSet::new();
+    for message_id in message_ids {
+        let Some(thread_id) = conn
+            .query_row(
+                "SELECT thread_id FROM messages WHERE message_id = ?1",
+                params![message_id],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(|err| format!("read message thread failed: {err}"))?
+        else {
+            continue;
+        };
+        conn.execute(
+            "DELETE FROM attachments WHERE message_id = ?1",
+            params![message_id],
+        )
+        .map_err(|err| format!("delete message attachments failed: {err}"))?;
+        conn.execute(
+            "DELETE FROM messages WHERE message_id = ?1",
+            params![message_id],
+        )
+        .map_err(|err| format!("delete message failed: {err}"))?;
+        conn.execute(
+            "
+            INSERT INTO message_tombstones (message_id, thread_id, reason, deleted_at_ms)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(message_id) DO UPDATE SET
+              reason = excluded.reason,
+              deleted_at_ms = excluded.deleted_at_ms
+            ",
+            params![message_id, &thread_id, reason, now_ms],
+        )
+        .map_err(|err| format!("record message tombstone failed: {err}"))?;
+        touched_threads.insert(thread_id);
+    }
+    Ok(touched_threads)
+}
+
+// Map points are extracted once per message write so map queries never need
+// to re-parse `fields_json`; the R*Tree rows follow via triggers.
+fn replace_message_map_points(conn: &Connection, row: &MessageRow) -> Result<(), String> {
+    conn.execute(
+        "DELETE FROM map_points WHERE message_id = ?1",
+        params![&row.message_id],
+    )
+    .map_err(|err| format!("clear map points failed: {err}"))?;
+
+    let context = MapPointMessageContext {
+        message_id: &row.message_id,
+        source: &row.source,
+        destination: &row.destination,
+        direction: &row.direction,
+        title: &row.title,
+        body: &row.body,
+        ts_ms: row.ts_ms,
+    };
+    let points = extract_map_points(&context, row.fields.as_ref().unwrap_or(&Value::Null));
+    for (ordinal, point) in points.iter().enumerate() {
+        conn.execute(
+            "
+            INSERT INTO map_points (
+              message_id,
+              ordinal,
+              peer,
+              direction,
+              ts_ms,
+              lat,
+              lon,
+              label
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ",
+            params![
+                &point.message_id,
+                ordinal as i64,
+                &point.peer,
+                &point.direction,
+                point.timestamp,
+                point.lat,
+                point.lon,
+                &point.label,
+            ],
+        )
+        .map_err(|err| format!("insert map point failed: {err}"))?;
+    }
     Ok(())
 }
 
-fn upsert_thread_summary_for_thread(conn: &mut Connection, thread_id: &str) -> Result<(), String> {
+/// Rows a thread summary is built from. Edits and deletes (`amends_message_id`)
+/// only change other messages and never count; a remotely deleted message drops
+/// out, while a thread made only of reactions still gets a summary. `alias` is
+/// the table prefix the query uses, e.g. "m." or "".
+fn thread_summary_messages_sql(alias: &str) -> String {
+    format!("{alias}amends_message_id IS NULL AND {alias}deleted_at_ms IS NULL")
+}
+
+/// Rows listed in a thread or as replies, leaving out edits and deletes. A
+/// deleted message keeps its place with empty content; reaction-only messages
+/// are shown on their target.
+fn listed_messages_sql(alias: &str) -> String {
+    format!("{alias}amends_message_id IS NULL AND {alias}reaction_only = 0")
+}
+
+/// Rows with content of their own: listed and not deleted. Search, unread
+/// counts and exports use this.
+fn visible_messages_sql(alias: &str) -> String {
+    format!(
+        "{} AND {alias}deleted_at_ms IS NULL",
+        listed_messages_sql(alias)
+    )
+}
+
+// The columns `message_row_from_sql` reads, in order.
+const MESSAGE_ROW_COLUMNS: &str =
+    "message_id, thread_id, direction, source, destination, ts_ms, title, body, receipt_status, fields_json";
+
+fn message_row_from_sql(row: &rusqlite::Row<'_>) -> rusqlite::Result<MessageRow> {
+    let fields_json = row.get::<_, Option<String>>(9).ok().flatten();
+    Ok(MessageRow {
+        message_id: row.get::<_, String>(0)?,
+        thread_id: row.get::<_, String>(1)?,
+        direction: row.get::<_, String>(2)?,
+        source: row.get::<_, String>(3)?,
+        destination: row.get::<_, String>(4)?,
+        ts_ms: row.get::<_, i64>(5)?,
+        title: row.get::<_, String>(6)?,
+        body: row.get::<_, String>(7)?,
+        receipt_status: row.get::<_, Option<String>>(8).ok().flatten(),
+        fields: fields_json
+            .as_deref()
+            .and_then(|value| serde_json::from_str::<Value>(value).ok()),
+    })
+}
+
+fn upsert_thread_summary_for_thread(conn: &Connection, thread_id: &str) -> Result<(), String> {
     let thread_id = thread_id.trim();
     if thread_id.is_empty() {
         return Ok(());
     }
 
-    let existing = conn
-        .query_row(
-            "SELECT display_name, pinned, muted FROM threads WHERE thread_id = ?1",
-            params![thread_id],
-            |row| {
-                Ok((
-                    row.get::<_, String>(0).unwrap_or_default(),
-                    row.get::<_, i64>(1).unwrap_or(0) == 1,
-                    row.get::<_, i64>(2).unwrap_or(0) == 1,
-                ))
-            },
-        )
-        .optional()
-        .map_err(|err| format!("read existing thread summary failed: {err}"))?;
-    let (display_name, pinned, muted) =
-        existing.unwrap_or_else(|| (short_hash(thread_id, 6), false, false));
+    let display_name = contacts::contact_display_name(conn, thread_id)?
+        .unwrap_or_else(|| short_hash(thread_id, 6));
+    let flags = thread_flags::read_thread_flags(conn, thread_id)?;
+    let (pinned, muted) = (flags.pinned, flags.muted);
 
     let latest = conn
         .query_row(
-            "
-            SELECT
-              message_id,
-              thread_id,
-              direction,
-              source,
-              destination,
-              ts_ms,
-              title,
-              body,
-              receipt_status,
-              fields_json
-            FROM messages
-            WHERE thread_id = ?1
-            ORDER BY ts_ms DESC, message_id DESC
-            LIMIT 1
-            ",
+            &format!(
+                "
+                SELECT {MESSAGE_ROW_COLUMNS}
+                FROM messages
+                WHERE thread_id = ?1 AND {}
+                ORDER BY reaction_only ASC, ts_ms DESC, message_id DESC
+                LIMIT 1
+                ",
+                thread_summary_messages_sql("")
+            ),
             params![thread_id],
-            |row| {
-                let fields_json = row.get::<_, Option<String>>(9).ok().flatten();
-                Ok(MessageRow {
-                    message_id: row.get::<_, String>(0)?,
-                    thread_id: row.get::<_, String>(1)?,
-                    direction: row.get::<_, String>(2)?,
-                    source: row.get::<_, String>(3)?,
-                    destination: row.get::<_, String>(4)?,
-                    ts_ms: row.get::<_, i64>(5)?,
-                    title: row.get::<_, String>(6)?,
-                    body: row.get::<_, String>(7)?,
-                    receipt_status: row.get::<_, Option<String>>(8).ok().flatten(),
-                    fields: fields_json
-                        .as_deref()
-                        .and_then(|value| serde_json::from_str::<Value>(value).ok()),
-                })
-            },
+            message_row_from_sql,
         )
         .optional()
         .map_err(|err| format!("read latest message for thread summary failed: {err}"))?;
@@ -499,14 +1231,7 @@ fn upsert_thread_summary_for_thread(conn: &mut Connection, thread_id: &str) -> R
         return Ok(());
     };
 
-    let unread = conn
-        .query_row(
-            "SELECT COUNT(*) FROM messages WHERE thread_id = ?1 AND direction != 'out'",
-            params![thread_id],
-            |row| row.get::<_, i64>(0),
-        )
-        .map_err(|err| format!("read unread count for thread summary failed: {err}"))?
-        .max(0) as usize;
+    let unread = count_unread_messages(conn, thread_id)?;
 
     conn.execute(
         "
@@ -549,33 +1274,19 @@ fn rebuild_threads_table(conn: &mut Connection) -> Result<(), String> {
     let mut messages = Vec::new();
     {
         let mut stmt = conn
-            .prepare(
+            .prepare(&format!(
                 "
-                SELECT message_id, thread_id, direction, source, destination, ts_ms, title, body, receipt_status, fields_json
+                SELECT {MESSAGE_ROW_COLUMNS}
                 FROM messages
+                WHERE {}
                 ORDER BY ts_ms DESC, message_id DESC
                 ",
-            )
+                thread_summary_messages_sql("")
+            ))
             .map_err(|err| format!("prepare rebuild thread rows failed: {err}"))?;
 
         let rows = stmt
-            .query_map([], |row| {
-                let fields_json = row.get::<_, Option<String>>(9).ok().flatten();
-                Ok(MessageRow {
-                    message_id: row.get::<_, String>(0)?,
-                    thread_id: row.get::<_, String>(1)?,
-                    direction: row.get::<_, String>(2)?,
-                    source: row.get::<_, String>(3)?,
-                    destination: row.get::<_, String>(4)?,
-                    ts_ms: row.get::<_, i64>(5)?,
-                    title: row.get::<_, String>(6)?,
-                    body: row.get::<_, String>(7)?,
-                    receipt_status: row.get::<_, Option<String>>(8).ok().flatten(),
-                    fields: fields_json
-                        .as_deref()
-                        .and_then(|value| serde_json::from_str::<Value>(value).ok()),
-                })
-            })
+            .query_map([], message_row_from_sql)
             .map_err(|err| format!("query rebuild thread rows failed: {err}"))?;
 
         for row in rows {
@@ -586,6 +1297,8 @@ fn rebuild_threads_table(conn: &mut Connection) -> Result<(), String> {
     rebuild_threads_from_message_rows(conn, &messages, &[])
 }
 
+// Peer names land in `contacts`, which then names the threads; a local alias
+// keeps precedence over whatever the peer announces.
 fn apply_peer_names_to_threads(conn: &mut Connection, peers: &[PeerSummary]) -> Result<(), String> {
     if peers.is_empty() {
         return Ok(());
@@ -593,17 +1306,9 @@ fn apply_peer_names_to_threads(conn: &mut Connection, peers: &[PeerSummary]) ->
     let tx = conn
         .transaction()
         .map_err(|err| format!("start peer name apply transaction failed: {err}"))?;
+    contacts::record_peers(&tx, peers)?;
     for peer in peers {
-        let thread_id = peer.peer.trim();
-        let name = peer.name.trim();
-        if thread_id.is_empty() || name.is_empty() {
-            continue;
-        }
-        tx.execute(
-            "UPDATE threads SET display_name = ?1 WHERE thread_id = ?2",
-            params![name, thread_id],
-        )
-        .map_err(|err| format!("update thread display name from peer failed: {err}"))?;
+        contacts::refresh_thread_display_name(&tx, &peer.peer)?;
     }
     tx.commit()
         .map_err(|err| format!("commit peer name apply failed: {err}"))?;
@@ -617,29 +1322,15 @@ fn rebuild_threads_from_message_rows(
 ) -> Result<(), String> {
     let mut peer_names = HashMap::new();
     for peer in peers {
-        peer_names.insert(peer.peer.to_string(), peer.name.to_string());
-    }
-
-    let mut pinned_state = HashMap::new();
-    {
-        let mut stmt = conn
-            .prepare("SELECT thread_id, pinned, muted FROM threads")
-            .map_err(|err| format!("prepare old thread state failed: {err}"))?;
-        let rows = stmt
-            .query_map([], |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, i64>(1).unwrap_or(0) == 1,
-                    row.get::<_, i64>(2).unwrap_or(0) == 1,
-                ))
-            })
-            .map_err(|err| format!("query old thread state failed: {err}"))?;
-        for row in rows {
-            let (thread_id, pinned, muted) =
-                row.map_err(|err| format!("parse old thread state failed: {err}"))?;
-            pinned_state.insert(thread_id, (pinned, muted));
+        if let Some(name) = peer.name.as_deref() {
+            peer_names.insert(peer.peer.to_string(), name.to_string());
         }
     }
+    peer_names.extend(contacts::load_contact_display_names(conn)?);
+
+    let pinned_state = load_thread_flags(conn)?;
+
+    let read_marks = load_read_watermarks(conn)?;
 
     let mut summaries = BTreeMap::<String, ThreadSummary>::new();
     for row in rows {
@@ -654,6 +1345,7 @@ fn rebuild_threads_from_message_rows(
                 preview: preview_from_message(row),
                 last_message_id: row.message_id.clone(),
                 last_activity_ms: row.ts_ms,
+                preview_is_reaction: reactions::is_reaction_only(row),
                 unread: 0,
                 pinned: pinned_state
                     .get(&row.thread_id)
@@ -665,13 +1357,25 @@ fn rebuild_threads_from_message_rows(
                     .unwrap_or(false),
             });
 
-        if row.ts_ms >= summary.last_activity_ms {
+        // A thread made only of reactions still gets a summary, but any real
+        // message takes over the preview.
+        let reaction_only = reactions::is_reaction_only(row);
+        let replaces_preview = if summary.preview_is_reaction != reaction_only {
+            !reaction_only
+        } else {
+            row.ts_ms >= summary.last_activity_ms
+        };
+        if replaces_preview {
             summary.last_activity_ms = row.ts_ms;
             summary.last_message_id = row.message_id.clone();
             summary.preview = preview_from_message(row);
+            summary.preview_is_reaction = reaction_only;
         }
 
-        if row.direction != "out" {
+        if !reaction_only
+            && row.direction != "out"
+            && is_after_read_watermark(read_marks.get(&row.thread_id), row)
+        {
             summary.unread += 1;
         }
     }
@@ -715,6 +1419,82 @@ fn rebuild_threads_from_message_rows(
     Ok(())
 }
 
+fn load_thread_flags(conn: &Connection) -> Result<HashMap<String, (bool, bool)>, String> {
+    let mut stmt = conn
+        .prepare("SELECT thread_id, pinned, muted FROM thread_flags")
+        .map_err(|err| format!("prepare thread flags query failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1).unwrap_or(0) == 1,
+                row.get::<_, i64>(2).unwrap_or(0) == 1,
+            ))
+        })
+        .map_err(|err| format!("query thread flags failed: {err}"))?;
+    let mut out = HashMap::new();
+    for row in rows {
+        let (thread_id, pinned, muted) =
+            row.map_err(|err| format!("parse thread flags failed: {err}"))?;
+        out.insert(thread_id, (pinned, muted));
+    }
+    Ok(out)
+}
+
+fn count_unread_messages(conn: &Connection, thread_id: &str) -> Result<usize, String> {
+    conn.query_row(
+        &format!(
+            "
+            SELECT COUNT(*)
+            FROM messages m
+            LEFT JOIN thread_read_state r ON r.thread_id = m.thread_id
+            WHERE m.thread_id = ?1
+              AND m.direction != 'out'
+              AND {}
+              AND (
+                r.thread_id IS NULL
+                OR m.ts_ms > r.last_read_ts_ms
+                OR (m.ts_ms = r.last_read_ts_ms AND m.message_id > r.last_read_message_id)
+              )
+            ",
+            visible_messages_sql("m.")
+        ),
+        params![thread_id],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count.max(0) as usize)
+    .map_err(|err| format!("read unread count for thread failed: {err}"))
+}
+
+fn load_read_watermarks(conn: &Connection) -> Result<HashMap<String, (i64, String)>, String> {
+    let mut stmt = conn
+        .prepare("SELECT thread_id, last_read_ts_ms, last_read_message_id FROM thread_read_state")
+        .map_err(|err| format!("prepare read watermark query failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1).unwrap_or(0),
+                row.get::<_, String>(2).unwrap_or_default(),
+            ))
+        })
+        .map_err(|err| format!("query read watermarks failed: {err}"))?;
+    let mut out = HashMap::new();
+    for row in rows {
+        let (thread_id, ts_ms, message_id) =
+            row.map_err(|err| format!("parse read watermark failed: {err}"))?;
+        out.insert(thread_id, (ts_ms, message_id));
+    }
+    Ok(out)
+}
+
+fn is_after_read_watermark(watermark: Option<&(i64, String)>, row: &MessageRow) -> bool {
+    let Some((ts_ms, message_id)) = watermark else {
+        return true;
+    };
+    row.ts_ms > *ts_ms || (row.ts_ms == *ts_ms && row.message_id.as_str() > message_id.as_str())
+}
+
 fn update_last_sync_state(
     conn: &mut Connection,
     last_sync_ms: i64,
@@ -775,14 +1555,23 @@ fn parse_peer_list(payload: &Value) -> Vec<PeerSummary> {
                 .map(str::trim)
                 .filter(|value| !value.is_empty())?
                 .to_string();
-            let name = record
-                .get("name")
-                .and_then(Value::as_str)
-                .map(str::trim)
-                .filter(|value| !value.is_empty())
-                .map(str::to_string)
-                .unwrap_or_else(|| short_hash(&peer, 6));
-            Some(PeerSummary { peer, name })
+            let capabilities = Some(extract_peer_capabilities(record))
+                .filter(|capabilities| !capabilities.is_empty());
+            let seen_ms = |key: &str| {
+                record
+                    .get(key)
+                    .and_then(Value::as_f64)
+                    .filter(|value| *value > 0.0)
+                    .map(normalize_timestamp_ms)
+            };
+            Some(PeerSummary {
+                name: extract_peer_display_name(record),
+                capabilities,
+                first_seen_ms: seen_ms("first_seen"),
+                // Announce records report when the peer was last heard as `timestamp`.
+                last_seen_ms: seen_ms("last_seen").or_else(|| seen_ms("timestamp")),
+                peer,
+            })
         })
         .collect()
 }
@@ -815,6 +1604,7 @@ fn parse_message_row(value: &Value) -> Result<MessageParseResult, String> {
         .as_ref()
         .map(extract_attachments_from_fields)
         .unwrap_or_default();
+    let sync_marker = sync_marker_for_record(record, value);
 
     Ok(MessageParseResult {
         row: MessageRow {
@@ -830,9 +1620,30 @@ fn parse_message_row(value: &Value) -> Result<MessageParseResult, String> {
             fields,
         },
         attachments,
+        sync_marker,
     })
 }
 
+// Prefer the runtime's own revision marker; otherwise fingerprint the record so
+// any change to status, fields or content is picked up by the next sync.
+fn sync_marker_for_record(record: &serde_json::Map<String, Value>, value: &Value) -> String {
+    for key in ["updated_at", "updated"] {
+        match record.get(key) {
+            Some(Value::Number(number)) => return format!("u:{number}"),
+            Some(Value::String(text)) if !text.trim().is_empty() => {
+                return format!("u:{}", text.trim())
+            }
+            _ => {}
+        }
+    }
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for byte in value.to_string().bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("h:{hash:016x}")
+}
+
 fn extract_attachments_from_fields(fields: &Value) -> Vec<AttachmentEntry> {
     let Some(root) = fields.as_object() else {
         return Vec::new();
@@ -1078,28 +1889,12 @@ fn build_map_point(message: &MapPointMessageContext<'_>, lat: f64, lon: f64) ->
         source: short_hash(who, 8),
         when: format_timestamp(message.ts_ms),
         direction: direction_label.to_string(),
+        message_id: message.message_id.to_string(),
+        peer: who.to_string(),
+        timestamp: message.ts_ms,
     }
 }
 
-fn build_fts_query(query: &str) -> Option<String> {
-    let terms = query
-        .split_whitespace()
-        .map(|term| {
-            term.chars()
-                .filter(|ch| ch.is_ascii_alphanumeric() || *ch == '_' || *ch == '-')
-                .collect::<String>()
-        })
-        .filter(|term| !term.is_empty())
-        .map(|term| format!("{}*", term))
-        .collect::<Vec<_>>();
-
-    if terms.is_empty() {
-        return None;
-    }
-
-    Some(terms.join(" AND "))
-}
-
 fn encode_thread_cursor(cursor: &ThreadCursorKey) -> Option<String> {
     serde_json::to_vec(cursor)
         .ok()
@@ -1160,63 +1955,100 @@ fn decode_file_cursor(cursor: Option<&str>) -> Option<FileCursorKey> {
     serde_json::from_slice::<FileCursorKey>(&decoded).ok()
 }
 
-fn parse_cursor_offset(cursor: Option<&str>) -> usize {
-    cursor
-        .and_then(|value| value.trim().parse::<usize>().ok())
-        .unwrap_or(0)
+fn encode_announce_cursor(cursor: &AnnounceCursorKey) -> Option<String> {
+    serde_json::to_vec(cursor)
+        .ok()
+        .map(|payload| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload))
 }
 
-fn normalize_limit(value: Option<usize>) -> usize {
-    value
-        .filter(|limit| *limit > 0)
-        .unwrap_or(DEFAULT_LIMIT)
-        .min(MAX_LIMIT)
+fn decode_announce_cursor(cursor: Option<&str>) -> Option<AnnounceCursorKey> {
+    let raw = cursor?.trim();
+    if raw.is_empty() {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(raw.as_bytes())
+        .ok()?;
+    serde_json::from_slice::<AnnounceCursorKey>(&decoded).ok()
 }
 
-fn run_schema_migrations(conn: &Connection) -> Result<(), String> {
-    if !table_column_exists(conn, "attachments", "created_at_ms")? {
-        conn.execute(
-            "ALTER TABLE attachments ADD COLUMN created_at_ms INTEGER NOT NULL DEFAULT 0",
-            [],
-        )
-        .map_err(|err| format!("migrate attachments.created_at_ms failed: {err}"))?;
-        conn.execute(
-            "
-            UPDATE attachments
-            SET created_at_ms = COALESCE(
-              (SELECT m.ts_ms FROM messages m WHERE m.message_id = attachments.message_id),
-              ?1
-            )
-            ",
-            params![current_timestamp_ms()],
-        )
-        .map_err(|err| format!("backfill attachments.created_at_ms failed: {err}"))?;
+fn encode_outbox_cursor(cursor: &OutboxCursorKey) -> Option<String> {
+    serde_json::to_vec(cursor)
+        .ok()
+        .map(|payload| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload))
+}
+
+fn decode_outbox_cursor(cursor: Option<&str>) -> Option<OutboxCursorKey> {
+    let raw = cursor?.trim();
+    if raw.is_empty() {
+        return None;
     }
-    conn.execute_batch(
-        "
-        CREATE INDEX IF NOT EXISTS idx_attachments_created_at
-          ON attachments(created_at_ms DESC, id DESC);
-        CREATE INDEX IF NOT EXISTS idx_threads_activity
-          ON threads(last_activity_ms DESC, thread_id DESC);
-        ",
-    )
-    .map_err(|err| format!("apply index migrations failed: {err}"))?;
-    Ok(())
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(raw.as_bytes())
+        .ok()?;
+    serde_json::from_slice::<OutboxCursorKey>(&decoded).ok()
 }
 
-fn table_column_exists(conn: &Connection, table: &str, column: &str) -> Result<bool, String> {
-    let mut stmt = conn
-        .prepare(&format!("PRAGMA table_info({table})"))
-        .map_err(|err| format!("inspect table {table} failed: {err}"))?;
-    let rows = stmt
-        .query_map([], |row| row.get::<_, String>(1))
-        .map_err(|err| format!("read table info {table} failed: {err}"))?;
-    for entry in rows {
-        if entry.map_err(|err| format!("parse table info {table} failed: {err}"))? == column {
-            return Ok(true);
-        }
+fn encode_contact_cursor(cursor: &ContactCursorKey) -> Option<String> {
+    serde_json::to_vec(cursor)
+        .ok()
+        .map(|payload| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload))
+}
+
+fn decode_contact_cursor(cursor: Option<&str>) -> Option<ContactCursorKey> {
+    let raw = cursor?.trim();
+    if raw.is_empty() {
+        return None;
     }
-    Ok(false)
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(raw.as_bytes())
+        .ok()?;
+    serde_json::from_slice::<ContactCursorKey>(&decoded).ok()
+}
+
+fn encode_map_point_cursor(cursor: &MapPointCursorKey) -> Option<String> {
+    serde_json::to_vec(cursor)
+        .ok()
+        .map(|payload| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload))
+}
+
+fn decode_map_point_cursor(cursor: Option<&str>) -> Option<MapPointCursorKey> {
+    let raw = cursor?.trim();
+    if raw.is_empty() {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(raw.as_bytes())
+        .ok()?;
+    serde_json::from_slice::<MapPointCursorKey>(&decoded).ok()
+}
+
+fn encode_search_cursor(cursor: &SearchCursorKey) -> Option<String> {
+    serde_json::to_vec(cursor)
+        .ok()
+        .map(|payload| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload))
+}
+
+// Unlike the other cursors, a search cursor that does not decode is an error:
+// older builds handed out numeric offsets, and silently restarting from the
+// first page would repeat results.
+fn decode_search_cursor(cursor: Option<&str>) -> Result<Option<SearchCursorKey>, String> {
+    let Some(raw) = cursor.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(raw.as_bytes())
+        .ok()
+        .and_then(|decoded| serde_json::from_slice::<SearchCursorKey>(&decoded).ok())
+        .map(Some)
+        .ok_or_else(|| "invalid search cursor; start the search again without one".to_string())
+}
+
+fn normalize_limit(value: Option<usize>) -> usize {
+    value
+        .filter(|limit| *limit > 0)
+        .unwrap_or(DEFAULT_LIMIT)
+        .min(MAX_LIMIT)
 }
 
 fn preview_from_message(row: &MessageRow) -> String {
@@ -1233,6 +2065,21 @@ fn preview_from_message(row: &MessageRow) -> String {
     "No messages yet".to_string()
 }
 
+// The app-extensions field (`"16"`) as a flat map; some senders nest the keys
+// under `extensions`, which take precedence.
+fn app_extensions(fields: &Value) -> Option<serde_json::Map<String, Value>> {
+    let root = fields.as_object()?;
+    let field = root
+        .get("16")
+        .or_else(|| root.get("app_extensions"))
+        .and_then(Value::as_object)?;
+    let mut out = field.clone();
+    if let Some(nested) = field.get("extensions").and_then(Value::as_object) {
+        out.extend(nested.clone());
+    }
+    Some(out)
+}
+
 fn has_paper_field(fields: Option<&Value>) -> bool {
     fields
         .and_then(Value::as_object)
@@ -1241,30 +2088,6 @@ fn has_paper_field(fields: Option<&Value>) -> bool {
         .is_some()
 }
 
-fn derive_reason_code(status_detail: Option<&str>) -> Option<String> {
-    let status = status_detail?.trim().to_ascii_lowercase();
-    if status.is_empty() {
-        return None;
-    }
-    if status.contains("receipt timeout") {
-        return Some("receipt_timeout".to_string());
-    }
-    if status.contains("timeout") {
-        return Some("timeout".to_string());
-    }
-    if status.contains("no route") || status.contains("no path") || status.contains("no known path")
-    {
-        return Some("no_path".to_string());
-    }
-    if status.contains("no propagation relay selected") {
-        return Some("relay_unset".to_string());
-    }
-    if status.contains("retry budget exhausted") {
-        return Some("retry_budget_exhausted".to_string());
-    }
-    None
-}
-
 fn read_required_string(
     record: &serde_json::Map<String, Value>,
     key: &str,
@@ -1388,80 +2211,40 @@ fn is_valid_coordinate(lat: f64, lon: f64) -> bool {
         && (-180.0..=180.0).contains(&lon)
 }
 
-const SCHEMA_SQL: &str = r#"
-CREATE TABLE IF NOT EXISTS messages (
-  message_id TEXT PRIMARY KEY,
-  thread_id TEXT NOT NULL,
-  direction TEXT NOT NULL,
-  source TEXT NOT NULL,
-  destination TEXT NOT NULL,
-  ts_ms INTEGER NOT NULL,
-  title TEXT NOT NULL,
-  body TEXT NOT NULL,
-  receipt_status TEXT,
-  status_reason_code TEXT,
-  has_attachments INTEGER NOT NULL DEFAULT 0,
-  has_paper INTEGER NOT NULL DEFAULT 0,
-  fields_json TEXT,
-  updated_at_ms INTEGER NOT NULL
-);
-
-CREATE TABLE IF NOT EXISTS attachments (
-  id INTEGER PRIMARY KEY AUTOINCREMENT,
-  message_id TEXT NOT NULL,
-  ordinal INTEGER NOT NULL,
-  name TEXT NOT NULL,
-  mime TEXT,
-  size_bytes INTEGER NOT NULL,
-  inline_base64 TEXT,
-  created_at_ms INTEGER NOT NULL DEFAULT 0,
-  FOREIGN KEY(message_id) REFERENCES messages(message_id) ON DELETE CASCADE
-);
-
-CREATE INDEX IF NOT EXISTS idx_attachments_message_id ON attachments(message_id);
-
-CREATE TABLE IF NOT EXISTS threads (
-  thread_id TEXT PRIMARY KEY,
-  display_name TEXT NOT NULL,
-  preview TEXT NOT NULL,
-  last_message_id TEXT,
-  last_activity_ms INTEGER NOT NULL,
-  unread_count INTEGER NOT NULL DEFAULT 0,
-  pinned INTEGER NOT NULL DEFAULT 0,
-  muted INTEGER NOT NULL DEFAULT 0
-);
-
-CREATE TABLE IF NOT EXISTS sync_state (
-  key TEXT PRIMARY KEY,
-  value TEXT NOT NULL
-);
-
-CREATE INDEX IF NOT EXISTS idx_threads_activity ON threads(last_activity_ms DESC, thread_id DESC);
-CREATE INDEX IF NOT EXISTS idx_messages_thread_ts ON messages(thread_id, ts_ms DESC, message_id DESC);
-CREATE INDEX IF NOT EXISTS idx_messages_ts ON messages(ts_ms DESC, message_id DESC);
-
-CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
-  message_id UNINDEXED,
-  title,
-  body,
-  content='messages',
-  content_rowid='rowid'
-);
-
-CREATE TRIGGER IF NOT EXISTS messages_ai AFTER INSERT ON messages BEGIN
-  INSERT INTO messages_fts(rowid, message_id, title, body)
-  VALUES (new.rowid, new.message_id, new.title, new.body);
-END;
-
-CREATE TRIGGER IF NOT EXISTS messages_ad AFTER DELETE ON messages BEGIN
-  INSERT INTO messages_fts(messages_fts, rowid, message_id, title, body)
-  VALUES('delete', old.rowid, old.message_id, old.title, old.body);
-END;
-
-CREATE TRIGGER IF NOT EXISTS messages_au AFTER UPDATE ON messages BEGIN
-  INSERT INTO messages_fts(messages_fts, rowid, message_id, title, body)
-  VALUES('delete', old.rowid, old.message_id, old.title, old.body);
-  INSERT INTO messages_fts(rowid, message_id, title, body)
-  VALUES (new.rowid, new.message_id, new.title, new.body);
-END;
-"#;
+// Fixtures shared by the submodule tests.
+#[cfg(test)]
+fn test_store() -> (tempfile::TempDir, IndexStore) {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+    (dir, store)
+}
+
+/// A runtime message payload from `peer`, as `list_messages` reports it.
+#[cfg(test)]
+fn inbound(id: &str, peer: &str, timestamp: i64, content: &str) -> Value {
+    json!({
+        "id": id, "source": peer, "destination": "self", "direction": "in",
+        "timestamp": timestamp, "content": content,
+    })
+}
+
+/// A runtime message payload sent to `peer`.
+#[cfg(test)]
+fn outbound(id: &str, peer: &str, timestamp: i64, content: &str) -> Value {
+    json!({
+        "id": id, "source": "self", "destination": peer, "direction": "out",
+        "timestamp": timestamp, "content": content,
+    })
+}
+
+#[cfg(test)]
+fn with_fields(mut message: Value, fields: Value) -> Value {
+    message["fields"] = fields;
+    message
+}
+
+/// `fields` carrying one inline attachment.
+#[cfg(test)]
+fn attachment_fields(name: &str, bytes: &[u8]) -> Value {
+    json!({ "attachments": [{ "name": name, "inline_base64": encode_bytes_base64(bytes) }] })
+}
@@ -0,0 +1,388 @@
+use super::*;
+
+// Bodies sent with edits and deletes, so peers without the app-extensions
+// field still see something readable. Edits carry the new text after the
+// prefix; ingest strips it again before applying.
+const EDIT_FALLBACK_PREFIX: &str = "(edited) ";
+const DELETE_FALLBACK_BODY: &str = "(message deleted)";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum AmendmentKind {
+    Edit,
+    Delete,
+}
+
+impl AmendmentKind {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Self::Edit => "edit",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MessageRevision {
+    revision: i64,
+    body: String,
+    ts_ms: i64,
+    amendment_id: Option<String>,
+}
+
+pub(crate) fn edit_fallback_body(content: &str) -> String {
+    format!("{EDIT_FALLBACK_PREFIX}{content}")
+}
+
+pub(crate) fn delete_fallback_body() -> &'static str {
+    DELETE_FALLBACK_BODY
+}
+
+impl IndexStore {
+    /// Every version of `message_id`, oldest first. Revision 0 is the text as
+    /// originally received; deleted messages have no history.
+    pub(crate) fn query_message_revisions(&self, message_id: &str) -> Result<Value, String> {
+        let message_id = message_id.trim();
+        if message_id.is_empty() {
+            return Err("message_id is required".to_string());
+        }
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let mut stmt = conn
+            .prepare(
+                "
+                SELECT revision, body, ts_ms, amendment_id
+                FROM message_revisions
+                WHERE message_id = ?1
+                ORDER BY revision ASC
+                ",
+            )
+            .map_err(|err| format!("prepare revision query failed: {err}"))?;
+        let rows = stmt
+            .query_map(params![message_id], |row| {
+                Ok(MessageRevision {
+                    revision: row.get(0)?,
+                    body: row.get(1)?,
+                    ts_ms: row.get(2)?,
+                    amendment_id: row.get(3)?,
+                })
+            })
+            .map_err(|err| format!("query revisions failed: {err}"))?;
+        let items = rows
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("parse revision failed: {err}"))?;
+        Ok(json!({ "message_id": message_id, "items": items }))
+    }
+}
+
+/// The message an edit or delete in the app-extensions field (`"16"`) targets.
+pub(super) fn extract_amendment(row: &MessageRow) -> Option<(AmendmentKind, String)> {
+    let extensions = app_extensions(row.fields.as_ref()?)?;
+    [
+        ("delete_of", AmendmentKind::Delete),
+        ("edit_of", AmendmentKind::Edit),
+    ]
+    .into_iter()
+    .find_map(|(key, kind)| {
+        extensions
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty() && *value != row.message_id)
+            .map(|target| (kind, target.to_string()))
+    })
+}
+
+/// Re-derives the visible state of every message `row` affects: its target
+/// when it is an edit or delete, and itself when earlier amendments point at
+/// it. Runs after each write, so edits arriving before their original still
+/// apply once it shows up.
+pub(super) fn apply_amendments_for_row(conn: &Connection, row: &MessageRow) -> Result<(), String> {
+    if let Some((_, target)) = extract_amendment(row) {
+        apply_amendments(conn, &target)?;
+    }
+    apply_amendments(conn, &row.message_id)
+}
+
+// Only amendments sent by the original author count. A delete wins over any
+// edit and also drops the revision history; otherwise the newest edit is the
+// visible body and every version is kept in `message_revisions`.
+fn apply_amendments(conn: &Connection, message_id: &str) -> Result<(), String> {
+    let Some((source, body, ts_ms)) = conn
+        .query_row(
+            "SELECT source, body, ts_ms FROM messages WHERE message_id = ?1",
+            params![message_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(|err| format!("read amended message failed: {err}"))?
+    else {
+        return Ok(());
+    };
+
+    let amendments = {
+        let mut stmt = conn
+            .prepare_cached(
+                "
+                SELECT message_id, amendment_kind, body, ts_ms
+                FROM messages
+                WHERE amends_message_id = ?1 AND source = ?2
+                ORDER BY ts_ms ASC, message_id ASC
+                ",
+            )
+            .map_err(|err| format!("prepare amendment query failed: {err}"))?;
+        let rows = stmt
+            .query_map(params![message_id, &source], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })
+            .map_err(|err| format!("query amendments failed: {err}"))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("parse amendment failed: {err}"))?
+    };
+
+    let original = conn
+        .query_row(
+            "SELECT body FROM message_revisions WHERE message_id = ?1 AND revision = 0",
+            params![message_id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .map_err(|err| format!("read original revision failed: {err}"))?
+        .unwrap_or(body);
+    conn.execute(
+        "DELETE FROM message_revisions WHERE message_id = ?1",
+        params![message_id],
+    )
+    .map_err(|err| format!("clear revisions failed: {err}"))?;
+
+    if let Some((_, _, _, deleted_at_ms)) = amendments
+        .iter()
+        .find(|(_, kind, _, _)| kind == AmendmentKind::Delete.as_str())
+    {
+        for statement in [
+            "DELETE FROM attachments WHERE message_id = ?1",
+            "DELETE FROM map_points WHERE message_id = ?1",
+        ] {
+            conn.execute(statement, params![message_id])
+                .map_err(|err| format!("clear deleted message payload failed: {err}"))?;
+        }
+        conn.execute(
+            "
+            UPDATE messages
+            SET body = '', title = '', fields_json = NULL, has_attachments = 0, has_paper = 0,
+                edited_at_ms = NULL, deleted_at_ms = ?1
+            WHERE message_id = ?2
+            ",
+            params![deleted_at_ms, message_id],
+        )
+        .map_err(|err| format!("apply delete failed: {err}"))?;
+        return Ok(());
+    }
+
+    let edits = amendments
+        .iter()
+        .filter(|(_, kind, _, _)| kind == AmendmentKind::Edit.as_str())
+        .collect::<Vec<_>>();
+    let Some((_, _, latest_body, latest_ts_ms)) = edits.last() else {
+        conn.execute(
+            "
+            UPDATE messages SET body = ?1, edited_at_ms = NULL, deleted_at_ms = NULL
+            WHERE message_id = ?2
+              AND (body != ?1 OR edited_at_ms IS NOT NULL OR deleted_at_ms IS NOT NULL)
+            ",
+            params![&original, message_id],
+        )
+        .map_err(|err| format!("restore unedited message failed: {err}"))?;
+        return Ok(());
+    };
+
+    let mut insert = conn
+        .prepare_cached(
+            "
+            INSERT INTO message_revisions (message_id, revision, body, ts_ms, amendment_id)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ",
+        )
+        .map_err(|err| format!("prepare revision insert failed: {err}"))?;
+    insert
+        .execute(params![message_id, 0_i64, &original, ts_ms, None::<String>])
+        .map_err(|err| format!("record original revision failed: {err}"))?;
+    for (index, (amendment_id, _, edit_body, edit_ts_ms)) in edits.iter().enumerate() {
+        insert
+            .execute(params![
+                message_id,
+                (index + 1) as i64,
+                strip_edit_fallback(edit_body),
+                edit_ts_ms,
+                amendment_id,
+            ])
+            .map_err(|err| format!("record revision failed: {err}"))?;
+    }
+    conn.execute(
+        "
+        UPDATE messages SET body = ?1, edited_at_ms = ?2, deleted_at_ms = NULL
+        WHERE message_id = ?3
+        ",
+        params![strip_edit_fallback(latest_body), latest_ts_ms, message_id],
+    )
+    .map_err(|err| format!("apply edit failed: {err}"))?;
+    Ok(())
+}
+
+/// When the message was last edited and when it was deleted for everyone.
+pub(super) fn load_amendment_state(
+    conn: &Connection,
+    message_id: &str,
+) -> Result<(Option<i64>, Option<i64>), String> {
+    conn.prepare_cached("SELECT edited_at_ms, deleted_at_ms FROM messages WHERE message_id = ?1")
+        .and_then(|mut stmt| {
+            stmt.query_row(params![message_id], |row| {
+                Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?))
+            })
+        })
+        .optional()
+        .map(Option::unwrap_or_default)
+        .map_err(|err| format!("read amendment state failed: {err}"))
+}
+
+fn strip_edit_fallback(body: &str) -> &str {
+    body.strip_prefix(EDIT_FALLBACK_PREFIX).unwrap_or(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thread_bodies(store: &IndexStore) -> Vec<(String, String)> {
+        let page = store
+            .query_thread_messages(ThreadMessageQueryParams {
+                thread_id: "peer-a".to_string(),
+                query: None,
+                limit: None,
+                cursor: None,
+            })
+            .expect("thread messages");
+        page["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .map(|item| {
+                (
+                    item["id"].as_str().unwrap_or_default().to_string(),
+                    item["content"].as_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_and_deletes_apply_only_from_the_original_author() {
+        let (_dir, store) = test_store();
+        let payload = json!([
+            inbound("m1", "peer-a", 1_000, "meet at the brdige"),
+            with_fields(
+                inbound("m2", "peer-a", 2_000, "see attached"),
+                attachment_fields("a.bin", b"xyz")
+            ),
+            with_fields(
+                inbound(
+                    "e1",
+                    "peer-a",
+                    3_000,
+                    &edit_fallback_body("meet at the bridge")
+                ),
+                json!({ "16": { "edit_of": "m1" } })
+            ),
+            with_fields(
+                inbound(
+                    "e2",
+                    "peer-b",
+                    4_000,
+                    &edit_fallback_body("meet at my place")
+                ),
+                json!({ "16": { "edit_of": "m1" } })
+            ),
+            with_fields(
+                inbound("d1", "peer-a", 5_000, delete_fallback_body()),
+                json!({ "16": { "delete_of": "m2" } })
+            ),
+        ]);
+        store
+            .reindex_from_runtime_payloads(&payload, &json!([]))
+            .expect("seed index");
+
+        assert_eq!(
+            thread_bodies(&store),
+            vec![
+                ("m2".to_string(), String::new()),
+                ("m1".to_string(), "meet at the bridge".to_string()),
+            ]
+        );
+        let revisions = store.query_message_revisions("m1").expect("revisions");
+        assert_eq!(revisions["items"][0]["body"], "meet at the brdige");
+        assert_eq!(revisions["items"][1]["amendment_id"], "e1");
+        assert_eq!(revisions["items"].as_array().map(Vec::len), Some(2));
+
+        // A later edit arriving live wins and survives the original being
+        // rewritten by a resync.
+        let live_edit = with_fields(
+            inbound(
+                "e3",
+                "peer-a",
+                6_000,
+                &edit_fallback_body("meet at the north bridge"),
+            ),
+            json!({ "16": { "edit_of": "m1" } }),
+        );
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "inbound",
+                "payload": { "message": live_edit.clone() },
+            }))
+            .expect("live edit");
+        let mut resync = payload.as_array().cloned().expect("payload");
+        resync[0]["title"] = json!("changed upstream");
+        resync.push(live_edit);
+        store
+            .reindex_from_runtime_payloads(&Value::Array(resync), &json!([]))
+            .expect("resync");
+        assert_eq!(thread_bodies(&store)[1].1, "meet at the north bridge");
+        assert_eq!(
+            store.query_message_revisions("m1").expect("revisions")["items"]
+                .as_array()
+                .map(Vec::len),
+            Some(3)
+        );
+
+        let conn = store.conn.lock().expect("lock");
+        let (deleted_at_ms, attachments): (Option<i64>, i64) = conn
+            .query_row(
+                "SELECT deleted_at_ms, (SELECT COUNT(*) FROM attachments WHERE message_id = 'm2')
+                 FROM messages WHERE message_id = 'm2'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .expect("deleted message");
+        assert_eq!((deleted_at_ms, attachments), (Some(5_000_000), 0));
+        let preview: String = conn
+            .query_row(
+                "SELECT preview FROM threads WHERE thread_id = 'peer-a'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("thread summary");
+        assert_eq!(preview, "meet at the north bridge");
+    }
+}
@@ -0,0 +1,643 @@
+use super::*;
+
+// Enough to chart how a route changed over a session without letting chatty
+// peers grow the table without bound.
+const HOP_HISTORY_LIMIT: i64 = 32;
+
+// Every destination on the mesh announces, so the history is capped by age
+// and size; a pruned peer simply reappears with its next announce.
+const ANNOUNCE_MAX_AGE_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+const ANNOUNCE_MAX_ENTRIES: i64 = 5_000;
+
+// Shared by the page and the `new_count` queries so both see the same rows.
+const ANNOUNCE_FILTER_SQL: &str = "
+    (
+      ?1 IS NULL
+      OR LOWER(COALESCE(name, '')) LIKE ?1
+      OR LOWER(peer) LIKE ?1
+    )
+    AND (?2 IS NULL OR aspect = ?2 OR aspect LIKE ?2 || '.%')
+    AND (
+      ?3 IS NULL
+      OR EXISTS (SELECT 1 FROM json_each(capabilities_json) WHERE value = ?3)
+    )
+    AND (?4 IS NULL OR hops <= ?4)
+    AND (?5 IS NULL OR last_seen_ms >= ?5)
+    AND (?6 IS NULL OR last_seen_ms > ?6)
+";
+
+/// One announce as reported by the runtime, before it is merged into the
+/// per-destination history.
+#[derive(Debug)]
+struct AnnounceRecord {
+    peer: String,
+    name: Option<String>,
+    name_source: Option<String>,
+    aspect: Option<String>,
+    app_data_hex: Option<String>,
+    capabilities: Vec<String>,
+    heard_ms: i64,
+    first_seen_ms: Option<i64>,
+    seen_count: Option<i64>,
+    hops: Option<i64>,
+    rssi: Option<f64>,
+    snr: Option<f64>,
+    q: Option<f64>,
+}
+
+impl IndexStore {
+    /// Merges announces from `list_announces` or an `announce_received`
+    /// event into the local history. Returns how many records were applied.
+    pub(crate) fn record_announces(&self, payload: &Value) -> Result<usize, String> {
+        let announces = parse_announce_list(payload);
+        if announces.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start announce transaction failed: {err}"))?;
+        for announce in &announces {
+            upsert_announce(&tx, announce)?;
+        }
+        tx.commit()
+            .map_err(|err| format!("commit announces failed: {err}"))?;
+        Ok(announces.len())
+    }
+
+    /// Announce history, one entry per destination, most recently heard
+    /// first. `new_count` counts the matching entries heard since the
+    /// Announces page was last marked viewed.
+    pub(crate) fn query_announces(&self, params: AnnounceQueryParams) -> Result<Value, String> {
+        let limit = normalize_limit(params.limit);
+        let keyset = decode_announce_cursor(params.cursor.as_deref());
+        let name_filter = params
+            .query
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| format!("%{}%", value.to_lowercase()));
+        let aspect = clean_filter(params.aspect.as_deref());
+        let capability = clean_filter(params.capability.as_deref());
+        let seen_since_ms = params
+            .seen_within_ms
+            .filter(|value| *value > 0)
+            .map(|value| current_timestamp_ms().saturating_sub(value));
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let last_viewed_ms = read_announces_viewed_ms(&conn)?;
+        let new_only_since = if params.new_only.unwrap_or(false) {
+            Some(last_viewed_ms.unwrap_or(0))
+        } else {
+            None
+        };
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "
+                SELECT
+                  peer,
+                  name,
+                  name_source,
+                  aspect,
+                  app_data_hex,
+                  capabilities_json,
+                  first_seen_ms,
+                  last_seen_ms,
+                  seen_count,
+                  hops,
+                  rssi,
+                  snr,
+                  q
+                FROM announces
+                WHERE {ANNOUNCE_FILTER_SQL}
+                  AND (
+                    ?7 IS NULL
+                    OR last_seen_ms < ?7
+                    OR (last_seen_ms = ?7 AND peer > ?8)
+                  )
+                ORDER BY last_seen_ms DESC, peer ASC
+                LIMIT ?9
+                "
+            ))
+            .map_err(|err| format!("prepare announce query failed: {err}"))?;
+        let rows = stmt
+            .query_map(
+                params![
+                    name_filter,
+                    aspect,
+                    capability,
+                    params.max_hops,
+                    seen_since_ms,
+                    new_only_since,
+                    keyset.as_ref().map(|cursor| cursor.last_seen_ms),
+                    keyset.as_ref().map(|cursor| cursor.peer.as_str()),
+                    (limit + 1) as i64
+                ],
+                |row| {
+                    let last_seen_ms = row.get::<_, i64>(7)?;
+                    Ok(AnnounceEntry {
+                        peer: row.get(0)?,
+                        name: row.get(1)?,
+                        name_source: row.get(2)?,
+                        aspect: row.get(3)?,
+                        app_data_hex: row.get(4)?,
+                        capabilities: serde_json::from_str(&row.get::<_, String>(5)?)
+                            .unwrap_or_default(),
+                        first_seen_ms: row.get(6)?,
+                        last_seen_ms,
+                        seen_count: row.get::<_, i64>(8)?.max(0) as usize,
+                        hops: row.get(9)?,
+                        hop_history: Vec::new(),
+                        rssi: row.get(10)?,
+                        snr: row.get(11)?,
+                        q: row.get(12)?,
+                        is_new: last_viewed_ms
+                            .map(|viewed| last_seen_ms > viewed)
+                            .unwrap_or(true),
+                    })
+                },
+            )
+            .map_err(|err| format!("run announce query failed: {err}"))?;
+        let mut items = Vec::new();
+        for result in rows {
+            items.push(result.map_err(|err| format!("parse announce row failed: {err}"))?);
+        }
+
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().and_then(|entry| {
+                encode_announce_cursor(&AnnounceCursorKey {
+                    last_seen_ms: entry.last_seen_ms,
+                    peer: entry.peer.clone(),
+                })
+            })
+        } else {
+            None
+        };
+        let mut hop_history = load_hop_history(&conn, &items)?;
+        for entry in &mut items {
+            entry.hop_history = hop_history.remove(&entry.peer).unwrap_or_default();
+        }
+
+        let new_count = conn
+            .query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM announces WHERE {ANNOUNCE_FILTER_SQL} AND last_seen_ms > ?7"
+                ),
+                params![
+                    name_filter,
+                    aspect,
+                    capability,
+                    params.max_hops,
+                    seen_since_ms,
+                    new_only_since,
+                    last_viewed_ms.unwrap_or(i64::MIN)
+                ],
+                |row| row.get::<_, i64>(0),
+            )
+            .map_err(|err| format!("count new announces failed: {err}"))?;
+
+        serde_json::to_value(AnnounceQueryResult {
+            items,
+            next_cursor,
+            last_viewed_ms,
+            new_count: new_count.max(0) as usize,
+        })
+        .map_err(|err| format!("serialize announce query failed: {err}"))
+    }
+
+    /// Drops announces not heard for `ANNOUNCE_MAX_AGE_MS` and the oldest
+    /// beyond `ANNOUNCE_MAX_ENTRIES`. Returns how many were removed.
+    pub(crate) fn prune_announces(&self) -> Result<usize, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        prune_announces_to(
+            &conn,
+            current_timestamp_ms().saturating_sub(ANNOUNCE_MAX_AGE_MS),
+            ANNOUNCE_MAX_ENTRIES,
+        )
+    }
+
+    /// Moves the "new since last viewed" marker, to now unless a time is given.
+    pub(crate) fn mark_announces_viewed(&self, viewed_at_ms: Option<i64>) -> Result<Value, String> {
+        let viewed_at_ms = viewed_at_ms
+            .filter(|value| *value > 0)
+            .map(|value| normalize_timestamp_ms(value as f64))
+            .unwrap_or_else(current_timestamp_ms);
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        conn.execute(
+            "
+            INSERT INTO sync_state (key, value) VALUES ('announces_viewed_ms', ?1)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value
+            ",
+            params![viewed_at_ms.to_string()],
+        )
+        .map_err(|err| format!("record announces viewed failed: {err}"))?;
+        Ok(json!({ "last_viewed_ms": viewed_at_ms }))
+    }
+}
+
+fn upsert_announce(conn: &Connection, announce: &AnnounceRecord) -> Result<(), String> {
+    let capabilities_json = serde_json::to_string(&announce.capabilities)
+        .map_err(|err| format!("encode announce capabilities failed: {err}"))?;
+    // The runtime replays its whole list on every `list_announces`, so a
+    // sighting only counts when it is newer than the one already stored.
+    conn.execute(
+        "
+        INSERT INTO announces (
+          peer,
+          name,
+          name_source,
+          aspect,
+          app_data_hex,
+          capabilities_json,
+          first_seen_ms,
+          last_seen_ms,
+          seen_count,
+          hops,
+          rssi,
+          snr,
+          q
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, COALESCE(?7, ?8), ?8, COALESCE(?9, 1), ?10, ?11, ?12, ?13)
+        ON CONFLICT(peer) DO UPDATE SET
+          name = COALESCE(excluded.name, name),
+          name_source = CASE WHEN excluded.name IS NULL THEN name_source ELSE excluded.name_source END,
+          aspect = COALESCE(excluded.aspect, aspect),
+          app_data_hex = COALESCE(excluded.app_data_hex, app_data_hex),
+          capabilities_json = CASE
+            WHEN excluded.capabilities_json = '[]' THEN capabilities_json
+            ELSE excluded.capabilities_json
+          END,
+          first_seen_ms = MIN(first_seen_ms, excluded.first_seen_ms),
+          seen_count = MAX(
+            CASE WHEN excluded.last_seen_ms > last_seen_ms THEN seen_count + 1 ELSE seen_count END,
+            COALESCE(?9, 0)
+          ),
+          hops = CASE
+            WHEN excluded.last_seen_ms >= last_seen_ms THEN COALESCE(excluded.hops, hops)
+            ELSE hops
+          END,
+          rssi = CASE
+            WHEN excluded.last_seen_ms >= last_seen_ms THEN COALESCE(excluded.rssi, rssi)
+            ELSE rssi
+          END,
+          snr = CASE
+            WHEN excluded.last_seen_ms >= last_seen_ms THEN COALESCE(excluded.snr, snr)
+            ELSE snr
+          END,
+          q = CASE
+            WHEN excluded.last_seen_ms >= last_seen_ms THEN COALESCE(excluded.q, q)
+            ELSE q
+          END,
+          last_seen_ms = MAX(last_seen_ms, excluded.last_seen_ms)
+        ",
+        params![
+            &announce.peer,
+            &announce.name,
+            &announce.name_source,
+            &announce.aspect,
+            &announce.app_data_hex,
+            capabilities_json,
+            announce.first_seen_ms,
+            announce.heard_ms,
+            announce.seen_count,
+            announce.hops,
+            announce.rssi,
+            announce.snr,
+            announce.q,
+        ],
+    )
+    .map_err(|err| format!("upsert announce failed: {err}"))?;
+
+    let Some(hops) = announce.hops else {
+        return Ok(());
+    };
+    conn.execute(
+        "INSERT OR REPLACE INTO announce_hops (peer, ts_ms, hops) VALUES (?1, ?2, ?3)",
+        params![&announce.peer, announce.heard_ms, hops],
+    )
+    .map_err(|err| format!("record announce hops failed: {err}"))?;
+    conn.execute(
+        "
+        DELETE FROM announce_hops
+        WHERE peer = ?1
+          AND ts_ms NOT IN (
+            SELECT ts_ms FROM announce_hops WHERE peer = ?1 ORDER BY ts_ms DESC LIMIT ?2
+          )
+        ",
+        params![&announce.peer, HOP_HISTORY_LIMIT],
+    )
+    .map_err(|err| format!("prune announce hops failed: {err}"))?;
+    Ok(())
+}
+
+fn prune_announces_to(
+    conn: &Connection,
+    cutoff_ms: i64,
+    max_entries: i64,
+) -> Result<usize, String> {
+    conn.execute(
+        "
+        DELETE FROM announces
+        WHERE last_seen_ms < ?1
+          OR peer NOT IN (
+            SELECT peer FROM announces ORDER BY last_seen_ms DESC, peer ASC LIMIT ?2
+          )
+        ",
+        params![cutoff_ms, max_entries],
+    )
+    .map_err(|err| format!("prune announces failed: {err}"))
+}
+
+/// Hop samples for every entry on the page, newest first, keyed by peer.
+fn load_hop_history(
+    conn: &Connection,
+    entries: &[AnnounceEntry],
+) -> Result<HashMap<String, Vec<HopSample>>, String> {
+    let mut history = HashMap::<String, Vec<HopSample>>::new();
+    if entries.is_empty() {
+        return Ok(history);
+    }
+    let peers = serde_json::to_string(
+        &entries
+            .iter()
+            .map(|entry| entry.peer.as_str())
+            .collect::<Vec<_>>(),
+    )
+    .map_err(|err| format!("encode hop history peers failed: {err}"))?;
+    let mut stmt = conn
+        .prepare_cached(
+            "
+            SELECT peer, ts_ms, hops
+            FROM announce_hops
+            WHERE peer IN (SELECT value FROM json_each(?1))
+            ORDER BY peer ASC, ts_ms DESC
+            ",
+        )
+        .map_err(|err| format!("prepare hop history query failed: {err}"))?;
+    let rows = stmt
+        .query_map(params![peers], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                HopSample {
+                    ts_ms: row.get(1)?,
+                    hops: row.get(2)?,
+                },
+            ))
+        })
+        .map_err(|err| format!("query hop history failed: {err}"))?;
+    for row in rows {
+        let (peer, sample) = row.map_err(|err| format!("parse hop history failed: {err}"))?;
+        history.entry(peer).or_default().push(sample);
+    }
+    Ok(history)
+}
+
+fn read_announces_viewed_ms(conn: &Connection) -> Result<Option<i64>, String> {
+    conn.query_row(
+        "SELECT value FROM sync_state WHERE key = 'announces_viewed_ms'",
+        [],
+        |row| row.get::<_, String>(0),
+    )
+    .optional()
+    .map(|value| value.and_then(|raw| raw.parse::<i64>().ok()))
+    .map_err(|err| format!("read announces viewed marker failed: {err}"))
+}
+
+fn clean_filter(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_ascii_lowercase)
+}
+
+fn parse_announce_list(payload: &Value) -> Vec<AnnounceRecord> {
+    let announces = if let Some(array) = payload.as_array() {
+        array.clone()
+    } else {
+        payload
+            .as_object()
+            .and_then(|object| object.get("announces"))
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default()
+    };
+    let now_ms = current_timestamp_ms();
+
+    announces
+        .iter()
+        .filter_map(|entry| {
+            let record = entry.as_object()?;
+            let peer = read_optional_string(record, "peer")?;
+            let text = |keys: &[&str]| {
+                keys.iter()
+                    .find_map(|key| read_optional_string(record, key))
+            };
+            let number = |keys: &[&str]| keys.iter().find_map(|key| record.get(*key)?.as_f64());
+            let timestamp_ms = |key: &str| {
+                number(&[key])
+                    .filter(|value| *value > 0.0)
+                    .map(normalize_timestamp_ms)
+            };
+            let name = extract_peer_display_name(record);
+            let name_source =
+                text(&["name_source"]).or_else(|| name.as_ref().map(|_| "app_data".to_string()));
+            Some(AnnounceRecord {
+                name_source,
+                name,
+                aspect: text(&["aspect", "app_name", "destination_aspect"])
+                    .map(|value| value.to_ascii_lowercase()),
+                app_data_hex: text(&["app_data_hex", "announce_app_data_hex"]),
+                capabilities: extract_peer_capabilities(record),
+                heard_ms: timestamp_ms("timestamp")
+                    .or_else(|| timestamp_ms("last_seen"))
+                    .unwrap_or(now_ms),
+                first_seen_ms: timestamp_ms("first_seen"),
+                seen_count: number(&["seen_count"]).map(|value| value.max(0.0) as i64),
+                hops: number(&["hops", "hop_count"]).map(|value| value.max(0.0) as i64),
+                rssi: number(&["rssi"]),
+                snr: number(&["snr"]),
+                q: number(&["q"]),
+                peer,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(store: &IndexStore, params: AnnounceQueryParams) -> Value {
+        store.query_announces(params).expect("announces")
+    }
+
+    fn no_filters() -> AnnounceQueryParams {
+        AnnounceQueryParams {
+            query: None,
+            aspect: None,
+            capability: None,
+            max_hops: None,
+            seen_within_ms: None,
+            new_only: None,
+            limit: None,
+            cursor: None,
+        }
+    }
+
+    fn peers(page: &Value) -> Vec<&str> {
+        page["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .map(|item| item["peer"].as_str().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn announces_dedup_per_destination_and_filter_server_side() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        let now_s = current_timestamp_ms() / 1000;
+        store
+            .record_announces(&json!({ "announces": [
+                { "peer": "aaaa", "timestamp": now_s - 600, "name": "Hub Node", "hops": 4,
+                  "aspect": "lxmf.delivery", "capabilities": ["topic_broker"] },
+                { "peer": "bbbb", "timestamp": now_s - 7_200, "name": "Relay",
+                  "aspect": "nomadnetwork.node", "hops": 1 },
+            ] }))
+            .expect("seed announces");
+        store
+            .mark_announces_viewed(Some((now_s - 60) * 1000))
+            .expect("mark viewed");
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "announce_received",
+                "payload": { "peer": "aaaa", "timestamp": now_s, "hops": 2, "aspect": "lxmf.delivery" },
+            }))
+            .expect("live announce");
+        // Replaying the same list must not count the sightings twice.
+        store
+            .record_announces(&json!([{ "peer": "bbbb", "timestamp": now_s - 7_200, "hops": 1 }]))
+            .expect("replay");
+
+        let all = query(&store, no_filters());
+        assert_eq!(peers(&all), vec!["aaaa", "bbbb"]);
+        assert_eq!(all["new_count"], 1);
+        let hub = &all["items"][0];
+        assert_eq!(hub["seen_count"], 2);
+        assert_eq!(hub["hops"], 2);
+        assert_eq!(hub["name"], "Hub Node");
+        assert_eq!(hub["capabilities"], json!(["topic_broker"]));
+        assert_eq!(hub["is_new"], true);
+        assert_eq!(
+            hub["hop_history"]
+                .as_array()
+                .expect("hop history")
+                .iter()
+                .map(|sample| sample["hops"].as_i64().unwrap_or_default())
+                .collect::<Vec<_>>(),
+            vec![2, 4]
+        );
+        assert_eq!(all["items"][1]["seen_count"], 1);
+
+        let filtered = |params: AnnounceQueryParams| peers(&query(&store, params)).join(",");
+        assert_eq!(
+            filtered(AnnounceQueryParams {
+                query: Some("rel".into()),
+                ..no_filters()
+            }),
+            "bbbb"
+        );
+        assert_eq!(
+            filtered(AnnounceQueryParams {
+                aspect: Some("LXMF".into()),
+                ..no_filters()
+            }),
+            "aaaa"
+        );
+        assert_eq!(
+            filtered(AnnounceQueryParams {
+                capability: Some("topic_broker".into()),
+                ..no_filters()
+            }),
+            "aaaa"
+        );
+        assert_eq!(
+            filtered(AnnounceQueryParams {
+                max_hops: Some(1),
+                ..no_filters()
+            }),
+            "bbbb"
+        );
+        assert_eq!(
+            filtered(AnnounceQueryParams {
+                seen_within_ms: Some(3_600_000),
+                ..no_filters()
+            }),
+            "aaaa"
+        );
+        assert_eq!(
+            filtered(AnnounceQueryParams {
+                new_only: Some(true),
+                ..no_filters()
+            }),
+            "aaaa"
+        );
+
+        let first = query(
+            &store,
+            AnnounceQueryParams {
+                limit: Some(1),
+                ..no_filters()
+            },
+        );
+        let second = query(
+            &store,
+            AnnounceQueryParams {
+                limit: Some(1),
+                cursor: first["next_cursor"].as_str().map(str::to_string),
+                ..no_filters()
+            },
+        );
+        assert_eq!(peers(&second), vec!["bbbb"]);
+    }
+
+    #[test]
+    fn pruning_caps_announce_history_by_age_and_size() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .record_announces(&json!([
+                { "peer": "aaaa", "timestamp": 1_000, "hops": 1 },
+                { "peer": "bbbb", "timestamp": 2_000, "hops": 2 },
+                { "peer": "cccc", "timestamp": 3_000, "hops": 3 },
+                { "peer": "dddd", "timestamp": 4_000, "hops": 4 },
+            ]))
+            .expect("seed announces");
+
+        let conn = store.conn.lock().expect("lock");
+        assert_eq!(prune_announces_to(&conn, 2_000_000, 10).expect("age"), 1);
+        assert_eq!(prune_announces_to(&conn, 0, 2).expect("size"), 1);
+        let hops: i64 = conn
+            .query_row("SELECT COUNT(*) FROM announce_hops", [], |row| row.get(0))
+            .expect("count hops");
+        assert_eq!(hops, 2);
+        drop(conn);
+        assert_eq!(peers(&query(&store, no_filters())), vec!["dddd", "cccc"]);
+        assert_eq!(store.prune_announces().expect("prune"), 2);
+    }
+}
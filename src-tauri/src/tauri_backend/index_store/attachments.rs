@@ -19,7 +19,7 @@ impl IndexStore {
         let mut stmt = conn
             .prepare(
                 "
-                SELECT mime, size_bytes, inline_base64
+                SELECT mime, size_bytes, inline_base64, blob_sha256
                 FROM attachments
                 WHERE message_id = ?1 AND name = ?2
                 ORDER BY ordinal ASC
@@ -33,18 +33,21 @@ impl IndexStore {
                 Ok((
                     row.get::<_, Option<String>>(0).ok().flatten(),
                     row.get::<_, i64>(1).unwrap_or(0),
-                    row.get::<_, Option<String>>(2).ok().flatten(),
+                    stored_payload(
+                        row.get::<_, Option<String>>(2).ok().flatten(),
+                        row.get::<_, Option<String>>(3).ok().flatten(),
+                    ),
                 ))
             })
             .optional()
             .map_err(|err| format!("read attachment blob failed: {err}"))?;
 
-        let Some((mime, size_bytes, data_base64)) = entry else {
+        let Some((mime, size_bytes, payload)) = entry else {
             return Err("attachment not found".to_string());
         };
 
-        let data_base64 =
-            data_base64.ok_or_else(|| "attachment payload unavailable".to_string())?;
+        let data_base64 = self
+            .payload_base64(payload.ok_or_else(|| "attachment payload unavailable".to_string())?)?;
 
         Ok(json!({
             "mime": mime,
@@ -58,7 +61,8 @@ impl IndexStore {
         params: AttachmentBytesParams,
     ) -> Result<Value, String> {
         let attachment_id = params.attachment_id.trim().to_string();
-        let (mime, size_bytes, data_base64) = self.query_attachment_entry_by_id(&attachment_id)?;
+        let (mime, size_bytes, payload) = self.query_attachment_entry_by_id(&attachment_id)?;
+        let data_base64 = self.payload_base64(payload)?;
         Ok(json!({
             "attachment_id": attachment_id,
             "mime": mime,
@@ -75,10 +79,8 @@ impl IndexStore {
         if attachment_id.is_empty() {
             return Err("attachment_id is required".to_string());
         }
-        let (mime, size_bytes, data_base64) = self.query_attachment_entry_by_id(attachment_id)?;
-        let bytes = base64::engine::general_purpose::STANDARD
-            .decode(data_base64.as_bytes())
-            .map_err(|err| format!("decode attachment payload failed: {err}"))?;
+        let (mime, size_bytes, payload) = self.query_attachment_entry_by_id(attachment_id)?;
+        let bytes = self.payload_bytes(payload)?;
 
         Ok(AttachmentBinary {
             mime,
@@ -90,7 +92,7 @@ impl IndexStore {
     fn query_attachment_entry_by_id(
         &self,
         attachment_id: &str,
-    ) -> Result<(Option<String>, i64, String), String> {
+    ) -> Result<(Option<String>, i64, StoredPayload), String> {
         let normalized_id = attachment_id.trim();
         if normalized_id.is_empty() {
             return Err("attachment_id is required".to_string());
@@ -106,7 +108,7 @@ impl IndexStore {
         let mut stmt = conn
             .prepare(
                 "
-                SELECT mime, size_bytes, inline_base64
+                SELECT mime, size_bytes, inline_base64, blob_sha256
                 FROM attachments
                 WHERE id = ?1
                 LIMIT 1
@@ -119,17 +121,55 @@ impl IndexStore {
                 Ok((
                     row.get::<_, Option<String>>(0).ok().flatten(),
                     row.get::<_, i64>(1).unwrap_or(0),
-                    row.get::<_, Option<String>>(2).ok().flatten(),
+                    stored_payload(
+                        row.get::<_, Option<String>>(2).ok().flatten(),
+                        row.get::<_, Option<String>>(3).ok().flatten(),
+                    ),
                 ))
             })
             .optional()
             .map_err(|err| format!("read attachment bytes failed: {err}"))?;
 
-        let Some((mime, size_bytes, data_base64)) = entry else {
+        let Some((mime, size_bytes, payload)) = entry else {
             return Err("attachment not found".to_string());
         };
-        let data_base64 =
-            data_base64.ok_or_else(|| "attachment payload unavailable".to_string())?;
-        Ok((mime, size_bytes, data_base64))
+        let payload = payload.ok_or_else(|| "attachment payload unavailable".to_string())?;
+        Ok((mime, size_bytes, payload))
     }
+
+    pub(super) fn payload_bytes(&self, payload: StoredPayload) -> Result<Vec<u8>, String> {
+        match payload {
+            StoredPayload::Inline(data_base64) => base64::engine::general_purpose::STANDARD
+                .decode(data_base64.as_bytes())
+                .map_err(|err| format!("decode attachment payload failed: {err}")),
+            StoredPayload::Blob(sha256) => self
+                .blobs
+                .as_ref()
+                .ok_or_else(|| "attachment payload unavailable".to_string())?
+                .read(&sha256),
+        }
+    }
+
+    pub(super) fn payload_base64(&self, payload: StoredPayload) -> Result<String, String> {
+        match payload {
+            StoredPayload::Inline(data_base64) => Ok(data_base64),
+            blob => Ok(encode_bytes_base64(&self.payload_bytes(blob)?)),
+        }
+    }
+}
+
+/// Where an attachment's bytes live: legacy and fallback rows keep base64
+/// inline, everything else references the blob store.
+pub(super) enum StoredPayload {
+    Inline(String),
+    Blob(String),
+}
+
+pub(super) fn stored_payload(
+    inline_base64: Option<String>,
+    blob_sha256: Option<String>,
+) -> Option<StoredPayload> {
+    blob_sha256
+        .map(StoredPayload::Blob)
+        .or_else(|| inline_base64.map(StoredPayload::Inline))
 }
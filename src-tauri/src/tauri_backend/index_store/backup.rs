@@ -0,0 +1,637 @@
+use super::*;
+use rusqlite::backup::Backup;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+const BACKUP_FORMAT: &str = "weft-index-backup";
+const BACKUP_FORMAT_VERSION: i64 = 1;
+const MANIFEST_ENTRY: &str = "manifest.json";
+const DATABASE_ENTRY: &str = "index.sqlite3";
+const DESKTOP_SHELL_ENTRY: &str = "desktop-shell.json";
+const BLOB_ENTRY_PREFIX: &str = "blobs/";
+const BACKUP_PAGES_PER_STEP: std::os::raw::c_int = 256;
+const TAR_BLOCK: usize = 512;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupManifest {
+    format: String,
+    format_version: i64,
+    app_version: String,
+    schema_version: i64,
+    created_at_ms: i64,
+    entries: Vec<BackupEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupEntry {
+    path: String,
+    size_bytes: u64,
+    sha256: String,
+}
+
+impl IndexStore {
+    /// Writes a tar archive holding an online-backup snapshot of the index,
+    /// every referenced attachment blob, the desktop shell preferences and a
+    /// manifest with versions and SHA-256 checksums. The manifest is the last
+    /// entry so the archive can be streamed in a single pass.
+    pub(crate) fn create_backup(&self, params: BackupParams) -> Result<BackupSummary, String> {
+        let destination = params.destination;
+        if destination.as_os_str().is_empty() {
+            return Err("destination is required".to_string());
+        }
+        if destination.exists() {
+            return Err(format!(
+                "backup destination {} already exists",
+                destination.display()
+            ));
+        }
+        if let Some(parent) = destination
+            .parent()
+            .filter(|path| !path.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("create backup directory failed: {err}"))?;
+        }
+        let snapshot_path = sibling_path(&destination, ".snapshot");
+        let archive_path = sibling_path(&destination, ".partial");
+        let result =
+            self.write_backup_archive(&snapshot_path, &archive_path, params.desktop_shell.as_ref());
+        let _ = std::fs::remove_file(&snapshot_path);
+        let mut summary = match result {
+            Ok(summary) => summary,
+            Err(err) => {
+                let _ = std::fs::remove_file(&archive_path);
+                return Err(err);
+            }
+        };
+        std::fs::rename(&archive_path, &destination)
+            .map_err(|err| format!("finalize backup archive failed: {err}"))?;
+        summary.path = destination.to_string_lossy().to_string();
+        Ok(summary)
+    }
+
+    fn write_backup_archive(
+        &self,
+        snapshot_path: &Path,
+        archive_path: &Path,
+        desktop_shell: Option<&Value>,
+    ) -> Result<BackupSummary, String> {
+        let _ = std::fs::remove_file(snapshot_path);
+        let mut snapshot = Connection::open(snapshot_path)
+            .map_err(|err| format!("open backup snapshot failed: {err}"))?;
+        {
+            let conn = self
+                .conn
+                .lock()
+                .map_err(|_| "index lock poisoned".to_string())?;
+            Backup::new(&conn, &mut snapshot)
+                .and_then(|backup| {
+                    backup.run_to_completion(BACKUP_PAGES_PER_STEP, Duration::ZERO, None)
+                })
+                .map_err(|err| format!("snapshot index failed: {err}"))?;
+        }
+        // Everything below reads the snapshot, so the live index stays usable.
+        let schema_version = migrations::read_schema_version(&snapshot)?;
+        let messages = count_rows(&snapshot, "messages")?;
+        let blob_digests = {
+            let mut stmt = snapshot
+                .prepare("SELECT sha256 FROM blobs ORDER BY sha256 ASC")
+                .map_err(|err| format!("prepare backup blob scan failed: {err}"))?;
+            let rows = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(|err| format!("scan backup blobs failed: {err}"))?;
+            rows.collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("parse backup blob failed: {err}"))?
+        };
+        drop(snapshot);
+
+        let file = File::create(archive_path)
+            .map_err(|err| format!("create backup archive failed: {err}"))?;
+        let mut archive = BufWriter::new(file);
+        let mut entries = Vec::new();
+
+        let database = File::open(snapshot_path)
+            .map_err(|err| format!("read backup snapshot failed: {err}"))?;
+        entries.push(write_tar_file(&mut archive, DATABASE_ENTRY, database)?);
+
+        let mut blob_count = 0;
+        if let Some(blobs) = self.blobs.as_ref() {
+            for sha256 in blob_digests {
+                let bytes = match blobs.read(&sha256) {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        log::warn!("backup skipped attachment blob {sha256}: {err}");
+                        continue;
+                    }
+                };
+                let path = format!("{BLOB_ENTRY_PREFIX}{}/{sha256}", &sha256[..2]);
+                entries.push(write_tar_file(&mut archive, &path, bytes.as_slice())?);
+                blob_count += 1;
+            }
+        }
+
+        if let Some(prefs) = desktop_shell {
+            let payload = serde_json::to_vec_pretty(prefs)
+                .map_err(|err| format!("serialize desktop shell prefs failed: {err}"))?;
+            entries.push(write_tar_file(
+                &mut archive,
+                DESKTOP_SHELL_ENTRY,
+                payload.as_slice(),
+            )?);
+        }
+
+        let manifest = BackupManifest {
+            format: BACKUP_FORMAT.to_string(),
+            format_version: BACKUP_FORMAT_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version,
+            created_at_ms: current_timestamp_ms(),
+            entries,
+        };
+        let payload = serde_json::to_vec_pretty(&manifest)
+            .map_err(|err| format!("serialize backup manifest failed: {err}"))?;
+        write_tar_file(&mut archive, MANIFEST_ENTRY, payload.as_slice())?;
+        archive
+            .write_all(&[0; TAR_BLOCK * 2])
+            .and_then(|_| archive.flush())
+            .map_err(|err| format!("write backup archive failed: {err}"))?;
+        let bytes_written = archive
+            .get_ref()
+            .metadata()
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        Ok(BackupSummary {
+            path: String::new(),
+            schema_version,
+            created_at_ms: manifest.created_at_ms,
+            messages,
+            blobs: blob_count,
+            bytes_written,
+        })
+    }
+
+    /// Validates a backup archive and replaces the live index with it in place:
+    /// the snapshot is copied into the open connection with the online backup
+    /// API, so callers keep using the same `IndexStore`. Archives from newer
+    /// schema or archive versions are refused; older schemas are migrated.
+    pub(crate) fn restore_backup(&self, archive: &Path) -> Result<RestoreSummary, String> {
+        let Some(blobs) = self.blobs.as_ref() else {
+            return Err("restore needs an on-disk index".to_string());
+        };
+        let staging = blobs.root().with_extension("restore");
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)
+                .map_err(|err| format!("clear restore staging failed: {err}"))?;
+        }
+        let result = self.restore_from_staging(archive, &staging, blobs);
+        let _ = std::fs::remove_dir_all(&staging);
+        result
+    }
+
+    fn restore_from_staging(
+        &self,
+        archive: &Path,
+        staging: &Path,
+        blobs: &BlobStore,
+    ) -> Result<RestoreSummary, String> {
+        let file =
+            File::open(archive).map_err(|err| format!("open backup archive failed: {err}"))?;
+        let extracted = extract_tar(BufReader::new(file), staging)?;
+        let manifest = validate_manifest(staging, &extracted)?;
+
+        let staged_db = Connection::open(staging.join(DATABASE_ENTRY))
+            .map_err(|err| format!("open backup snapshot failed: {err}"))?;
+        let schema_version = migrations::read_schema_version(&staged_db)?;
+        if schema_version != manifest.schema_version {
+            return Err("backup snapshot does not match its manifest".to_string());
+        }
+        let check = staged_db
+            .query_row("PRAGMA quick_check", [], |row| row.get::<_, String>(0))
+            .map_err(|err| format!("check backup snapshot failed: {err}"))?;
+        if check != "ok" {
+            return Err(format!("backup snapshot is corrupted: {check}"));
+        }
+
+        // Blobs go in first so the restored rows never point at missing files.
+        let mut restored_blobs = 0;
+        for entry in &manifest.entries {
+            if !entry.path.starts_with(BLOB_ENTRY_PREFIX) {
+                continue;
+            }
+            let bytes = std::fs::read(staging.join(&entry.path))
+                .map_err(|err| format!("read staged blob failed: {err}"))?;
+            blobs.put(&bytes)?;
+            restored_blobs += 1;
+        }
+
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        Backup::new(&staged_db, &mut conn)
+            .and_then(|backup| {
+                backup.run_to_completion(BACKUP_PAGES_PER_STEP, Duration::ZERO, None)
+            })
+            .map_err(|err| format!("restore index failed: {err}"))?;
+        drop(staged_db);
+        migrations::run_schema_migrations(&mut conn)?;
+        let orphaned = blobs::sweep_orphan_blob_files(&conn, blobs);
+        if orphaned > 0 {
+            log::info!("restore removed {orphaned} attachment blobs not in the backup");
+        }
+
+        let desktop_shell = match std::fs::read(staging.join(DESKTOP_SHELL_ENTRY)) {
+            Ok(bytes) => Some(
+                serde_json::from_slice::<Value>(&bytes)
+                    .map_err(|err| format!("parse backup desktop shell prefs failed: {err}"))?,
+            ),
+            Err(_) => None,
+        };
+        Ok(RestoreSummary {
+            app_version: manifest.app_version,
+            schema_version,
+            created_at_ms: manifest.created_at_ms,
+            messages: count_rows(&conn, "messages")?,
+            threads: count_rows(&conn, "threads")?,
+            blobs: restored_blobs,
+            desktop_shell,
+        })
+    }
+}
+
+fn count_rows(conn: &Connection, table: &str) -> Result<i64, String> {
+    conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+        row.get::<_, i64>(0)
+    })
+    .map_err(|err| format!("count {table} failed: {err}"))
+}
+
+fn validate_manifest(staging: &Path, extracted: &[String]) -> Result<BackupManifest, String> {
+    let payload = std::fs::read(staging.join(MANIFEST_ENTRY))
+        .map_err(|_| "backup archive has no manifest".to_string())?;
+    let manifest = serde_json::from_slice::<BackupManifest>(&payload)
+        .map_err(|err| format!("parse backup manifest failed: {err}"))?;
+    if manifest.format != BACKUP_FORMAT {
+        return Err(format!("not a Weft backup (format '{}')", manifest.format));
+    }
+    if manifest.format_version > BACKUP_FORMAT_VERSION {
+        return Err(format!(
+            "backup format version {} is newer than supported version {BACKUP_FORMAT_VERSION}",
+            manifest.format_version
+        ));
+    }
+    let latest = migrations::latest_schema_version();
+    if manifest.schema_version > latest {
+        return Err(format!(
+            "backup index schema version {} is newer than supported version {latest}; update the app to restore it",
+            manifest.schema_version
+        ));
+    }
+
+    let listed = manifest
+        .entries
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect::<BTreeSet<_>>();
+    if !listed.contains(DATABASE_ENTRY) {
+        return Err("backup manifest does not list the index snapshot".to_string());
+    }
+    if let Some(extra) = extracted
+        .iter()
+        .find(|path| path.as_str() != MANIFEST_ENTRY && !listed.contains(path.as_str()))
+    {
+        return Err(format!("backup archive contains unlisted entry {extra}"));
+    }
+    for entry in &manifest.entries {
+        if !is_backup_entry_name(&entry.path) || entry.path == MANIFEST_ENTRY {
+            return Err(format!(
+                "backup manifest lists unexpected entry {}",
+                entry.path
+            ));
+        }
+        let file = File::open(staging.join(&entry.path))
+            .map_err(|_| format!("backup archive is missing {}", entry.path))?;
+        let (size_bytes, sha256) = copy_hashed(file, std::io::sink())?;
+        if size_bytes != entry.size_bytes || sha256 != entry.sha256 {
+            return Err(format!("backup entry {} failed checksum", entry.path));
+        }
+        if entry.path.starts_with(BLOB_ENTRY_PREFIX) && !entry.path.ends_with(&sha256) {
+            return Err(format!(
+                "backup blob {} does not match its digest",
+                entry.path
+            ));
+        }
+    }
+    Ok(manifest)
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn copy_hashed(mut reader: impl Read, mut writer: impl Write) -> Result<(u64, String), String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut total = 0u64;
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .map_err(|err| format!("read backup data failed: {err}"))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        writer
+            .write_all(&buffer[..read])
+            .map_err(|err| format!("write backup data failed: {err}"))?;
+        total += read as u64;
+    }
+    Ok((total, hex::encode(hasher.finalize())))
+}
+
+// Archives are plain POSIX ustar so any `tar` can inspect them. Only regular
+// files with names up to 100 bytes and sizes below 8 GiB (the largest value
+// the octal size field holds) are written or accepted.
+fn write_tar_file(
+    archive: &mut impl Write,
+    path: &str,
+    mut source: impl Read + TarSized,
+) -> Result<BackupEntry, String> {
+    let size = source.tar_size()?;
+    archive
+        .write_all(&tar_header(path, size)?)
+        .map_err(|err| format!("write backup archive failed: {err}"))?;
+    let (written, sha256) = copy_hashed(source.by_ref().take(size), &mut *archive)?;
+    if written != size {
+        return Err(format!("{path} changed while it was archived"));
+    }
+    let padding = (TAR_BLOCK - (size as usize % TAR_BLOCK)) % TAR_BLOCK;
+    archive
+        .write_all(&[0; TAR_BLOCK][..padding])
+        .map_err(|err| format!("write backup archive failed: {err}"))?;
+    Ok(BackupEntry {
+        path: path.to_string(),
+        size_bytes: size,
+        sha256,
+    })
+}
+
+trait TarSized {
+    fn tar_size(&self) -> Result<u64, String>;
+}
+
+impl TarSized for File {
+    fn tar_size(&self) -> Result<u64, String> {
+        self.metadata()
+            .map(|metadata| metadata.len())
+            .map_err(|err| format!("stat backup file failed: {err}"))
+    }
+}
+
+impl TarSized for &[u8] {
+    fn tar_size(&self) -> Result<u64, String> {
+        Ok(self.len() as u64)
+    }
+}
+
+fn tar_header(path: &str, size: u64) -> Result<[u8; TAR_BLOCK], String> {
+    if path.len() > 100 {
+        return Err(format!("backup entry name {path} is too long"));
+    }
+    let mut header = [0u8; TAR_BLOCK];
+    header[..path.len()].copy_from_slice(path.as_bytes());
+    write_octal(&mut header[100..108], 0o644)?;
+    write_octal(&mut header[108..116], 0)?;
+    write_octal(&mut header[116..124], 0)?;
+    write_octal(&mut header[124..136], size)
+        .map_err(|_| format!("backup entry {path} is too large for the archive"))?;
+    write_octal(
+        &mut header[136..148],
+        (current_timestamp_ms() / 1000).max(0) as u64,
+    )?;
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[148..156].fill(b' ');
+    let checksum = header.iter().map(|byte| u64::from(*byte)).sum::<u64>();
+    write_octal(&mut header[148..155], checksum)?;
+    Ok(header)
+}
+
+fn write_octal(field: &mut [u8], value: u64) -> Result<(), String> {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    if digits.len() >= field.len() {
+        return Err(format!("{value} does not fit a tar header field"));
+    }
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+    Ok(())
+}
+
+fn read_octal(field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field).ok()?;
+    let digits = text.trim_matches(|ch: char| ch == '\0' || ch == ' ');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// Extracts the archive into `staging`, accepting only the entry names a
+/// backup can contain so a crafted archive cannot write outside it.
+fn extract_tar(mut archive: impl Read, staging: &Path) -> Result<Vec<String>, String> {
+    std::fs::create_dir_all(staging)
+        .map_err(|err| format!("create restore staging failed: {err}"))?;
+    let mut names = Vec::new();
+    loop {
+        let mut header = [0u8; TAR_BLOCK];
+        archive
+            .read_exact(&mut header)
+            .map_err(|_| "backup archive is truncated".to_string())?;
+        if header.iter().all(|byte| *byte == 0) {
+            return Ok(names);
+        }
+        let stored = read_octal(&header[148..156])
+            .ok_or_else(|| "backup archive header is malformed".to_string())?;
+        let mut unsigned = header;
+        unsigned[148..156].fill(b' ');
+        if unsigned.iter().map(|byte| u64::from(*byte)).sum::<u64>() != stored {
+            return Err("backup archive header checksum mismatch".to_string());
+        }
+        let name_end = header[..100]
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(100);
+        let name = std::str::from_utf8(&header[..name_end])
+            .map_err(|_| "backup archive entry name is not UTF-8".to_string())?
+            .to_string();
+        let size = read_octal(&header[124..136])
+            .ok_or_else(|| "backup archive header is malformed".to_string())?;
+        if !matches!(header[156], b'0' | 0) || !is_backup_entry_name(&name) {
+            return Err(format!("backup archive contains unexpected entry '{name}'"));
+        }
+        if names.contains(&name) {
+            return Err(format!("backup archive repeats entry {name}"));
+        }
+
+        let target = staging.join(&name);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("create restore staging failed: {err}"))?;
+        }
+        let file =
+            File::create(&target).map_err(|err| format!("write restore staging failed: {err}"))?;
+        let (written, _) = copy_hashed(archive.by_ref().take(size), BufWriter::new(file))?;
+        if written != size {
+            return Err("backup archive is truncated".to_string());
+        }
+        let padding = (TAR_BLOCK - (size as usize % TAR_BLOCK)) % TAR_BLOCK;
+        std::io::copy(
+            &mut archive.by_ref().take(padding as u64),
+            &mut std::io::sink(),
+        )
+        .map_err(|err| format!("read backup archive failed: {err}"))?;
+        names.push(name);
+    }
+}
+
+fn is_backup_entry_name(name: &str) -> bool {
+    if matches!(name, MANIFEST_ENTRY | DATABASE_ENTRY | DESKTOP_SHELL_ENTRY) {
+        return true;
+    }
+    let Some(rest) = name.strip_prefix(BLOB_ENTRY_PREFIX) else {
+        return false;
+    };
+    let Some((shard, digest)) = rest.split_once('/') else {
+        return false;
+    };
+    digest.len() == 64
+        && digest.bytes().all(|byte| byte.is_ascii_hexdigit())
+        && digest.starts_with(shard)
+        && shard.len() == 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed(store: &IndexStore, ids: &[&str]) {
+        let payload = ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| {
+                with_fields(
+                    inbound(id, "peer-a", 1_000 + index as i64, &format!("note {id}")),
+                    attachment_fields("a.bin", id.as_bytes()),
+                )
+            })
+            .collect::<Vec<_>>();
+        store
+            .reindex_from_runtime_payloads(&Value::Array(payload), &json!([]))
+            .expect("seed index");
+    }
+
+    #[test]
+    fn backup_round_trips_into_a_live_store() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let source = IndexStore::new(dir.path().join("a").join("index.sqlite3")).expect("open a");
+        seed(&source, &["m1", "m2"]);
+        let archive = dir.path().join("backup.tar");
+        let summary = source
+            .create_backup(BackupParams {
+                destination: archive.clone(),
+                desktop_shell: Some(json!({ "start_in_tray": true })),
+            })
+            .expect("backup");
+        assert_eq!(summary.messages, 2);
+        assert_eq!(summary.blobs, 2);
+        assert!(source
+            .create_backup(BackupParams {
+                destination: archive.clone(),
+                desktop_shell: None,
+            })
+            .is_err());
+
+        let target = IndexStore::new(dir.path().join("b").join("index.sqlite3")).expect("open b");
+        seed(&target, &["other"]);
+        let restored = target.restore_backup(&archive).expect("restore");
+        assert_eq!(restored.messages, 2);
+        assert_eq!(restored.blobs, 2);
+        assert_eq!(
+            restored.desktop_shell,
+            Some(json!({ "start_in_tray": true }))
+        );
+        let files = target
+            .query_files(FilesQueryParams {
+                query: None,
+                kind: None,
+                limit: None,
+                cursor: None,
+                include_bytes: Some(true),
+            })
+            .expect("files");
+        let payloads = files["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .map(|item| item["data_base64"].as_str().unwrap_or_default().to_string())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            payloads,
+            BTreeSet::from([encode_bytes_base64(b"m1"), encode_bytes_base64(b"m2")])
+        );
+        let blob_dir = dir.path().join("b").join("index.blobs");
+        let files_on_disk = std::fs::read_dir(&blob_dir)
+            .expect("blob dir")
+            .flatten()
+            .map(|shard| std::fs::read_dir(shard.path()).expect("shard").count())
+            .sum::<usize>();
+        assert_eq!(files_on_disk, 2);
+    }
+
+    #[test]
+    fn restore_rejects_tampered_and_newer_archives() {
+        let (dir, store) = test_store();
+        seed(&store, &["m1"]);
+
+        let mut archive = Vec::new();
+        write_tar_file(&mut archive, DATABASE_ENTRY, &b"not a database"[..]).expect("db entry");
+        let manifest = json!({
+            "format": BACKUP_FORMAT,
+            "format_version": BACKUP_FORMAT_VERSION,
+            "app_version": "9.9.9",
+            "schema_version": migrations::latest_schema_version() + 1,
+            "created_at_ms": 0,
+            "entries": [{ "path": DATABASE_ENTRY, "size_bytes": 14,
+                          "sha256": hex::encode(Sha256::digest(b"not a database")) }],
+        })
+        .to_string();
+        write_tar_file(&mut archive, MANIFEST_ENTRY, manifest.as_bytes()).expect("manifest");
+        archive.extend_from_slice(&[0; TAR_BLOCK * 2]);
+        let newer = dir.path().join("newer.tar");
+        std::fs::write(&newer, &archive).expect("write archive");
+        let err = store
+            .restore_backup(&newer)
+            .expect_err("newer schema refused");
+        assert!(err.contains("newer than supported"), "{err}");
+
+        let err = tar_header(DATABASE_ENTRY, 8 << 30).expect_err("8 GiB entry refused");
+        assert!(err.contains("too large"), "{err}");
+        assert!(tar_header(DATABASE_ENTRY, (8 << 30) - 1).is_ok());
+
+        let mut escaping = tar_header("../escape", 0).expect("header").to_vec();
+        escaping.extend_from_slice(&[0; TAR_BLOCK * 2]);
+        let hostile = dir.path().join("hostile.tar");
+        std::fs::write(&hostile, &escaping).expect("write archive");
+        assert!(store.restore_backup(&hostile).is_err());
+        assert!(!dir.path().join("escape").exists());
+
+        assert_eq!(store.index_status().expect("status").message_count, 1);
+    }
+}
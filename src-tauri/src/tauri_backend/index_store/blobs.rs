@@ -0,0 +1,455 @@
+use super::*;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+const EXTERNALIZE_BATCH_SIZE: usize = 64;
+
+/// Content-addressed attachment payloads stored as `<root>/<aa>/<sha256>`.
+/// Reference counts live in the `blobs` table and are maintained by triggers on
+/// `attachments`, so a file is only removed once no attachment row points at it.
+#[derive(Debug)]
+pub(crate) struct BlobStore {
+    root: PathBuf,
+}
+
+impl BlobStore {
+    pub(crate) fn for_index(index_path: &Path) -> Self {
+        Self {
+            root: index_path.with_extension("blobs"),
+        }
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub(crate) fn put(&self, bytes: &[u8]) -> Result<String, String> {
+        let sha256 = hex::encode(Sha256::digest(bytes));
+        let path = self.path_for(&sha256)?;
+        if path.is_file() {
+            return Ok(sha256);
+        }
+        let parent = path
+            .parent()
+            .ok_or_else(|| "blob directory is missing".to_string())?;
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("create blob directory failed: {err}"))?;
+        // Write to a sibling temp file first so readers never observe a partial blob.
+        let staging = parent.join(format!("{sha256}.tmp"));
+        std::fs::write(&staging, bytes).map_err(|err| format!("write blob failed: {err}"))?;
+        std::fs::rename(&staging, &path).map_err(|err| {
+            let _ = std::fs::remove_file(&staging);
+            format!("commit blob failed: {err}")
+        })?;
+        Ok(sha256)
+    }
+
+    pub(crate) fn read(&self, sha256: &str) -> Result<Vec<u8>, String> {
+        let path = self.path_for(sha256)?;
+        match std::fs::read(&path) {
+            Ok(bytes) => Ok(bytes),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err("attachment payload unavailable".to_string())
+            }
+            Err(err) => Err(format!("read blob failed: {err}")),
+        }
+    }
+
+    pub(crate) fn contains(&self, sha256: &str) -> bool {
+        self.path_for(sha256)
+            .map(|path| path.is_file())
+            .unwrap_or(false)
+    }
+
+    fn remove(&self, sha256: &str) {
+        if let Ok(path) = self.path_for(sha256) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn path_for(&self, sha256: &str) -> Result<PathBuf, String> {
+        if sha256.len() != 64 || !sha256.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(format!("invalid blob digest '{sha256}'"));
+        }
+        Ok(self.root.join(&sha256[..2]).join(sha256))
+    }
+}
+
+/// Writes an attachment payload to the blob store and registers it in `blobs`.
+/// Returns `None` when the payload is not valid base64 so callers keep it inline.
+pub(super) fn store_attachment_blob(
+    conn: &Connection,
+    blobs: &BlobStore,
+    inline_base64: &str,
+) -> Result<Option<String>, String> {
+    let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(inline_base64.as_bytes())
+    else {
+        return Ok(None);
+    };
+    let sha256 = blobs.put(&bytes)?;
+    conn.execute(
+        "
+        INSERT INTO blobs (sha256, size_bytes, refcount, created_at_ms)
+        VALUES (?1, ?2, 0, ?3)
+        ON CONFLICT(sha256) DO NOTHING
+        ",
+        params![&sha256, bytes.len() as i64, current_timestamp_ms()],
+    )
+    .map_err(|err| format!("register blob failed: {err}"))?;
+    Ok(Some(sha256))
+}
+
+/// Drops blobs whose reference count reached zero. Runs after the writing
+/// transaction commits so a rolled-back delete never loses a payload.
+pub(super) fn collect_unreferenced_blobs(
+    conn: &Connection,
+    blobs: Option<&BlobStore>,
+) -> Result<usize, String> {
+    let Some(blobs) = blobs else {
+        return Ok(0);
+    };
+    let mut stmt = conn
+        .prepare("SELECT sha256 FROM blobs WHERE refcount <= 0")
+        .map_err(|err| format!("prepare unreferenced blob query failed: {err}"))?;
+    let unreferenced = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(|err| format!("query unreferenced blobs failed: {err}"))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("read unreferenced blob failed: {err}"))?;
+    for sha256 in &unreferenced {
+        conn.execute(
+            "DELETE FROM blobs WHERE sha256 = ?1 AND refcount <= 0",
+            params![sha256],
+        )
+        .map_err(|err| format!("delete blob row failed: {err}"))?;
+        blobs.remove(sha256);
+    }
+    Ok(unreferenced.len())
+}
+
+/// Removes files left behind by transactions that wrote a blob and then rolled
+/// back before its row was committed.
+pub(super) fn sweep_orphan_blob_files(conn: &Connection, blobs: &BlobStore) -> usize {
+    let Ok(shards) = std::fs::read_dir(blobs.root()) else {
+        return 0;
+    };
+    // Without the registry nothing can be told apart from an orphan, so skip.
+    let known = match conn
+        .prepare("SELECT sha256 FROM blobs")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<HashSet<_>, _>>()
+        }) {
+        Ok(known) => known,
+        Err(err) => {
+            log::warn!("orphan blob sweep skipped: {err}");
+            return 0;
+        }
+    };
+    let mut removed = 0;
+    for shard in shards.flatten() {
+        let Ok(entries) = std::fs::read_dir(shard.path()) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !known.contains(&name) && std::fs::remove_file(entry.path()).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+/// Moves payloads written by builds that kept attachments as `inline_base64`
+/// into the blob store and drops the duplicate bytes from `fields_json`.
+/// Idempotent, so an interrupted run resumes on the next start.
+pub(super) fn externalize_inline_attachments(
+    conn: &mut Connection,
+    blobs: &BlobStore,
+) -> Result<usize, String> {
+    let mut moved = 0_usize;
+    let mut after_id = 0_i64;
+    loop {
+        let batch = {
+            let mut stmt = conn
+                .prepare(
+                    "
+                    SELECT id, message_id, inline_base64
+                    FROM attachments
+                    WHERE id > ?1
+                      AND blob_sha256 IS NULL
+                      AND inline_base64 IS NOT NULL
+                      AND inline_base64 != ''
+                    ORDER BY id ASC
+                    LIMIT ?2
+                    ",
+                )
+                .map_err(|err| format!("prepare inline attachment scan failed: {err}"))?;
+            let rows = stmt
+                .query_map(params![after_id, EXTERNALIZE_BATCH_SIZE as i64], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                })
+                .map_err(|err| format!("scan inline attachments failed: {err}"))?;
+            rows.collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("read inline attachment failed: {err}"))?
+        };
+        let Some((last_id, _, _)) = batch.last() else {
+            break;
+        };
+        after_id = *last_id;
+
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start blob externalize transaction failed: {err}"))?;
+        let mut messages = BTreeSet::new();
+        for (id, message_id, inline_base64) in &batch {
+            let Some(sha256) = store_attachment_blob(&tx, blobs, inline_base64)? else {
+                continue;
+            };
+            tx.execute(
+                "UPDATE attachments SET blob_sha256 = ?1, inline_base64 = NULL WHERE id = ?2",
+                params![&sha256, id],
+            )
+            .map_err(|err| format!("externalize attachment failed: {err}"))?;
+            messages.insert(message_id.clone());
+            moved += 1;
+        }
+        for message_id in &messages {
+            strip_stored_fields_payloads(&tx, message_id)?;
+        }
+        tx.commit()
+            .map_err(|err| format!("commit blob externalize failed: {err}"))?;
+    }
+    Ok(moved)
+}
+
+fn strip_stored_fields_payloads(conn: &Connection, message_id: &str) -> Result<(), String> {
+    let fields_json = conn
+        .query_row(
+            "SELECT fields_json FROM messages WHERE message_id = ?1",
+            params![message_id],
+            |row| row.get::<_, Option<String>>(0),
+        )
+        .optional()
+        .map_err(|err| format!("read message fields failed: {err}"))?
+        .flatten();
+    let Some(fields) = fields_json.and_then(|raw| serde_json::from_str::<Value>(&raw).ok()) else {
+        return Ok(());
+    };
+    conn.execute(
+        "UPDATE messages SET fields_json = ?1 WHERE message_id = ?2",
+        params![strip_attachment_payloads(fields).to_string(), message_id],
+    )
+    .map_err(|err| format!("strip message fields failed: {err}"))?;
+    Ok(())
+}
+
+/// Removes attachment bytes from a fields object before it is persisted; the
+/// attachments table (and the blob store) is the only copy of the payload.
+pub(super) fn strip_attachment_payloads(fields: Value) -> Value {
+    let Value::Object(mut root) = fields else {
+        return fields;
+    };
+    if let Some(Value::Array(entries)) = root.get_mut("attachments") {
+        for entry in entries.iter_mut() {
+            if let Some(record) = entry.as_object_mut() {
+                record.remove("inline_base64");
+                record.remove("data");
+            }
+        }
+    }
+    if let Some(Value::Array(entries)) = root.get_mut("5") {
+        for entry in entries.iter_mut() {
+            match entry {
+                Value::Object(record) => {
+                    record.remove("inline_base64");
+                    record.remove("data");
+                }
+                Value::Array(parts) if parts.len() >= 2 => parts[1] = Value::Null,
+                _ => {}
+            }
+        }
+    }
+    Value::Object(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment_message(id: &str, payload: &[u8]) -> Value {
+        json!({
+            "id": id, "source": "peer-a", "destination": "self", "direction": "in",
+            "timestamp": 1_000, "content": "file",
+            "fields": { "attachments": [{
+                "name": "photo.jpg",
+                "mime": "image/jpeg",
+                "inline_base64": base64::engine::general_purpose::STANDARD.encode(payload),
+            }] },
+        })
+    }
+
+    fn blob_files(root: &Path) -> usize {
+        std::fs::read_dir(root)
+            .map(|shards| {
+                shards
+                    .flatten()
+                    .filter_map(|shard| std::fs::read_dir(shard.path()).ok())
+                    .map(|entries| entries.count())
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn identical_payloads_share_one_refcounted_blob() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("index.sqlite3");
+        let store = IndexStore::new(path.clone()).expect("open store");
+        let blob_root = BlobStore::for_index(&path).root().to_path_buf();
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    attachment_message("m1", b"same bytes"),
+                    attachment_message("m2", b"same bytes"),
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+        assert_eq!(blob_files(&blob_root), 1);
+
+        let (inline, refcount, fields_json) = {
+            let conn = store.conn.lock().expect("lock");
+            let inline = conn
+                .query_row(
+                    "SELECT COUNT(*) FROM attachments WHERE inline_base64 IS NOT NULL",
+                    [],
+                    |row| row.get::<_, i64>(0),
+                )
+                .expect("inline count");
+            let refcount = conn
+                .query_row("SELECT refcount FROM blobs", [], |row| row.get::<_, i64>(0))
+                .expect("refcount");
+            let fields_json = conn
+                .query_row(
+                    "SELECT fields_json FROM messages WHERE message_id = 'm1'",
+                    [],
+                    |row| row.get::<_, String>(0),
+                )
+                .expect("fields");
+            (inline, refcount, fields_json)
+        };
+        assert_eq!(inline, 0);
+        assert_eq!(refcount, 2);
+        assert!(!fields_json.contains("inline_base64"));
+
+        let files = store
+            .query_files(FilesQueryParams {
+                query: None,
+                kind: None,
+                limit: None,
+                cursor: None,
+                include_bytes: Some(true),
+            })
+            .expect("files");
+        let id = files["items"][0]["id"].as_str().expect("id").to_string();
+        assert_eq!(files["items"][0]["has_inline_data"], true);
+        let binary = store
+            .get_attachment_binary(AttachmentBytesParams { attachment_id: id })
+            .expect("binary");
+        assert_eq!(binary.bytes, b"same bytes");
+
+        store
+            .reindex_from_runtime_payloads(
+                &json!([attachment_message("m2", b"same bytes")]),
+                &json!([]),
+            )
+            .expect("drop m1");
+        assert_eq!(blob_files(&blob_root), 1);
+        store
+            .reindex_from_runtime_payloads(&json!([]), &json!([]))
+            .expect("drop m2");
+        assert_eq!(blob_files(&blob_root), 0);
+    }
+
+    #[test]
+    fn opening_sweeps_blob_files_without_a_registry_row() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("index.sqlite3");
+        let blob_root = BlobStore::for_index(&path).root().to_path_buf();
+        {
+            let store = IndexStore::new(path.clone()).expect("open store");
+            store
+                .reindex_from_runtime_payloads(
+                    &json!([attachment_message("m1", b"kept bytes")]),
+                    &json!([]),
+                )
+                .expect("seed index");
+        }
+        let shard = blob_root.join("00");
+        std::fs::create_dir_all(&shard).expect("shard");
+        std::fs::write(shard.join("00".repeat(32)), b"orphan").expect("orphan file");
+        assert_eq!(blob_files(&blob_root), 2);
+
+        IndexStore::new(path).expect("reopen store");
+        assert_eq!(blob_files(&blob_root), 1);
+        assert!(!shard.join("00".repeat(32)).exists());
+    }
+
+    #[test]
+    fn legacy_inline_rows_are_externalized_on_open() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("index.sqlite3");
+        {
+            let mut conn = Connection::open(&path).expect("open fixture");
+            migrations::run_schema_migrations(&mut conn).expect("migrate fixture");
+            conn.execute_batch(
+                "
+                INSERT INTO messages (message_id, thread_id, direction, source, destination, ts_ms,
+                  title, body, has_attachments, fields_json, updated_at_ms)
+                VALUES ('legacy', 'peer-a', 'in', 'peer-a', 'self', 1000, '', 'hi', 1,
+                  '{\"attachments\":[{\"name\":\"a.txt\",\"inline_base64\":\"aGVsbG8=\"}]}', 0);
+                INSERT INTO attachments (message_id, ordinal, name, mime, size_bytes, inline_base64)
+                VALUES ('legacy', 0, 'a.txt', 'text/plain', 5, 'aGVsbG8=');
+                ",
+            )
+            .expect("seed legacy rows");
+        }
+
+        let store = IndexStore::new(path.clone()).expect("reopen store");
+        let attachment_id = {
+            let conn = store.conn.lock().expect("lock");
+            let (id, inline, fields_json) = conn
+                .query_row(
+                    "
+                    SELECT a.id, a.inline_base64, m.fields_json
+                    FROM attachments a JOIN messages m ON m.message_id = a.message_id
+                    ",
+                    [],
+                    |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, Option<String>>(1)?,
+                            row.get::<_, String>(2)?,
+                        ))
+                    },
+                )
+                .expect("legacy row");
+            assert!(inline.is_none());
+            assert!(!fields_json.contains("aGVsbG8="));
+            id
+        };
+        let bytes = store
+            .get_attachment_bytes(AttachmentBytesParams {
+                attachment_id: attachment_id.to_string(),
+            })
+            .expect("bytes");
+        assert_eq!(bytes["data_base64"], "aGVsbG8=");
+        assert_eq!(blob_files(BlobStore::for_index(&path).root()), 1);
+    }
+}
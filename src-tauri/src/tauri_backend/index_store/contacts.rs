@@ -0,0 +1,492 @@
+use super::*;
+
+const TRUST_LEVELS: &[&str] = &["unknown", "untrusted", "trusted", "verified"];
+
+impl IndexStore {
+    pub(crate) fn query_contacts(&self, params: ContactQueryParams) -> Result<Value, String> {
+        let limit = normalize_limit(params.limit);
+        let keyset = decode_contact_cursor(params.cursor.as_deref());
+        let filter_query = params
+            .query
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| format!("%{}%", value.to_lowercase()));
+        let trust = params.trust.as_deref().map(normalize_trust).transpose()?;
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let mut stmt = conn
+            .prepare(
+                "
+                WITH ranked AS (
+                  SELECT
+                    c.*,
+                    LOWER(COALESCE(NULLIF(c.alias, ''), NULLIF(c.announced_name, ''), c.peer))
+                      AS sort_name
+                  FROM contacts c
+                )
+                SELECT
+                  peer,
+                  alias,
+                  announced_name,
+                  notes,
+                  trust,
+                  favorite,
+                  first_seen_ms,
+                  last_seen_ms,
+                  updated_at_ms,
+                  capabilities_json,
+                  sort_name
+                FROM ranked
+                WHERE (
+                    ?1 IS NULL
+                    OR LOWER(peer) LIKE ?1
+                    OR LOWER(COALESCE(alias, '')) LIKE ?1
+                    OR LOWER(COALESCE(announced_name, '')) LIKE ?1
+                    OR LOWER(notes) LIKE ?1
+                  )
+                  AND (?2 = 0 OR favorite = 1)
+                  AND (?3 IS NULL OR trust = ?3)
+                  AND (
+                    ?4 IS NULL
+                    OR favorite < ?4
+                    OR (favorite = ?4 AND sort_name > ?5)
+                    OR (favorite = ?4 AND sort_name = ?5 AND peer > ?6)
+                  )
+                ORDER BY favorite DESC, sort_name ASC, peer ASC
+                LIMIT ?7
+                ",
+            )
+            .map_err(|err| format!("prepare contact query failed: {err}"))?;
+        let rows = stmt
+            .query_map(
+                params![
+                    filter_query,
+                    if params.favorites_only.unwrap_or(false) {
+                        1
+                    } else {
+                        0
+                    },
+                    trust,
+                    keyset
+                        .as_ref()
+                        .map(|cursor| if cursor.favorite { 1 } else { 0 }),
+                    keyset.as_ref().map(|cursor| cursor.sort_name.as_str()),
+                    keyset.as_ref().map(|cursor| cursor.peer.as_str()),
+                    (limit + 1) as i64
+                ],
+                |row| Ok((contact_from_row(row)?, row.get::<_, String>(10)?)),
+            )
+            .map_err(|err| format!("run contact query failed: {err}"))?;
+        let mut items = Vec::new();
+        for result in rows {
+            items.push(result.map_err(|err| format!("parse contact row failed: {err}"))?);
+        }
+
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().and_then(|(contact, sort_name)| {
+                encode_contact_cursor(&ContactCursorKey {
+                    favorite: contact.favorite,
+                    sort_name: sort_name.clone(),
+                    peer: contact.peer.clone(),
+                })
+            })
+        } else {
+            None
+        };
+        let items = items
+            .into_iter()
+            .map(|(contact, _)| contact)
+            .collect::<Vec<_>>();
+        serde_json::to_value(CursorResult { items, next_cursor })
+            .map_err(|err| format!("serialize contact query failed: {err}"))
+    }
+
+    pub(crate) fn get_contact(&self, peer: &str) -> Result<Value, String> {
+        let peer = required_peer(peer)?;
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let contact = read_contact(&conn, peer)?;
+        serde_json::to_value(contact).map_err(|err| format!("serialize contact failed: {err}"))
+    }
+
+    /// Creates or updates the local fields of a contact. Omitted fields keep
+    /// their value; an empty `alias` falls back to the announced name.
+    pub(crate) fn update_contact(&self, params: ContactUpdateParams) -> Result<Value, String> {
+        let peer = required_peer(&params.peer)?;
+        let trust = params.trust.as_deref().map(normalize_trust).transpose()?;
+        let alias = params.alias.as_deref().map(str::trim);
+        let notes = params.notes.as_deref().map(str::trim);
+
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start contact transaction failed: {err}"))?;
+        tx.execute(
+            "
+            INSERT INTO contacts (peer, alias, notes, trust, favorite, updated_at_ms)
+            VALUES (?1, NULLIF(?2, ''), COALESCE(?3, ''), COALESCE(?4, 'unknown'), COALESCE(?5, 0), ?6)
+            ON CONFLICT(peer) DO UPDATE SET
+              alias = CASE WHEN ?2 IS NULL THEN alias ELSE NULLIF(?2, '') END,
+              notes = COALESCE(?3, notes),
+              trust = COALESCE(?4, trust),
+              favorite = COALESCE(?5, favorite),
+              updated_at_ms = excluded.updated_at_ms
+            ",
+            params![
+                peer,
+                alias,
+                notes,
+                trust,
+                params.favorite.map(|value| if value { 1 } else { 0 }),
+                current_timestamp_ms(),
+            ],
+        )
+        .map_err(|err| format!("update contact failed: {err}"))?;
+        refresh_thread_display_name(&tx, peer)?;
+        tx.commit()
+            .map_err(|err| format!("commit contact update failed: {err}"))?;
+        let contact = read_contact(&conn, peer)?;
+        serde_json::to_value(contact).map_err(|err| format!("serialize contact failed: {err}"))
+    }
+
+    /// Forgets everything stored about `peer`. The thread keeps its messages
+    /// and shows the short hash until the peer announces a name again.
+    pub(crate) fn delete_contact(&self, peer: &str) -> Result<Value, String> {
+        let peer = required_peer(peer)?;
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start contact transaction failed: {err}"))?;
+        let deleted = tx
+            .execute("DELETE FROM contacts WHERE peer = ?1", params![peer])
+            .map_err(|err| format!("delete contact failed: {err}"))?;
+        refresh_thread_display_name(&tx, peer)?;
+        tx.commit()
+            .map_err(|err| format!("commit contact delete failed: {err}"))?;
+        Ok(json!({ "peer": peer, "deleted": deleted > 0 }))
+    }
+}
+
+/// Records names, capabilities and sighting times reported by the runtime's
+/// peer list or a live announce. Aliases are local and never overwritten;
+/// announced names and capabilities only replace the stored ones when the
+/// peer reports them.
+pub(super) fn record_peers(conn: &Connection, peers: &[PeerSummary]) -> Result<(), String> {
+    let now_ms = current_timestamp_ms();
+    for peer in peers {
+        let capabilities_json = peer
+            .capabilities
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|err| format!("encode peer capabilities failed: {err}"))?;
+        conn.execute(
+            "
+            INSERT INTO contacts (
+              peer,
+              announced_name,
+              first_seen_ms,
+              last_seen_ms,
+              updated_at_ms,
+              capabilities_json
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, COALESCE(?6, '[]'))
+            ON CONFLICT(peer) DO UPDATE SET
+              announced_name = COALESCE(excluded.announced_name, announced_name),
+              capabilities_json = COALESCE(?6, capabilities_json),
+              first_seen_ms = MIN(
+                COALESCE(first_seen_ms, excluded.first_seen_ms),
+                COALESCE(excluded.first_seen_ms, first_seen_ms)
+              ),
+              last_seen_ms = MAX(
+                COALESCE(last_seen_ms, excluded.last_seen_ms),
+                COALESCE(excluded.last_seen_ms, last_seen_ms)
+              )
+            ",
+            params![
+                &peer.peer,
+                &peer.name,
+                peer.first_seen_ms,
+                peer.last_seen_ms,
+                now_ms,
+                capabilities_json
+            ],
+        )
+        .map_err(|err| format!("record peer contact failed: {err}"))?;
+    }
+    Ok(())
+}
+
+/// Widens the first/last-seen window of the contact who sent an inbound
+/// message, creating the contact on first contact.
+pub(super) fn note_peer_seen(conn: &Connection, peer: &str, ts_ms: i64) -> Result<(), String> {
+    conn.execute(
+        "
+        INSERT INTO contacts (peer, first_seen_ms, last_seen_ms, updated_at_ms)
+        VALUES (?1, ?2, ?2, ?3)
+        ON CONFLICT(peer) DO UPDATE SET
+          first_seen_ms = MIN(COALESCE(first_seen_ms, excluded.first_seen_ms), excluded.first_seen_ms),
+          last_seen_ms = MAX(COALESCE(last_seen_ms, excluded.last_seen_ms), excluded.last_seen_ms)
+        ",
+        params![peer, ts_ms, current_timestamp_ms()],
+    )
+    .map_err(|err| format!("record contact sighting failed: {err}"))?;
+    Ok(())
+}
+
+/// The name threads show for `peer`: the local alias, else the announced name.
+pub(super) fn contact_display_name(
+    conn: &Connection,
+    peer: &str,
+) -> Result<Option<String>, String> {
+    conn.query_row(
+        "
+        SELECT COALESCE(NULLIF(alias, ''), NULLIF(announced_name, ''))
+        FROM contacts
+        WHERE peer = ?1
+        ",
+        params![peer],
+        |row| row.get::<_, Option<String>>(0),
+    )
+    .optional()
+    .map(Option::flatten)
+    .map_err(|err| format!("read contact name failed: {err}"))
+}
+
+pub(super) fn load_contact_display_names(
+    conn: &Connection,
+) -> Result<HashMap<String, String>, String> {
+    let mut stmt = conn
+        .prepare(
+            "
+            SELECT peer, COALESCE(NULLIF(alias, ''), NULLIF(announced_name, '')) AS name
+            FROM contacts
+            WHERE name IS NOT NULL
+            ",
+        )
+        .map_err(|err| format!("prepare contact names query failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(|err| format!("query contact names failed: {err}"))?;
+    rows.collect::<Result<HashMap<_, _>, _>>()
+        .map_err(|err| format!("parse contact name failed: {err}"))
+}
+
+pub(super) fn refresh_thread_display_name(conn: &Connection, peer: &str) -> Result<(), String> {
+    let name = contact_display_name(conn, peer)?.unwrap_or_else(|| short_hash(peer, 6));
+    conn.execute(
+        "UPDATE threads SET display_name = ?1 WHERE thread_id = ?2",
+        params![name, peer],
+    )
+    .map_err(|err| format!("update thread display name failed: {err}"))?;
+    Ok(())
+}
+
+fn read_contact(conn: &Connection, peer: &str) -> Result<Contact, String> {
+    conn.query_row(
+        "
+        SELECT
+          peer,
+          alias,
+          announced_name,
+          notes,
+          trust,
+          favorite,
+          first_seen_ms,
+          last_seen_ms,
+          updated_at_ms,
+          capabilities_json
+        FROM contacts
+        WHERE peer = ?1
+        ",
+        params![peer],
+        contact_from_row,
+    )
+    .optional()
+    .map_err(|err| format!("read contact failed: {err}"))?
+    .ok_or_else(|| format!("contact {peer} not found"))
+}
+
+fn contact_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Contact> {
+    let peer = row.get::<_, String>(0)?;
+    let alias = row.get::<_, Option<String>>(1)?;
+    let announced_name = row.get::<_, Option<String>>(2)?;
+    let display_name = alias
+        .clone()
+        .filter(|value| !value.is_empty())
+        .or_else(|| announced_name.clone().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| short_hash(&peer, 6));
+    Ok(Contact {
+        peer,
+        display_name,
+        alias,
+        announced_name,
+        notes: row.get(3)?,
+        trust: row.get(4)?,
+        favorite: row.get::<_, i64>(5)? == 1,
+        first_seen_ms: row.get(6)?,
+        last_seen_ms: row.get(7)?,
+        updated_at_ms: row.get(8)?,
+        capabilities: serde_json::from_str(&row.get::<_, String>(9)?).unwrap_or_default(),
+    })
+}
+
+fn required_peer(peer: &str) -> Result<&str, String> {
+    let peer = peer.trim();
+    if peer.is_empty() {
+        return Err("peer is required".to_string());
+    }
+    Ok(peer)
+}
+
+fn normalize_trust(value: &str) -> Result<String, String> {
+    let trust = value.trim().to_ascii_lowercase();
+    if TRUST_LEVELS.contains(&trust.as_str()) {
+        Ok(trust)
+    } else {
+        Err(format!(
+            "unsupported trust level '{}' (expected {})",
+            value.trim(),
+            TRUST_LEVELS.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(store: &IndexStore, params: ContactQueryParams) -> Vec<String> {
+        let page = store.query_contacts(params).expect("contacts");
+        page["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .map(|item| {
+                item["display_name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn all_contacts(query: Option<&str>) -> ContactQueryParams {
+        ContactQueryParams {
+            query: query.map(str::to_string),
+            favorites_only: None,
+            trust: None,
+            limit: None,
+            cursor: None,
+        }
+    }
+
+    fn thread_name(store: &IndexStore, thread_id: &str) -> String {
+        store
+            .conn
+            .lock()
+            .expect("lock")
+            .query_row(
+                "SELECT display_name FROM threads WHERE thread_id = ?1",
+                params![thread_id],
+                |row| row.get(0),
+            )
+            .expect("thread")
+    }
+
+    #[test]
+    fn aliases_name_threads_and_survive_reindex() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        let messages = json!([
+            { "id": "m1", "source": "peer-aaaaaaaaaaaa", "destination": "self",
+              "direction": "in", "timestamp": 1_000, "content": "hi" },
+            { "id": "m2", "source": "peer-bbbbbbbbbbbb", "destination": "self",
+              "direction": "in", "timestamp": 2_000, "content": "hello" },
+        ]);
+        let peers = json!([
+            { "peer": "peer-aaaaaaaaaaaa", "name": "Base Camp", "first_seen": 500, "last_seen": 900 },
+        ]);
+        store
+            .reindex_from_runtime_payloads(&messages, &peers)
+            .expect("seed index");
+        assert_eq!(thread_name(&store, "peer-aaaaaaaaaaaa"), "Base Camp");
+
+        let updated = store
+            .update_contact(ContactUpdateParams {
+                peer: "peer-bbbbbbbbbbbb".to_string(),
+                alias: Some("Ridge Team".to_string()),
+                notes: Some("relay on ch 3".to_string()),
+                trust: Some("Trusted".to_string()),
+                favorite: Some(true),
+            })
+            .expect("update contact");
+        assert_eq!(updated["trust"], "trusted");
+        assert_eq!(updated["first_seen_ms"], 2_000_000);
+        assert_eq!(thread_name(&store, "peer-bbbbbbbbbbbb"), "Ridge Team");
+
+        store.force_reindex().expect("force reindex");
+        store
+            .reindex_from_runtime_payloads(&messages, &json!([]))
+            .expect("resync");
+        {
+            let mut conn = store.conn.lock().expect("lock");
+            rebuild_threads_table(&mut conn).expect("rebuild threads");
+        }
+        assert_eq!(thread_name(&store, "peer-bbbbbbbbbbbb"), "Ridge Team");
+        assert_eq!(thread_name(&store, "peer-aaaaaaaaaaaa"), "Base Camp");
+
+        assert_eq!(
+            names(&store, all_contacts(None)),
+            vec!["Ridge Team", "Base Camp"]
+        );
+        assert_eq!(
+            names(&store, all_contacts(Some("ch 3"))),
+            vec!["Ridge Team"]
+        );
+        let first = store
+            .query_contacts(ContactQueryParams {
+                limit: Some(1),
+                ..all_contacts(None)
+            })
+            .expect("first page");
+        let second = names(
+            &store,
+            ContactQueryParams {
+                limit: Some(1),
+                cursor: first["next_cursor"].as_str().map(str::to_string),
+                ..all_contacts(None)
+            },
+        );
+        assert_eq!(second, vec!["Base Camp"]);
+
+        store
+            .delete_contact("peer-bbbbbbbbbbbb")
+            .expect("delete contact");
+        assert_eq!(thread_name(&store, "peer-bbbbbbbbbbbb"), "peer-b...bbbbbb");
+        assert!(store
+            .update_contact(ContactUpdateParams {
+                peer: "peer-aaaaaaaaaaaa".to_string(),
+                alias: None,
+                notes: None,
+                trust: Some("best friend".to_string()),
+                favorite: None,
+            })
+            .is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use super::*;
+
+impl IndexStore {
+    /// Deletes the given messages, or every message in `thread_id`, from the
+    /// index. Tombstones keep them from returning on the next sync even if the
+    /// runtime still reports them.
+    pub(crate) fn delete_messages(
+        &self,
+        params: DeleteMessagesParams,
+    ) -> Result<DeleteSummary, String> {
+        let thread_id = params
+            .thread_id
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+        let requested = params
+            .message_ids
+            .iter()
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect::<BTreeSet<_>>();
+        match (thread_id.is_some(), requested.is_empty()) {
+            (true, false) => {
+                return Err("pass either message_ids or thread_id, not both".to_string())
+            }
+            (false, true) => return Err("message_ids or thread_id is required".to_string()),
+            _ => {}
+        }
+
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let message_ids = match thread_id {
+            Some(thread_id) => {
+                let mut stmt = conn
+                    .prepare("SELECT message_id FROM messages WHERE thread_id = ?1")
+                    .map_err(|err| format!("prepare thread message scan failed: {err}"))?;
+                let rows = stmt
+                    .query_map(params![thread_id], |row| row.get::<_, String>(0))
+                    .map_err(|err| format!("scan thread messages failed: {err}"))?;
+                rows.collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| format!("parse thread message failed: {err}"))?
+            }
+            None => requested.into_iter().collect(),
+        };
+
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start delete transaction failed: {err}"))?;
+        let mut deleted_ids = Vec::with_capacity(message_ids.len());
+        for message_id in &message_ids {
+            let exists = tx
+                .query_row(
+                    "SELECT 1 FROM messages WHERE message_id = ?1",
+                    params![message_id],
+                    |_| Ok(()),
+                )
+                .optional()
+                .map_err(|err| format!("read message failed: {err}"))?
+                .is_some();
+            if exists {
+                deleted_ids.push(message_id.clone());
+            }
+        }
+        let mut touched_threads = delete_indexed_messages(&tx, &deleted_ids, "user")?;
+        if let Some(thread_id) = thread_id {
+            // Deleting a conversation also forgets its per-thread state.
+            for statement in [
+                "DELETE FROM thread_flags WHERE thread_id = ?1",
+                "DELETE FROM thread_read_state WHERE thread_id = ?1",
+                "DELETE FROM retention_policies WHERE thread_id = ?1",
+            ] {
+                tx.execute(statement, params![thread_id])
+                    .map_err(|err| format!("clear thread state failed: {err}"))?;
+            }
+            touched_threads.insert(thread_id.to_string());
+        }
+        tx.commit()
+            .map_err(|err| format!("commit delete failed: {err}"))?;
+        blobs::collect_unreferenced_blobs(&conn, self.blobs.as_ref())?;
+
+        let mut summary = DeleteSummary {
+            deleted: deleted_ids.len(),
+            message_ids: deleted_ids,
+            ..DeleteSummary::default()
+        };
+        for thread_id in touched_threads {
+            upsert_thread_summary_for_thread(&conn, &thread_id)?;
+            let still_exists = conn
+                .query_row(
+                    "SELECT 1 FROM threads WHERE thread_id = ?1",
+                    params![&thread_id],
+                    |_| Ok(()),
+                )
+                .optional()
+                .map_err(|err| format!("read thread summary failed: {err}"))?
+                .is_some();
+            if still_exists {
+                summary.threads_updated.push(thread_id);
+            } else {
+                summary.threads_removed.push(thread_id);
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thread(store: &IndexStore, thread_id: &str) -> Option<Value> {
+        let threads = store
+            .query_threads(ThreadQueryParams {
+                query: None,
+                limit: None,
+                cursor: None,
+                pinned_only: None,
+                archived: None,
+            })
+            .expect("threads");
+        threads["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .find(|item| item["thread_id"] == thread_id)
+            .cloned()
+    }
+
+    fn search_ids(store: &IndexStore, query: &str) -> Vec<String> {
+        let hits = store
+            .search_messages(SearchQueryParams {
+                query: query.to_string(),
+                thread_id: None,
+                limit: None,
+                cursor: None,
+                sort: None,
+            })
+            .expect("search");
+        hits["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .map(|item| item["id"].as_str().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn deletes_messages_and_threads_across_index_fts_and_blobs() {
+        let (_dir, store) = test_store();
+        let payload = json!([
+            inbound("a1", "peer-a", 1_000, "alpha harbour"),
+            with_fields(
+                inbound("a2", "peer-a", 2_000, "bravo harbour"),
+                attachment_fields("x.bin", b"xyz"),
+            ),
+            inbound("a3", "peer-a", 3_000, "charlie harbour"),
+            inbound("b1", "peer-b", 4_000, "delta harbour"),
+        ]);
+        store
+            .reindex_from_runtime_payloads(&payload, &json!([]))
+            .expect("seed index");
+
+        let single = store
+            .delete_messages(DeleteMessagesParams {
+                message_ids: vec!["a3".to_string(), "missing".to_string()],
+                thread_id: None,
+            })
+            .expect("delete single");
+        assert_eq!(single.message_ids, vec!["a3"]);
+        assert_eq!(single.threads_updated, vec!["peer-a"]);
+        let summary = thread(&store, "peer-a").expect("thread a");
+        assert_eq!(summary["unread"], 2);
+        assert_eq!(summary["preview"], "bravo harbour");
+
+        let selection = store
+            .delete_messages(DeleteMessagesParams {
+                message_ids: vec!["a1".to_string(), "a2".to_string()],
+                thread_id: None,
+            })
+            .expect("delete selection");
+        assert_eq!(selection.deleted, 2);
+        assert_eq!(selection.threads_removed, vec!["peer-a"]);
+        assert!(thread(&store, "peer-a").is_none());
+        assert_eq!(search_ids(&store, "harbour"), vec!["b1"]);
+        let blob_rows = store
+            .conn
+            .lock()
+            .expect("lock")
+            .query_row("SELECT COUNT(*) FROM blobs", [], |row| row.get::<_, i64>(0))
+            .expect("blob count");
+        assert_eq!(blob_rows, 0);
+
+        let whole = store
+            .delete_messages(DeleteMessagesParams {
+                message_ids: Vec::new(),
+                thread_id: Some("peer-b".to_string()),
+            })
+            .expect("delete thread");
+        assert_eq!(whole.threads_removed, vec!["peer-b"]);
+
+        store
+            .reindex_from_runtime_payloads(&payload, &json!([]))
+            .expect("resync");
+        assert_eq!(store.index_status().expect("status").message_count, 0);
+
+        assert!(store
+            .delete_messages(DeleteMessagesParams {
+                message_ids: Vec::new(),
+                thread_id: None,
+            })
+            .is_err());
+    }
+}
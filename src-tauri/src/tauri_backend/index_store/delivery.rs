@@ -0,0 +1,542 @@
+use super::*;
+
+/// Where an outbound message is in its delivery. Stored as the `as_str`
+/// value in `messages.delivery_state` and `delivery_events.state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DeliveryState {
+    Queued,
+    Sending,
+    Sent,
+    Delivered,
+    Propagated,
+    Failed,
+    Cancelled,
+}
+
+impl DeliveryState {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Sending => "sending",
+            Self::Sent => "sent",
+            Self::Delivered => "delivered",
+            Self::Propagated => "propagated",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    pub(super) fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "queued" => Some(Self::Queued),
+            "sending" => Some(Self::Sending),
+            "sent" => Some(Self::Sent),
+            "delivered" => Some(Self::Delivered),
+            "propagated" => Some(Self::Propagated),
+            "failed" => Some(Self::Failed),
+            "cancelled" | "canceled" => Some(Self::Cancelled),
+            _ => None,
+        }
+    }
+
+    // Delivery is final. The runtime cannot cancel a send, so a cancelled
+    // message that still arrives is recorded as delivered; anything else the
+    // runtime reports leaves it cancelled until the outbox re-queues it. A
+    // failed message may likewise still be delivered by a late receipt.
+    fn accepts_transition(self, next: Self) -> bool {
+        match self {
+            Self::Delivered => false,
+            Self::Cancelled => next == Self::Delivered,
+            _ => true,
+        }
+    }
+}
+
+/// The state and reason code described by a runtime status string. This is
+/// the only place the free-form `receipt_status` text is interpreted; every
+/// query works on the stored state instead.
+pub(super) fn classify_status(status: Option<&str>) -> Option<(DeliveryState, Option<String>)> {
+    let status = status?.trim().to_ascii_lowercase();
+    if status.is_empty() {
+        return None;
+    }
+    if let Some(state) = DeliveryState::parse(&status) {
+        return Some((state, None));
+    }
+    let reason = status_reason_code(&status);
+    let state = if status.contains("cancel") {
+        DeliveryState::Cancelled
+    } else if status.starts_with("fail") || reason.as_deref() == Some("retry_budget_exhausted") {
+        DeliveryState::Failed
+    } else if status.contains("deliver") {
+        DeliveryState::Delivered
+    } else if status.contains("retry") || status.starts_with("outbound_attempt") {
+        DeliveryState::Sending
+    } else if status.starts_with("sent") && !status.contains("propagat") {
+        // `sent: link` and `sent: opportunistic` left this node; only the
+        // relay hand-off counts as propagated.
+        DeliveryState::Sent
+    } else if status.contains("propagat") {
+        DeliveryState::Propagated
+    } else if reason.is_some()
+        || ["fail", "error", "drop", "rejected", "invalid"]
+            .iter()
+            .any(|needle| status.contains(needle))
+    {
+        DeliveryState::Failed
+    } else if status.contains("sending") || status.contains("link") {
+        DeliveryState::Sending
+    } else if status.contains("sent") || status.contains("broadcast") {
+        DeliveryState::Sent
+    } else if ["queue", "pending", "waiting", "accepted", "stored"]
+        .iter()
+        .any(|needle| status.contains(needle))
+    {
+        DeliveryState::Queued
+    } else {
+        return None;
+    };
+    Some((state, reason))
+}
+
+fn status_reason_code(status: &str) -> Option<String> {
+    let code = if status.contains("receipt timeout") {
+        "receipt_timeout"
+    } else if status.contains("timeout") {
+        "timeout"
+    } else if status.contains("no route")
+        || status.contains("no path")
+        || status.contains("no known path")
+    {
+        "no_path"
+    } else if status.contains("no propagation relay selected") {
+        "relay_unset"
+    } else if status.contains("retry budget exhausted") {
+        "retry_budget_exhausted"
+    } else {
+        return None;
+    };
+    Some(code.to_string())
+}
+
+impl IndexStore {
+    /// Every recorded transition of `message_id`, oldest first, with the
+    /// current state and reason code.
+    pub(crate) fn query_delivery_timeline(&self, message_id: &str) -> Result<Value, String> {
+        let message_id = message_id.trim();
+        if message_id.is_empty() {
+            return Err("message_id is required".to_string());
+        }
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        delivery_timeline(&conn, message_id)
+    }
+
+    /// Folds a runtime `message_delivery_trace` response into the stored
+    /// history and returns the merged timeline. Trace entries are the
+    /// runtime's own record, so they are kept even when they arrive out of
+    /// order; the current state follows the newest event.
+    pub(crate) fn merge_delivery_trace(&self, trace: &Value) -> Result<Value, String> {
+        let trace = trace.get("message_delivery_trace").unwrap_or(trace);
+        let message_id = trace
+            .get("message_id")
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| "delivery trace missing message_id".to_string())?;
+        let transitions = trace
+            .get("transitions")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        if current_delivery_state(&conn, message_id)?.is_none() {
+            // Not an indexed outbound message; nothing to attach events to.
+            return delivery_timeline(&conn, message_id);
+        }
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start delivery trace transaction failed: {err}"))?;
+        for transition in &transitions {
+            let status = transition.get("status").and_then(Value::as_str);
+            let Some((state, derived_reason)) = classify_status(status) else {
+                continue;
+            };
+            let Some(timestamp) = transition.get("timestamp").and_then(Value::as_f64) else {
+                continue;
+            };
+            let reason_code = transition
+                .get("reason_code")
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+                .or(derived_reason);
+            insert_delivery_event(
+                &tx,
+                message_id,
+                state,
+                normalize_timestamp_ms(timestamp),
+                reason_code.as_deref(),
+                status,
+                "trace",
+            )?;
+        }
+        // The newest event becomes the current state, under the same rules as
+        // live transitions.
+        let current = current_delivery_state(&tx, message_id)?;
+        let newest = tx
+            .query_row(
+                "
+                SELECT state FROM delivery_events
+                WHERE message_id = ?1
+                ORDER BY ts_ms DESC, id DESC
+                LIMIT 1
+                ",
+                params![message_id],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(|err| format!("read latest delivery event failed: {err}"))?
+            .as_deref()
+            .and_then(DeliveryState::parse);
+        if let Some(newest) = newest {
+            if current
+                .map(|current| current != newest && current.accepts_transition(newest))
+                .unwrap_or(true)
+            {
+                tx.execute(
+                    "UPDATE messages SET delivery_state = ?1 WHERE message_id = ?2",
+                    params![newest.as_str(), message_id],
+                )
+                .map_err(|err| format!("apply delivery trace failed: {err}"))?;
+            }
+        }
+        tx.commit()
+            .map_err(|err| format!("commit delivery trace failed: {err}"))?;
+        delivery_timeline(&conn, message_id)
+    }
+}
+
+/// Records the state implied by an outbound row's `receipt_status`. Syncs
+/// report a snapshot rather than a transition, so a status that cannot be
+/// classified keeps the current state and a message with no state yet
+/// starts out queued.
+pub(super) fn record_row_delivery_state(conn: &Connection, row: &MessageRow) -> Result<(), String> {
+    if row.direction != "out" {
+        return Ok(());
+    }
+    let (state, reason_code) = match classify_status(row.receipt_status.as_deref()) {
+        Some(classified) => classified,
+        None if current_delivery_state(conn, &row.message_id)?.is_some() => return Ok(()),
+        None => (DeliveryState::Queued, None),
+    };
+    record_delivery_transition(
+        conn,
+        &row.message_id,
+        state,
+        row.ts_ms,
+        reason_code.as_deref(),
+        row.receipt_status.as_deref(),
+        "sync",
+    )?;
+    Ok(())
+}
+
+/// Moves `message_id` to `state` unless it is already there or the current
+/// state does not allow it. A transition never predates the one before it.
+/// Returns whether a transition was recorded.
+pub(super) fn record_delivery_transition(
+    conn: &Connection,
+    message_id: &str,
+    state: DeliveryState,
+    ts_ms: i64,
+    reason_code: Option<&str>,
+    detail: Option<&str>,
+    source: &str,
+) -> Result<bool, String> {
+    let latest = conn
+        .query_row(
+            "
+            SELECT state, ts_ms FROM delivery_events
+            WHERE message_id = ?1
+            ORDER BY ts_ms DESC, id DESC
+            LIMIT 1
+            ",
+            params![message_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+        )
+        .optional()
+        .map_err(|err| format!("read latest delivery event failed: {err}"))?;
+    let mut ts_ms = ts_ms;
+    if let Some((current, current_ts_ms)) = latest {
+        if let Some(current) = DeliveryState::parse(&current) {
+            if current == state || !current.accepts_transition(state) {
+                return Ok(false);
+            }
+        }
+        ts_ms = ts_ms.max(current_ts_ms);
+    }
+    insert_delivery_event(conn, message_id, state, ts_ms, reason_code, detail, source)?;
+    conn.execute(
+        "UPDATE messages SET delivery_state = ?1 WHERE message_id = ?2",
+        params![state.as_str(), message_id],
+    )
+    .map_err(|err| format!("update delivery state failed: {err}"))?;
+    Ok(true)
+}
+
+/// Puts `message_id` back in the queue for an outbox resend. Unlike
+/// `record_delivery_transition` this reopens cancelled messages and logs a
+/// new attempt for one that is already queued; a delivered message stays
+/// closed.
+pub(super) fn requeue_delivery(
+    conn: &Connection,
+    message_id: &str,
+    detail: &str,
+) -> Result<bool, String> {
+    let latest_ts_ms = conn
+        .query_row(
+            "SELECT MAX(ts_ms) FROM delivery_events WHERE message_id = ?1",
+            params![message_id],
+            |row| row.get::<_, Option<i64>>(0),
+        )
+        .map_err(|err| format!("read latest delivery event failed: {err}"))?;
+    if current_delivery_state(conn, message_id)? == Some(DeliveryState::Delivered) {
+        return Ok(false);
+    }
+    // Strictly after the previous event, so a retry in the same millisecond
+    // is still recorded.
+    let ts_ms = latest_ts_ms
+        .map(|latest| latest.saturating_add(1))
+        .unwrap_or(i64::MIN)
+        .max(current_timestamp_ms());
+    insert_delivery_event(
+        conn,
+        message_id,
+        DeliveryState::Queued,
+        ts_ms,
+        None,
+        Some(detail),
+        "outbox",
+    )?;
+    conn.execute(
+        "UPDATE messages SET delivery_state = ?1, status_reason_code = NULL WHERE message_id = ?2",
+        params![DeliveryState::Queued.as_str(), message_id],
+    )
+    .map_err(|err| format!("update delivery state failed: {err}"))?;
+    Ok(true)
+}
+
+pub(super) fn current_delivery_state(
+    conn: &Connection,
+    message_id: &str,
+) -> Result<Option<DeliveryState>, String> {
+    load_delivery_state(conn, message_id).map(|(state, _)| state)
+}
+
+/// The current state and reason code of `message_id`, for message listings.
+pub(super) fn load_delivery_state(
+    conn: &Connection,
+    message_id: &str,
+) -> Result<(Option<DeliveryState>, Option<String>), String> {
+    conn.prepare_cached(
+        "SELECT delivery_state, status_reason_code FROM messages WHERE message_id = ?1",
+    )
+    .and_then(|mut stmt| {
+        stmt.query_row(params![message_id], |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?
+                    .as_deref()
+                    .and_then(DeliveryState::parse),
+                row.get::<_, Option<String>>(1)?,
+            ))
+        })
+    })
+    .optional()
+    .map(Option::unwrap_or_default)
+    .map_err(|err| format!("read delivery state failed: {err}"))
+}
+
+fn insert_delivery_event(
+    conn: &Connection,
+    message_id: &str,
+    state: DeliveryState,
+    ts_ms: i64,
+    reason_code: Option<&str>,
+    detail: Option<&str>,
+    source: &str,
+) -> Result<(), String> {
+    conn.execute(
+        "
+        INSERT OR IGNORE INTO delivery_events (message_id, state, ts_ms, reason_code, detail, source)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ",
+        params![message_id, state.as_str(), ts_ms, reason_code, detail, source],
+    )
+    .map_err(|err| format!("insert delivery event failed: {err}"))?;
+    Ok(())
+}
+
+fn delivery_timeline(conn: &Connection, message_id: &str) -> Result<Value, String> {
+    let (state, reason_code) = load_delivery_state(conn, message_id)?;
+    let mut stmt = conn
+        .prepare_cached(
+            "
+            SELECT state, ts_ms, reason_code, detail, source
+            FROM delivery_events
+            WHERE message_id = ?1
+            ORDER BY ts_ms ASC, id ASC
+            ",
+        )
+        .map_err(|err| format!("prepare delivery timeline failed: {err}"))?;
+    let rows = stmt
+        .query_map(params![message_id], |row| {
+            Ok(DeliveryEvent {
+                state: row.get(0)?,
+                ts_ms: row.get(1)?,
+                reason_code: row.get(2)?,
+                detail: row.get(3)?,
+                source: row.get(4)?,
+            })
+        })
+        .map_err(|err| format!("query delivery timeline failed: {err}"))?;
+    let events = rows
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("parse delivery event failed: {err}"))?;
+    Ok(json!({
+        "message_id": message_id,
+        "state": state,
+        "reason_code": reason_code,
+        "events": events,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states(timeline: &Value) -> Vec<&str> {
+        timeline["events"]
+            .as_array()
+            .expect("events")
+            .iter()
+            .map(|event| event["state"].as_str().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn status_strings_classify_into_states() {
+        let classify = |status: &str| classify_status(Some(status));
+        assert_eq!(
+            classify("delivered"),
+            Some((DeliveryState::Delivered, None))
+        );
+        assert_eq!(
+            classify("retrying: propagated relay"),
+            Some((DeliveryState::Sending, None))
+        );
+        assert_eq!(
+            classify("failed: no route"),
+            Some((DeliveryState::Failed, Some("no_path".to_string())))
+        );
+        assert_eq!(
+            classify("sent to propagation node"),
+            Some((DeliveryState::Propagated, None))
+        );
+        assert_eq!(
+            classify("outbound_attempt: link"),
+            Some((DeliveryState::Sending, None))
+        );
+        assert_eq!(classify("sent: link"), Some((DeliveryState::Sent, None)));
+        assert_eq!(
+            classify("sent: opportunistic"),
+            Some((DeliveryState::Sent, None))
+        );
+        assert_eq!(
+            classify("sent: propagated relay"),
+            Some((DeliveryState::Propagated, None))
+        );
+        assert_eq!(classify("pending"), Some((DeliveryState::Queued, None)));
+        assert_eq!(classify("something else"), None);
+    }
+
+    #[test]
+    fn receipts_and_traces_build_one_timeline() {
+        let (_dir, store) = test_store();
+        let mut sending = outbound("m2", "peer-a", 1_000, "ping");
+        sending["receipt_status"] = json!("sending");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([outbound("m1", "peer-a", 1_000, "ping"), sending]),
+                &json!([]),
+            )
+            .expect("seed index");
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "receipt",
+                "payload": { "message_id": "m1", "status": "failed", "reason_code": "timeout" },
+            }))
+            .expect("failure receipt");
+        assert_eq!(store.runtime_metrics().expect("metrics").queue_size, 1);
+
+        let timeline = store
+            .merge_delivery_trace(&json!({
+                "message_id": "m1",
+                "transitions": [
+                    { "status": "queued", "timestamp": 1_000 },
+                    { "status": "retrying: propagated relay", "timestamp": 1_005, "reason_code": "timeout" },
+                    { "status": "delivered", "timestamp": 4_000_000_000_i64 },
+                ],
+            }))
+            .expect("merge trace");
+        assert_eq!(
+            states(&timeline),
+            vec!["queued", "sending", "failed", "delivered"]
+        );
+        assert_eq!(timeline["state"], "delivered");
+        assert_eq!(timeline["events"][1]["reason_code"], "timeout");
+
+        // Delivery is final: a stale failure does not reopen the message.
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "receipt",
+                "payload": { "message_id": "m1", "status": "failed: receipt timeout" },
+            }))
+            .expect("late receipt");
+        let timeline = store.query_delivery_timeline("m1").expect("timeline");
+        assert_eq!(timeline["events"].as_array().map(Vec::len), Some(4));
+        // A later trace keeps its events but cannot reopen it either.
+        let timeline = store
+            .merge_delivery_trace(&json!({
+                "message_id": "m1",
+                "transitions": [{ "status": "failed: timeout", "timestamp": 4_000_000_100_i64 }],
+            }))
+            .expect("late trace");
+        assert_eq!(timeline["events"].as_array().map(Vec::len), Some(5));
+        assert_eq!(timeline["state"], "delivered");
+
+        let page = store
+            .query_thread_messages(ThreadMessageQueryParams {
+                thread_id: "peer-a".to_string(),
+                query: None,
+                limit: None,
+                cursor: None,
+            })
+            .expect("thread messages");
+        let m1 = page["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .find(|item| item["id"] == "m1")
+            .expect("m1");
+        assert_eq!(m1["delivery_state"], "delivered");
+    }
+}
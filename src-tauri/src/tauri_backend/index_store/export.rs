@@ -0,0 +1,1058 @@
+use super::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const EXPORT_BATCH_SIZE: usize = 200;
+const MIME_LINE_WIDTH: usize = 76;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    Jsonl,
+    Markdown,
+    Maildir,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AttachmentMode {
+    Files,
+    Embed,
+    Skip,
+}
+
+// One exported message: the indexed row plus its attachments, with
+// `inline_base64` filled from the blob store when payloads are exported.
+struct ExportMessage {
+    row: MessageRow,
+    attachments: Vec<AttachmentEntry>,
+}
+
+enum ExportSink {
+    Lines(BufWriter<File>),
+    Maildir(PathBuf),
+}
+
+struct ExportWriter {
+    format: ExportFormat,
+    attachments: AttachmentMode,
+    sink: ExportSink,
+    // Where `Files` mode writes payloads: `<destination>.attachments/<message>/`.
+    attachment_root: PathBuf,
+    names: HashMap<String, String>,
+    summary: ExportSummary,
+}
+
+impl IndexStore {
+    /// Streams a thread, or the results of a search, to `destination` one batch
+    /// at a time. JSON Lines and Markdown write a single file; Maildir writes a
+    /// directory with one RFC 5322 message per LXMF message.
+    pub(crate) fn export_messages(&self, params: ExportParams) -> Result<ExportSummary, String> {
+        let format = match params.format.trim().to_ascii_lowercase().as_str() {
+            "jsonl" | "json" => ExportFormat::Jsonl,
+            "markdown" | "md" => ExportFormat::Markdown,
+            "maildir" => ExportFormat::Maildir,
+            other => {
+                return Err(format!(
+                    "unsupported export format '{other}' (expected jsonl, markdown or maildir)"
+                ))
+            }
+        };
+        let attachments = match params.attachments.as_deref().map(str::trim) {
+            None | Some("") if format == ExportFormat::Maildir => AttachmentMode::Embed,
+            None | Some("") => AttachmentMode::Files,
+            Some("files") => AttachmentMode::Files,
+            Some("embed") => AttachmentMode::Embed,
+            Some("none") => AttachmentMode::Skip,
+            Some(other) => {
+                return Err(format!(
+                    "unsupported attachment mode '{other}' (expected files, embed or none)"
+                ))
+            }
+        };
+        let thread_id = params
+            .thread_id
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+        let query = params
+            .query
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+        if thread_id.is_none() && query.is_none() {
+            return Err("thread_id or query is required".to_string());
+        }
+        if params.destination.as_os_str().is_empty() {
+            return Err("destination is required".to_string());
+        }
+        if params.destination.exists() {
+            return Err(format!(
+                "export destination {} already exists",
+                params.destination.display()
+            ));
+        }
+        let attachment_root = attachment_root(&params.destination);
+        if attachments == AttachmentMode::Files && attachment_root.exists() {
+            return Err(format!(
+                "export attachment directory {} already exists",
+                attachment_root.display()
+            ));
+        }
+
+        let title = match (thread_id, query) {
+            (_, Some(query)) => format!("Search results for \"{query}\""),
+            (Some(thread_id), None) => {
+                format!("Conversation with {}", self.thread_display_name(thread_id)?)
+            }
+            (None, None) => unreachable!("validated above"),
+        };
+        let exported = ExportWriter::create(format, attachments, &params.destination).and_then(
+            |mut writer| {
+                writer.begin(&title)?;
+                self.write_export_messages(&mut writer, thread_id, query, attachments)?;
+                writer.finish(&params.destination)
+            },
+        );
+        if exported.is_err() {
+            discard_partial_export(format, attachments, &params.destination);
+        }
+        exported
+    }
+
+    fn write_export_messages(
+        &self,
+        writer: &mut ExportWriter,
+        thread_id: Option<&str>,
+        query: Option<&str>,
+        attachments: AttachmentMode,
+    ) -> Result<(), String> {
+        match query {
+            Some(query) => {
+                let mut cursor = None;
+                loop {
+                    let page = self.search_messages(SearchQueryParams {
+                        query: query.to_string(),
+                        thread_id: thread_id.map(str::to_string),
+                        limit: Some(EXPORT_BATCH_SIZE),
+                        cursor: cursor.take(),
+                        sort: None,
+                    })?;
+                    let ids = page["items"]
+                        .as_array()
+                        .map(|items| {
+                            items
+                                .iter()
+                                .filter_map(|item| item["id"].as_str().map(str::to_string))
+                                .collect::<Vec<_>>()
+                        })
+                        .unwrap_or_default();
+                    for message in self.load_export_batch(&ids, attachments)? {
+                        writer.write_message(self, &message)?;
+                    }
+                    cursor = page["next_cursor"].as_str().map(str::to_string);
+                    if cursor.is_none() {
+                        break;
+                    }
+                }
+            }
+            None => {
+                let thread_id = thread_id.unwrap_or_default();
+                let mut after = None::<(i64, String)>;
+                loop {
+                    let ids = self.thread_message_ids_after(thread_id, after.as_ref())?;
+                    if ids.is_empty() {
+                        break;
+                    }
+                    let batch = self.load_export_batch(
+                        &ids.iter().map(|(_, id)| id.clone()).collect::<Vec<_>>(),
+                        attachments,
+                    )?;
+                    for message in &batch {
+                        writer.write_message(self, message)?;
+                    }
+                    after = ids.last().cloned();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn thread_display_name(&self, thread_id: &str) -> Result<String, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        Ok(conn
+            .query_row(
+                "SELECT display_name FROM threads WHERE thread_id = ?1",
+                params![thread_id],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(|err| format!("read thread name failed: {err}"))?
+            .unwrap_or_else(|| short_hash(thread_id, 6)))
+    }
+
+    fn thread_message_ids_after(
+        &self,
+        thread_id: &str,
+        after: Option<&(i64, String)>,
+    ) -> Result<Vec<(i64, String)>, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "
+                SELECT ts_ms, message_id
+                FROM messages
+                WHERE thread_id = ?1
+                  AND {}
+                  AND (?2 IS NULL OR ts_ms > ?2 OR (ts_ms = ?2 AND message_id > ?3))
+                ORDER BY ts_ms ASC, message_id ASC
+                LIMIT ?4
+                ",
+                visible_messages_sql("")
+            ))
+            .map_err(|err| format!("prepare export scan failed: {err}"))?;
+        let rows = stmt
+            .query_map(
+                params![
+                    thread_id,
+                    after.map(|value| value.0),
+                    after.map(|value| value.1.as_str()),
+                    EXPORT_BATCH_SIZE as i64
+                ],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+            )
+            .map_err(|err| format!("scan export messages failed: {err}"))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("parse export message id failed: {err}"))
+    }
+
+    fn load_export_batch(
+        &self,
+        message_ids: &[String],
+        mode: AttachmentMode,
+    ) -> Result<Vec<ExportMessage>, String> {
+        let mut batch = Vec::with_capacity(message_ids.len());
+        let mut payloads = Vec::new();
+        {
+            let conn = self
+                .conn
+                .lock()
+                .map_err(|_| "index lock poisoned".to_string())?;
+            let mut message_stmt = conn
+                .prepare_cached(&format!(
+                    "
+                    SELECT {MESSAGE_ROW_COLUMNS}
+                    FROM messages
+                    WHERE message_id = ?1 AND {}
+                    ",
+                    visible_messages_sql("")
+                ))
+                .map_err(|err| format!("prepare export message query failed: {err}"))?;
+            let mut attachment_stmt = conn
+                .prepare_cached(
+                    "
+                    SELECT name, mime, size_bytes, inline_base64, blob_sha256
+                    FROM attachments
+                    WHERE message_id = ?1
+                    ORDER BY ordinal ASC
+                    ",
+                )
+                .map_err(|err| format!("prepare export attachment query failed: {err}"))?;
+            for message_id in message_ids {
+                let row = message_stmt
+                    .query_row(params![message_id], message_row_from_sql)
+                    .optional()
+                    .map_err(|err| format!("read export message failed: {err}"))?;
+                let Some(row) = row else {
+                    continue;
+                };
+                let attachments = attachment_stmt
+                    .query_map(params![message_id], |row| {
+                        Ok((
+                            AttachmentEntry {
+                                name: row.get::<_, String>(0)?,
+                                mime: row.get::<_, Option<String>>(1).ok().flatten(),
+                                size_bytes: row.get::<_, i64>(2).unwrap_or(0).max(0),
+                                inline_base64: None,
+                            },
+                            super::attachments::stored_payload(
+                                row.get::<_, Option<String>>(3).ok().flatten(),
+                                row.get::<_, Option<String>>(4).ok().flatten(),
+                            ),
+                        ))
+                    })
+                    .map_err(|err| format!("query export attachments failed: {err}"))?
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| format!("read export attachment failed: {err}"))?;
+                let mut entries = Vec::with_capacity(attachments.len());
+                for (entry, payload) in attachments {
+                    payloads.push((batch.len(), entries.len(), payload));
+                    entries.push(entry);
+                }
+                batch.push(ExportMessage {
+                    row,
+                    attachments: entries,
+                });
+            }
+        }
+        // Blob reads happen after the index lock is released.
+        if mode != AttachmentMode::Skip {
+            for (message_index, attachment_index, payload) in payloads {
+                let Some(payload) = payload else {
+                    continue;
+                };
+                match self.payload_base64(payload) {
+                    Ok(data) => {
+                        batch[message_index].attachments[attachment_index].inline_base64 =
+                            Some(data)
+                    }
+                    Err(err) => log::warn!(
+                        "export skipped attachment payload for {}: {err}",
+                        batch[message_index].row.message_id
+                    ),
+                }
+            }
+        }
+        Ok(batch)
+    }
+}
+
+impl ExportWriter {
+    fn create(
+        format: ExportFormat,
+        attachments: AttachmentMode,
+        destination: &Path,
+    ) -> Result<Self, String> {
+        if let Some(parent) = destination
+            .parent()
+            .filter(|path| !path.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("create export directory failed: {err}"))?;
+        }
+        let sink = match format {
+            ExportFormat::Jsonl | ExportFormat::Markdown => {
+                let file = File::create(partial_path(destination))
+                    .map_err(|err| format!("create export file failed: {err}"))?;
+                ExportSink::Lines(BufWriter::new(file))
+            }
+            ExportFormat::Maildir => {
+                for folder in ["tmp", "new", "cur"] {
+                    std::fs::create_dir_all(destination.join(folder))
+                        .map_err(|err| format!("create maildir failed: {err}"))?;
+                }
+                ExportSink::Maildir(destination.to_path_buf())
+            }
+        };
+        Ok(Self {
+            format,
+            attachments,
+            sink,
+            attachment_root: attachment_root(destination),
+            names: HashMap::new(),
+            summary: ExportSummary {
+                format: match format {
+                    ExportFormat::Jsonl => "jsonl",
+                    ExportFormat::Markdown => "markdown",
+                    ExportFormat::Maildir => "maildir",
+                }
+                .to_string(),
+                path: destination.to_string_lossy().to_string(),
+                ..ExportSummary::default()
+            },
+        })
+    }
+
+    fn begin(&mut self, title: &str) -> Result<(), String> {
+        if self.format != ExportFormat::Markdown {
+            return Ok(());
+        }
+        let header = format!(
+            "# {title}\n\nExported {}\n\n",
+            format_utc(current_timestamp_ms(), false)
+        );
+        self.write_lines(header.as_bytes())
+    }
+
+    fn write_message(&mut self, store: &IndexStore, message: &ExportMessage) -> Result<(), String> {
+        let files = if self.attachments == AttachmentMode::Files {
+            self.write_attachment_files(message)?
+        } else {
+            Vec::new()
+        };
+        match self.format {
+            ExportFormat::Jsonl => {
+                let mut line = export_json_line(message, self.attachments, &files).to_string();
+                line.push('\n');
+                self.write_lines(line.as_bytes())?;
+            }
+            ExportFormat::Markdown => {
+                let sender = self.sender_label(store, &message.row);
+                let block = markdown_block(message, &sender, self.attachments, &files);
+                self.write_lines(block.as_bytes())?;
+            }
+            ExportFormat::Maildir => {
+                let peer = if message.row.direction == "out" {
+                    &message.row.destination
+                } else {
+                    &message.row.source
+                };
+                let peer_name = self.peer_label(store, peer);
+                let rendered = rfc5322_message(message, &peer_name, self.attachments, &files);
+                self.write_maildir_message(&message.row, rendered.as_bytes())?;
+            }
+        }
+        self.summary.messages += 1;
+        self.summary.attachments += message
+            .attachments
+            .iter()
+            .filter(|entry| entry.inline_base64.is_some())
+            .count();
+        Ok(())
+    }
+
+    fn finish(mut self, destination: &Path) -> Result<ExportSummary, String> {
+        if let ExportSink::Lines(writer) = &mut self.sink {
+            writer
+                .flush()
+                .map_err(|err| format!("flush export file failed: {err}"))?;
+            std::fs::rename(partial_path(destination), destination)
+                .map_err(|err| format!("finalize export file failed: {err}"))?;
+        }
+        if self.attachments == AttachmentMode::Files && self.attachment_root.exists() {
+            self.summary.attachments_path =
+                Some(self.attachment_root.to_string_lossy().to_string());
+        }
+        Ok(self.summary)
+    }
+
+    fn write_lines(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let ExportSink::Lines(writer) = &mut self.sink else {
+            return Ok(());
+        };
+        writer
+            .write_all(bytes)
+            .map_err(|err| format!("write export failed: {err}"))?;
+        self.summary.bytes_written += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn write_maildir_message(&mut self, row: &MessageRow, bytes: &[u8]) -> Result<(), String> {
+        let ExportSink::Maildir(root) = &self.sink else {
+            return Ok(());
+        };
+        let unique = format!(
+            "{}.{}.weft-export",
+            row.ts_ms.max(0) / 1000,
+            safe_file_name(&row.message_id)
+        );
+        let staged = root.join("tmp").join(&unique);
+        std::fs::write(&staged, bytes)
+            .map_err(|err| format!("write maildir message failed: {err}"))?;
+        // Exported mail is already read; `:2,S` marks it seen (`!` on Windows).
+        let flags = if cfg!(windows) { "!2,S" } else { ":2,S" };
+        std::fs::rename(&staged, root.join("cur").join(format!("{unique}{flags}")))
+            .map_err(|err| format!("deliver maildir message failed: {err}"))?;
+        self.summary.bytes_written += bytes.len() as u64;
+        Ok(())
+    }
+
+    // Returns the relative path of each attachment written, in message order.
+    fn write_attachment_files(
+        &mut self,
+        message: &ExportMessage,
+    ) -> Result<Vec<Option<String>>, String> {
+        let mut paths = Vec::with_capacity(message.attachments.len());
+        for (index, entry) in message.attachments.iter().enumerate() {
+            let Some(bytes) = entry.inline_base64.as_deref().and_then(|data| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(data.as_bytes())
+                    .ok()
+            }) else {
+                paths.push(None);
+                continue;
+            };
+            let folder = safe_file_name(&message.row.message_id);
+            let file_name = format!("{index}-{}", safe_file_name(&entry.name));
+            let directory = self.attachment_root.join(&folder);
+            std::fs::create_dir_all(&directory)
+                .map_err(|err| format!("create attachment export directory failed: {err}"))?;
+            std::fs::write(directory.join(&file_name), &bytes)
+                .map_err(|err| format!("write exported attachment failed: {err}"))?;
+            self.summary.bytes_written += bytes.len() as u64;
+            let root_name = self
+                .attachment_root
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            paths.push(Some(format!("{root_name}/{folder}/{file_name}")));
+        }
+        Ok(paths)
+    }
+
+    fn sender_label(&mut self, store: &IndexStore, row: &MessageRow) -> String {
+        if row.direction == "out" {
+            return "Me".to_string();
+        }
+        self.peer_label(store, &row.source)
+    }
+
+    fn peer_label(&mut self, store: &IndexStore, peer: &str) -> String {
+        if let Some(name) = self.names.get(peer) {
+            return name.clone();
+        }
+        let name = store
+            .thread_display_name(peer)
+            .unwrap_or_else(|_| short_hash(peer, 6));
+        self.names.insert(peer.to_string(), name.clone());
+        name
+    }
+}
+
+fn export_json_line(
+    message: &ExportMessage,
+    mode: AttachmentMode,
+    files: &[Option<String>],
+) -> Value {
+    let row = &message.row;
+    let mut fields = row.fields.clone().unwrap_or_else(|| json!({}));
+    if let Some(root) = fields.as_object_mut() {
+        root.remove("attachments");
+        root.remove("5");
+    }
+    let attachments = message
+        .attachments
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let mut value = json!({
+                "name": entry.name,
+                "mime": entry.mime,
+                "size_bytes": entry.size_bytes,
+            });
+            match mode {
+                AttachmentMode::Files => {
+                    value["path"] = json!(files.get(index).cloned().flatten());
+                }
+                AttachmentMode::Embed => value["data_base64"] = json!(entry.inline_base64),
+                AttachmentMode::Skip => {}
+            }
+            value
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "id": row.message_id,
+        "thread_id": row.thread_id,
+        "direction": row.direction,
+        "source": row.source,
+        "destination": row.destination,
+        "timestamp": row.ts_ms,
+        "title": row.title,
+        "content": row.body,
+        "receipt_status": row.receipt_status,
+        "fields": fields,
+        "attachments": attachments,
+    })
+}
+
+fn markdown_block(
+    message: &ExportMessage,
+    sender: &str,
+    mode: AttachmentMode,
+    files: &[Option<String>],
+) -> String {
+    let row = &message.row;
+    let mut out = format!("### {} · {sender}\n\n", format_utc(row.ts_ms, false));
+    if !row.title.trim().is_empty() {
+        out.push_str(&format!("**{}**\n\n", row.title.trim()));
+    }
+    if !row.body.trim().is_empty() {
+        out.push_str(row.body.trim_end());
+        out.push_str("\n\n");
+    }
+    for (index, entry) in message.attachments.iter().enumerate() {
+        let target = match mode {
+            AttachmentMode::Files => files.get(index).cloned().flatten(),
+            AttachmentMode::Embed => entry.inline_base64.as_ref().map(|data| {
+                format!(
+                    "data:{};base64,{data}",
+                    entry.mime.as_deref().unwrap_or("application/octet-stream")
+                )
+            }),
+            AttachmentMode::Skip => None,
+        };
+        match target {
+            Some(target) => out.push_str(&format!("- Attachment: [{}](<{target}>)\n", entry.name)),
+            None => out.push_str(&format!("- Attachment: `{}`\n", entry.name)),
+        }
+    }
+    if !message.attachments.is_empty() {
+        out.push('\n');
+    }
+    out.push_str("---\n\n");
+    out
+}
+
+fn rfc5322_message(
+    message: &ExportMessage,
+    peer_name: &str,
+    mode: AttachmentMode,
+    files: &[Option<String>],
+) -> String {
+    let row = &message.row;
+    let (from, to) = if row.direction == "out" {
+        (("Me", &row.source), (peer_name, &row.destination))
+    } else {
+        ((peer_name, &row.source), ("Me", &row.destination))
+    };
+    let subject = if row.title.trim().is_empty() {
+        first_line_excerpt(&row.body)
+    } else {
+        row.title.trim().to_string()
+    };
+    let mut out = String::new();
+    out.push_str(&format!("From: {}\n", mailbox(from.0, from.1)));
+    out.push_str(&format!("To: {}\n", mailbox(to.0, to.1)));
+    out.push_str(&format!("Date: {}\n", format_utc(row.ts_ms, true)));
+    out.push_str(&format!("Subject: {}\n", encode_header_text(&subject)));
+    out.push_str(&format!(
+        "Message-ID: <{}@lxmf>\n",
+        safe_header_token(&row.message_id)
+    ));
+    out.push_str(&format!(
+        "X-LXMF-Direction: {}\n",
+        safe_header_token(&row.direction)
+    ));
+    if let Some(status) = row.receipt_status.as_deref() {
+        out.push_str(&format!(
+            "X-LXMF-Receipt-Status: {}\n",
+            encode_header_text(status)
+        ));
+    }
+    out.push_str("MIME-Version: 1.0\n");
+
+    let mut body = row.body.clone();
+    if mode == AttachmentMode::Files {
+        for (index, entry) in message.attachments.iter().enumerate() {
+            let location = files.get(index).cloned().flatten();
+            body.push_str(&format!(
+                "\n[Attachment: {}{}]",
+                entry.name,
+                location
+                    .map(|path| format!(" -> {path}"))
+                    .unwrap_or_default()
+            ));
+        }
+    }
+    let embedded = message
+        .attachments
+        .iter()
+        .filter(|entry| mode == AttachmentMode::Embed && entry.inline_base64.is_some())
+        .collect::<Vec<_>>();
+    if embedded.is_empty() {
+        out.push_str("Content-Type: text/plain; charset=utf-8\n");
+        out.push_str("Content-Transfer-Encoding: base64\n\n");
+        out.push_str(&wrap_base64(&encode_bytes_base64(body.as_bytes())));
+        return out;
+    }
+
+    let boundary = format!("weft-{}", safe_header_token(&row.message_id));
+    out.push_str(&format!(
+        "Content-Type: multipart/mixed; boundary=\"{boundary}\"\n\n"
+    ));
+    out.push_str(&format!("--{boundary}\n"));
+    out.push_str("Content-Type: text/plain; charset=utf-8\n");
+    out.push_str("Content-Transfer-Encoding: base64\n\n");
+    out.push_str(&wrap_base64(&encode_bytes_base64(body.as_bytes())));
+    for entry in embedded {
+        let name = encode_header_text(&entry.name).replace('"', "'");
+        out.push_str(&format!("--{boundary}\n"));
+        out.push_str(&format!(
+            "Content-Type: {}; name=\"{name}\"\n",
+            safe_header_token(entry.mime.as_deref().unwrap_or("application/octet-stream"))
+        ));
+        out.push_str(&format!(
+            "Content-Disposition: attachment; filename=\"{name}\"\n"
+        ));
+        out.push_str("Content-Transfer-Encoding: base64\n\n");
+        out.push_str(&wrap_base64(
+            entry.inline_base64.as_deref().unwrap_or_default(),
+        ));
+    }
+    out.push_str(&format!("--{boundary}--\n"));
+    out
+}
+
+fn mailbox(name: &str, hash: &str) -> String {
+    let display = encode_header_text(name);
+    let address = format!("{}@lxmf", safe_header_token(hash));
+    if display.starts_with("=?") {
+        format!("{display} <{address}>")
+    } else {
+        format!("\"{}\" <{address}>", display.replace(['"', '\\'], ""))
+    }
+}
+
+// Header values come from remote peers: fold line breaks away and use RFC 2047
+// encoded-words for anything outside printable ASCII.
+fn encode_header_text(value: &str) -> String {
+    let flat = value
+        .chars()
+        .map(|ch| if ch.is_control() { ' ' } else { ch })
+        .collect::<String>();
+    if flat
+        .chars()
+        .all(|ch| ch.is_ascii() && !ch.is_ascii_control())
+    {
+        return flat;
+    }
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for ch in flat.chars() {
+        // 45 input bytes keep each encoded-word within the 75 character limit.
+        if chunk.len() + ch.len_utf8() > 45 {
+            words.push(format!(
+                "=?UTF-8?B?{}?=",
+                encode_bytes_base64(chunk.as_bytes())
+            ));
+            chunk.clear();
+        }
+        chunk.push(ch);
+    }
+    if !chunk.is_empty() {
+        words.push(format!(
+            "=?UTF-8?B?{}?=",
+            encode_bytes_base64(chunk.as_bytes())
+        ));
+    }
+    words.join("\n ")
+}
+
+fn safe_header_token(value: &str) -> String {
+    value
+        .chars()
+        .filter(|ch| ch.is_ascii_graphic() && !matches!(ch, '<' | '>' | '"' | '\\'))
+        .collect()
+}
+
+fn first_line_excerpt(body: &str) -> String {
+    let line = body
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("");
+    let excerpt = line.trim().chars().take(78).collect::<String>();
+    if excerpt.is_empty() {
+        "(no subject)".to_string()
+    } else {
+        excerpt
+    }
+}
+
+fn wrap_base64(data: &str) -> String {
+    let mut out = String::with_capacity(data.len() + data.len() / MIME_LINE_WIDTH + 2);
+    for line in data.as_bytes().chunks(MIME_LINE_WIDTH) {
+        out.push_str(&String::from_utf8_lossy(line));
+        out.push('\n');
+    }
+    out
+}
+
+// Attachment names and message ids are attacker-controlled; keep them to a
+// single path component without separators or reserved characters.
+fn safe_file_name(value: &str) -> String {
+    let cleaned = value
+        .chars()
+        .map(|ch| match ch {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            ch if ch.is_control() => '_',
+            ch => ch,
+        })
+        .collect::<String>();
+    let trimmed = cleaned
+        .trim()
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' ']);
+    if trimmed.is_empty() {
+        "attachment".to_string()
+    } else {
+        trimmed.chars().take(120).collect()
+    }
+}
+
+fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination.as_os_str().to_os_string();
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+fn attachment_root(destination: &Path) -> PathBuf {
+    destination.with_extension("attachments")
+}
+
+// Removes what a failed export wrote. The destination and the attachment
+// directory did not exist when it started, so everything found there is its own.
+fn discard_partial_export(format: ExportFormat, attachments: AttachmentMode, destination: &Path) {
+    let removed = match format {
+        ExportFormat::Jsonl | ExportFormat::Markdown => {
+            std::fs::remove_file(partial_path(destination))
+        }
+        ExportFormat::Maildir => std::fs::remove_dir_all(destination),
+    };
+    if let Err(err) = ignore_missing(removed) {
+        log::warn!("remove partial export failed: {err}");
+    }
+    if attachments == AttachmentMode::Files {
+        let removed = std::fs::remove_dir_all(attachment_root(destination));
+        if let Err(err) = ignore_missing(removed) {
+            log::warn!("remove partial export attachments failed: {err}");
+        }
+    }
+}
+
+fn ignore_missing(result: std::io::Result<()>) -> std::io::Result<()> {
+    match result {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+// Formats a UTC timestamp either as RFC 5322 (`Tue, 01 Jul 2025 12:00:00 +0000`)
+// or as `2025-07-01 12:00:00 UTC`.
+fn format_utc(timestamp_ms: i64, rfc5322: bool) -> String {
+    let seconds = timestamp_ms.div_euclid(1000);
+    let days = seconds.div_euclid(86_400);
+    let second_of_day = seconds.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second) = (
+        second_of_day / 3600,
+        second_of_day % 3600 / 60,
+        second_of_day % 60,
+    );
+    if !rfc5322 {
+        return format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02} UTC");
+    }
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    format!(
+        "{}, {day:02} {} {year:04} {hour:02}:{minute:02}:{second:02} +0000",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        MONTHS[(month - 1) as usize]
+    )
+}
+
+// Inverse of `search_query::days_from_civil` (Howard Hinnant's algorithm).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seeded_store() -> (tempfile::TempDir, IndexStore) {
+        let (dir, store) = test_store();
+        let mut status = with_fields(
+            inbound("m1", "peer-a", 1_751_371_200, "All clear at the ridge"),
+            json!({ "attachments": [{ "name": "../map.png", "mime": "image/png",
+                                      "inline_base64": "iVBORw==" }] }),
+        );
+        status["title"] = json!("Status");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    status,
+                    outbound(
+                        "m2",
+                        "peer-a",
+                        1_751_371_260,
+                        "Ack, héading back\r\nBcc: evil"
+                    ),
+                ]),
+                &json!([{ "peer": "peer-a", "name": "Ridge Team" }]),
+            )
+            .expect("seed index");
+        (dir, store)
+    }
+
+    fn params(format: &str, destination: PathBuf, attachments: Option<&str>) -> ExportParams {
+        ExportParams {
+            thread_id: Some("peer-a".to_string()),
+            query: None,
+            format: format.to_string(),
+            destination,
+            attachments: attachments.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn exports_thread_as_jsonl_and_markdown_with_attachment_files() {
+        let (dir, store) = seeded_store();
+
+        let jsonl_path = dir.path().join("out").join("thread.jsonl");
+        let summary = store
+            .export_messages(params("jsonl", jsonl_path.clone(), None))
+            .expect("jsonl export");
+        assert_eq!(summary.messages, 2);
+        assert_eq!(summary.attachments, 1);
+        let text = std::fs::read_to_string(&jsonl_path).expect("read jsonl");
+        let lines = text
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).expect("json line"))
+            .collect::<Vec<_>>();
+        assert_eq!(lines[0]["id"], "m1");
+        assert_eq!(lines[1]["id"], "m2");
+        let path = lines[0]["attachments"][0]["path"].as_str().expect("path");
+        assert_eq!(path, "thread.attachments/m1/0-_map.png");
+        let exported = std::fs::read(jsonl_path.parent().expect("parent").join(path))
+            .expect("attachment file");
+        assert_eq!(exported, b"\x89PNG");
+
+        let markdown_path = dir.path().join("out").join("notes.md");
+        store
+            .export_messages(params("markdown", markdown_path.clone(), Some("none")))
+            .expect("markdown export");
+        let markdown = std::fs::read_to_string(&markdown_path).expect("read markdown");
+        assert!(markdown.starts_with("# Conversation with Ridge Team"));
+        assert!(markdown.contains("### 2025-07-01 12:00:00 UTC · Ridge Team"));
+        assert!(markdown.contains("- Attachment: `../map.png`"));
+        assert!(!markdown_path.with_extension("attachments").exists());
+
+        assert!(store
+            .export_messages(params("markdown", markdown_path, None))
+            .is_err());
+    }
+
+    #[test]
+    fn failed_exports_leave_nothing_behind() {
+        let (dir, store) = seeded_store();
+
+        for (format, name) in [("markdown", "notes.md"), ("maildir", "Export")] {
+            let destination = dir.path().join(name);
+            let result = store.export_messages(ExportParams {
+                thread_id: None,
+                query: Some("ridge \"unterminated".to_string()),
+                format: format.to_string(),
+                destination: destination.clone(),
+                attachments: Some("files".to_string()),
+            });
+            assert!(result.is_err(), "{format} export must fail");
+            assert!(!destination.exists());
+            assert!(!partial_path(&destination).exists());
+            assert!(!attachment_root(&destination).exists());
+        }
+
+        let destination = dir.path().join("thread.jsonl");
+        std::fs::create_dir(attachment_root(&destination)).expect("attachment dir");
+        assert!(store
+            .export_messages(params("jsonl", destination.clone(), None))
+            .is_err());
+        assert!(!destination.exists());
+        store
+            .export_messages(params("jsonl", destination.clone(), Some("none")))
+            .expect("export without attachment files");
+    }
+
+    #[test]
+    fn exports_leave_out_amendments_reactions_and_deleted_messages() {
+        let (dir, store) = test_store();
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    inbound("m1", "peer-a", 1_751_371_200, "All clear at the ridge"),
+                    outbound("m2", "peer-a", 1_751_371_260, "Heading back"),
+                    with_fields(
+                        inbound(
+                            "e1",
+                            "peer-a",
+                            1_751_371_300,
+                            &edit_fallback_body("All clear at the pass")
+                        ),
+                        json!({ "16": { "edit_of": "m1" } }),
+                    ),
+                    with_fields(
+                        inbound("r1", "peer-a", 1_751_371_320, ""),
+                        json!({ "16": { "reaction_to": "m2", "emoji": "👍" } }),
+                    ),
+                    with_fields(
+                        outbound("d1", "peer-a", 1_751_371_340, delete_fallback_body()),
+                        json!({ "16": { "delete_of": "m2" } }),
+                    ),
+                ]),
+                &json!([]),
+            )
+            .expect("amend index");
+
+        let jsonl_path = dir.path().join("thread.jsonl");
+        let summary = store
+            .export_messages(params("jsonl", jsonl_path.clone(), Some("none")))
+            .expect("jsonl export");
+        assert_eq!(summary.messages, 1);
+        let text = std::fs::read_to_string(&jsonl_path).expect("read jsonl");
+        let line = serde_json::from_str::<Value>(text.trim()).expect("json line");
+        assert_eq!(line["id"], "m1");
+        assert_eq!(line["content"], "All clear at the pass");
+    }
+
+    #[test]
+    fn exports_search_results_to_maildir_with_mime_attachments() {
+        let (dir, store) = seeded_store();
+
+        let maildir = dir.path().join("Export");
+        let summary = store
+            .export_messages(ExportParams {
+                thread_id: None,
+                query: Some("ridge".to_string()),
+                format: "maildir".to_string(),
+                destination: maildir.clone(),
+                attachments: None,
+            })
+            .expect("maildir export");
+        assert_eq!(summary.messages, 1);
+        let entries = std::fs::read_dir(maildir.join("cur"))
+            .expect("cur")
+            .flatten()
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+        let mail = std::fs::read_to_string(entries[0].path()).expect("read mail");
+        assert!(mail.contains("From: \"Ridge Team\" <peer-a@lxmf>\n"));
+        assert!(mail.contains("Date: Tue, 01 Jul 2025 12:00:00 +0000\n"));
+        assert!(mail.contains("Subject: Status\n"));
+        assert!(mail.contains("Content-Type: multipart/mixed; boundary=\"weft-m1\""));
+        assert!(mail.contains("Content-Disposition: attachment; filename=\"../map.png\""));
+        assert!(mail.contains("iVBORw=="));
+
+        let thread_maildir = dir.path().join("Thread");
+        store
+            .export_messages(params("maildir", thread_maildir.clone(), None))
+            .expect("thread maildir export");
+        let outbound = std::fs::read_dir(thread_maildir.join("cur"))
+            .expect("cur")
+            .flatten()
+            .map(|entry| std::fs::read_to_string(entry.path()).expect("read mail"))
+            .find(|mail| mail.contains("X-LXMF-Direction: out\n"))
+            .expect("outbound mail");
+        assert!(outbound.contains("From: \"Me\" <self@lxmf>\n"));
+        assert!(outbound.contains("To: \"Ridge Team\" <peer-a@lxmf>\n"));
+
+        let header = encode_header_text("Ack, héading back\r\nBcc: evil");
+        assert!(header.starts_with("=?UTF-8?B?"));
+        assert!(!header.contains('\r'));
+        assert_eq!(safe_file_name("..\\..//etc/passwd"), "_..__etc_passwd");
+    }
+}
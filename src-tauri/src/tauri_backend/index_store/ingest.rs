@@ -1,13 +1,14 @@
 use super::*;
 
 const REINDEX_BATCH_SIZE: usize = 500;
+const INCREMENTAL_THREAD_REFRESH_LIMIT: usize = 64;
 
 impl IndexStore {
     pub(crate) fn reindex_from_runtime_payloads(
         &self,
         messages_payload: &Value,
         peers_payload: &Value,
-    ) -> Result<(), String> {
+    ) -> Result<SyncSummary, String> {
         let messages = parse_message_list(messages_payload)?;
         let peers = parse_peer_list(peers_payload);
         self.ingest_messages_and_peers(messages, &peers)
@@ -28,6 +29,13 @@ impl IndexStore {
             return self.apply_receipt_event(payload);
         }
 
+        if event_type == "announce_received" {
+            self.record_announces(&Value::Array(vec![payload.clone()]))?;
+        }
+        if event_type == "announce_received" || event_type == "peer_sync" {
+            return self.apply_peer_event(payload, event_type == "announce_received");
+        }
+
         if event_type == "inbound" || event_type == "outbound" {
             let message = payload.get("message").unwrap_or(payload);
             let parsed = match parse_message_row(message) {
@@ -38,13 +46,17 @@ impl IndexStore {
                 .conn
                 .lock()
                 .map_err(|_| "index lock poisoned".to_string())?;
+            if is_message_tombstoned(&conn, &parsed.row.message_id)? {
+                return Ok(());
+            }
             let tx = conn
                 .transaction()
                 .map_err(|err| format!("start event ingest transaction failed: {err}"))?;
-            upsert_message_row(&tx, &parsed)?;
+            upsert_message_row(&tx, &parsed, self.blobs.as_ref())?;
             tx.commit()
                 .map_err(|err| format!("commit event ingest failed: {err}"))?;
-            upsert_thread_summary_for_thread(&mut conn, &parsed.row.thread_id)?;
+            blobs::collect_unreferenced_blobs(&conn, self.blobs.as_ref())?;
+            upsert_thread_summary_for_thread(&conn, &parsed.row.thread_id)?;
             update_last_sync_state(
                 &mut conn,
                 parsed.row.ts_ms,
@@ -60,63 +72,169 @@ impl IndexStore {
         &self,
         messages: &[Value],
         peers: &[PeerSummary],
-    ) -> Result<(), String> {
+    ) -> Result<SyncSummary, String> {
+        let mut parsed = Vec::with_capacity(messages.len());
+        // Rows the runtime still holds but that did not parse are kept as they
+        // are rather than being swept as stale.
+        let mut unparsed_ids = Vec::new();
+        for value in messages {
+            match parse_message_row(value) {
+                Ok(entry) => parsed.push(entry),
+                Err(err) => {
+                    let message_id = value.get("id").and_then(Value::as_str);
+                    log::warn!(
+                        "index sync skipped unparsable message id={}: {err}",
+                        message_id.unwrap_or("<none>")
+                    );
+                    unparsed_ids.extend(message_id.map(str::to_string));
+                }
+            }
+        }
+        parsed.sort_by(|left, right| {
+            (left.row.ts_ms, &left.row.message_id).cmp(&(right.row.ts_ms, &right.row.message_id))
+        });
+
         let mut conn = self
             .conn
             .lock()
             .map_err(|_| "index lock poisoned".to_string())?;
-        conn.execute_batch(
-            "
-            BEGIN IMMEDIATE;
-            DELETE FROM attachments;
-            DELETE FROM messages;
-            COMMIT;
-            ",
-        )
-        .map_err(|err| format!("clear tables for reindex failed: {err}"))?;
+        let mut indexed = load_indexed_sync_markers(&conn)?;
+        for message_id in &unparsed_ids {
+            indexed.remove(message_id);
+        }
+        // Tombstones the runtime no longer reports are dropped after this sync.
+        let mut stale_tombstones = load_message_tombstones(&conn)?;
+        let resume_from = read_sync_cursor(&conn)?;
+        let mut summary = SyncSummary {
+            resumed: resume_from.is_some(),
+            ..SyncSummary::default()
+        };
+        if let Some((ts_ms, message_id)) = resume_from.as_ref() {
+            log::info!("index sync resuming after ts_ms={ts_ms} message_id={message_id}");
+        }
 
-        let mut latest_ts = 0_i64;
-        let mut latest_id: Option<String> = None;
+        let mut touched_threads = BTreeSet::new();
         let mut batch_count = 0_usize;
         let mut tx = conn
             .transaction()
-            .map_err(|err| format!("start reindex batch transaction failed: {err}"))?;
+            .map_err(|err| format!("start sync batch transaction failed: {err}"))?;
 
-        for value in messages {
-            let parsed = match parse_message_row(value) {
-                Ok(parsed) => parsed,
-                Err(_) => continue,
-            };
-            upsert_message_row(&tx, &parsed)?;
-            if latest_id.is_none() || parsed.row.ts_ms >= latest_ts {
-                latest_ts = parsed.row.ts_ms;
-                latest_id = Some(parsed.row.message_id.clone());
+        for entry in &parsed {
+            if stale_tombstones.remove(&entry.row.message_id) {
+                continue;
+            }
+            let previous = indexed.remove(&entry.row.message_id);
+            // An interrupted sync already committed everything up to its
+            // cursor, so indexed rows at or before it are not compared again.
+            let committed = resume_from.as_ref().is_some_and(|(ts_ms, message_id)| {
+                (entry.row.ts_ms, &entry.row.message_id) <= (*ts_ms, message_id)
+            });
+            if previous.is_some() && committed {
+                summary.unchanged += 1;
+                continue;
             }
+            if let Some((thread_id, marker)) = previous.as_ref() {
+                if marker.as_deref() == Some(entry.sync_marker.as_str()) {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                if thread_id != &entry.row.thread_id {
+                    touched_threads.insert(thread_id.clone());
+                }
+            }
+            upsert_message_row(&tx, entry, self.blobs.as_ref())?;
+            touched_threads.insert(entry.row.thread_id.clone());
+            summary.upserted += 1;
             batch_count += 1;
             if batch_count >= REINDEX_BATCH_SIZE {
+                write_sync_cursor(&tx, entry.row.ts_ms, &entry.row.message_id)?;
                 tx.commit()
-                    .map_err(|err| format!("commit reindex batch failed: {err}"))?;
+                    .map_err(|err| format!("commit sync batch failed: {err}"))?;
                 tx = conn
                     .transaction()
-                    .map_err(|err| format!("start reindex batch transaction failed: {err}"))?;
+                    .map_err(|err| format!("start sync batch transaction failed: {err}"))?;
                 batch_count = 0;
             }
         }
 
+        // Whatever is left in `indexed` no longer exists in the runtime.
+        for (message_id, (thread_id, _)) in &indexed {
+            tx.execute(
+                "DELETE FROM attachments WHERE message_id = ?1",
+                params![message_id],
+            )
+            .map_err(|err| format!("delete stale attachments failed: {err}"))?;
+            tx.execute(
+                "DELETE FROM messages WHERE message_id = ?1",
+                params![message_id],
+            )
+            .map_err(|err| format!("delete stale message failed: {err}"))?;
+            touched_threads.insert(thread_id.clone());
+            summary.deleted += 1;
+        }
+        for message_id in &stale_tombstones {
+            tx.execute(
+                "DELETE FROM message_tombstones WHERE message_id = ?1",
+                params![message_id],
+            )
+            .map_err(|err| format!("delete stale tombstone failed: {err}"))?;
+        }
+        tx.execute("DELETE FROM sync_state WHERE key = 'sync_cursor'", [])
+            .map_err(|err| format!("clear sync cursor failed: {err}"))?;
         tx.commit()
-            .map_err(|err| format!("commit reindex transaction failed: {err}"))?;
-        rebuild_threads_table(&mut conn)?;
-        apply_peer_names_to_threads(&mut conn, peers)?;
+            .map_err(|err| format!("commit sync transaction failed: {err}"))?;
+        blobs::collect_unreferenced_blobs(&conn, self.blobs.as_ref())?;
 
-        let sync_ts = if latest_id.is_some() {
-            latest_ts
+        summary.threads_updated = touched_threads.len();
+        if touched_threads.len() > INCREMENTAL_THREAD_REFRESH_LIMIT {
+            rebuild_threads_table(&mut conn)?;
         } else {
-            current_timestamp_ms()
-        };
+            for thread_id in &touched_threads {
+                upsert_thread_summary_for_thread(&conn, thread_id)?;
+            }
+        }
+        apply_peer_names_to_threads(&mut conn, peers)?;
 
+        let (sync_ts, latest_id) = match parsed.last() {
+            Some(latest) => (latest.row.ts_ms, Some(latest.row.message_id.clone())),
+            None => (current_timestamp_ms(), None),
+        };
         update_last_sync_state(&mut conn, sync_ts, latest_id)?;
         self.ready.store(true, Ordering::Relaxed);
-        Ok(())
+        Ok(summary)
+    }
+
+    // Announces carry one peer at the top level; peer syncs may batch them.
+    // An announce means the peer was just heard, so a missing timestamp means
+    // now. A peer sync only reports on a sync we asked for, so it updates
+    // contacts with what it carries and never counts as a sighting itself.
+    fn apply_peer_event(&self, payload: &Value, announced: bool) -> Result<(), String> {
+        let mut peers = match payload.get("peers") {
+            Some(list) => parse_peer_list(list),
+            None => parse_peer_list(&Value::Array(vec![payload.clone()])),
+        };
+        if announced {
+            let now_ms = current_timestamp_ms();
+            for peer in &mut peers {
+                peer.last_seen_ms.get_or_insert(now_ms);
+            }
+        } else {
+            peers.retain(|peer| {
+                peer.name.is_some()
+                    || peer.capabilities.is_some()
+                    || peer.first_seen_ms.is_some()
+                    || peer.last_seen_ms.is_some()
+            });
+        }
+        if peers.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        apply_peer_names_to_threads(&mut conn, &peers)
     }
 
     fn apply_receipt_event(&self, payload: &Value) -> Result<(), String> {
@@ -136,12 +254,20 @@ impl IndexStore {
             .map(str::trim)
             .filter(|value| !value.is_empty())
             .map(str::to_string);
+        let classified = delivery::classify_status(status.as_deref());
         let reason_code = payload
             .get("reason_code")
             .and_then(Value::as_str)
             .map(str::trim)
             .filter(|value| !value.is_empty())
-            .map(str::to_string);
+            .map(str::to_string)
+            .or_else(|| classified.as_ref().and_then(|(_, reason)| reason.clone()));
+        let receipt_ms = payload
+            .get("timestamp")
+            .and_then(Value::as_f64)
+            .filter(|value| *value > 0.0)
+            .map(normalize_timestamp_ms)
+            .unwrap_or_else(current_timestamp_ms);
 
         let mut conn = self
             .conn
@@ -156,6 +282,22 @@ impl IndexStore {
             .optional()
             .map_err(|err| format!("read thread for receipt update failed: {err}"))?;
 
+        // A receipt the state machine rejects (say, a late failure for a
+        // delivered message) is stale and must not overwrite the status.
+        if let Some((state, _)) = classified {
+            let recorded = delivery::record_delivery_transition(
+                &conn,
+                message_id,
+                state,
+                receipt_ms,
+                reason_code.as_deref(),
+                status.as_deref(),
+                "receipt",
+            )?;
+            if !recorded && delivery::current_delivery_state(&conn, message_id)? != Some(state) {
+                return Ok(());
+            }
+        }
         conn.execute(
             "
             UPDATE messages
@@ -170,7 +312,7 @@ impl IndexStore {
         .map_err(|err| format!("apply receipt update failed: {err}"))?;
 
         if let Some(thread_id) = thread_id.as_deref() {
-            upsert_thread_summary_for_thread(&mut conn, thread_id)?;
+            upsert_thread_summary_for_thread(&conn, thread_id)?;
         }
         update_last_sync_state(
             &mut conn,
@@ -181,3 +323,298 @@ impl IndexStore {
         Ok(())
     }
 }
+
+fn load_indexed_sync_markers(
+    conn: &Connection,
+) -> Result<HashMap<String, (String, Option<String>)>, String> {
+    let mut stmt = conn
+        .prepare("SELECT message_id, thread_id, sync_marker FROM messages")
+        .map_err(|err| format!("prepare sync marker query failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                (row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?),
+            ))
+        })
+        .map_err(|err| format!("query sync markers failed: {err}"))?;
+    let mut out = HashMap::new();
+    for row in rows {
+        let (message_id, entry) = row.map_err(|err| format!("parse sync marker failed: {err}"))?;
+        out.insert(message_id, entry);
+    }
+    Ok(out)
+}
+
+fn load_message_tombstones(conn: &Connection) -> Result<BTreeSet<String>, String> {
+    let mut stmt = conn
+        .prepare("SELECT message_id FROM message_tombstones")
+        .map_err(|err| format!("prepare tombstone query failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(|err| format!("query tombstones failed: {err}"))?;
+    rows.collect::<Result<BTreeSet<_>, _>>()
+        .map_err(|err| format!("parse tombstone failed: {err}"))
+}
+
+fn is_message_tombstoned(conn: &Connection, message_id: &str) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT 1 FROM message_tombstones WHERE message_id = ?1",
+        params![message_id],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|value| value.is_some())
+    .map_err(|err| format!("read tombstone failed: {err}"))
+}
+
+fn read_sync_cursor(conn: &Connection) -> Result<Option<(i64, String)>, String> {
+    let value = conn
+        .query_row(
+            "SELECT value FROM sync_state WHERE key = 'sync_cursor'",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .map_err(|err| format!("read sync cursor failed: {err}"))?;
+    Ok(value.and_then(|value| {
+        let (ts_ms, message_id) = value.split_once(':')?;
+        Some((ts_ms.parse::<i64>().ok()?, message_id.to_string()))
+    }))
+}
+
+fn write_sync_cursor(
+    tx: &rusqlite::Transaction<'_>,
+    ts_ms: i64,
+    message_id: &str,
+) -> Result<(), String> {
+    tx.execute(
+        "INSERT INTO sync_state(key, value) VALUES('sync_cursor', ?1)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![format!("{ts_ms}:{message_id}")],
+    )
+    .map_err(|err| format!("update sync cursor failed: {err}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, peer: &str, timestamp: i64, content: &str) -> Value {
+        json!({
+            "id": id,
+            "source": peer,
+            "destination": "self",
+            "direction": "in",
+            "timestamp": timestamp,
+            "content": content,
+        })
+    }
+
+    fn open_store() -> (tempfile::TempDir, IndexStore) {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        (dir, store)
+    }
+
+    #[test]
+    fn sync_upserts_changes_and_deletes_missing_messages() {
+        let (_dir, store) = open_store();
+        let first = store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    message("m1", "peer-a", 1_000, "one"),
+                    message("m2", "peer-a", 2_000, "two"),
+                    message("m3", "peer-b", 3_000, "three"),
+                ]),
+                &json!([]),
+            )
+            .expect("initial sync");
+        assert_eq!((first.upserted, first.unchanged, first.deleted), (3, 0, 0));
+
+        let second = store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    message("m1", "peer-a", 1_000, "one"),
+                    message("m2", "peer-a", 2_000, "two, edited"),
+                ]),
+                &json!([]),
+            )
+            .expect("incremental sync");
+        assert_eq!(
+            (second.upserted, second.unchanged, second.deleted),
+            (1, 1, 1)
+        );
+        assert!(!second.resumed);
+
+        let status = store.index_status().expect("status");
+        assert_eq!(status.message_count, 2);
+        assert_eq!(status.thread_count, 1);
+        let conn = store.conn.lock().expect("lock");
+        let preview: String = conn
+            .query_row(
+                "SELECT preview FROM threads WHERE thread_id = 'peer-a'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("thread summary");
+        assert_eq!(preview, "two, edited");
+    }
+
+    #[test]
+    fn sync_keeps_rows_that_fail_to_parse() {
+        let (_dir, store) = open_store();
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    message("m1", "peer-a", 1_000, "one"),
+                    message("m2", "peer-a", 2_000, "two"),
+                ]),
+                &json!([]),
+            )
+            .expect("initial sync");
+
+        let mut broken = message("m2", "peer-a", 2_000, "two");
+        broken["timestamp"] = json!("later");
+        let summary = store
+            .reindex_from_runtime_payloads(
+                &json!([message("m1", "peer-a", 1_000, "one"), broken]),
+                &json!([]),
+            )
+            .expect("sync with unparsable row");
+        assert_eq!((summary.unchanged, summary.deleted), (1, 0));
+        assert_eq!(store.index_status().expect("status").message_count, 2);
+    }
+
+    #[test]
+    fn force_reindex_keeps_rows_until_resync() {
+        let (_dir, store) = open_store();
+        let messages = json!([message("m1", "peer-a", 1_000, "one")]);
+        store
+            .reindex_from_runtime_payloads(&messages, &json!([]))
+            .expect("initial sync");
+
+        store.force_reindex().expect("force reindex");
+        assert_eq!(store.index_status().expect("status").message_count, 1);
+
+        let summary = store
+            .reindex_from_runtime_payloads(&messages, &json!([]))
+            .expect("resync");
+        assert_eq!((summary.upserted, summary.unchanged), (1, 0));
+    }
+
+    #[test]
+    fn announce_events_update_contacts_and_thread_names() {
+        let (_dir, store) = open_store();
+        let peer = "4444444444444444";
+        store
+            .reindex_from_runtime_payloads(&json!([message("m1", peer, 1_000, "hi")]), &json!([]))
+            .expect("initial sync");
+        let capability_payload =
+            rmp_serde::to_vec(&json!({ "caps": ["Topic_Broker", "attachments"] }))
+                .expect("encode capability payload");
+        let app_data = rmp_serde::to_vec(&(b"Hub Node".to_vec(), 1u32, capability_payload))
+            .expect("encode announce app-data");
+
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "announce_received",
+                "payload": { "peer": peer, "timestamp": 5_000, "app_data_hex": hex::encode(app_data) },
+            }))
+            .expect("ingest announce");
+        let contact = store.get_contact(peer).expect("contact");
+        assert_eq!(contact["announced_name"], "Hub Node");
+        assert_eq!(
+            contact["capabilities"],
+            json!(["topic_broker", "attachments"])
+        );
+        assert_eq!(contact["last_seen_ms"], 5_000_000);
+        let thread_name: String = store
+            .conn
+            .lock()
+            .expect("lock")
+            .query_row(
+                "SELECT display_name FROM threads WHERE thread_id = ?1",
+                params![peer],
+                |row| row.get(0),
+            )
+            .expect("thread");
+        assert_eq!(thread_name, "Hub Node");
+
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "peer_sync",
+                "payload": { "peers": [{ "peer": peer, "name": "Hub Node 2", "last_seen": 4_000 }] },
+            }))
+            .expect("ingest peer sync");
+        let contact = store.get_contact(peer).expect("contact");
+        assert_eq!(contact["display_name"], "Hub Node 2");
+        assert_eq!(
+            contact["capabilities"],
+            json!(["topic_broker", "attachments"])
+        );
+        assert_eq!(contact["last_seen_ms"], 5_000_000);
+
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "peer_sync",
+                "payload": { "peer": "5555555555555555", "synced": true },
+            }))
+            .expect("ingest bare peer sync");
+        let contacts: i64 = store
+            .conn
+            .lock()
+            .expect("lock")
+            .query_row("SELECT COUNT(*) FROM contacts", [], |row| row.get(0))
+            .expect("count contacts");
+        assert_eq!(contacts, 1);
+
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "peer_sync",
+                "payload": { "peer": peer, "name": "Hub Node 3" },
+            }))
+            .expect("ingest named peer sync");
+        let contact = store.get_contact(peer).expect("contact");
+        assert_eq!(contact["display_name"], "Hub Node 3");
+        assert_eq!(contact["last_seen_ms"], 5_000_000);
+    }
+
+    #[test]
+    fn sync_resumes_after_interrupted_cursor() {
+        let (_dir, store) = open_store();
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    message("m1", "peer-a", 1_000, "one"),
+                    message("m2", "peer-a", 2_000, "two"),
+                ]),
+                &json!([]),
+            )
+            .expect("initial sync");
+        {
+            let conn = store.conn.lock().expect("lock");
+            conn.execute(
+                "INSERT INTO sync_state(key, value) VALUES('sync_cursor', '1000000:m1')",
+                [],
+            )
+            .expect("seed cursor");
+        }
+
+        let summary = store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    message("m0", "peer-a", 500, "zero"),
+                    message("m1", "peer-a", 1_000, "one, edited"),
+                    message("m2", "peer-a", 2_000, "two, edited"),
+                ]),
+                &json!([]),
+            )
+            .expect("resumed sync");
+        assert!(summary.resumed);
+        assert_eq!((summary.upserted, summary.unchanged), (2, 1));
+        let conn = store.conn.lock().expect("lock");
+        assert!(read_sync_cursor(&conn).expect("cursor").is_none());
+    }
+}
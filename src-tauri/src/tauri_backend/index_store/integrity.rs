@@ -0,0 +1,369 @@
+use super::*;
+use rusqlite::ErrorCode;
+use std::path::Path;
+
+const FTS_TABLES: [&str; 2] = ["messages_fts", "messages_fts_trigram"];
+const REPAIR_THREAD_BATCH_SIZE: usize = 200;
+
+/// Why an on-disk index could not be opened. Only `Corrupt` files are moved
+/// aside; anything else (permissions, a newer schema) keeps the file intact.
+pub(super) enum OpenFailure {
+    Corrupt(String),
+    Other(String),
+}
+
+impl From<String> for OpenFailure {
+    fn from(value: String) -> Self {
+        Self::Other(value)
+    }
+}
+
+impl From<OpenFailure> for String {
+    fn from(value: OpenFailure) -> Self {
+        match value {
+            OpenFailure::Corrupt(reason) => format!("index database is corrupted: {reason}"),
+            OpenFailure::Other(reason) => reason,
+        }
+    }
+}
+
+impl IndexStore {
+    /// Runs `PRAGMA quick_check` and the FTS integrity checks, rebuilds the
+    /// search tables if they fail, fixes blob refcounts and re-derives every
+    /// thread summary from `messages`, and reports what was wrong. Page-level
+    /// damage cannot be fixed in place and is only reported.
+    pub(crate) fn verify_and_repair(&self) -> Result<IndexRepairReport, String> {
+        let (report, before, thread_ids) = {
+            let mut conn = self
+                .conn
+                .lock()
+                .map_err(|_| "index lock poisoned".to_string())?;
+            let report = self.repair_derived_tables(&mut conn)?;
+            let before = thread_snapshot(&conn)?;
+            let thread_ids = {
+                let mut stmt = conn
+                    .prepare("SELECT thread_id FROM threads UNION SELECT thread_id FROM messages")
+                    .map_err(|err| format!("prepare repair thread scan failed: {err}"))?;
+                let rows = stmt
+                    .query_map([], |row| row.get::<_, String>(0))
+                    .map_err(|err| format!("scan repair threads failed: {err}"))?;
+                rows.collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| format!("parse repair thread failed: {err}"))?
+            };
+            (report, before, thread_ids)
+        };
+
+        // Summaries are rebuilt a batch of threads at a time so syncs and
+        // queries can take the lock in between; each thread is re-derived
+        // whole, so a write landing between batches cannot leave it stale.
+        for batch in thread_ids.chunks(REPAIR_THREAD_BATCH_SIZE) {
+            let mut conn = self
+                .conn
+                .lock()
+                .map_err(|_| "index lock poisoned".to_string())?;
+            let tx = conn
+                .transaction()
+                .map_err(|err| format!("start thread repair failed: {err}"))?;
+            for thread_id in batch {
+                upsert_thread_summary_for_thread(&tx, thread_id)?;
+            }
+            tx.commit()
+                .map_err(|err| format!("commit thread repair failed: {err}"))?;
+        }
+
+        let after = {
+            let conn = self
+                .conn
+                .lock()
+                .map_err(|_| "index lock poisoned".to_string())?;
+            thread_snapshot(&conn)?
+        };
+        Ok(IndexRepairReport {
+            threads_fixed: before
+                .keys()
+                .chain(after.keys())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .filter(|thread_id| before.get(*thread_id) != after.get(*thread_id))
+                .count(),
+            ..report
+        })
+    }
+
+    fn repair_derived_tables(&self, conn: &mut Connection) -> Result<IndexRepairReport, String> {
+        let mut report = IndexRepairReport {
+            database_problems: quick_check(conn)
+                .map_err(|err| format!("quick_check failed: {err}"))?,
+            fts_problems: fts_problems(conn),
+            ..IndexRepairReport::default()
+        };
+        report.database_ok = report.database_problems.is_empty();
+
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start index repair failed: {err}"))?;
+        if !report.fts_problems.is_empty() {
+            for table in FTS_TABLES {
+                tx.execute_batch(&format!("INSERT INTO {table}({table}) VALUES('rebuild');"))
+                    .map_err(|err| format!("rebuild {table} failed: {err}"))?;
+            }
+            report.fts_rebuilt = true;
+        }
+        report.orphan_attachments_removed = tx
+            .execute(
+                "DELETE FROM attachments WHERE message_id NOT IN (SELECT message_id FROM messages)",
+                [],
+            )
+            .map_err(|err| format!("remove orphan attachments failed: {err}"))?;
+        report.blob_refcounts_fixed = tx
+            .execute(
+                "
+                UPDATE blobs
+                SET refcount = (SELECT COUNT(*) FROM attachments WHERE blob_sha256 = blobs.sha256)
+                WHERE refcount != (SELECT COUNT(*) FROM attachments WHERE blob_sha256 = blobs.sha256)
+                ",
+                [],
+            )
+            .map_err(|err| format!("recount blob references failed: {err}"))?;
+        tx.commit()
+            .map_err(|err| format!("commit index repair failed: {err}"))?;
+        report.blobs_removed = blobs::collect_unreferenced_blobs(conn, self.blobs.as_ref())?;
+        if let Some(blobs) = self.blobs.as_ref() {
+            let mut stmt = conn
+                .prepare("SELECT sha256 FROM blobs")
+                .map_err(|err| format!("prepare blob scan failed: {err}"))?;
+            let digests = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(|err| format!("scan blobs failed: {err}"))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("parse blob digest failed: {err}"))?;
+            report.missing_blob_files = digests
+                .iter()
+                .filter(|sha256| !blobs.contains(sha256))
+                .count();
+        }
+        Ok(report)
+    }
+}
+
+type ThreadSnapshotRow = (String, String, Option<String>, i64, i64);
+
+fn thread_snapshot(conn: &Connection) -> Result<BTreeMap<String, ThreadSnapshotRow>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT thread_id, display_name, preview, last_message_id, last_activity_ms, unread_count FROM threads",
+        )
+        .map_err(|err| format!("prepare thread snapshot failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                (
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                ),
+            ))
+        })
+        .map_err(|err| format!("read thread snapshot failed: {err}"))?;
+    rows.collect::<Result<BTreeMap<_, _>, _>>()
+        .map_err(|err| format!("parse thread snapshot failed: {err}"))
+}
+
+/// Startup check for a freshly opened connection: the first read surfaces
+/// files that are not databases at all, then `quick_check` covers b-tree and
+/// page damage. The slower FTS integrity check only runs once `quick_check`
+/// has failed, in case the damage is confined to the search tables.
+pub(super) fn check_database(conn: &Connection) -> Result<(), OpenFailure> {
+    match quick_check(conn) {
+        Ok(problems) if problems.is_empty() => Ok(()),
+        Ok(problems) => {
+            ensure_fts_consistent(conn)?;
+            match quick_check(conn) {
+                Ok(remaining) if remaining.is_empty() => Ok(()),
+                _ => Err(OpenFailure::Corrupt(problems.join("; "))),
+            }
+        }
+        Err(err) if is_corruption(&err) => Err(OpenFailure::Corrupt(err.to_string())),
+        Err(err) => Err(OpenFailure::Other(format!("quick_check failed: {err}"))),
+    }
+}
+
+/// The search tables are derived from `messages`, so a failed integrity check
+/// is repaired by rebuilding them rather than discarding the index.
+fn ensure_fts_consistent(conn: &Connection) -> Result<(), OpenFailure> {
+    let problems = fts_problems(conn);
+    if problems.is_empty() {
+        return Ok(());
+    }
+    log::warn!("rebuilding search index: {}", problems.join("; "));
+    for table in FTS_TABLES {
+        conn.execute_batch(&format!("INSERT INTO {table}({table}) VALUES('rebuild');"))
+            .map_err(|err| OpenFailure::Corrupt(format!("rebuild {table} failed: {err}")))?;
+    }
+    let remaining = fts_problems(conn);
+    if remaining.is_empty() {
+        Ok(())
+    } else {
+        Err(OpenFailure::Corrupt(remaining.join("; ")))
+    }
+}
+
+pub(super) fn is_corruption(err: &rusqlite::Error) -> bool {
+    matches!(
+        err.sqlite_error_code(),
+        Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)
+    )
+}
+
+/// Moves a corrupted database, its WAL sidecars and blob directory to
+/// `<stem>.corrupt-<ms>.<ext>` so a fresh index can take its place while the
+/// damaged copy stays available for inspection.
+pub(super) fn quarantine_index(path: &Path) -> Result<PathBuf, String> {
+    let stem = path
+        .file_stem()
+        .map(|value| value.to_string_lossy().to_string())
+        .unwrap_or_else(|| "index".to_string());
+    let extension = path
+        .extension()
+        .map(|value| value.to_string_lossy().to_string())
+        .unwrap_or_else(|| "sqlite3".to_string());
+    let target = path.with_file_name(format!(
+        "{stem}.corrupt-{}.{extension}",
+        current_timestamp_ms()
+    ));
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = path.as_os_str().to_os_string();
+        sidecar.push(suffix);
+        let sidecar = PathBuf::from(sidecar);
+        if sidecar.exists() {
+            let mut moved = target.as_os_str().to_os_string();
+            moved.push(suffix);
+            std::fs::rename(&sidecar, PathBuf::from(moved))
+                .map_err(|err| format!("quarantine index {suffix} file failed: {err}"))?;
+        }
+    }
+    let blobs = BlobStore::for_index(path);
+    if blobs.root().is_dir() {
+        std::fs::rename(blobs.root(), BlobStore::for_index(&target).root())
+            .map_err(|err| format!("quarantine index blobs failed: {err}"))?;
+    }
+    std::fs::rename(path, &target)
+        .map_err(|err| format!("quarantine index database failed: {err}"))?;
+    Ok(target)
+}
+
+fn quick_check(conn: &Connection) -> Result<Vec<String>, rusqlite::Error> {
+    let mut stmt = conn.prepare("PRAGMA quick_check")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut problems = Vec::new();
+    for row in rows {
+        let row = row?;
+        if row != "ok" {
+            problems.push(row);
+        }
+    }
+    Ok(problems)
+}
+
+// `rank = 1` also compares the index against the `messages` content table.
+fn fts_problems(conn: &Connection) -> Vec<String> {
+    FTS_TABLES
+        .iter()
+        .filter_map(|table| {
+            conn.execute_batch(&format!(
+                "INSERT INTO {table}({table}, rank) VALUES('integrity-check', 1);"
+            ))
+            .err()
+            .map(|err| format!("{table}: {err}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed(store: &IndexStore) {
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "m1", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 1_000, "content": "first light",
+                      "fields": { "attachments": [{ "name": "a.bin", "inline_base64": "eHl6" }] } },
+                    { "id": "m2", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 2_000, "content": "second light" },
+                ]),
+                &json!([{ "peer": "peer-a", "name": "Ridge Team" }]),
+            )
+            .expect("seed index");
+    }
+
+    #[test]
+    fn corrupted_index_is_quarantined_and_replaced() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("weft-index-v1.default.sqlite3");
+        std::fs::write(&path, vec![0x5a; 8192]).expect("write garbage");
+
+        let store = IndexStore::open_or_fallback(path.clone());
+        let status = store.index_status().expect("status");
+        assert!(status.schema_error.is_none());
+        assert_eq!(status.message_count, 0);
+        let quarantined = PathBuf::from(status.quarantined_path.expect("quarantined"));
+        assert_eq!(
+            std::fs::read(&quarantined).expect("moved file"),
+            vec![0x5a; 8192]
+        );
+        assert!(path.is_file());
+        seed(&store);
+        assert_eq!(store.index_status().expect("status").message_count, 2);
+    }
+
+    #[test]
+    fn verify_and_repair_rebuilds_search_threads_and_refcounts() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        seed(&store);
+        {
+            let conn = store.conn.lock().expect("lock");
+            conn.execute_batch(
+                "
+                INSERT INTO messages_fts(rowid, message_id, title, body) VALUES (999, 'ghost', '', 'phantom');
+                UPDATE threads SET unread_count = 42;
+                UPDATE blobs SET refcount = 7;
+                INSERT INTO threads (thread_id, display_name, preview, last_message_id, last_activity_ms, unread_count)
+                  VALUES ('peer-gone', 'Gone', '', 'missing', 1, 1);
+                ",
+            )
+            .expect("damage index");
+        }
+
+        let report = store.verify_and_repair().expect("repair");
+        assert!(report.database_ok);
+        assert_eq!(report.fts_problems.len(), 1);
+        assert!(report.fts_rebuilt);
+        assert_eq!(report.threads_fixed, 2);
+        assert_eq!(report.blob_refcounts_fixed, 1);
+        assert_eq!(report.missing_blob_files, 0);
+
+        let thread = store
+            .query_threads(ThreadQueryParams {
+                query: None,
+                limit: None,
+                cursor: None,
+                pinned_only: None,
+                archived: None,
+            })
+            .expect("threads")["items"][0]
+            .clone();
+        assert_eq!(thread["thread_id"], "peer-a");
+        assert_eq!(thread["name"], "Ridge Team");
+        assert_eq!(thread["unread"], 2);
+        let clean = store.verify_and_repair().expect("second pass");
+        assert!(clean.fts_problems.is_empty());
+        assert!(!clean.fts_rebuilt);
+        assert_eq!(clean.threads_fixed, 0);
+    }
+}
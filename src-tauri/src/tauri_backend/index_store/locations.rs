@@ -0,0 +1,397 @@
+use super::*;
+
+// Positions older than this are reported as stale unless the caller overrides it.
+const DEFAULT_STALE_AFTER_MS: i64 = 15 * 60 * 1000;
+const DEFAULT_TRACK_WINDOW_MS: i64 = 24 * 60 * 60 * 1000;
+const DEFAULT_TRACK_POINTS: usize = 500;
+const MAX_TRACK_POINTS: usize = 5000;
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+impl IndexStore {
+    // Only inbound points are used: outbound points carry our own position and
+    // are attributed to the destination peer.
+    pub(crate) fn query_peer_positions(
+        &self,
+        params: PeerPositionsQueryParams,
+    ) -> Result<Value, String> {
+        let stale_after_ms = match params.stale_after_ms {
+            Some(value) if value <= 0 => {
+                return Err("stale_after_ms must be positive".to_string());
+            }
+            Some(value) => value,
+            None => DEFAULT_STALE_AFTER_MS,
+        };
+        let peer = params
+            .peer
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+        let now_ms = current_timestamp_ms();
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        // Heading and speed compare the two newest fixes, so a message counts
+        // once (it can carry the same fix as telemetry and as a geo: link) and
+        // a fix repeated by another message counts once too.
+        let mut stmt = conn
+            .prepare(
+                "
+                WITH per_message AS (
+                  SELECT peer, message_id, ts_ms, lat, lon, label, MAX(id) AS id
+                  FROM map_points
+                  WHERE direction = 'in'
+                    AND (?1 IS NULL OR peer = ?1)
+                  GROUP BY message_id
+                ),
+                fixes AS (
+                  SELECT peer, message_id, ts_ms, lat, lon, label, MAX(id) AS id
+                  FROM per_message
+                  GROUP BY peer, ts_ms, lat, lon
+                ),
+                ranked AS (
+                  SELECT
+                    f.peer, f.message_id, f.ts_ms, f.lat, f.lon, f.label,
+                    ROW_NUMBER() OVER (PARTITION BY f.peer ORDER BY f.ts_ms DESC, f.id DESC) AS rank
+                  FROM fixes f
+                )
+                SELECT r.peer, t.display_name, r.message_id, r.ts_ms, r.lat, r.lon, r.label, r.rank
+                FROM ranked r
+                LEFT JOIN threads t ON t.thread_id = r.peer
+                WHERE r.rank <= 2
+                  AND (?2 IS NULL OR r.peer IN (
+                    SELECT peer FROM ranked WHERE rank = 1 AND ts_ms >= ?2
+                  ))
+                ORDER BY r.peer ASC, r.rank ASC
+                ",
+            )
+            .map_err(|err| format!("prepare peer positions query failed: {err}"))?;
+        let rows = stmt
+            .query_map(params![peer, params.since_ms], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, f64>(4)?,
+                    row.get::<_, f64>(5)?,
+                    row.get::<_, String>(6)?,
+                    row.get::<_, i64>(7)?,
+                ))
+            })
+            .map_err(|err| format!("query peer positions failed: {err}"))?;
+
+        let mut positions: Vec<PeerPosition> = Vec::new();
+        for row in rows {
+            let (peer, display_name, message_id, ts_ms, lat, lon, label, rank) =
+                row.map_err(|err| format!("read peer position row failed: {err}"))?;
+            if rank == 1 {
+                let age_ms = now_ms.saturating_sub(ts_ms).max(0);
+                positions.push(PeerPosition {
+                    display_name: display_name.unwrap_or_else(|| short_hash(&peer, 6)),
+                    peer,
+                    lat,
+                    lon,
+                    timestamp: ts_ms,
+                    age_ms,
+                    stale: age_ms > stale_after_ms,
+                    message_id,
+                    label,
+                    heading_deg: None,
+                    speed_mps: None,
+                });
+                continue;
+            }
+            let Some(latest) = positions.last_mut().filter(|item| item.peer == peer) else {
+                continue;
+            };
+            let elapsed_ms = latest.timestamp - ts_ms;
+            let distance_m = haversine_distance_m(lat, lon, latest.lat, latest.lon);
+            if distance_m > 0.0 {
+                latest.heading_deg = Some(initial_bearing_deg(lat, lon, latest.lat, latest.lon));
+            }
+            if elapsed_ms > 0 {
+                latest.speed_mps = Some(distance_m / (elapsed_ms as f64 / 1000.0));
+            }
+        }
+        positions.sort_by(|left, right| {
+            right
+                .timestamp
+                .cmp(&left.timestamp)
+                .then_with(|| left.peer.cmp(&right.peer))
+        });
+
+        Ok(json!({
+            "items": positions,
+            "stale_after_ms": stale_after_ms,
+            "now_ms": now_ms,
+        }))
+    }
+
+    pub(crate) fn query_peer_track(&self, params: PeerTrackQueryParams) -> Result<Value, String> {
+        let peer = params.peer.trim();
+        if peer.is_empty() {
+            return Err("peer is required".to_string());
+        }
+        let until_ms = params.until_ms.unwrap_or_else(current_timestamp_ms);
+        let since_ms = params
+            .since_ms
+            .unwrap_or_else(|| until_ms.saturating_sub(DEFAULT_TRACK_WINDOW_MS));
+        if since_ms > until_ms {
+            return Err("since_ms must not exceed until_ms".to_string());
+        }
+        let limit = params
+            .limit
+            .filter(|limit| *limit > 0)
+            .unwrap_or(DEFAULT_TRACK_POINTS)
+            .min(MAX_TRACK_POINTS);
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let display_name = conn
+            .query_row(
+                "SELECT display_name FROM threads WHERE thread_id = ?1",
+                params![peer],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(|err| format!("read track peer name failed: {err}"))?
+            .unwrap_or_else(|| short_hash(peer, 6));
+
+        // Newest points win when the window holds more than the limit, so the
+        // track always ends at the latest known position.
+        let mut stmt = conn
+            .prepare(
+                "
+                SELECT message_id, ts_ms, lat, lon
+                FROM map_points
+                WHERE peer = ?1
+                  AND direction = 'in'
+                  AND ts_ms >= ?2
+                  AND ts_ms <= ?3
+                ORDER BY ts_ms DESC, id DESC
+                LIMIT ?4
+                ",
+            )
+            .map_err(|err| format!("prepare peer track query failed: {err}"))?;
+        let rows = stmt
+            .query_map(
+                params![peer, since_ms, until_ms, (limit + 1) as i64],
+                |row| {
+                    Ok(TrackPoint {
+                        message_id: row.get::<_, String>(0)?,
+                        timestamp: row.get::<_, i64>(1)?,
+                        lat: row.get::<_, f64>(2)?,
+                        lon: row.get::<_, f64>(3)?,
+                    })
+                },
+            )
+            .map_err(|err| format!("query peer track failed: {err}"))?;
+        let mut points = Vec::new();
+        for row in rows {
+            points.push(row.map_err(|err| format!("read peer track row failed: {err}"))?);
+        }
+        let truncated = points.len() > limit;
+        points.truncate(limit);
+        points.reverse();
+        // A message can carry the same fix as telemetry and as a geo: link.
+        points.dedup_by(|next, previous| {
+            next.timestamp == previous.timestamp
+                && next.lat == previous.lat
+                && next.lon == previous.lon
+        });
+
+        let distance_m = points
+            .windows(2)
+            .map(|pair| haversine_distance_m(pair[0].lat, pair[0].lon, pair[1].lat, pair[1].lon))
+            .sum();
+
+        serde_json::to_value(PeerTrack {
+            peer: peer.to_string(),
+            display_name,
+            since_ms,
+            until_ms,
+            points,
+            distance_m,
+            truncated,
+        })
+        .map_err(|err| format!("encode peer track failed: {err}"))
+    }
+}
+
+fn haversine_distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lon2 - lon1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+}
+
+fn initial_bearing_deg(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lambda = (lon2 - lon1).to_radians();
+    let y = d_lambda.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * d_lambda.cos();
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed(store: &IndexStore) {
+        let now_s = current_timestamp_ms() / 1000;
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "a1", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": now_s - 600, "content": "geo:52.0,4.0" },
+                    { "id": "a2", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": now_s - 540, "content": "geo:52.01,4.0",
+                      "fields": { "2": { "lat": 52.01, "lon": 4.0 } } },
+                    { "id": "a3", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": now_s - 480, "content": "geo:52.02,4.0" },
+                    { "id": "b1", "source": "peer-b", "destination": "self", "direction": "in",
+                      "timestamp": now_s - 7_200, "content": "geo:-33.9,18.4" },
+                    { "id": "out", "source": "self", "destination": "peer-b", "direction": "out",
+                      "timestamp": now_s - 60, "content": "geo:10.0,10.0" },
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+    }
+
+    #[test]
+    fn peer_positions_report_latest_fix_age_and_staleness() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        seed(&store);
+
+        let result = store
+            .query_peer_positions(PeerPositionsQueryParams {
+                peer: None,
+                since_ms: None,
+                stale_after_ms: None,
+            })
+            .expect("positions");
+        let items = result["items"].as_array().expect("items");
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["peer"], "peer-a");
+        assert_eq!(items[0]["message_id"], "a3");
+        assert_eq!(items[0]["stale"], false);
+        let heading = items[0]["heading_deg"].as_f64().expect("heading");
+        assert!(!(1.0..=359.0).contains(&heading), "heading {heading}");
+        let speed = items[0]["speed_mps"].as_f64().expect("speed");
+        assert!((speed - 1112.0 / 60.0).abs() < 1.0, "speed {speed}");
+
+        assert_eq!(items[1]["peer"], "peer-b");
+        assert_eq!(items[1]["lat"], -33.9);
+        assert_eq!(items[1]["stale"], true);
+        assert!(items[1]["heading_deg"].is_null());
+
+        let recent = store
+            .query_peer_positions(PeerPositionsQueryParams {
+                peer: None,
+                since_ms: Some(current_timestamp_ms() - 3_600_000),
+                stale_after_ms: Some(60_000),
+            })
+            .expect("recent positions");
+        let items = recent["items"].as_array().expect("items");
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["stale"], true);
+    }
+
+    #[test]
+    fn peer_positions_ignore_repeated_fixes() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        let now_s = current_timestamp_ms() / 1000;
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "c1", "source": "peer-c", "destination": "self", "direction": "in",
+                      "timestamp": now_s - 120, "content": "geo:52.0,4.0" },
+                    { "id": "c2", "source": "peer-c", "destination": "self", "direction": "in",
+                      "timestamp": now_s - 60, "content": "geo:52.01,4.0",
+                      "fields": { "2": { "lat": 52.01, "lon": 4.0 } } },
+                    { "id": "c3", "source": "peer-c", "destination": "self", "direction": "in",
+                      "timestamp": now_s - 60, "content": "relay of geo:52.01,4.0" },
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+
+        let result = store
+            .query_peer_positions(PeerPositionsQueryParams {
+                peer: Some("peer-c".to_string()),
+                since_ms: None,
+                stale_after_ms: None,
+            })
+            .expect("positions");
+        let item = &result["items"][0];
+        assert_eq!(item["lat"], 52.01);
+        let heading = item["heading_deg"].as_f64().expect("heading");
+        assert!(!(1.0..=359.0).contains(&heading), "heading {heading}");
+        let speed = item["speed_mps"].as_f64().expect("speed");
+        assert!((speed - 1112.0 / 60.0).abs() < 1.0, "speed {speed}");
+    }
+
+    #[test]
+    fn peer_track_is_ordered_deduplicated_and_truncated_from_oldest() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        seed(&store);
+
+        let track = store
+            .query_peer_track(PeerTrackQueryParams {
+                peer: "peer-a".to_string(),
+                since_ms: None,
+                until_ms: None,
+                limit: None,
+            })
+            .expect("track");
+        let ids: Vec<&str> = track["points"]
+            .as_array()
+            .expect("points")
+            .iter()
+            .map(|point| point["message_id"].as_str().unwrap_or_default())
+            .collect();
+        assert_eq!(ids, vec!["a1", "a2", "a3"]);
+        assert_eq!(track["truncated"], false);
+        let distance = track["distance_m"].as_f64().expect("distance");
+        assert!((distance - 2224.0).abs() < 5.0, "distance {distance}");
+
+        let truncated = store
+            .query_peer_track(PeerTrackQueryParams {
+                peer: "peer-a".to_string(),
+                since_ms: None,
+                until_ms: None,
+                limit: Some(2),
+            })
+            .expect("truncated track");
+        assert_eq!(truncated["truncated"], true);
+        assert_eq!(truncated["points"][1]["message_id"], "a3");
+
+        let inbound_only = store
+            .query_peer_track(PeerTrackQueryParams {
+                peer: "peer-b".to_string(),
+                since_ms: None,
+                until_ms: None,
+                limit: None,
+            })
+            .expect("inbound-only track");
+        assert_eq!(inbound_only["points"].as_array().map(Vec::len), Some(1));
+
+        assert!(store
+            .query_peer_track(PeerTrackQueryParams {
+                peer: " ".to_string(),
+                since_ms: None,
+                until_ms: None,
+                limit: None,
+            })
+            .is_err());
+    }
+}
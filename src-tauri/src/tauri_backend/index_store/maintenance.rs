@@ -1,5 +1,9 @@
 use super::*;
 
+// A VACUUM rewrites the whole file under the index lock, so it only runs once
+// enough pages are free to be worth it.
+const VACUUM_MIN_FREE_PAGES: i64 = 1_024;
+
 impl IndexStore {
     pub(crate) fn runtime_metrics(&self) -> Result<RuntimeMetrics, String> {
         let conn = self
@@ -25,12 +29,7 @@ impl IndexStore {
                 SELECT COUNT(*)
                 FROM messages
                 WHERE direction = 'out'
-                  AND (
-                    receipt_status IS NULL
-                    OR LOWER(receipt_status) LIKE '%pending%'
-                    OR LOWER(receipt_status) LIKE '%queue%'
-                    OR LOWER(receipt_status) LIKE '%send%'
-                  )
+                  AND delivery_state IN ('queued', 'sending')
                 ",
                 [],
                 |row| row.get::<_, i64>(0),
@@ -64,24 +63,36 @@ impl IndexStore {
         })
     }
 
+    // Invalidates every sync marker so the next sync rewrites all rows, while the
+    // existing index keeps serving queries until the runtime copy replaces it.
+    // Other `sync_state` keys, such as when announces were last viewed, stay.
     pub(crate) fn force_reindex(&self) -> Result<(), String> {
-        self.ready.store(false, Ordering::Relaxed);
         let conn = self
             .conn
             .lock()
             .map_err(|_| "index lock poisoned".to_string())?;
         conn.execute_batch(
             "
-            DELETE FROM attachments;
-            DELETE FROM messages;
-            DELETE FROM threads;
-            DELETE FROM sync_state;
+            UPDATE messages SET sync_marker = NULL;
+            DELETE FROM sync_state
+            WHERE key IN ('last_sync_ms', 'last_sync_message_id', 'sync_cursor');
             ",
         )
-        .map_err(|err| format!("clear index failed: {err}"))?;
+        .map_err(|err| format!("invalidate index sync state failed: {err}"))?;
         Ok(())
     }
 
+    /// Runs `VACUUM` when at least a quarter of the database pages are free,
+    /// as after the blob migration or a large retention pass. Returns whether
+    /// it ran.
+    pub(crate) fn reclaim_free_pages(&self) -> Result<bool, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        vacuum_if_sparse(&conn, VACUUM_MIN_FREE_PAGES)
+    }
+
     pub(crate) fn rebuild_thread_summaries(&self) -> Result<(), String> {
         let mut conn = self
             .conn
@@ -90,3 +101,77 @@ impl IndexStore {
         rebuild_threads_table(&mut conn)
     }
 }
+
+fn vacuum_if_sparse(conn: &Connection, min_free_pages: i64) -> Result<bool, String> {
+    let free_pages = conn
+        .query_row("PRAGMA freelist_count", [], |row| row.get::<_, i64>(0))
+        .map_err(|err| format!("read freelist_count failed: {err}"))?;
+    let page_count = conn
+        .query_row("PRAGMA page_count", [], |row| row.get::<_, i64>(0))
+        .map_err(|err| format!("read page_count failed: {err}"))?;
+    if free_pages < min_free_pages || free_pages * 4 < page_count {
+        return Ok(false);
+    }
+    conn.execute_batch("VACUUM")
+        .map_err(|err| format!("vacuum index failed: {err}"))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vacuum_runs_only_once_enough_pages_are_free() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        let bulky = "x".repeat(4_000);
+        let messages = (0..200)
+            .map(|index| {
+                json!({ "id": format!("m{index}"), "source": "peer-a", "destination": "self",
+                        "direction": "in", "timestamp": 1_000 + index, "content": bulky })
+            })
+            .collect::<Vec<_>>();
+        store
+            .reindex_from_runtime_payloads(&Value::Array(messages), &json!([]))
+            .expect("seed index");
+        let conn = store.conn.lock().expect("lock");
+        assert!(!vacuum_if_sparse(&conn, 1).expect("dense file"));
+
+        conn.execute("DELETE FROM messages", [])
+            .expect("drop messages");
+        assert!(!vacuum_if_sparse(&conn, i64::MAX).expect("below minimum"));
+        assert!(vacuum_if_sparse(&conn, 1).expect("sparse file"));
+        let free_pages: i64 = conn
+            .query_row("PRAGMA freelist_count", [], |row| row.get(0))
+            .expect("freelist");
+        assert_eq!(free_pages, 0);
+    }
+
+    #[test]
+    fn force_reindex_keeps_markers_other_than_sync_progress() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([{ "id": "m1", "source": "peer-a", "destination": "self",
+                          "direction": "in", "timestamp": 1_000, "content": "hello" }]),
+                &json!([]),
+            )
+            .expect("seed index");
+        store
+            .mark_announces_viewed(Some(5_000))
+            .expect("mark viewed");
+
+        store.force_reindex().expect("force reindex");
+        let conn = store.conn.lock().expect("lock");
+        let keys = conn
+            .prepare("SELECT key FROM sync_state ORDER BY key")
+            .expect("prepare")
+            .query_map([], |row| row.get::<_, String>(0))
+            .expect("query")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("keys");
+        assert_eq!(keys, ["announces_viewed_ms"]);
+    }
+}
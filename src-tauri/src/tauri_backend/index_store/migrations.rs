@@ -0,0 +1,1100 @@
+use super::*;
+
+/// One forward-only schema step. `version` is the `PRAGMA user_version` the
+/// database reports once the step has been committed.
+pub(super) struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub apply: fn(&Connection) -> Result<(), String>,
+}
+
+// Append new steps at the end; never edit or reorder a migration that has shipped.
+pub(super) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "base_schema",
+        apply: migrate_base_schema,
+    },
+    Migration {
+        version: 2,
+        name: "thread_read_state",
+        apply: migrate_thread_read_state,
+    },
+    Migration {
+        version: 3,
+        name: "thread_flags",
+        apply: migrate_thread_flags,
+    },
+    Migration {
+        version: 4,
+        name: "message_sync_markers",
+        apply: migrate_message_sync_markers,
+    },
+    Migration {
+        version: 5,
+        name: "unicode_fts",
+        apply: migrate_unicode_fts,
+    },
+    Migration {
+        version: 6,
+        name: "map_points",
+        apply: migrate_map_points,
+    },
+    Migration {
+        version: 7,
+        name: "attachment_blobs",
+        apply: migrate_attachment_blobs,
+    },
+    Migration {
+        version: 8,
+        name: "retention",
+        apply: migrate_retention,
+    },
+    Migration {
+        version: 9,
+        name: "reactions",
+        apply: migrate_reactions,
+    },
+    Migration {
+        version: 10,
+        name: "reply_links",
+        apply: migrate_reply_links,
+    },
+    Migration {
+        version: 11,
+        name: "message_amendments",
+        apply: migrate_message_amendments,
+    },
+    Migration {
+        version: 12,
+        name: "contacts",
+        apply: migrate_contacts,
+    },
+    Migration {
+        version: 13,
+        name: "contact_capabilities",
+        apply: migrate_contact_capabilities,
+    },
+    Migration {
+        version: 14,
+        name: "announce_history",
+        apply: migrate_announce_history,
+    },
+    Migration {
+        version: 15,
+        name: "delivery_state",
+        apply: migrate_delivery_state,
+    },
+    Migration {
+        version: 16,
+        name: "send_queue",
+        apply: migrate_send_queue,
+    },
+];
+
+pub(super) fn latest_schema_version() -> i64 {
+    MIGRATIONS
+        .last()
+        .map(|migration| migration.version)
+        .unwrap_or(0)
+}
+
+pub(super) fn read_schema_version(conn: &Connection) -> Result<i64, String> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))
+        .map_err(|err| format!("read index schema version failed: {err}"))
+}
+
+pub(super) fn run_schema_migrations(conn: &mut Connection) -> Result<(), String> {
+    apply_migrations(conn, MIGRATIONS)
+}
+
+fn apply_migrations(conn: &mut Connection, migrations: &[Migration]) -> Result<(), String> {
+    let current = read_schema_version(conn)?;
+    let latest = migrations
+        .last()
+        .map(|migration| migration.version)
+        .unwrap_or(0);
+    if current > latest {
+        return Err(format!(
+            "index schema version {current} is newer than supported version {latest}"
+        ));
+    }
+
+    for migration in migrations
+        .iter()
+        .filter(|migration| migration.version > current)
+    {
+        let tx = conn.transaction().map_err(|err| {
+            format!(
+                "start migration {} ({}) failed: {err}",
+                migration.version, migration.name
+            )
+        })?;
+        (migration.apply)(&tx).map_err(|err| {
+            format!(
+                "migration {} ({}) failed: {err}",
+                migration.version, migration.name
+            )
+        })?;
+        tx.pragma_update(None, "user_version", migration.version)
+            .map_err(|err| {
+                format!(
+                    "record migration {} ({}) failed: {err}",
+                    migration.version, migration.name
+                )
+            })?;
+        tx.commit().map_err(|err| {
+            format!(
+                "commit migration {} ({}) failed: {err}",
+                migration.version, migration.name
+            )
+        })?;
+        log::info!(
+            "index schema migrated to version {} ({})",
+            migration.version,
+            migration.name
+        );
+    }
+    Ok(())
+}
+
+// Version 1 is the schema that shipped before versioning existed. Databases
+// created by those builds report user_version 0 but already contain these
+// tables, so every statement here must tolerate existing objects.
+fn migrate_base_schema(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(BASE_SCHEMA_SQL)
+        .map_err(|err| format!("init index schema failed: {err}"))?;
+    if !table_column_exists(conn, "attachments", "created_at_ms")? {
+        conn.execute(
+            "ALTER TABLE attachments ADD COLUMN created_at_ms INTEGER NOT NULL DEFAULT 0",
+            [],
+        )
+        .map_err(|err| format!("migrate attachments.created_at_ms failed: {err}"))?;
+        conn.execute(
+            "
+            UPDATE attachments
+            SET created_at_ms = COALESCE(
+              (SELECT m.ts_ms FROM messages m WHERE m.message_id = attachments.message_id),
+              ?1
+            )
+            ",
+            params![current_timestamp_ms()],
+        )
+        .map_err(|err| format!("backfill attachments.created_at_ms failed: {err}"))?;
+    }
+    conn.execute_batch(
+        "
+        CREATE INDEX IF NOT EXISTS idx_attachments_created_at
+          ON attachments(created_at_ms DESC, id DESC);
+        ",
+    )
+    .map_err(|err| format!("apply index migrations failed: {err}"))?;
+    Ok(())
+}
+
+fn migrate_thread_read_state(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS thread_read_state (
+          thread_id TEXT PRIMARY KEY,
+          last_read_message_id TEXT NOT NULL,
+          last_read_ts_ms INTEGER NOT NULL,
+          updated_at_ms INTEGER NOT NULL
+        );
+        ",
+    )
+    .map_err(|err| format!("create thread_read_state failed: {err}"))
+}
+
+fn migrate_thread_flags(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS thread_flags (
+          thread_id TEXT PRIMARY KEY,
+          pinned INTEGER NOT NULL DEFAULT 0,
+          muted INTEGER NOT NULL DEFAULT 0,
+          muted_until_ms INTEGER,
+          archived INTEGER NOT NULL DEFAULT 0,
+          updated_at_ms INTEGER NOT NULL
+        );
+        ",
+    )
+    .map_err(|err| format!("create thread_flags failed: {err}"))?;
+    conn.execute(
+        "
+        INSERT OR IGNORE INTO thread_flags (thread_id, pinned, muted, archived, updated_at_ms)
+        SELECT thread_id, pinned, muted, 0, ?1
+        FROM threads
+        WHERE pinned = 1 OR muted = 1
+        ",
+        params![current_timestamp_ms()],
+    )
+    .map_err(|err| format!("migrate thread flags failed: {err}"))?;
+    Ok(())
+}
+
+fn migrate_message_sync_markers(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "
+        ALTER TABLE messages ADD COLUMN sync_marker TEXT;
+        DELETE FROM sync_state WHERE key = 'sync_cursor';
+        ",
+    )
+    .map_err(|err| format!("add messages.sync_marker failed: {err}"))
+}
+
+// Recreates the FTS index with a diacritic-folding unicode61 tokenizer and adds
+// a trigram index for scripts that do not separate words with spaces.
+fn migrate_unicode_fts(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(UNICODE_FTS_SQL)
+        .map_err(|err| format!("rebuild messages_fts failed: {err}"))
+}
+
+fn migrate_map_points(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(MAP_POINTS_SQL)
+        .map_err(|err| format!("create map_points failed: {err}"))?;
+
+    for_each_backfill_row(conn, "", "map point", |row| {
+        let context = MapPointMessageContext {
+            message_id: &row.message_id,
+            source: &row.source,
+            destination: &row.destination,
+            direction: &row.direction,
+            title: &row.title,
+            body: &row.body,
+            ts_ms: row.ts_ms,
+        };
+        let points = extract_map_points(&context, row.fields.as_ref().unwrap_or(&Value::Null));
+        for (ordinal, point) in points.iter().enumerate() {
+            conn.execute(
+                "
+                INSERT INTO map_points (message_id, ordinal, peer, direction, ts_ms, lat, lon, label)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                ",
+                params![
+                    &point.message_id,
+                    ordinal as i64,
+                    &point.peer,
+                    &point.direction,
+                    point.timestamp,
+                    point.lat,
+                    point.lon,
+                    &point.label,
+                ],
+            )
+            .map_err(|err| format!("insert map point failed: {err}"))?;
+        }
+        Ok(())
+    })
+}
+
+// Adds the refcounted blob registry. Moving existing payloads out of
+// `inline_base64` needs the blob directory, so it runs after migrations via
+// `blobs::externalize_inline_attachments` when the store is opened.
+fn migrate_attachment_blobs(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(ATTACHMENT_BLOBS_SQL)
+        .map_err(|err| format!("create attachment blobs failed: {err}"))
+}
+
+// Policies are keyed by thread id, with `*` holding the global policy. Messages
+// removed locally leave a tombstone so sync does not re-import them while the
+// runtime still reports them.
+fn migrate_retention(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "
+        CREATE TABLE retention_policies (
+          thread_id TEXT PRIMARY KEY,
+          max_age_ms INTEGER,
+          max_messages INTEGER,
+          max_attachment_bytes INTEGER,
+          updated_at_ms INTEGER NOT NULL
+        );
+
+        CREATE TABLE message_tombstones (
+          message_id TEXT PRIMARY KEY,
+          thread_id TEXT NOT NULL,
+          reason TEXT NOT NULL,
+          deleted_at_ms INTEGER NOT NULL
+        );
+        ",
+    )
+    .map_err(|err| format!("create retention tables failed: {err}"))
+}
+
+// Reactions are keyed by the reaction message so a resync replaces them; the
+// flag on `messages` keeps reaction-only rows out of previews and unread counts.
+fn migrate_reactions(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(REACTIONS_SQL)
+        .map_err(|err| format!("create reactions failed: {err}"))?;
+
+    for_each_backfill_row(conn, "WHERE fields_json IS NOT NULL", "reaction", |row| {
+        let Some(reaction) = reactions::extract_reaction(row) else {
+            return Ok(());
+        };
+        conn.execute(
+            "
+            INSERT INTO reactions (message_id, target_message_id, sender, emoji, ts_ms)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ",
+            params![
+                &row.message_id,
+                &reaction.target_message_id,
+                &reaction.sender,
+                &reaction.emoji,
+                row.ts_ms,
+            ],
+        )
+        .map_err(|err| format!("insert reaction failed: {err}"))?;
+        if reactions::is_reaction_only(row) {
+            conn.execute(
+                "UPDATE messages SET reaction_only = 1 WHERE message_id = ?1",
+                params![&row.message_id],
+            )
+            .map_err(|err| format!("flag reaction message failed: {err}"))?;
+        }
+        Ok(())
+    })
+}
+
+fn migrate_reply_links(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "
+        ALTER TABLE messages ADD COLUMN reply_to_message_id TEXT;
+        CREATE INDEX idx_messages_reply_to
+          ON messages(reply_to_message_id, ts_ms, message_id)
+          WHERE reply_to_message_id IS NOT NULL;
+        ",
+    )
+    .map_err(|err| format!("add messages.reply_to_message_id failed: {err}"))?;
+
+    for_each_backfill_row(conn, "WHERE fields_json IS NOT NULL", "reply link", |row| {
+        if let Some(parent_id) = replies::extract_reply_to(row) {
+            conn.execute(
+                "UPDATE messages SET reply_to_message_id = ?1 WHERE message_id = ?2",
+                params![parent_id, &row.message_id],
+            )
+            .map_err(|err| format!("link reply failed: {err}"))?;
+        }
+        Ok(())
+    })
+}
+
+// Edits and deletes stay in `messages` (hidden from listings) so they can be
+// re-applied whenever their target is rewritten by a sync. Messages already
+// indexed predate the keys, so there is nothing to backfill.
+fn migrate_message_amendments(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(MESSAGE_AMENDMENTS_SQL)
+        .map_err(|err| format!("create message amendments failed: {err}"))
+}
+
+// Seeds contacts from existing threads: names that differ from the short-hash
+// placeholder came from the runtime's peer list, and inbound messages give the
+// first/last-seen window.
+fn migrate_contacts(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "
+        CREATE TABLE contacts (
+          peer TEXT PRIMARY KEY,
+          announced_name TEXT,
+          alias TEXT,
+          notes TEXT NOT NULL DEFAULT '',
+          trust TEXT NOT NULL DEFAULT 'unknown',
+          favorite INTEGER NOT NULL DEFAULT 0,
+          first_seen_ms INTEGER,
+          last_seen_ms INTEGER,
+          updated_at_ms INTEGER NOT NULL
+        );
+        ",
+    )
+    .map_err(|err| format!("create contacts failed: {err}"))?;
+
+    let mut stmt = conn
+        .prepare(
+            "
+            SELECT
+              t.thread_id,
+              t.display_name,
+              MIN(m.ts_ms),
+              MAX(m.ts_ms)
+            FROM threads t
+            LEFT JOIN messages m ON m.thread_id = t.thread_id AND m.direction != 'out'
+            GROUP BY t.thread_id
+            ",
+        )
+        .map_err(|err| format!("prepare contact backfill failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<i64>>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+            ))
+        })
+        .map_err(|err| format!("query contact backfill failed: {err}"))?;
+    for row in rows {
+        let (peer, display_name, first_seen_ms, last_seen_ms) =
+            row.map_err(|err| format!("parse contact backfill row failed: {err}"))?;
+        let announced_name = Some(display_name.trim())
+            .filter(|name| !name.is_empty() && *name != short_hash(&peer, 6));
+        conn.execute(
+            "
+            INSERT INTO contacts (peer, announced_name, first_seen_ms, last_seen_ms, updated_at_ms)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ",
+            params![
+                peer,
+                announced_name,
+                first_seen_ms,
+                last_seen_ms,
+                current_timestamp_ms()
+            ],
+        )
+        .map_err(|err| format!("seed contact failed: {err}"))?;
+    }
+    Ok(())
+}
+
+// Capabilities only arrive with announces, so existing contacts start empty
+// and fill in as peers are heard again.
+fn migrate_contact_capabilities(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "ALTER TABLE contacts ADD COLUMN capabilities_json TEXT NOT NULL DEFAULT '[]';",
+    )
+    .map_err(|err| format!("add contacts.capabilities_json failed: {err}"))
+}
+
+// Announces were only ever held by the runtime, so the history starts empty
+// and fills from events and `list_announces` responses.
+fn migrate_announce_history(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "
+        CREATE TABLE announces (
+          peer TEXT PRIMARY KEY,
+          name TEXT,
+          name_source TEXT,
+          aspect TEXT,
+          app_data_hex TEXT,
+          capabilities_json TEXT NOT NULL DEFAULT '[]',
+          first_seen_ms INTEGER NOT NULL,
+          last_seen_ms INTEGER NOT NULL,
+          seen_count INTEGER NOT NULL DEFAULT 1,
+          hops INTEGER,
+          rssi REAL,
+          snr REAL,
+          q REAL
+        );
+        CREATE INDEX idx_announces_last_seen ON announces(last_seen_ms DESC, peer);
+        CREATE TABLE announce_hops (
+          peer TEXT NOT NULL,
+          ts_ms INTEGER NOT NULL,
+          hops INTEGER NOT NULL,
+          PRIMARY KEY (peer, ts_ms)
+        );
+        CREATE TRIGGER announces_ad AFTER DELETE ON announces BEGIN
+          DELETE FROM announce_hops WHERE peer = old.peer;
+        END;
+        ",
+    )
+    .map_err(|err| format!("create announce history failed: {err}"))
+}
+
+// Outbound messages get their state from the stored status text once, with a
+// single event at send time; later transitions are recorded as they happen.
+fn migrate_delivery_state(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "
+        ALTER TABLE messages ADD COLUMN delivery_state TEXT;
+        CREATE INDEX idx_messages_delivery_state
+          ON messages(delivery_state, ts_ms)
+          WHERE direction = 'out';
+        CREATE TABLE delivery_events (
+          id INTEGER PRIMARY KEY AUTOINCREMENT,
+          message_id TEXT NOT NULL,
+          state TEXT NOT NULL,
+          ts_ms INTEGER NOT NULL,
+          reason_code TEXT,
+          detail TEXT,
+          source TEXT NOT NULL
+        );
+        CREATE UNIQUE INDEX idx_delivery_events_message
+          ON delivery_events(message_id, ts_ms, state);
+        CREATE TRIGGER messages_delivery_events_ad AFTER DELETE ON messages BEGIN
+          DELETE FROM delivery_events WHERE message_id = old.message_id;
+        END;
+        ",
+    )
+    .map_err(|err| format!("create delivery events failed: {err}"))?;
+
+    let mut stmt = conn
+        .prepare("SELECT message_id, ts_ms, receipt_status FROM messages WHERE direction = 'out'")
+        .map_err(|err| format!("prepare delivery state backfill failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })
+        .map_err(|err| format!("query delivery state backfill failed: {err}"))?;
+    for row in rows {
+        let (message_id, ts_ms, receipt_status) =
+            row.map_err(|err| format!("parse delivery state backfill row failed: {err}"))?;
+        let (state, reason_code) = delivery::classify_status(receipt_status.as_deref())
+            .unwrap_or((DeliveryState::Queued, None));
+        conn.execute(
+            "
+            INSERT INTO delivery_events (message_id, state, ts_ms, reason_code, detail, source)
+            VALUES (?1, ?2, ?3, ?4, ?5, 'migration')
+            ",
+            params![
+                &message_id,
+                state.as_str(),
+                ts_ms,
+                reason_code,
+                receipt_status
+            ],
+        )
+        .map_err(|err| format!("insert delivery event failed: {err}"))?;
+        conn.execute(
+            "UPDATE messages SET delivery_state = ?1 WHERE message_id = ?2",
+            params![state.as_str(), &message_id],
+        )
+        .map_err(|err| format!("update delivery state failed: {err}"))?;
+    }
+    Ok(())
+}
+
+// Sends composed while the runtime is down wait here until the queue worker
+// hands them over; the table starts empty.
+fn migrate_send_queue(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "
+        CREATE TABLE send_queue (
+          id INTEGER PRIMARY KEY AUTOINCREMENT,
+          message_id TEXT NOT NULL UNIQUE,
+          profile TEXT NOT NULL,
+          rpc TEXT NOT NULL,
+          request_json TEXT NOT NULL,
+          created_at_ms INTEGER NOT NULL,
+          attempts INTEGER NOT NULL DEFAULT 0,
+          next_attempt_ms INTEGER NOT NULL,
+          last_error TEXT,
+          failed_at_ms INTEGER,
+          unconfirmed INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX idx_send_queue_next_attempt ON send_queue(next_attempt_ms, id);
+        ",
+    )
+    .map_err(|err| format!("create send queue failed: {err}"))
+}
+
+// Backfills read and write the schema as it stood when their step shipped,
+// so nothing here goes through the live row mapping or upsert helpers; a later
+// column change must not break upgrading an old index. Parsing a message's
+// fields (map points, reactions, reply targets, status text, short hashes)
+// does use the live code: it reads no schema, and a newer reading is what a
+// fresh sync of the same message would store anyway.
+fn for_each_backfill_row(
+    conn: &Connection,
+    filter_sql: &str,
+    what: &str,
+    mut apply: impl FnMut(&MessageRow) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "
+            SELECT message_id, thread_id, direction, source, destination, ts_ms, title, body, receipt_status, fields_json
+            FROM messages
+            {filter_sql}
+            "
+        ))
+        .map_err(|err| format!("prepare {what} backfill failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| {
+            let fields_json = row.get::<_, Option<String>>(9).ok().flatten();
+            Ok(MessageRow {
+                message_id: row.get::<_, String>(0)?,
+                thread_id: row.get::<_, String>(1)?,
+                direction: row.get::<_, String>(2)?,
+                source: row.get::<_, String>(3)?,
+                destination: row.get::<_, String>(4)?,
+                ts_ms: row.get::<_, i64>(5)?,
+                title: row.get::<_, String>(6)?,
+                body: row.get::<_, String>(7)?,
+                receipt_status: row.get::<_, Option<String>>(8).ok().flatten(),
+                fields: fields_json
+                    .as_deref()
+                    .and_then(|value| serde_json::from_str::<Value>(value).ok()),
+            })
+        })
+        .map_err(|err| format!("query {what} backfill failed: {err}"))?;
+    for row in rows {
+        apply(&row.map_err(|err| format!("parse {what} backfill row failed: {err}"))?)?;
+    }
+    Ok(())
+}
+
+fn table_column_exists(conn: &Connection, table: &str, column: &str) -> Result<bool, String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({table})"))
+        .map_err(|err| format!("inspect table {table} failed: {err}"))?;
+    let rows = stmt
+        .query_map([], |row| row.get::<_, String>(1))
+        .map_err(|err| format!("read table info {table} failed: {err}"))?;
+    for entry in rows {
+        if entry.map_err(|err| format!("parse table info {table} failed: {err}"))? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+const BASE_SCHEMA_SQL: &str = r#"
+CREATE TABLE IF NOT EXISTS messages (
+  message_id TEXT PRIMARY KEY,
+  thread_id TEXT NOT NULL,
+  direction TEXT NOT NULL,
+  source TEXT NOT NULL,
+  destination TEXT NOT NULL,
+  ts_ms INTEGER NOT NULL,
+  title TEXT NOT NULL,
+  body TEXT NOT NULL,
+  receipt_status TEXT,
+  status_reason_code TEXT,
+  has_attachments INTEGER NOT NULL DEFAULT 0,
+  has_paper INTEGER NOT NULL DEFAULT 0,
+  fields_json TEXT,
+  updated_at_ms INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS attachments (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  message_id TEXT NOT NULL,
+  ordinal INTEGER NOT NULL,
+  name TEXT NOT NULL,
+  mime TEXT,
+  size_bytes INTEGER NOT NULL,
+  inline_base64 TEXT,
+  created_at_ms INTEGER NOT NULL DEFAULT 0,
+  FOREIGN KEY(message_id) REFERENCES messages(message_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_attachments_message_id ON attachments(message_id);
+
+CREATE TABLE IF NOT EXISTS threads (
+  thread_id TEXT PRIMARY KEY,
+  display_name TEXT NOT NULL,
+  preview TEXT NOT NULL,
+  last_message_id TEXT,
+  last_activity_ms INTEGER NOT NULL,
+  unread_count INTEGER NOT NULL DEFAULT 0,
+  pinned INTEGER NOT NULL DEFAULT 0,
+  muted INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS sync_state (
+  key TEXT PRIMARY KEY,
+  value TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_threads_activity ON threads(last_activity_ms DESC, thread_id DESC);
+CREATE INDEX IF NOT EXISTS idx_messages_thread_ts ON messages(thread_id, ts_ms DESC, message_id DESC);
+CREATE INDEX IF NOT EXISTS idx_messages_ts ON messages(ts_ms DESC, message_id DESC);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+  message_id UNINDEXED,
+  title,
+  body,
+  content='messages',
+  content_rowid='rowid'
+);
+
+CREATE TRIGGER IF NOT EXISTS messages_ai AFTER INSERT ON messages BEGIN
+  INSERT INTO messages_fts(rowid, message_id, title, body)
+  VALUES (new.rowid, new.message_id, new.title, new.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_ad AFTER DELETE ON messages BEGIN
+  INSERT INTO messages_fts(messages_fts, rowid, message_id, title, body)
+  VALUES('delete', old.rowid, old.message_id, old.title, old.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_au AFTER UPDATE ON messages BEGIN
+  INSERT INTO messages_fts(messages_fts, rowid, message_id, title, body)
+  VALUES('delete', old.rowid, old.message_id, old.title, old.body);
+  INSERT INTO messages_fts(rowid, message_id, title, body)
+  VALUES (new.rowid, new.message_id, new.title, new.body);
+END;
+"#;
+
+const UNICODE_FTS_SQL: &str = r#"
+DROP TRIGGER IF EXISTS messages_ai;
+DROP TRIGGER IF EXISTS messages_ad;
+DROP TRIGGER IF EXISTS messages_au;
+DROP TABLE IF EXISTS messages_fts;
+
+CREATE VIRTUAL TABLE messages_fts USING fts5(
+  message_id UNINDEXED,
+  title,
+  body,
+  content='messages',
+  content_rowid='rowid',
+  tokenize='unicode61 remove_diacritics 2'
+);
+
+CREATE VIRTUAL TABLE messages_fts_trigram USING fts5(
+  title,
+  body,
+  content='messages',
+  content_rowid='rowid',
+  tokenize='trigram remove_diacritics 1'
+);
+
+CREATE TRIGGER messages_ai AFTER INSERT ON messages BEGIN
+  INSERT INTO messages_fts(rowid, message_id, title, body)
+  VALUES (new.rowid, new.message_id, new.title, new.body);
+  INSERT INTO messages_fts_trigram(rowid, title, body)
+  VALUES (new.rowid, new.title, new.body);
+END;
+
+CREATE TRIGGER messages_ad AFTER DELETE ON messages BEGIN
+  INSERT INTO messages_fts(messages_fts, rowid, message_id, title, body)
+  VALUES('delete', old.rowid, old.message_id, old.title, old.body);
+  INSERT INTO messages_fts_trigram(messages_fts_trigram, rowid, title, body)
+  VALUES('delete', old.rowid, old.title, old.body);
+END;
+
+CREATE TRIGGER messages_au AFTER UPDATE OF title, body ON messages BEGIN
+  INSERT INTO messages_fts(messages_fts, rowid, message_id, title, body)
+  VALUES('delete', old.rowid, old.message_id, old.title, old.body);
+  INSERT INTO messages_fts_trigram(messages_fts_trigram, rowid, title, body)
+  VALUES('delete', old.rowid, old.title, old.body);
+  INSERT INTO messages_fts(rowid, message_id, title, body)
+  VALUES (new.rowid, new.message_id, new.title, new.body);
+  INSERT INTO messages_fts_trigram(rowid, title, body)
+  VALUES (new.rowid, new.title, new.body);
+END;
+
+INSERT INTO messages_fts(messages_fts) VALUES('rebuild');
+INSERT INTO messages_fts_trigram(messages_fts_trigram) VALUES('rebuild');
+"#;
+
+const MAP_POINTS_SQL: &str = r#"
+CREATE TABLE map_points (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  message_id TEXT NOT NULL,
+  ordinal INTEGER NOT NULL,
+  peer TEXT NOT NULL,
+  direction TEXT NOT NULL,
+  ts_ms INTEGER NOT NULL,
+  lat REAL NOT NULL,
+  lon REAL NOT NULL,
+  label TEXT NOT NULL
+);
+
+CREATE INDEX idx_map_points_message_id ON map_points(message_id);
+CREATE INDEX idx_map_points_ts ON map_points(ts_ms DESC, id DESC);
+CREATE INDEX idx_map_points_peer_ts ON map_points(peer, ts_ms DESC, id DESC);
+
+CREATE VIRTUAL TABLE map_points_rtree USING rtree(
+  id,
+  min_lat,
+  max_lat,
+  min_lon,
+  max_lon
+);
+
+CREATE TRIGGER map_points_ai AFTER INSERT ON map_points BEGIN
+  INSERT INTO map_points_rtree(id, min_lat, max_lat, min_lon, max_lon)
+  VALUES (new.id, new.lat, new.lat, new.lon, new.lon);
+END;
+
+CREATE TRIGGER map_points_ad AFTER DELETE ON map_points BEGIN
+  DELETE FROM map_points_rtree WHERE id = old.id;
+END;
+
+CREATE TRIGGER messages_map_points_ad AFTER DELETE ON messages BEGIN
+  DELETE FROM map_points WHERE message_id = old.message_id;
+END;
+"#;
+
+const ATTACHMENT_BLOBS_SQL: &str = r#"
+ALTER TABLE attachments ADD COLUMN blob_sha256 TEXT;
+
+CREATE TABLE blobs (
+  sha256 TEXT PRIMARY KEY,
+  size_bytes INTEGER NOT NULL,
+  refcount INTEGER NOT NULL DEFAULT 0,
+  created_at_ms INTEGER NOT NULL
+);
+
+CREATE INDEX idx_attachments_blob ON attachments(blob_sha256);
+CREATE INDEX idx_blobs_unreferenced ON blobs(refcount) WHERE refcount <= 0;
+
+CREATE TRIGGER attachments_blob_ai AFTER INSERT ON attachments BEGIN
+  UPDATE blobs SET refcount = refcount + 1 WHERE sha256 = new.blob_sha256;
+END;
+
+CREATE TRIGGER attachments_blob_ad AFTER DELETE ON attachments BEGIN
+  UPDATE blobs SET refcount = refcount - 1 WHERE sha256 = old.blob_sha256;
+END;
+
+CREATE TRIGGER attachments_blob_au AFTER UPDATE OF blob_sha256 ON attachments BEGIN
+  UPDATE blobs SET refcount = refcount - 1 WHERE sha256 = old.blob_sha256;
+  UPDATE blobs SET refcount = refcount + 1 WHERE sha256 = new.blob_sha256;
+END;
+"#;
+
+const REACTIONS_SQL: &str = r#"
+ALTER TABLE messages ADD COLUMN reaction_only INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE reactions (
+  message_id TEXT PRIMARY KEY,
+  target_message_id TEXT NOT NULL,
+  sender TEXT NOT NULL,
+  emoji TEXT NOT NULL,
+  ts_ms INTEGER NOT NULL
+);
+
+CREATE INDEX idx_reactions_target ON reactions(target_message_id, emoji, sender);
+
+CREATE TRIGGER messages_reactions_ad AFTER DELETE ON messages BEGIN
+  DELETE FROM reactions WHERE message_id = old.message_id;
+END;
+"#;
+
+const MESSAGE_AMENDMENTS_SQL: &str = r#"
+ALTER TABLE messages ADD COLUMN amends_message_id TEXT;
+ALTER TABLE messages ADD COLUMN amendment_kind TEXT;
+ALTER TABLE messages ADD COLUMN edited_at_ms INTEGER;
+ALTER TABLE messages ADD COLUMN deleted_at_ms INTEGER;
+
+CREATE INDEX idx_messages_amends
+  ON messages(amends_message_id, source, ts_ms)
+  WHERE amends_message_id IS NOT NULL;
+
+CREATE TABLE message_revisions (
+  message_id TEXT NOT NULL,
+  revision INTEGER NOT NULL,
+  body TEXT NOT NULL,
+  ts_ms INTEGER NOT NULL,
+  amendment_id TEXT,
+  PRIMARY KEY (message_id, revision)
+);
+
+CREATE TRIGGER messages_revisions_ad AFTER DELETE ON messages BEGIN
+  DELETE FROM message_revisions WHERE message_id = old.message_id;
+END;
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY_UNVERSIONED_SQL: &str = r#"
+CREATE TABLE messages (
+  message_id TEXT PRIMARY KEY,
+  thread_id TEXT NOT NULL,
+  direction TEXT NOT NULL,
+  source TEXT NOT NULL,
+  destination TEXT NOT NULL,
+  ts_ms INTEGER NOT NULL,
+  title TEXT NOT NULL,
+  body TEXT NOT NULL,
+  receipt_status TEXT,
+  status_reason_code TEXT,
+  has_attachments INTEGER NOT NULL DEFAULT 0,
+  has_paper INTEGER NOT NULL DEFAULT 0,
+  fields_json TEXT,
+  updated_at_ms INTEGER NOT NULL
+);
+CREATE TABLE attachments (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  message_id TEXT NOT NULL,
+  ordinal INTEGER NOT NULL,
+  name TEXT NOT NULL,
+  mime TEXT,
+  size_bytes INTEGER NOT NULL,
+  inline_base64 TEXT
+);
+CREATE TABLE threads (
+  thread_id TEXT PRIMARY KEY,
+  display_name TEXT NOT NULL,
+  preview TEXT NOT NULL,
+  last_message_id TEXT,
+  last_activity_ms INTEGER NOT NULL,
+  unread_count INTEGER NOT NULL DEFAULT 0,
+  pinned INTEGER NOT NULL DEFAULT 0,
+  muted INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE sync_state (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+"#;
+
+    fn open_fixture(version: i64) -> (tempfile::TempDir, Connection) {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut conn = Connection::open(dir.path().join("index.sqlite3")).expect("open fixture");
+        if version == 0 {
+            conn.execute_batch(LEGACY_UNVERSIONED_SQL)
+                .expect("create legacy schema");
+        } else {
+            let steps = MIGRATIONS
+                .iter()
+                .position(|migration| migration.version == version)
+                .expect("known schema version");
+            apply_migrations(&mut conn, &MIGRATIONS[..=steps]).expect("build fixture");
+        }
+        conn.execute_batch(
+            "
+            INSERT INTO messages (
+              message_id, thread_id, direction, source, destination, ts_ms,
+              title, body, updated_at_ms
+            ) VALUES
+              ('m1', 'peer-a', 'in', 'peer-a', 'self', 1000, 'hello', 'fixture body', 1000),
+              ('m2', 'peer-a', 'in', 'peer-a', 'self', 2000, '', 'at geo:46.5,8.25', 2000);
+            INSERT INTO attachments (message_id, ordinal, name, size_bytes)
+            VALUES ('m1', 0, 'note.txt', 4);
+            INSERT INTO threads (
+              thread_id, display_name, preview, last_message_id, last_activity_ms, pinned
+            ) VALUES ('peer-a', 'Peer A', 'fixture body', 'm1', 1000, 1);
+            ",
+        )
+        .expect("seed fixture");
+        (dir, conn)
+    }
+
+    #[test]
+    fn upgrades_fixture_from_every_historical_version() {
+        let versions =
+            std::iter::once(0).chain(MIGRATIONS.iter().map(|migration| migration.version));
+        for version in versions {
+            let (_dir, mut conn) = open_fixture(version);
+            run_schema_migrations(&mut conn)
+                .unwrap_or_else(|err| panic!("upgrade from version {version} failed: {err}"));
+
+            assert_eq!(
+                read_schema_version(&conn).expect("schema version"),
+                latest_schema_version()
+            );
+            let body: String = conn
+                .query_row(
+                    "SELECT body FROM messages WHERE message_id = 'm1'",
+                    [],
+                    |row| row.get(0),
+                )
+                .expect("message survives upgrade");
+            assert_eq!(body, "fixture body");
+            let fts_hits: i64 = conn
+                .query_row(
+                    "SELECT COUNT(*) FROM messages_fts WHERE messages_fts MATCH 'fixture'",
+                    [],
+                    |row| row.get(0),
+                )
+                .expect("fts rebuilt after upgrade");
+            assert_eq!(fts_hits, 1, "fts rebuilt from version {version}");
+            let spatial_hits: i64 = conn
+                .query_row(
+                    "
+                    SELECT COUNT(*)
+                    FROM map_points p
+                    JOIN map_points_rtree r ON r.id = p.id
+                    WHERE p.message_id = 'm2' AND r.min_lat <= 46.5 AND r.max_lat >= 46.5
+                    ",
+                    [],
+                    |row| row.get(0),
+                )
+                .expect("map points backfilled");
+            // Fixtures seeded after version 6 bypass ingest, so nothing extracts points.
+            if version < 6 {
+                assert_eq!(
+                    spatial_hits, 1,
+                    "map points backfilled from version {version}"
+                );
+            }
+            let attachment_created_at: i64 = conn
+                .query_row(
+                    "SELECT created_at_ms FROM attachments WHERE message_id = 'm1'",
+                    [],
+                    |row| row.get(0),
+                )
+                .expect("attachment survives upgrade");
+            if version == 0 {
+                assert_eq!(attachment_created_at, 1000);
+            }
+            let pinned: i64 = conn
+                .query_row(
+                    "SELECT pinned FROM thread_flags WHERE thread_id = 'peer-a'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap_or(0);
+            if version < 3 {
+                assert_eq!(pinned, 1, "legacy pin copied from version {version}");
+            }
+
+            // A second pass must be a no-op.
+            run_schema_migrations(&mut conn).expect("rerun migrations");
+            assert_eq!(
+                read_schema_version(&conn).expect("schema version"),
+                latest_schema_version()
+            );
+        }
+    }
+
+    #[test]
+    fn failed_migration_rolls_back_and_keeps_version() {
+        fn broken(conn: &Connection) -> Result<(), String> {
+            conn.execute_batch("CREATE TABLE half_applied (id INTEGER);")
+                .map_err(|err| err.to_string())?;
+            Err("boom".to_string())
+        }
+        let migrations = [
+            Migration {
+                version: 1,
+                name: "base_schema",
+                apply: migrate_base_schema,
+            },
+            Migration {
+                version: 2,
+                name: "broken",
+                apply: broken,
+            },
+        ];
+
+        let (_dir, mut conn) = open_fixture(0);
+        let err = apply_migrations(&mut conn, &migrations).expect_err("migration should fail");
+        assert!(err.contains("migration 2 (broken) failed: boom"), "{err}");
+        assert_eq!(read_schema_version(&conn).expect("schema version"), 1);
+        assert!(!table_column_exists(&conn, "half_applied", "id").expect("inspect"));
+    }
+
+    #[test]
+    fn rejects_newer_schema_version() {
+        let (_dir, mut conn) = open_fixture(latest_schema_version());
+        conn.pragma_update(None, "user_version", latest_schema_version() + 1)
+            .expect("bump version");
+        let err = run_schema_migrations(&mut conn).expect_err("newer schema rejected");
+        assert!(err.contains("newer than supported"), "{err}");
+    }
+
+    #[test]
+    fn index_status_reports_migration_failure() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("index.sqlite3");
+        {
+            let conn = Connection::open(&path).expect("open");
+            conn.pragma_update(None, "user_version", latest_schema_version() + 1)
+                .expect("bump version");
+        }
+
+        let store = IndexStore::open_or_fallback(path);
+        let status = store.index_status().expect("status");
+        assert!(status
+            .schema_error
+            .as_deref()
+            .is_some_and(|err| err.contains("newer than supported")));
+        assert_eq!(status.schema_version, latest_schema_version());
+        assert_eq!(status.message_count, 0);
+    }
+}
@@ -0,0 +1,493 @@
+use super::attachments::stored_payload;
+use super::delivery::{current_delivery_state, record_delivery_transition, requeue_delivery};
+use super::*;
+
+const OUTBOX_DEFAULT_STATES: [&str; 3] = ["queued", "sending", "failed"];
+const SEND_METHODS: [&str; 4] = ["direct", "opportunistic", "propagated", "paper"];
+
+/// The fallback after `method` when a message is stuck: direct (or no
+/// explicit method) goes through a propagation node, and a propagated message
+/// is handed over on paper. Paper is the last resort.
+fn next_route_method(method: Option<&str>) -> Option<&'static str> {
+    match method.map(str::trim).unwrap_or_default() {
+        "paper" => None,
+        "propagated" => Some("paper"),
+        _ => Some("propagated"),
+    }
+}
+
+impl IndexStore {
+    /// Outbound messages that have not gone out, newest first. Without
+    /// `states` this lists queued, sending and failed messages; cancelled
+    /// ones are only listed when asked for. Deleted messages and bare
+    /// reactions are never listed.
+    pub(crate) fn query_outbox(&self, params: OutboxQueryParams) -> Result<Value, String> {
+        let limit = normalize_limit(params.limit);
+        let keyset = decode_outbox_cursor(params.cursor.as_deref());
+        let mut states = Vec::new();
+        for state in params.states.unwrap_or_default() {
+            let parsed = DeliveryState::parse(&state)
+                .ok_or_else(|| format!("unknown delivery state: {state}"))?;
+            states.push(parsed.as_str());
+        }
+        if states.is_empty() {
+            states.extend(OUTBOX_DEFAULT_STATES);
+        }
+        let states_json = serde_json::to_string(&states)
+            .map_err(|err| format!("encode outbox states failed: {err}"))?;
+        let reason_code = params
+            .reason_code
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_ascii_lowercase);
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let mut stmt = conn
+            .prepare(
+                "
+                SELECT
+                  m.message_id,
+                  m.thread_id,
+                  m.destination,
+                  m.title,
+                  m.body,
+                  m.ts_ms,
+                  m.delivery_state,
+                  m.status_reason_code,
+                  m.receipt_status,
+                  json_extract(m.fields_json, '$._lxmf.method'),
+                  m.has_attachments,
+                  (
+                    SELECT COUNT(*) FROM delivery_events e
+                    WHERE e.message_id = m.message_id AND e.source = 'outbox' AND e.state = 'queued'
+                  ),
+                  (SELECT MAX(e.ts_ms) FROM delivery_events e WHERE e.message_id = m.message_id)
+                FROM messages m
+                WHERE m.direction = 'out'
+                  AND m.deleted_at_ms IS NULL
+                  AND m.reaction_only = 0
+                  AND m.delivery_state IN (SELECT value FROM json_each(?1))
+                  AND (?2 IS NULL OR m.status_reason_code = ?2)
+                  AND (
+                    ?3 IS NULL
+                    OR m.ts_ms < ?3
+                    OR (m.ts_ms = ?3 AND m.message_id > ?4)
+                  )
+                ORDER BY m.ts_ms DESC, m.message_id ASC
+                LIMIT ?5
+                ",
+            )
+            .map_err(|err| format!("prepare outbox query failed: {err}"))?;
+        let rows = stmt
+            .query_map(
+                params![
+                    states_json,
+                    reason_code,
+                    keyset.as_ref().map(|cursor| cursor.ts_ms),
+                    keyset.as_ref().map(|cursor| cursor.message_id.as_str()),
+                    (limit + 1) as i64
+                ],
+                |row| {
+                    let method = row
+                        .get::<_, Option<String>>(9)?
+                        .map(|value| value.trim().to_ascii_lowercase())
+                        .filter(|value| !value.is_empty());
+                    Ok(OutboxEntry {
+                        message_id: row.get(0)?,
+                        thread_id: row.get(1)?,
+                        destination: row.get(2)?,
+                        title: row.get(3)?,
+                        preview: row.get(4)?,
+                        ts_ms: row.get(5)?,
+                        state: row
+                            .get::<_, Option<String>>(6)?
+                            .as_deref()
+                            .and_then(DeliveryState::parse),
+                        reason_code: row.get(7)?,
+                        receipt_status: row.get(8)?,
+                        next_method: next_route_method(method.as_deref()),
+                        method,
+                        has_attachments: row.get::<_, i64>(10)? != 0,
+                        retry_count: row.get::<_, i64>(11)?.max(0) as usize,
+                        state_since_ms: row.get(12)?,
+                    })
+                },
+            )
+            .map_err(|err| format!("run outbox query failed: {err}"))?;
+        let mut items = Vec::new();
+        for result in rows {
+            items.push(result.map_err(|err| format!("parse outbox row failed: {err}"))?);
+        }
+
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().and_then(|entry| {
+                encode_outbox_cursor(&OutboxCursorKey {
+                    ts_ms: entry.ts_ms,
+                    message_id: entry.message_id.clone(),
+                })
+            })
+        } else {
+            None
+        };
+
+        serde_json::to_value(OutboxQueryResult { items, next_cursor })
+            .map_err(|err| format!("serialize outbox query failed: {err}"))
+    }
+
+    /// Re-queues a stored outbound message and returns what is needed to
+    /// send it again under the same id. A rerouted message keeps its new
+    /// method, so a later retry goes the same way.
+    pub(crate) fn begin_outbox_resend(
+        &self,
+        message_id: &str,
+        route: OutboxRoute,
+    ) -> Result<OutboxResend, String> {
+        let message_id = message_id.trim();
+        if message_id.is_empty() {
+            return Err("message_id is required".to_string());
+        }
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let stored = conn
+            .query_row(
+                "
+                SELECT source, destination, title, body, fields_json, deleted_at_ms
+                FROM messages
+                WHERE message_id = ?1 AND direction = 'out'
+                ",
+                params![message_id],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                        row.get::<_, Option<i64>>(5)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(|err| format!("read outbox message failed: {err}"))?;
+        let Some((source, destination, title, content, fields_json, deleted_at_ms)) = stored else {
+            return Err("outbound message not found".to_string());
+        };
+        if deleted_at_ms.is_some() {
+            return Err("message was deleted".to_string());
+        }
+        if current_delivery_state(&conn, message_id)? == Some(DeliveryState::Delivered) {
+            return Err("message was already delivered".to_string());
+        }
+
+        let mut fields = fields_json
+            .as_deref()
+            .and_then(|raw| serde_json::from_str::<Value>(raw).ok())
+            .and_then(|value| match value {
+                Value::Object(map) => Some(map),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let settings = match fields.remove("_lxmf") {
+            Some(Value::Object(settings)) => settings,
+            _ => serde_json::Map::new(),
+        };
+        let stored_method = settings
+            .get("method")
+            .and_then(Value::as_str)
+            .map(|value| value.trim().to_ascii_lowercase())
+            .filter(|value| !value.is_empty());
+        let method = match route {
+            OutboxRoute::Same => stored_method.clone(),
+            OutboxRoute::Next => Some(
+                next_route_method(stored_method.as_deref())
+                    .ok_or_else(|| "no fallback route after paper".to_string())?
+                    .to_string(),
+            ),
+            OutboxRoute::Method(method) => {
+                let method = method.trim().to_ascii_lowercase();
+                if !SEND_METHODS.contains(&method.as_str()) {
+                    return Err(format!("unknown send method: {method}"));
+                }
+                Some(method)
+            }
+        };
+        let rerouted = method != stored_method;
+        fields.remove("attachments");
+        fields.remove("5");
+        let attachments = self.load_resend_attachments(&conn, message_id)?;
+
+        let detail = match method.as_deref() {
+            Some(method) if rerouted => format!("reroute via {method}"),
+            _ => "retry".to_string(),
+        };
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start outbox transaction failed: {err}"))?;
+        requeue_delivery(&tx, message_id, &detail)?;
+        if rerouted {
+            let mut stored_fields = fields_json
+                .as_deref()
+                .and_then(|raw| serde_json::from_str::<Value>(raw).ok())
+                .filter(Value::is_object)
+                .unwrap_or_else(|| json!({}));
+            let mut settings = settings.clone();
+            settings.insert("method".to_string(), json!(method));
+            stored_fields["_lxmf"] = Value::Object(settings);
+            tx.execute(
+                "UPDATE messages SET fields_json = ?1 WHERE message_id = ?2",
+                params![stored_fields.to_string(), message_id],
+            )
+            .map_err(|err| format!("store outbox route failed: {err}"))?;
+        }
+        tx.commit()
+            .map_err(|err| format!("commit outbox transaction failed: {err}"))?;
+
+        Ok(OutboxResend {
+            message_id: message_id.to_string(),
+            source: Some(source).filter(|value| !value.trim().is_empty()),
+            destination,
+            title,
+            content,
+            fields: if fields.is_empty() {
+                None
+            } else {
+                Some(Value::Object(fields))
+            },
+            attachments,
+            method,
+            stamp_cost: settings
+                .get("stamp_cost")
+                .and_then(Value::as_u64)
+                .and_then(|value| u32::try_from(value).ok()),
+            include_ticket: settings
+                .get("include_ticket")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        })
+    }
+
+    /// Records that a resend started by `begin_outbox_resend` never reached
+    /// the runtime.
+    pub(crate) fn fail_outbox_resend(&self, message_id: &str, error: &str) -> Result<(), String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        record_delivery_transition(
+            &conn,
+            message_id.trim(),
+            DeliveryState::Failed,
+            current_timestamp_ms(),
+            None,
+            Some(error),
+            "outbox",
+        )?;
+        Ok(())
+    }
+
+    /// Takes a queued, sending or failed message out of the outbox. The
+    /// runtime cannot abort a send, so this only stops the app from
+    /// retrying or counting it; an attempt already in flight may still
+    /// arrive. Returns the updated delivery timeline.
+    pub(crate) fn cancel_outbox_message(&self, message_id: &str) -> Result<Value, String> {
+        let message_id = message_id.trim();
+        if message_id.is_empty() {
+            return Err("message_id is required".to_string());
+        }
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        match current_delivery_state(&conn, message_id)? {
+            Some(
+                DeliveryState::Queued
+                | DeliveryState::Sending
+                | DeliveryState::Failed
+                | DeliveryState::Cancelled,
+            ) => {}
+            Some(state) => {
+                return Err(format!(
+                    "cannot cancel a message that is already {}",
+                    state.as_str()
+                ))
+            }
+            None => return Err("outbound message not found".to_string()),
+        }
+        record_delivery_transition(
+            &conn,
+            message_id,
+            DeliveryState::Cancelled,
+            current_timestamp_ms(),
+            None,
+            None,
+            "outbox",
+        )?;
+        drop(conn);
+        self.query_delivery_timeline(message_id)
+    }
+
+    fn load_resend_attachments(
+        &self,
+        conn: &Connection,
+        message_id: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let mut stmt = conn
+            .prepare_cached(
+                "
+                SELECT name, inline_base64, blob_sha256
+                FROM attachments
+                WHERE message_id = ?1
+                ORDER BY ordinal ASC
+                ",
+            )
+            .map_err(|err| format!("prepare resend attachments failed: {err}"))?;
+        let rows = stmt
+            .query_map(params![message_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })
+            .map_err(|err| format!("query resend attachments failed: {err}"))?;
+        let mut attachments = Vec::new();
+        for row in rows {
+            let (name, inline_base64, blob_sha256) =
+                row.map_err(|err| format!("parse resend attachment failed: {err}"))?;
+            let payload = stored_payload(inline_base64, blob_sha256)
+                .ok_or_else(|| format!("attachment {name} has no stored payload"))?;
+            attachments.push((name, self.payload_bytes(payload)?));
+        }
+        Ok(attachments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outbox_lists_stuck_messages_and_requeues_them() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        let payload = base64::engine::general_purpose::STANDARD.encode(b"report");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    {
+                        "id": "m1", "source": "self", "destination": "peer-a", "direction": "out",
+                        "timestamp": 1_000, "content": "with file", "receipt_status": "failed: no path",
+                        "fields": {
+                            "_lxmf": { "method": "direct", "stamp_cost": 8, "include_ticket": true },
+                            "attachments": [{ "name": "report.txt", "inline_base64": payload }],
+                        },
+                    },
+                    {
+                        "id": "m2", "source": "self", "destination": "peer-a", "direction": "out",
+                        "timestamp": 2_000, "content": "waiting", "receipt_status": "pending",
+                    },
+                    {
+                        "id": "m3", "source": "self", "destination": "peer-b", "direction": "out",
+                        "timestamp": 3_000, "content": "done", "receipt_status": "delivered",
+                    },
+                    {
+                        "id": "m4", "source": "self", "destination": "peer-b", "direction": "out",
+                        "timestamp": 4_000, "content": "oops", "receipt_status": "failed: no path",
+                    },
+                    {
+                        "id": "d4", "source": "self", "destination": "peer-b", "direction": "out",
+                        "timestamp": 5_000, "content": delete_fallback_body(),
+                        "receipt_status": "delivered",
+                        "fields": { "16": { "delete_of": "m4" } },
+                    },
+                    {
+                        "id": "r1", "source": "self", "destination": "peer-a", "direction": "out",
+                        "timestamp": 6_000, "content": "", "receipt_status": "pending",
+                        "fields": { "16": { "reaction_to": "m1", "emoji": "👍" } },
+                    },
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+
+        let outbox = store
+            .query_outbox(OutboxQueryParams {
+                states: None,
+                reason_code: None,
+                limit: None,
+                cursor: None,
+            })
+            .expect("outbox");
+        let ids: Vec<_> = outbox["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .map(|item| item["message_id"].as_str().unwrap_or_default())
+            .collect();
+        assert_eq!(ids, vec!["m2", "m1"]);
+        assert_eq!(
+            store
+                .begin_outbox_resend("m4", OutboxRoute::Same)
+                .expect_err("deleted message"),
+            "message was deleted"
+        );
+        let no_path = store
+            .query_outbox(OutboxQueryParams {
+                states: None,
+                reason_code: Some("no_path".to_string()),
+                limit: None,
+                cursor: None,
+            })
+            .expect("filtered outbox");
+        assert_eq!(no_path["items"][0]["message_id"], "m1");
+        assert_eq!(no_path["items"][0]["next_method"], "propagated");
+
+        let resend = store
+            .begin_outbox_resend("m1", OutboxRoute::Next)
+            .expect("reroute");
+        assert_eq!(resend.method.as_deref(), Some("propagated"));
+        assert_eq!(resend.stamp_cost, Some(8));
+        assert!(resend.include_ticket);
+        assert_eq!(
+            resend.attachments,
+            vec![("report.txt".to_string(), b"report".to_vec())]
+        );
+        assert!(resend.fields.is_none());
+        let retry = store
+            .begin_outbox_resend("m1", OutboxRoute::Same)
+            .expect("retry");
+        assert_eq!(retry.method.as_deref(), Some("propagated"));
+        let timeline = store.query_delivery_timeline("m1").expect("timeline");
+        assert_eq!(timeline["state"], "queued");
+        assert_eq!(timeline["reason_code"], Value::Null);
+
+        store.cancel_outbox_message("m2").expect("cancel");
+        // A sync still reporting the old status does not reopen it.
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "receipt",
+                "payload": { "message_id": "m2", "status": "sending" },
+            }))
+            .expect("stale receipt");
+        let timeline = store.query_delivery_timeline("m2").expect("timeline");
+        assert_eq!(timeline["state"], "cancelled");
+        // An attempt that was already in flight can still arrive.
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "receipt",
+                "payload": { "message_id": "m2", "status": "delivered" },
+            }))
+            .expect("late delivery");
+        let timeline = store.query_delivery_timeline("m2").expect("timeline");
+        assert_eq!(timeline["state"], "delivered");
+        assert!(store.cancel_outbox_message("m3").is_err());
+        assert!(store.begin_outbox_resend("m3", OutboxRoute::Same).is_err());
+    }
+}
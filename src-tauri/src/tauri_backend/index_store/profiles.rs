@@ -0,0 +1,297 @@
+use super::*;
+use std::path::Path;
+use std::sync::Arc;
+
+/// One index database per runtime profile, so identities never share
+/// conversations and a backfill for one profile cannot prune another's rows.
+/// Stores open lazily and stay open; `active` is the one the UI queries.
+pub(crate) struct IndexStores {
+    base_path: PathBuf,
+    stores: Mutex<HashMap<String, Arc<IndexStore>>>,
+    active: Mutex<(String, Arc<IndexStore>)>,
+}
+
+impl IndexStores {
+    /// Opens the index for `profile` next to `base_path`. A shared index left
+    /// by builds without per-profile databases is assigned to this profile;
+    /// the next backfill prunes whatever belonged to other identities.
+    pub(crate) fn open(base_path: PathBuf, profile: &str) -> Self {
+        let profile_path = profile_index_path(&base_path, profile);
+        match adopt_legacy_index(&base_path, &profile_path) {
+            Ok(true) => log::info!(
+                "assigned shared index {} to profile '{profile}'",
+                base_path.display()
+            ),
+            Ok(false) => {}
+            Err(err) => log::warn!("shared index left in place: {err}"),
+        }
+        let store = Arc::new(IndexStore::open_or_fallback(profile_path));
+        Self {
+            base_path,
+            stores: Mutex::new(HashMap::from([(profile.to_string(), store.clone())])),
+            active: Mutex::new((profile.to_string(), store)),
+        }
+    }
+
+    pub(crate) fn active(&self) -> Arc<IndexStore> {
+        match self.active.lock() {
+            Ok(guard) => guard.1.clone(),
+            Err(poisoned) => poisoned.into_inner().1.clone(),
+        }
+    }
+
+    pub(crate) fn active_profile(&self) -> String {
+        match self.active.lock() {
+            Ok(guard) => guard.0.clone(),
+            Err(poisoned) => poisoned.into_inner().0.clone(),
+        }
+    }
+
+    pub(crate) fn for_profile(&self, profile: &str) -> Arc<IndexStore> {
+        let mut stores = match self.stores.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        stores
+            .entry(profile.to_string())
+            .or_insert_with(|| {
+                Arc::new(IndexStore::open_or_fallback(profile_index_path(
+                    &self.base_path,
+                    profile,
+                )))
+            })
+            .clone()
+    }
+
+    /// Makes `profile` the active index. Returns its store when this changed
+    /// the active profile so the caller can start a backfill.
+    pub(crate) fn activate(&self, profile: &str) -> Option<Arc<IndexStore>> {
+        if self.active_profile() == profile {
+            return None;
+        }
+        let store = self.for_profile(profile);
+        let mut active = match self.active.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if active.0 == profile {
+            return None;
+        }
+        log::info!("index switched from profile '{}' to '{profile}'", active.0);
+        *active = (profile.to_string(), store.clone());
+        Some(store)
+    }
+
+    pub(crate) fn open_stores(&self) -> Vec<Arc<IndexStore>> {
+        match self.stores.lock() {
+            Ok(guard) => guard.values().cloned().collect(),
+            Err(poisoned) => poisoned.into_inner().values().cloned().collect(),
+        }
+    }
+
+    /// Opens the index of every other profile on disk that still has sends
+    /// waiting, so they go out after a restart that selected another profile.
+    /// Returns the profiles it opened.
+    pub(crate) fn open_profiles_with_pending_sends(&self) -> Vec<String> {
+        let Some(dir) = self.base_path.parent() else {
+            return Vec::new();
+        };
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!("scan profile indexes failed: {err}");
+                return Vec::new();
+            }
+        };
+        let mut profiles = entries
+            .flatten()
+            .filter_map(|entry| profile_from_index_path(&self.base_path, &entry.path()))
+            .collect::<Vec<_>>();
+        profiles.sort();
+
+        let mut opened = Vec::new();
+        for profile in profiles {
+            let mut stores = match self.stores.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if stores.contains_key(&profile) {
+                continue;
+            }
+            let store = IndexStore::open_or_fallback(profile_index_path(&self.base_path, &profile));
+            match store.has_pending_sends() {
+                Ok(true) => {
+                    stores.insert(profile.clone(), Arc::new(store));
+                    opened.push(profile);
+                }
+                Ok(false) => {}
+                Err(err) => log::warn!("send queue of profile '{profile}' unreadable: {err}"),
+            }
+        }
+        opened
+    }
+}
+
+/// `weft-index-v1.sqlite3` becomes `weft-index-v1.<profile>.sqlite3`. Profile
+/// names are validated by `RuntimeSelector`; anything else that could form a
+/// path is replaced so the result stays a sibling of `base_path`.
+fn profile_index_path(base_path: &Path, profile: &str) -> PathBuf {
+    let stem = base_path
+        .file_stem()
+        .map(|value| value.to_string_lossy().to_string())
+        .unwrap_or_else(|| "weft-index".to_string());
+    let extension = base_path
+        .extension()
+        .map(|value| value.to_string_lossy().to_string())
+        .unwrap_or_else(|| "sqlite3".to_string());
+    let profile = profile
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.') {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    base_path.with_file_name(format!("{stem}.{profile}.{extension}"))
+}
+
+// The inverse of `profile_index_path` for names it could have produced.
+fn profile_from_index_path(base_path: &Path, path: &Path) -> Option<String> {
+    let stem = base_path.file_stem()?.to_str()?;
+    let extension = base_path.extension()?.to_str()?;
+    let name = path.file_name()?.to_str()?;
+    let profile = name
+        .strip_prefix(stem)?
+        .strip_prefix('.')?
+        .strip_suffix(extension)?
+        .strip_suffix('.')?;
+    let valid = !profile.is_empty()
+        && profile
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'));
+    (valid && path.is_file()).then(|| profile.to_string())
+}
+
+// Moves the database, its WAL sidecars and blob directory together so SQLite
+// still finds uncheckpointed pages under the new name.
+fn adopt_legacy_index(base_path: &Path, profile_path: &Path) -> Result<bool, String> {
+    if !base_path.is_file() || profile_path.exists() {
+        return Ok(false);
+    }
+    for suffix in ["-wal", "-shm"] {
+        let mut legacy = base_path.as_os_str().to_os_string();
+        legacy.push(suffix);
+        let legacy = PathBuf::from(legacy);
+        if legacy.exists() {
+            let mut target = profile_path.as_os_str().to_os_string();
+            target.push(suffix);
+            std::fs::rename(&legacy, PathBuf::from(target))
+                .map_err(|err| format!("move index {suffix} file failed: {err}"))?;
+        }
+    }
+    let legacy_blobs = BlobStore::for_index(base_path);
+    let profile_blobs = BlobStore::for_index(profile_path);
+    if legacy_blobs.root().is_dir() && !profile_blobs.root().exists() {
+        std::fs::rename(legacy_blobs.root(), profile_blobs.root())
+            .map_err(|err| format!("move index blobs failed: {err}"))?;
+    }
+    std::fs::rename(base_path, profile_path)
+        .map_err(|err| format!("move index database failed: {err}"))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str) -> Value {
+        json!([{ "id": id, "source": "peer-a", "destination": "self", "direction": "in",
+                 "timestamp": 1_000, "content": format!("hello {id}"),
+                 "fields": { "attachments": [{ "name": "a.bin", "inline_base64": "eHl6" }] } }])
+    }
+
+    #[test]
+    fn profiles_get_separate_indexes_and_adopt_the_shared_one() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let base = dir.path().join("weft-index-v1.sqlite3");
+        {
+            let shared = IndexStore::new(base.clone()).expect("open shared");
+            shared
+                .reindex_from_runtime_payloads(&message("legacy"), &json!([]))
+                .expect("seed shared");
+        }
+
+        let stores = IndexStores::open(base.clone(), "alice");
+        assert!(!base.exists());
+        assert!(dir.path().join("weft-index-v1.alice.sqlite3").is_file());
+        assert!(dir.path().join("weft-index-v1.alice.blobs").is_dir());
+        assert_eq!(
+            stores
+                .active()
+                .index_status()
+                .expect("status")
+                .message_count,
+            1
+        );
+
+        let bob = stores.activate("bob").expect("switched to bob");
+        assert!(stores.activate("bob").is_none());
+        assert_eq!(stores.active_profile(), "bob");
+        assert_eq!(bob.index_status().expect("status").message_count, 0);
+        bob.reindex_from_runtime_payloads(&message("b1"), &json!([]))
+            .expect("sync bob");
+        assert_eq!(
+            stores
+                .for_profile("alice")
+                .index_status()
+                .expect("status")
+                .message_count,
+            1
+        );
+        assert_eq!(stores.open_stores().len(), 2);
+        assert_eq!(
+            profile_index_path(&base, "a/../b"),
+            dir.path().join("weft-index-v1.a_.._b.sqlite3")
+        );
+    }
+
+    #[test]
+    fn opens_other_profiles_that_still_have_sends_queued() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let base = dir.path().join("weft-index-v1.sqlite3");
+        {
+            let stores = IndexStores::open(base.clone(), "alice");
+            let queued = QueuedSend {
+                id: None,
+                source: None,
+                destination: "peer-a".to_string(),
+                title: String::new(),
+                content: "hello".to_string(),
+                fields: None,
+                method: None,
+                stamp_cost: None,
+                include_ticket: false,
+            };
+            stores
+                .for_profile("bob")
+                .enqueue_send("bob", "127.0.0.1:4243", queued)
+                .expect("enqueue for bob");
+            stores.for_profile("carol");
+        }
+
+        let stores = IndexStores::open(base.clone(), "alice");
+        assert_eq!(stores.open_profiles_with_pending_sends(), ["bob"]);
+        assert_eq!(stores.open_stores().len(), 2);
+        assert!(stores.open_profiles_with_pending_sends().is_empty());
+        assert_eq!(
+            profile_from_index_path(&base, &profile_index_path(&base, "carol")),
+            Some("carol".to_string())
+        );
+        assert_eq!(
+            profile_from_index_path(&base, &dir.path().join("weft-index-v1.alice.sqlite3-wal")),
+            None
+        );
+    }
+}
@@ -1,7 +1,7 @@
+use super::attachments::StoredPayload;
+use super::search_query::{parse_search_query, scan_snippet, split_snippet_markers};
 use super::*;
-
-const MAP_QUERY_MESSAGE_BATCH: usize = 320;
-const MAP_QUERY_SCAN_LIMIT: usize = 4_000;
+use rusqlite::types::Value as SqlValue;
 
 impl IndexStore {
     pub(crate) fn query_threads(&self, params: ThreadQueryParams) -> Result<Value, String> {
@@ -15,6 +15,7 @@ impl IndexStore {
             .map(|value| value.to_ascii_lowercase());
         let query_like = query_filter.as_deref().map(|value| format!("%{}%", value));
         let pinned_only = params.pinned_only.unwrap_or(false);
+        let archived = params.archived.map(|value| if value { 1 } else { 0 });
 
         let conn = self
             .conn
@@ -25,29 +26,35 @@ impl IndexStore {
             .prepare(
                 "
                 SELECT
-                  thread_id,
-                  display_name,
-                  preview,
-                  unread_count,
-                  pinned,
-                  muted,
-                  last_message_id,
-                  last_activity_ms
-                FROM threads
-                WHERE (?1 = 0 OR pinned = 1)
+                  t.thread_id,
+                  t.display_name,
+                  t.preview,
+                  t.unread_count,
+                  t.pinned,
+                  t.muted,
+                  t.last_message_id,
+                  t.last_activity_ms,
+                  r.last_read_message_id,
+                  COALESCE(f.archived, 0),
+                  f.muted_until_ms
+                FROM threads t
+                LEFT JOIN thread_read_state r ON r.thread_id = t.thread_id
+                LEFT JOIN thread_flags f ON f.thread_id = t.thread_id
+                WHERE (?1 = 0 OR t.pinned = 1)
+                  AND (?7 IS NULL OR COALESCE(f.archived, 0) = ?7)
                   AND (
                     ?2 IS NULL
-                    OR LOWER(display_name) LIKE ?2
-                    OR LOWER(thread_id) LIKE ?2
-                    OR LOWER(preview) LIKE ?2
+                    OR LOWER(t.display_name) LIKE ?2
+                    OR LOWER(t.thread_id) LIKE ?2
+                    OR LOWER(t.preview) LIKE ?2
                   )
                   AND (
                     ?3 IS NULL
-                    OR pinned < ?3
-                    OR (pinned = ?3 AND last_activity_ms < ?4)
-                    OR (pinned = ?3 AND last_activity_ms = ?4 AND thread_id < ?5)
+                    OR t.pinned < ?3
+                    OR (t.pinned = ?3 AND t.last_activity_ms < ?4)
+                    OR (t.pinned = ?3 AND t.last_activity_ms = ?4 AND t.thread_id < ?5)
                   )
-                ORDER BY pinned DESC, last_activity_ms DESC, thread_id DESC
+                ORDER BY t.pinned DESC, t.last_activity_ms DESC, t.thread_id DESC
                 LIMIT ?6
                 ",
             )
@@ -63,9 +70,11 @@ impl IndexStore {
                         .map(|cursor| if cursor.pinned { 1 } else { 0 }),
                     keyset.as_ref().map(|cursor| cursor.last_activity_ms),
                     keyset.as_ref().map(|cursor| cursor.thread_id.as_str()),
-                    (limit + 1) as i64
+                    (limit + 1) as i64,
+                    archived,
                 ],
                 |row| {
+                    let muted_until_ms = row.get::<_, Option<i64>>(10).ok().flatten();
                     Ok(IndexedThread {
                         thread_id: row.get::<_, String>(0)?,
                         name: row.get::<_, String>(1)?,
@@ -73,9 +82,15 @@ impl IndexStore {
                         preview: row.get::<_, String>(2)?,
                         unread: row.get::<_, i64>(3).unwrap_or(0).max(0) as usize,
                         pinned: row.get::<_, i64>(4).unwrap_or(0) == 1,
-                        muted: row.get::<_, i64>(5).unwrap_or(0) == 1,
+                        muted: row.get::<_, i64>(5).unwrap_or(0) == 1
+                            && muted_until_ms
+                                .map(|until| until > current_timestamp_ms())
+                                .unwrap_or(true),
                         last_message_id: row.get::<_, Option<String>>(6).ok().flatten(),
                         last_activity_ms: row.get::<_, i64>(7).unwrap_or(0),
+                        last_read_message_id: row.get::<_, Option<String>>(8).ok().flatten(),
+                        archived: row.get::<_, i64>(9).unwrap_or(0) == 1,
+                        muted_until_ms,
                     })
                 },
             )
@@ -128,7 +143,7 @@ impl IndexStore {
             .map_err(|_| "index lock poisoned".to_string())?;
 
         let mut stmt = conn
-            .prepare(
+            .prepare(&format!(
                 "
                 SELECT
                   message_id,
@@ -142,6 +157,7 @@ impl IndexStore {
                   fields_json
                 FROM messages
                 WHERE thread_id = ?1
+                  AND {}
                   AND (
                     ?2 IS NULL
                     OR LOWER(title) LIKE ?2
@@ -156,7 +172,8 @@ impl IndexStore {
                 ORDER BY ts_ms DESC, message_id DESC
                 LIMIT ?5
                 ",
-            )
+                listed_messages_sql("")
+            ))
             .map_err(|err| format!("prepare message query failed: {err}"))?;
 
         let rows = stmt
@@ -168,25 +185,7 @@ impl IndexStore {
                     keyset.as_ref().map(|cursor| cursor.message_id.as_str()),
                     (limit + 1) as i64
                 ],
-                |row| {
-                    let message_id = row.get::<_, String>(0)?;
-                    let fields_json = row.get::<_, Option<String>>(8).ok().flatten();
-                    let fields = fields_json
-                        .as_deref()
-                        .and_then(|value| serde_json::from_str::<Value>(value).ok())
-                        .unwrap_or_else(|| Value::Object(serde_json::Map::new()));
-                    Ok(IndexedMessage {
-                        id: message_id.clone(),
-                        source: row.get::<_, String>(1)?,
-                        destination: row.get::<_, String>(2)?,
-                        title: row.get::<_, String>(3)?,
-                        content: row.get::<_, String>(4)?,
-                        timestamp: row.get::<_, i64>(5)?,
-                        direction: row.get::<_, String>(6)?,
-                        fields: sanitize_fields_for_client(&conn, &message_id, fields),
-                        receipt_status: row.get::<_, Option<String>>(7).ok().flatten(),
-                    })
-                },
+                |row| indexed_message_from_row(&conn, row),
             )
             .map_err(|err| format!("run message query failed: {err}"))?;
 
@@ -217,134 +216,100 @@ impl IndexStore {
         if query.is_empty() {
             return Err("query is required".to_string());
         }
+        let parsed = parse_search_query(query)?;
+        let by_relevance = match params.sort.as_deref().map(str::trim) {
+            None | Some("") | Some("time") => false,
+            Some("relevance") => true,
+            Some(other) => {
+                return Err(format!(
+                    "unsupported search sort '{other}' (expected time or relevance)"
+                ))
+            }
+        };
         let limit = normalize_limit(params.limit);
-        let offset = parse_cursor_offset(params.cursor.as_deref());
+        let keyset = decode_search_cursor(params.cursor.as_deref())?;
 
         let conn = self
             .conn
             .lock()
             .map_err(|_| "index lock poisoned".to_string())?;
 
-        let thread_id = params
+        // Edits and deletes only change other messages; their fallback text
+        // and the text of deleted messages are not searchable. Reaction-only
+        // messages are not messages of their own.
+        let mut filters = vec![visible_messages_sql("m.")];
+        let mut filter_params = Vec::new();
+        if let Some(thread_id) = params
             .thread_id
             .as_deref()
             .map(str::trim)
             .filter(|value| !value.is_empty())
-            .map(str::to_string);
+        {
+            filters.push("m.thread_id = ?".to_string());
+            filter_params.push(SqlValue::Text(thread_id.to_string()));
+        }
+        parsed.push_filters(&mut filters, &mut filter_params);
 
+        let fts = parsed.fts_match();
+        let resume_scan = keyset.as_ref().map(|cursor| cursor.scan);
         let mut items = Vec::new();
-        if let Some(fts_query) = build_fts_query(query) {
-            let mut stmt = conn
-                .prepare(
-                    "
-                    SELECT
-                      m.message_id,
-                      m.source,
-                      m.destination,
-                      m.title,
-                      m.body,
-                      m.ts_ms,
-                      m.direction,
-                      m.receipt_status,
-                      m.fields_json
-                    FROM messages_fts f
-                    JOIN messages m ON m.rowid = f.rowid
-                    WHERE f.messages_fts MATCH ?1
-                      AND (?2 IS NULL OR m.thread_id = ?2)
-                    ORDER BY m.ts_ms DESC, m.message_id DESC
-                    LIMIT ?3 OFFSET ?4
-                    ",
-                )
-                .map_err(|err| format!("prepare fts search failed: {err}"))?;
-            let rows = stmt
-                .query_map(
-                    params![fts_query, thread_id, (limit + 1) as i64, offset as i64],
-                    |row| {
-                        let message_id = row.get::<_, String>(0)?;
-                        let fields_json = row.get::<_, Option<String>>(8).ok().flatten();
-                        let fields = fields_json
-                            .as_deref()
-                            .and_then(|value| serde_json::from_str::<Value>(value).ok())
-                            .unwrap_or_else(|| Value::Object(serde_json::Map::new()));
-                        Ok(IndexedMessage {
-                            id: message_id.clone(),
-                            source: row.get::<_, String>(1)?,
-                            destination: row.get::<_, String>(2)?,
-                            title: row.get::<_, String>(3)?,
-                            content: row.get::<_, String>(4)?,
-                            timestamp: row.get::<_, i64>(5)?,
-                            direction: row.get::<_, String>(6)?,
-                            fields: sanitize_fields_for_client(&conn, &message_id, fields),
-                            receipt_status: row.get::<_, Option<String>>(7).ok().flatten(),
-                        })
-                    },
-                )
-                .map_err(|err| format!("run fts search failed: {err}"))?;
-            for result in rows {
-                items.push(result.map_err(|err| format!("parse search row failed: {err}"))?);
-            }
+        let mut scan = fts.is_none() || resume_scan == Some(true);
+        if let (Some((fts_table, fts_query)), false) = (fts, scan) {
+            let source = SearchSource::Fts {
+                table: fts_table,
+                query: fts_query,
+                by_relevance,
+            };
+            items = run_message_search(
+                &conn,
+                &source,
+                &filters,
+                filter_params.clone(),
+                keyset.as_ref(),
+                limit,
+            )
+            .map_err(|err| format!("fts search failed: {err}"))?;
+            // Free text that FTS cannot match (e.g. infixes) falls back to a
+            // substring scan, but only when starting a fresh search.
+            scan = items.is_empty() && keyset.is_none();
         }
 
-        if items.is_empty() {
-            let like = format!("%{}%", query.to_ascii_lowercase());
-            let mut stmt = conn
-                .prepare(
-                    "
-                    SELECT
-                      message_id,
-                      source,
-                      destination,
-                      title,
-                      body,
-                      ts_ms,
-                      direction,
-                      receipt_status,
-                      fields_json
-                    FROM messages
-                    WHERE (LOWER(title) LIKE ?1 OR LOWER(body) LIKE ?1)
-                      AND (?2 IS NULL OR thread_id = ?2)
-                    ORDER BY ts_ms DESC, message_id DESC
-                    LIMIT ?3 OFFSET ?4
-                    ",
-                )
-                .map_err(|err| format!("prepare fallback search failed: {err}"))?;
-            let rows = stmt
-                .query_map(
-                    params![like, thread_id, (limit + 1) as i64, offset as i64],
-                    |row| {
-                        let message_id = row.get::<_, String>(0)?;
-                        let fields_json = row.get::<_, Option<String>>(8).ok().flatten();
-                        let fields = fields_json
-                            .as_deref()
-                            .and_then(|value| serde_json::from_str::<Value>(value).ok())
-                            .unwrap_or_else(|| Value::Object(serde_json::Map::new()));
-                        Ok(IndexedMessage {
-                            id: message_id.clone(),
-                            source: row.get::<_, String>(1)?,
-                            destination: row.get::<_, String>(2)?,
-                            title: row.get::<_, String>(3)?,
-                            content: row.get::<_, String>(4)?,
-                            timestamp: row.get::<_, i64>(5)?,
-                            direction: row.get::<_, String>(6)?,
-                            fields: sanitize_fields_for_client(&conn, &message_id, fields),
-                            receipt_status: row.get::<_, Option<String>>(7).ok().flatten(),
-                        })
-                    },
-                )
-                .map_err(|err| format!("run fallback search failed: {err}"))?;
-            for result in rows {
-                items.push(result.map_err(|err| format!("parse fallback row failed: {err}"))?);
+        if scan {
+            let mut clauses = Vec::new();
+            let mut values = Vec::new();
+            parsed.push_text_filters(&mut clauses, &mut values);
+            clauses.extend(filters);
+            values.extend(filter_params);
+            items = run_message_search(
+                &conn,
+                &SearchSource::Scan,
+                &clauses,
+                values,
+                keyset.as_ref(),
+                limit,
+            )
+            .map_err(|err| format!("fallback search failed: {err}"))?;
+            let terms = parsed.highlight_terms();
+            for hit in &mut items {
+                let (snippet, highlights) = scan_snippet(&hit.message, &terms);
+                hit.snippet = snippet;
+                hit.highlights = highlights;
             }
         }
 
         let next_cursor = if items.len() > limit {
-            Some((offset + limit).to_string())
+            items.truncate(limit);
+            items.last().and_then(|last| {
+                encode_search_cursor(&SearchCursorKey {
+                    timestamp: last.message.timestamp,
+                    message_id: last.message.id.clone(),
+                    score: last.score.map(|score| score.to_string()),
+                    scan,
+                })
+            })
         } else {
             None
         };
-        if items.len() > limit {
-            items.truncate(limit);
-        }
 
         serde_json::to_value(CursorResult { items, next_cursor })
             .map_err(|err| format!("serialize search query failed: {err}"))
@@ -388,7 +353,8 @@ impl IndexStore {
                   file_rows.paper_uri,
                   file_rows.paper_title,
                   file_rows.paper_category,
-                  file_rows.sort_id
+                  file_rows.sort_id,
+                  file_rows.blob_sha256
                 FROM (
                   SELECT
                     CAST(a.id AS TEXT) AS id,
@@ -410,6 +376,7 @@ impl IndexStore {
                     m.source AS owner_source,
                     a.mime AS mime,
                     CASE
+                      WHEN a.blob_sha256 IS NOT NULL THEN 1
                       WHEN a.inline_base64 IS NULL OR a.inline_base64 = '' THEN 0
                       ELSE 1
                     END AS has_inline_data,
@@ -420,7 +387,8 @@ impl IndexStore {
                     NULL AS paper_uri,
                     NULL AS paper_title,
                     NULL AS paper_category,
-                    printf('a:%020d', a.id) AS sort_id
+                    printf('a:%020d', a.id) AS sort_id,
+                    a.blob_sha256 AS blob_sha256
                   FROM attachments a
                   JOIN messages m ON m.message_id = a.message_id
                   WHERE (
@@ -449,7 +417,8 @@ impl IndexStore {
                     NULLIF(TRIM(json_extract(m.fields_json, '$.paper.uri')), '') AS paper_uri,
                     NULLIF(TRIM(json_extract(m.fields_json, '$.paper.title')), '') AS paper_title,
                     NULLIF(TRIM(json_extract(m.fields_json, '$.paper.category')), '') AS paper_category,
-                    'p:' || m.message_id AS sort_id
+                    'p:' || m.message_id AS sort_id,
+                    NULL AS blob_sha256
                   FROM messages m
                   WHERE m.fields_json IS NOT NULL
                     AND json_type(m.fields_json, '$.paper') = 'object'
@@ -503,6 +472,7 @@ impl IndexStore {
                     let paper_title = row.get::<_, Option<String>>(10).ok().flatten();
                     let paper_category = row.get::<_, Option<String>>(11).ok().flatten();
                     let sort_id = row.get::<_, String>(12)?;
+                    let blob_sha256 = row.get::<_, Option<String>>(13).ok().flatten();
                     let size_label = if kind == "Note" {
                         "—".to_string()
                     } else {
@@ -526,12 +496,13 @@ impl IndexStore {
                         },
                         created_at_ms,
                         sort_id,
+                        blob_sha256,
                     ))
                 },
             )
             .map_err(|err| format!("run file query failed: {err}"))?;
 
-        let mut entries = Vec::<(IndexedFileItem, i64, String)>::new();
+        let mut entries = Vec::<(IndexedFileItem, i64, String, Option<String>)>::new();
         for result in rows {
             entries.push(result.map_err(|err| format!("parse file row failed: {err}"))?);
         }
@@ -539,7 +510,7 @@ impl IndexStore {
         let next_cursor = if entries.len() > limit {
             let marker = entries
                 .get(limit.saturating_sub(1))
-                .map(|(_, created_at_ms, sort_id)| (*created_at_ms, sort_id.clone()));
+                .map(|(_, created_at_ms, sort_id, _)| (*created_at_ms, sort_id.clone()));
             entries.truncate(limit);
             marker.and_then(|(created_at_ms, sort_id)| {
                 encode_file_cursor(&FileCursorKey {
@@ -553,7 +524,13 @@ impl IndexStore {
 
         let items = entries
             .into_iter()
-            .map(|(item, _, _)| item)
+            .map(|(mut item, _, _, blob_sha256)| {
+                if let Some(sha256) = blob_sha256.filter(|_| include_bytes) {
+                    item.data_base64 = self.payload_base64(StoredPayload::Blob(sha256)).ok();
+                    item.has_inline_data = item.data_base64.is_some();
+                }
+                item
+            })
             .collect::<Vec<_>>();
 
         serde_json::to_value(CursorResult { items, next_cursor })
@@ -562,158 +539,459 @@ impl IndexStore {
 
     pub(crate) fn query_map_points(&self, params: MapPointsQueryParams) -> Result<Value, String> {
         let limit = normalize_limit(params.limit);
-        let mut cursor = decode_message_cursor(params.cursor.as_deref());
-        let query = params
+        let cursor = decode_map_point_cursor(params.cursor.as_deref());
+        let query_like = params
             .query
             .as_deref()
             .map(str::trim)
             .filter(|value| !value.is_empty())
-            .map(|value| value.to_ascii_lowercase());
+            .map(|value| format!("%{}%", value.to_lowercase()));
+        let bbox = match (
+            params.min_lat,
+            params.min_lon,
+            params.max_lat,
+            params.max_lon,
+        ) {
+            (None, None, None, None) => None,
+            (Some(min_lat), Some(min_lon), Some(max_lat), Some(max_lon)) => {
+                if !is_valid_coordinate(min_lat, min_lon) || !is_valid_coordinate(max_lat, max_lon)
+                {
+                    return Err("bounding box coordinates are out of range".to_string());
+                }
+                if min_lat > max_lat {
+                    return Err("min_lat must not exceed max_lat".to_string());
+                }
+                Some((min_lat, min_lon, max_lat, max_lon))
+            }
+            _ => {
+                return Err(
+                    "bounding box requires min_lat, min_lon, max_lat and max_lon".to_string(),
+                )
+            }
+        };
+        if let (Some(since_ms), Some(until_ms)) = (params.since_ms, params.until_ms) {
+            if since_ms > until_ms {
+                return Err("since_ms must not exceed until_ms".to_string());
+            }
+        }
+        let peer = params
+            .peer
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+        let direction = match params.direction.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(value @ ("in" | "out")) => Some(value),
+            Some(other) => {
+                return Err(format!(
+                    "unsupported map direction '{other}' (expected in or out)"
+                ))
+            }
+        };
 
         let conn = self
             .conn
             .lock()
             .map_err(|_| "index lock poisoned".to_string())?;
 
-        let mut points = Vec::new();
-        let mut scanned_messages = 0usize;
-        let mut next_cursor: Option<String> = None;
-        let mut has_more = false;
-
-        'scan: loop {
-            if scanned_messages >= MAP_QUERY_SCAN_LIMIT {
-                has_more = true;
-                next_cursor = cursor.as_ref().and_then(encode_message_cursor);
-                break;
-            }
-
-            let mut stmt = conn
-                .prepare(
-                    "
-                    SELECT message_id, source, destination, direction, title, body, ts_ms, fields_json
-                    FROM messages
-                    WHERE (
-                      ?1 IS NULL
-                      OR ts_ms < ?1
-                      OR (ts_ms = ?1 AND message_id < ?2)
+        // A bounding box whose min_lon is east of max_lon crosses the antimeridian.
+        let mut stmt = conn
+            .prepare(
+                "
+                SELECT p.id, p.message_id, p.peer, p.direction, p.ts_ms, p.lat, p.lon, p.label
+                FROM map_points p
+                WHERE (
+                    ?1 IS NULL
+                    OR p.id IN (
+                      SELECT r.id
+                      FROM map_points_rtree r
+                      WHERE r.max_lat >= ?1
+                        AND r.min_lat <= ?3
+                        AND (
+                          (?2 <= ?4 AND r.max_lon >= ?2 AND r.min_lon <= ?4)
+                          OR (?2 > ?4 AND (r.max_lon >= ?2 OR r.min_lon <= ?4))
+                        )
                     )
-                    ORDER BY ts_ms DESC, message_id DESC
-                    LIMIT ?3
-                    ",
-                )
-                .map_err(|err| format!("prepare map query failed: {err}"))?;
-
-            let rows = stmt
-                .query_map(
-                    params![
-                        cursor.as_ref().map(|value| value.timestamp),
-                        cursor.as_ref().map(|value| value.message_id.as_str()),
-                        MAP_QUERY_MESSAGE_BATCH as i64
-                    ],
-                    |row| {
-                        let message_id = row.get::<_, String>(0)?;
-                        let source = row.get::<_, String>(1)?;
-                        let destination = row.get::<_, String>(2)?;
-                        let direction = row.get::<_, String>(3)?;
-                        let title = row.get::<_, String>(4)?;
-                        let body = row.get::<_, String>(5)?;
-                        let ts_ms = row.get::<_, i64>(6)?;
-                        let fields_json = row.get::<_, Option<String>>(7).ok().flatten();
-                        let fields = fields_json
-                            .as_deref()
-                            .and_then(|value| serde_json::from_str::<Value>(value).ok())
-                            .unwrap_or(Value::Null);
-                        Ok((
+                  )
+                  AND (?5 IS NULL OR p.ts_ms >= ?5)
+                  AND (?6 IS NULL OR p.ts_ms <= ?6)
+                  AND (?7 IS NULL OR p.peer = ?7)
+                  AND (?8 IS NULL OR p.direction = ?8)
+                  AND (?9 IS NULL OR LOWER(p.label) LIKE ?9 OR LOWER(p.peer) LIKE ?9)
+                  AND (
+                    ?10 IS NULL
+                    OR p.ts_ms < ?10
+                    OR (p.ts_ms = ?10 AND p.id < ?11)
+                  )
+                ORDER BY p.ts_ms DESC, p.id DESC
+                LIMIT ?12
+                ",
+            )
+            .map_err(|err| format!("prepare map query failed: {err}"))?;
+        let rows = stmt
+            .query_map(
+                params![
+                    bbox.map(|value| value.0),
+                    bbox.map(|value| value.1),
+                    bbox.map(|value| value.2),
+                    bbox.map(|value| value.3),
+                    params.since_ms,
+                    params.until_ms,
+                    peer,
+                    direction,
+                    query_like,
+                    cursor.as_ref().map(|value| value.timestamp),
+                    cursor.as_ref().map(|value| value.id),
+                    (limit + 1) as i64,
+                ],
+                |row| {
+                    let id = row.get::<_, i64>(0)?;
+                    let message_id = row.get::<_, String>(1)?;
+                    let peer = row.get::<_, String>(2)?;
+                    let ts_ms = row.get::<_, i64>(4)?;
+                    let lat = row.get::<_, f64>(5)?;
+                    let lon = row.get::<_, f64>(6)?;
+                    Ok((
+                        id,
+                        IndexedMapPoint {
+                            id: format!("{message_id}:{ts_ms}:{lat}:{lon}"),
+                            label: row.get::<_, String>(7)?,
+                            lat,
+                            lon,
+                            source: short_hash(&peer, 8),
+                            when: format_timestamp(ts_ms),
+                            direction: row.get::<_, String>(3)?,
                             message_id,
-                            source,
-                            destination,
-                            direction,
-                            title,
-                            body,
-                            ts_ms,
-                            fields,
-                        ))
-                    },
-                )
-                .map_err(|err| format!("run map query failed: {err}"))?;
-
-            let mut batch = Vec::new();
-            for result in rows {
-                batch.push(result.map_err(|err| format!("parse map row failed: {err}"))?);
-            }
-
-            if batch.is_empty() {
-                break;
-            }
+                            peer,
+                            timestamp: ts_ms,
+                        },
+                    ))
+                },
+            )
+            .map_err(|err| format!("run map query failed: {err}"))?;
 
-            for (message_id, source, destination, direction, title, body, ts_ms, fields) in &batch {
-                scanned_messages += 1;
-                let context = MapPointMessageContext {
-                    message_id,
-                    source,
-                    destination,
-                    direction,
-                    title,
-                    body,
-                    ts_ms: *ts_ms,
-                };
-                let extracted = extract_map_points(&context, fields);
-
-                let mut filtered = Vec::new();
-                for item in extracted {
-                    if let Some(search) = query.as_deref() {
-                        let haystack = format!(
-                            "{} {} {} {} {}",
-                            item.label.to_ascii_lowercase(),
-                            item.source.to_ascii_lowercase(),
-                            item.when.to_ascii_lowercase(),
-                            item.lat,
-                            item.lon
-                        );
-                        if !haystack.contains(search) {
-                            continue;
-                        }
-                    }
-                    filtered.push(item);
-                }
+        let mut points = Vec::new();
+        for result in rows {
+            points.push(result.map_err(|err| format!("parse map row failed: {err}"))?);
+        }
+        let next_cursor = if points.len() > limit {
+            points.truncate(limit);
+            points.last().and_then(|(id, point)| {
+                encode_map_point_cursor(&MapPointCursorKey {
+                    timestamp: point.timestamp,
+                    id: *id,
+                })
+            })
+        } else {
+            None
+        };
 
-                if !filtered.is_empty() && points.len() + filtered.len() > limit {
-                    has_more = true;
-                    next_cursor = cursor.as_ref().and_then(encode_message_cursor);
-                    break 'scan;
-                }
+        serde_json::to_value(CursorResult {
+            items: points.into_iter().map(|(_, point)| point).collect(),
+            next_cursor,
+        })
+        .map_err(|err| format!("serialize map query failed: {err}"))
+    }
+}
 
-                points.extend(filtered);
-                cursor = Some(MessageCursorKey {
-                    timestamp: *ts_ms,
-                    message_id: message_id.clone(),
-                });
+enum SearchSource {
+    Fts {
+        table: &'static str,
+        query: String,
+        by_relevance: bool,
+    },
+    Scan,
+}
 
-                if scanned_messages >= MAP_QUERY_SCAN_LIMIT {
-                    has_more = true;
-                    next_cursor = cursor.as_ref().and_then(encode_message_cursor);
-                    break 'scan;
-                }
-            }
+fn run_message_search(
+    conn: &Connection,
+    source: &SearchSource,
+    clauses: &[String],
+    mut values: Vec<SqlValue>,
+    keyset: Option<&SearchCursorKey>,
+    limit: usize,
+) -> Result<Vec<SearchHit>, String> {
+    let mut clauses = clauses.to_vec();
+    let (from, score_sql, by_relevance) = match source {
+        SearchSource::Fts {
+            table,
+            by_relevance,
+            ..
+        } => {
+            clauses.insert(0, format!("f.{table} MATCH ?"));
+            (
+                format!("{table} f JOIN messages m ON m.rowid = f.rowid"),
+                format!("bm25(f.{table})"),
+                *by_relevance,
+            )
+        }
+        SearchSource::Scan => ("messages m".to_string(), "NULL".to_string(), false),
+    };
+    if let SearchSource::Fts { query, .. } = source {
+        values.insert(0, SqlValue::Text(query.clone()));
+    }
 
-            if batch.len() < MAP_QUERY_MESSAGE_BATCH {
-                break;
-            }
-            if points.len() >= limit {
-                has_more = true;
-                next_cursor = cursor.as_ref().and_then(encode_message_cursor);
-                break;
+    // The keyset is applied while ranking so a later page does not rank and
+    // discard every earlier hit again.
+    if let Some(cursor) = keyset {
+        let score = cursor
+            .score
+            .as_deref()
+            .and_then(|value| value.parse::<f64>().ok());
+        match (by_relevance, score) {
+            (true, Some(score)) => {
+                clauses.push(format!(
+                    "({score_sql} > ? OR ({score_sql} = ? AND (m.ts_ms < ? OR (m.ts_ms = ? AND m.message_id < ?))))"
+                ));
+                values.push(SqlValue::Real(score));
+                values.push(SqlValue::Real(score));
             }
+            _ => clauses.push("(m.ts_ms < ? OR (m.ts_ms = ? AND m.message_id < ?))".to_string()),
+        }
+        values.push(SqlValue::Integer(cursor.timestamp));
+        values.push(SqlValue::Integer(cursor.timestamp));
+        values.push(SqlValue::Text(cursor.message_id.clone()));
+    }
+    let where_sql = if clauses.is_empty() {
+        "1 = 1".to_string()
+    } else {
+        clauses.join("\n    AND ")
+    };
+    let (page_order_sql, order_sql) = if by_relevance {
+        (
+            "score ASC, m.ts_ms DESC, m.message_id DESC",
+            "page.score ASC, m.ts_ms DESC, m.message_id DESC",
+        )
+    } else {
+        (
+            "m.ts_ms DESC, m.message_id DESC",
+            "m.ts_ms DESC, m.message_id DESC",
+        )
+    };
+    values.push(SqlValue::Integer((limit + 1) as i64));
+
+    // Snippets are only worth building for the rows that make the page.
+    let (snippet_sql, snippet_join) = match source {
+        SearchSource::Fts { table, query, .. } => {
+            values.push(SqlValue::Text(query.clone()));
+            (
+                format!("snippet(f.{table}, -1, char(1), char(2), '…', 16)"),
+                format!("JOIN {table} f ON f.rowid = page.row_id AND f.{table} MATCH ?"),
+            )
         }
+        SearchSource::Scan => ("NULL".to_string(), String::new()),
+    };
+
+    let sql = format!(
+        "
+        WITH page AS (
+          SELECT m.rowid AS row_id, {score_sql} AS score
+          FROM {from}
+          WHERE {where_sql}
+          ORDER BY {page_order_sql}
+          LIMIT ?
+        )
+        SELECT
+          m.message_id,
+          m.source,
+          m.destination,
+          m.title,
+          m.body,
+          m.ts_ms,
+          m.direction,
+          m.receipt_status,
+          m.fields_json,
+          m.thread_id,
+          t.display_name,
+          page.score,
+          {snippet_sql}
+        FROM page
+        JOIN messages m ON m.rowid = page.row_id
+        {snippet_join}
+        LEFT JOIN threads t ON t.thread_id = m.thread_id
+        ORDER BY {order_sql}
+        "
+    );
+
+    let mut stmt = conn
+        .prepare(&sql)
+        .map_err(|err| format!("prepare failed: {err}"))?;
+    let rows = stmt
+        .query_map(rusqlite::params_from_iter(values), |row| {
+            let thread_id = row.get::<_, String>(9)?;
+            let thread_name = row
+                .get::<_, Option<String>>(10)
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| short_hash(&thread_id, 6));
+            let (snippet, highlights) = row
+                .get::<_, Option<String>>(12)
+                .ok()
+                .flatten()
+                .map(|raw| split_snippet_markers(&raw))
+                .unwrap_or_default();
+            Ok(SearchHit {
+                message: indexed_message_from_row(conn, row)?,
+                thread_id,
+                thread_name,
+                snippet,
+                highlights,
+                score: row.get::<_, Option<f64>>(11).ok().flatten(),
+            })
+        })
+        .map_err(|err| format!("query failed: {err}"))?;
+    let mut items = Vec::new();
+    for result in rows {
+        items.push(result.map_err(|err| format!("parse row failed: {err}"))?);
+    }
+    Ok(items)
+}
 
-        if !has_more {
-            next_cursor = None;
+/// Builds a message from rows that start with `message_id, source,
+/// destination, title, body, ts_ms, direction, receipt_status, fields_json`,
+/// adding the reactions, reply context and delivery state stored alongside it.
+pub(super) fn indexed_message_from_row(
+    conn: &Connection,
+    row: &rusqlite::Row<'_>,
+) -> rusqlite::Result<IndexedMessage> {
+    let message_id = row.get::<_, String>(0)?;
+    let fields_json = row.get::<_, Option<String>>(8).ok().flatten();
+    let fields = fields_json
+        .as_deref()
+        .and_then(|value| serde_json::from_str::<Value>(value).ok())
+        .unwrap_or_else(|| Value::Object(serde_json::Map::new()));
+    let (reply_to, reply_count) =
+        replies::load_reply_context(conn, &message_id).unwrap_or_default();
+    let (edited_at_ms, deleted_at_ms) =
+        amendments::load_amendment_state(conn, &message_id).unwrap_or_default();
+    let (delivery_state, delivery_reason_code) =
+        delivery::load_delivery_state(conn, &message_id).unwrap_or_default();
+    Ok(IndexedMessage {
+        id: message_id.clone(),
+        source: row.get::<_, String>(1)?,
+        destination: row.get::<_, String>(2)?,
+        title: row.get::<_, String>(3)?,
+        content: row.get::<_, String>(4)?,
+        timestamp: row.get::<_, i64>(5)?,
+        direction: row.get::<_, String>(6)?,
+        fields: sanitize_fields_for_client(conn, &message_id, fields),
+        receipt_status: row.get::<_, Option<String>>(7).ok().flatten(),
+        reactions: reactions::load_message_reactions(conn, &message_id).unwrap_or_default(),
+        reply_to,
+        reply_count,
+        edited_at_ms,
+        deleted_at_ms,
+        delivery_state,
+        delivery_reason_code,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_params() -> MapPointsQueryParams {
+        MapPointsQueryParams {
+            query: None,
+            limit: None,
+            cursor: None,
+            min_lat: None,
+            min_lon: None,
+            max_lat: None,
+            max_lon: None,
+            since_ms: None,
+            until_ms: None,
+            peer: None,
+            direction: None,
         }
+    }
 
-        serde_json::to_value(CursorResult {
-            items: points,
-            next_cursor,
-        })
-        .map_err(|err| format!("serialize map query failed: {err}"))
+    fn point_ids(value: &Value) -> Vec<String> {
+        value["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .map(|item| item["message_id"].as_str().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn map_points_filter_by_viewport_time_peer_and_direction() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "alps", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 1_000, "content": "camp at geo:46.5,8.25" },
+                    { "id": "fiji", "source": "self", "destination": "peer-b", "direction": "out",
+                      "timestamp": 2_000, "content": "",
+                      "fields": { "2": { "lat": -17.7, "lon": 179.5 } } },
+                    { "id": "samoa", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 3_000, "content": "geo:-13.8,-171.7" },
+                    { "id": "plain", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 4_000, "content": "no location here" },
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+
+        let all = store.query_map_points(map_params()).expect("all points");
+        assert_eq!(point_ids(&all), vec!["samoa", "fiji", "alps"]);
+
+        let alps = store
+            .query_map_points(MapPointsQueryParams {
+                min_lat: Some(45.0),
+                min_lon: Some(5.0),
+                max_lat: Some(48.0),
+                max_lon: Some(11.0),
+                ..map_params()
+            })
+            .expect("alps viewport");
+        assert_eq!(point_ids(&alps), vec!["alps"]);
+
+        let pacific = store
+            .query_map_points(MapPointsQueryParams {
+                min_lat: Some(-20.0),
+                min_lon: Some(170.0),
+                max_lat: Some(-10.0),
+                max_lon: Some(-170.0),
+                ..map_params()
+            })
+            .expect("antimeridian viewport");
+        assert_eq!(point_ids(&pacific), vec!["samoa", "fiji"]);
+
+        let filtered = store
+            .query_map_points(MapPointsQueryParams {
+                since_ms: Some(1_500_000),
+                peer: Some("peer-a".to_string()),
+                direction: Some("in".to_string()),
+                ..map_params()
+            })
+            .expect("filtered points");
+        assert_eq!(point_ids(&filtered), vec!["samoa"]);
+
+        let first = store
+            .query_map_points(MapPointsQueryParams {
+                limit: Some(2),
+                ..map_params()
+            })
+            .expect("first page");
+        let second = store
+            .query_map_points(MapPointsQueryParams {
+                limit: Some(2),
+                cursor: first["next_cursor"].as_str().map(str::to_string),
+                ..map_params()
+            })
+            .expect("second page");
+        assert_eq!(point_ids(&second), vec!["alps"]);
+
+        let err = store
+            .query_map_points(MapPointsQueryParams {
+                min_lat: Some(1.0),
+                ..map_params()
+            })
+            .expect_err("partial bbox rejected");
+        assert!(err.contains("bounding box requires"), "{err}");
     }
 }
@@ -0,0 +1,224 @@
+use super::*;
+
+/// A reaction carried in the app-extensions field (`"16"`) of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct ReactionRef {
+    pub(super) target_message_id: String,
+    pub(super) emoji: String,
+    pub(super) sender: String,
+}
+
+// Reactions are extracted once per message write, like map points, so message
+// queries only aggregate rows instead of re-parsing `fields_json`.
+pub(super) fn replace_message_reaction(conn: &Connection, row: &MessageRow) -> Result<(), String> {
+    conn.execute(
+        "DELETE FROM reactions WHERE message_id = ?1",
+        params![&row.message_id],
+    )
+    .map_err(|err| format!("clear reaction failed: {err}"))?;
+    let Some(reaction) = extract_reaction(row) else {
+        return Ok(());
+    };
+    conn.execute(
+        "
+        INSERT INTO reactions (message_id, target_message_id, sender, emoji, ts_ms)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ",
+        params![
+            &row.message_id,
+            &reaction.target_message_id,
+            &reaction.sender,
+            &reaction.emoji,
+            row.ts_ms,
+        ],
+    )
+    .map_err(|err| format!("insert reaction failed: {err}"))?;
+    Ok(())
+}
+
+/// Reactions whose body is empty or just repeats the emoji carry nothing to
+/// show on their own; they only count towards the target message.
+pub(super) fn is_reaction_only(row: &MessageRow) -> bool {
+    let Some(reaction) = extract_reaction(row) else {
+        return false;
+    };
+    let body = row.body.trim();
+    let has_attachments = row
+        .fields
+        .as_ref()
+        .and_then(Value::as_object)
+        .is_some_and(|root| root.contains_key("attachments") || root.contains_key("5"));
+    row.title.trim().is_empty() && (body.is_empty() || body == reaction.emoji) && !has_attachments
+}
+
+/// Per-emoji counts for `message_id`, in the order each emoji first appeared.
+/// A peer reacting twice with the same emoji is counted once.
+pub(super) fn load_message_reactions(
+    conn: &Connection,
+    message_id: &str,
+) -> Result<Vec<ReactionSummary>, String> {
+    let mut stmt = conn
+        .prepare_cached(
+            "
+            SELECT emoji, sender, MIN(ts_ms) AS first_ts_ms
+            FROM reactions
+            WHERE target_message_id = ?1
+            GROUP BY emoji, sender
+            ORDER BY first_ts_ms ASC, sender ASC
+            ",
+        )
+        .map_err(|err| format!("prepare reaction query failed: {err}"))?;
+    let rows = stmt
+        .query_map(params![message_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(|err| format!("query reactions failed: {err}"))?;
+    let mut out: Vec<ReactionSummary> = Vec::new();
+    for row in rows {
+        let (emoji, sender) = row.map_err(|err| format!("parse reaction failed: {err}"))?;
+        match out.iter_mut().find(|entry| entry.emoji == emoji) {
+            Some(entry) => {
+                entry.count += 1;
+                entry.peers.push(sender);
+            }
+            None => out.push(ReactionSummary {
+                emoji,
+                count: 1,
+                peers: vec![sender],
+            }),
+        }
+    }
+    Ok(out)
+}
+
+pub(super) fn extract_reaction(row: &MessageRow) -> Option<ReactionRef> {
+    let extensions = app_extensions(row.fields.as_ref()?)?;
+    let read = |keys: &[&str]| {
+        keys.iter().find_map(|key| {
+            extensions
+                .get(*key)
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        })
+    };
+    let target_message_id = read(&["reaction_to", "reactionTo"])?;
+    let emoji = read(&["emoji", "reaction_emoji"])?;
+    // The transport source identifies who reacted. `sender` on the wire is
+    // only trusted on our own outbound reactions; a peer could otherwise
+    // attribute reactions to someone else.
+    let sender = if row.direction == "out" {
+        read(&["sender", "reaction_sender"]).unwrap_or_else(|| row.source.clone())
+    } else {
+        row.source.clone()
+    };
+    Some(ReactionRef {
+        target_message_id,
+        emoji,
+        sender,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reaction(id: &str, peer: &str, timestamp: i64, target: &str, emoji: &str) -> Value {
+        json!({
+            "id": id, "source": peer, "destination": "self", "direction": "in",
+            "timestamp": timestamp, "content": emoji,
+            "fields": { "16": { "reaction_to": target, "emoji": emoji } },
+        })
+    }
+
+    #[test]
+    fn reactions_aggregate_on_target_and_stay_out_of_summaries() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "m1", "source": "self", "destination": "peer-a", "direction": "out",
+                      "timestamp": 1_000, "content": "lunch at noon?" },
+                    reaction("r1", "peer-a", 2_000, "m1", "👍"),
+                    reaction("r2", "peer-a", 2_500, "m1", "👍"),
+                    reaction("r3", "peer-a", 3_000, "m1", "🎉"),
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "inbound",
+                "payload": { "message": reaction("r4", "peer-b", 4_000, "m1", "👍") },
+            }))
+            .expect("live reaction");
+
+        let page = store
+            .query_thread_messages(ThreadMessageQueryParams {
+                thread_id: "peer-a".to_string(),
+                query: None,
+                limit: None,
+                cursor: None,
+            })
+            .expect("thread messages");
+        let items = page["items"].as_array().expect("items");
+        assert_eq!(items.len(), 1);
+        assert_eq!(
+            items[0]["reactions"],
+            json!([
+                { "emoji": "👍", "count": 2, "peers": ["peer-a", "peer-b"] },
+                { "emoji": "🎉", "count": 1, "peers": ["peer-a"] },
+            ])
+        );
+
+        let hits = store
+            .search_messages(SearchQueryParams {
+                query: "from:peer-b".to_string(),
+                thread_id: None,
+                limit: None,
+                cursor: None,
+                sort: None,
+            })
+            .expect("search");
+        assert_eq!(hits["items"].as_array().map(Vec::len), Some(0));
+
+        let conn = store.conn.lock().expect("lock");
+        let (preview, unread): (String, i64) = conn
+            .query_row(
+                "SELECT preview, unread_count FROM threads WHERE thread_id = 'peer-a'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .expect("thread summary");
+        assert_eq!((preview.as_str(), unread), ("lunch at noon?", 0));
+        assert_eq!(count_unread_messages(&conn, "peer-b").expect("unread"), 0);
+    }
+
+    #[test]
+    fn inbound_reactions_cannot_claim_another_sender() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        let mut forged = reaction("r2", "peer-a", 2_500, "m1", "👍");
+        forged["fields"]["16"]["sender"] = json!("peer-b");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "m1", "source": "self", "destination": "peer-a", "direction": "out",
+                      "timestamp": 1_000, "content": "lunch at noon?" },
+                    reaction("r1", "peer-a", 2_000, "m1", "👍"),
+                    forged,
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+
+        let conn = store.conn.lock().expect("lock");
+        let reactions = load_message_reactions(&conn, "m1").expect("reactions");
+        assert_eq!(
+            serde_json::to_value(reactions).expect("serialize"),
+            json!([{ "emoji": "👍", "count": 1, "peers": ["peer-a"] }])
+        );
+    }
+}
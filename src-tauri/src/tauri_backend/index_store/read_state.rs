@@ -0,0 +1,408 @@
+use super::*;
+
+impl IndexStore {
+    pub(crate) fn mark_thread_read(&self, params: ThreadReadStateParams) -> Result<Value, String> {
+        let thread_id = params.thread_id.trim();
+        if thread_id.is_empty() {
+            return Err("thread_id is required".to_string());
+        }
+        let message_id = params
+            .message_id
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+
+        let target = match message_id {
+            Some(message_id) => Some(
+                read_thread_message_position(&conn, thread_id, message_id)?
+                    .ok_or_else(|| "message not found in thread".to_string())?,
+            ),
+            None => read_latest_thread_position(&conn, thread_id, false)?,
+        };
+
+        if let Some((ts_ms, message_id)) = target {
+            conn.execute(
+                "
+                INSERT INTO thread_read_state (
+                  thread_id,
+                  last_read_message_id,
+                  last_read_ts_ms,
+                  updated_at_ms
+                ) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(thread_id) DO UPDATE SET
+                  last_read_message_id = excluded.last_read_message_id,
+                  last_read_ts_ms = excluded.last_read_ts_ms,
+                  updated_at_ms = excluded.updated_at_ms
+                WHERE excluded.last_read_ts_ms > thread_read_state.last_read_ts_ms
+                  OR (
+                    excluded.last_read_ts_ms = thread_read_state.last_read_ts_ms
+                    AND excluded.last_read_message_id > thread_read_state.last_read_message_id
+                  )
+                ",
+                params![thread_id, message_id, ts_ms, current_timestamp_ms()],
+            )
+            .map_err(|err| format!("update thread read state failed: {err}"))?;
+        }
+
+        refresh_thread_unread_count(&conn, thread_id)?;
+        let state = read_thread_read_state(&conn, thread_id)?;
+        serde_json::to_value(state).map_err(|err| format!("serialize read state failed: {err}"))
+    }
+
+    pub(crate) fn mark_all_threads_read(&self) -> Result<Value, String> {
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start mark all read transaction failed: {err}"))?;
+        let updated = tx
+            .execute(
+                "
+                INSERT INTO thread_read_state (
+                  thread_id,
+                  last_read_message_id,
+                  last_read_ts_ms,
+                  updated_at_ms
+                )
+                SELECT thread_id, last_message_id, last_activity_ms, ?1
+                FROM threads
+                WHERE last_message_id IS NOT NULL
+                ON CONFLICT(thread_id) DO UPDATE SET
+                  last_read_message_id = excluded.last_read_message_id,
+                  last_read_ts_ms = excluded.last_read_ts_ms,
+                  updated_at_ms = excluded.updated_at_ms
+                WHERE excluded.last_read_ts_ms > thread_read_state.last_read_ts_ms
+                  OR (
+                    excluded.last_read_ts_ms = thread_read_state.last_read_ts_ms
+                    AND excluded.last_read_message_id > thread_read_state.last_read_message_id
+                  )
+                ",
+                params![current_timestamp_ms()],
+            )
+            .map_err(|err| format!("mark all threads read failed: {err}"))?;
+        tx.execute("UPDATE threads SET unread_count = 0", [])
+            .map_err(|err| format!("reset unread counts failed: {err}"))?;
+        tx.commit()
+            .map_err(|err| format!("commit mark all read failed: {err}"))?;
+        Ok(json!({ "updated": updated }))
+    }
+
+    pub(crate) fn mark_thread_unread(
+        &self,
+        params: ThreadReadStateParams,
+    ) -> Result<Value, String> {
+        let thread_id = params.thread_id.trim();
+        if thread_id.is_empty() {
+            return Err("thread_id is required".to_string());
+        }
+        let message_id = params
+            .message_id
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+
+        let target = match message_id {
+            Some(message_id) => Some(
+                read_thread_message_position(&conn, thread_id, message_id)?
+                    .ok_or_else(|| "message not found in thread".to_string())?,
+            ),
+            None => match read_latest_thread_position(&conn, thread_id, true)? {
+                Some(position) => Some(position),
+                None => read_latest_thread_position(&conn, thread_id, false)?,
+            },
+        };
+
+        if let Some((ts_ms, message_id)) = target {
+            let previous = conn
+                .query_row(
+                    &format!(
+                        "
+                        SELECT ts_ms, message_id
+                        FROM messages
+                        WHERE thread_id = ?1
+                          AND {}
+                          AND (ts_ms < ?2 OR (ts_ms = ?2 AND message_id < ?3))
+                        ORDER BY ts_ms DESC, message_id DESC
+                        LIMIT 1
+                        ",
+                        listed_messages_sql("")
+                    ),
+                    params![thread_id, ts_ms, message_id],
+                    |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+                )
+                .optional()
+                .map_err(|err| format!("read previous message for unread marker failed: {err}"))?;
+
+            match previous {
+                Some((previous_ts_ms, previous_id)) => {
+                    conn.execute(
+                        "
+                        INSERT INTO thread_read_state (
+                          thread_id,
+                          last_read_message_id,
+                          last_read_ts_ms,
+                          updated_at_ms
+                        ) VALUES (?1, ?2, ?3, ?4)
+                        ON CONFLICT(thread_id) DO UPDATE SET
+                          last_read_message_id = excluded.last_read_message_id,
+                          last_read_ts_ms = excluded.last_read_ts_ms,
+                          updated_at_ms = excluded.updated_at_ms
+                        ",
+                        params![
+                            thread_id,
+                            previous_id,
+                            previous_ts_ms,
+                            current_timestamp_ms()
+                        ],
+                    )
+                    .map_err(|err| format!("update thread unread marker failed: {err}"))?;
+                }
+                None => {
+                    conn.execute(
+                        "DELETE FROM thread_read_state WHERE thread_id = ?1",
+                        params![thread_id],
+                    )
+                    .map_err(|err| format!("clear thread read state failed: {err}"))?;
+                }
+            }
+        }
+
+        refresh_thread_unread_count(&conn, thread_id)?;
+        let state = read_thread_read_state(&conn, thread_id)?;
+        serde_json::to_value(state).map_err(|err| format!("serialize read state failed: {err}"))
+    }
+}
+
+fn read_thread_message_position(
+    conn: &Connection,
+    thread_id: &str,
+    message_id: &str,
+) -> Result<Option<(i64, String)>, String> {
+    conn.query_row(
+        "SELECT ts_ms, message_id FROM messages WHERE thread_id = ?1 AND message_id = ?2",
+        params![thread_id, message_id],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+    )
+    .optional()
+    .map_err(|err| format!("read message position failed: {err}"))
+}
+
+fn read_latest_thread_position(
+    conn: &Connection,
+    thread_id: &str,
+    inbound_only: bool,
+) -> Result<Option<(i64, String)>, String> {
+    conn.query_row(
+        &format!(
+            "
+            SELECT ts_ms, message_id
+            FROM messages
+            WHERE thread_id = ?1
+              AND {}
+              AND (?2 = 0 OR direction != 'out')
+            ORDER BY ts_ms DESC, message_id DESC
+            LIMIT 1
+            ",
+            listed_messages_sql("")
+        ),
+        params![thread_id, if inbound_only { 1 } else { 0 }],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+    )
+    .optional()
+    .map_err(|err| format!("read latest thread message failed: {err}"))
+}
+
+fn refresh_thread_unread_count(conn: &Connection, thread_id: &str) -> Result<(), String> {
+    let unread = count_unread_messages(conn, thread_id)?;
+    conn.execute(
+        "UPDATE threads SET unread_count = ?1 WHERE thread_id = ?2",
+        params![unread as i64, thread_id],
+    )
+    .map_err(|err| format!("update thread unread count failed: {err}"))?;
+    Ok(())
+}
+
+fn read_thread_read_state(conn: &Connection, thread_id: &str) -> Result<ThreadReadState, String> {
+    let watermark = conn
+        .query_row(
+            "
+            SELECT last_read_message_id, last_read_ts_ms
+            FROM thread_read_state
+            WHERE thread_id = ?1
+            ",
+            params![thread_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+        )
+        .optional()
+        .map_err(|err| format!("read thread read state failed: {err}"))?;
+    Ok(ThreadReadState {
+        thread_id: thread_id.to_string(),
+        unread: count_unread_messages(conn, thread_id)?,
+        last_read_message_id: watermark.as_ref().map(|(message_id, _)| message_id.clone()),
+        last_read_ts_ms: watermark.map(|(_, ts_ms)| ts_ms),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_params(thread_id: &str, message_id: Option<&str>) -> ThreadReadStateParams {
+        ThreadReadStateParams {
+            thread_id: thread_id.to_string(),
+            message_id: message_id.map(str::to_string),
+        }
+    }
+
+    fn listed_unread(store: &IndexStore) -> Vec<(String, i64)> {
+        store
+            .query_threads(ThreadQueryParams {
+                query: None,
+                limit: None,
+                cursor: None,
+                pinned_only: None,
+                archived: None,
+            })
+            .expect("threads")["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .map(|item| {
+                (
+                    item["thread_id"].as_str().unwrap_or_default().to_string(),
+                    item["unread"].as_i64().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    fn seeded_store() -> (tempfile::TempDir, IndexStore) {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "a1", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 1_000, "content": "one" },
+                    // Reactions sit between messages but never carry the watermark.
+                    { "id": "r1", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 1_500, "content": "",
+                      "fields": { "16": { "reaction_to": "a1", "emoji": "👍" } } },
+                    { "id": "a2", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 2_000, "content": "two" },
+                    { "id": "a3", "source": "self", "destination": "peer-a", "direction": "out",
+                      "timestamp": 3_000, "content": "reply" },
+                    { "id": "a4", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 4_000, "content": "four" },
+                    { "id": "r2", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 4_500, "content": "",
+                      "fields": { "16": { "reaction_to": "a3", "emoji": "👍" } } },
+                    { "id": "b1", "source": "peer-b", "destination": "self", "direction": "in",
+                      "timestamp": 5_000, "content": "other" },
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+        (dir, store)
+    }
+
+    #[test]
+    fn marking_read_and_unread_moves_the_watermark_and_unread_count() {
+        let (_dir, store) = seeded_store();
+        assert_eq!(
+            listed_unread(&store),
+            vec![("peer-b".to_string(), 1), ("peer-a".to_string(), 3)]
+        );
+
+        let state = store
+            .mark_thread_read(read_params("peer-a", Some("a2")))
+            .expect("read up to a2");
+        assert_eq!(state["last_read_message_id"], "a2");
+        // Only a4 is left: a3 is our own message.
+        assert_eq!(state["unread"], 1);
+
+        // The watermark never moves back when marking read.
+        let state = store
+            .mark_thread_read(read_params("peer-a", Some("a1")))
+            .expect("read up to a1");
+        assert_eq!(state["last_read_message_id"], "a2");
+        assert_eq!(state["unread"], 1);
+
+        let state = store
+            .mark_thread_read(read_params("peer-a", None))
+            .expect("read all of thread");
+        assert_eq!(state["last_read_message_id"], "a4");
+        assert_eq!(state["unread"], 0);
+        assert_eq!(
+            listed_unread(&store),
+            vec![("peer-b".to_string(), 1), ("peer-a".to_string(), 0)]
+        );
+
+        // Without a message, the latest inbound message becomes unread again.
+        let state = store
+            .mark_thread_unread(read_params("peer-a", None))
+            .expect("unread latest");
+        assert_eq!(state["last_read_message_id"], "a3");
+        assert_eq!(state["unread"], 1);
+
+        let state = store
+            .mark_thread_unread(read_params("peer-a", Some("a2")))
+            .expect("unread from a2");
+        assert_eq!(state["last_read_message_id"], "a1");
+        assert_eq!(state["unread"], 2);
+
+        let state = store
+            .mark_thread_unread(read_params("peer-a", Some("a1")))
+            .expect("unread from a1");
+        assert_eq!(state["last_read_message_id"], Value::Null);
+        assert_eq!(state["unread"], 3);
+        assert_eq!(
+            listed_unread(&store),
+            vec![("peer-b".to_string(), 1), ("peer-a".to_string(), 3)]
+        );
+
+        let err = store
+            .mark_thread_read(read_params("peer-a", Some("b1")))
+            .expect_err("message from another thread");
+        assert!(err.contains("not found in thread"), "{err}");
+    }
+
+    #[test]
+    fn mark_all_read_clears_every_thread_until_new_messages_arrive() {
+        let (_dir, store) = seeded_store();
+        store
+            .mark_thread_read(read_params("peer-a", Some("a2")))
+            .expect("read up to a2");
+
+        let result = store.mark_all_threads_read().expect("mark all read");
+        assert_eq!(result["updated"], 2);
+        assert_eq!(
+            listed_unread(&store),
+            vec![("peer-b".to_string(), 0), ("peer-a".to_string(), 0)]
+        );
+
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "inbound",
+                "payload": { "id": "a5", "source": "peer-a", "destination": "self",
+                             "direction": "in", "timestamp": 6_000, "content": "five" },
+            }))
+            .expect("ingest");
+        assert_eq!(
+            listed_unread(&store),
+            vec![("peer-a".to_string(), 1), ("peer-b".to_string(), 0)]
+        );
+    }
+}
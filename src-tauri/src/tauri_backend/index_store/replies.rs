@@ -0,0 +1,246 @@
+use super::queries::indexed_message_from_row;
+use super::*;
+
+const QUOTE_MAX_CHARS: usize = 120;
+
+impl IndexStore {
+    /// Direct replies to `message_id` in time order, from any thread. Each
+    /// reply carries its own `reply_count`, so clients can expand the tree
+    /// one level at a time.
+    pub(crate) fn query_message_replies(
+        &self,
+        params: MessageRepliesQueryParams,
+    ) -> Result<Value, String> {
+        let message_id = params.message_id.trim();
+        if message_id.is_empty() {
+            return Err("message_id is required".to_string());
+        }
+        let limit = normalize_limit(params.limit);
+        let keyset = decode_message_cursor(params.cursor.as_deref());
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let mut stmt = conn
+            .prepare(&format!(
+                "
+                SELECT
+                  message_id,
+                  source,
+                  destination,
+                  title,
+                  body,
+                  ts_ms,
+                  direction,
+                  receipt_status,
+                  fields_json
+                FROM messages
+                WHERE reply_to_message_id = ?1
+                  AND {}
+                  AND (
+                    ?2 IS NULL
+                    OR ts_ms > ?2
+                    OR (ts_ms = ?2 AND message_id > ?3)
+                  )
+                ORDER BY ts_ms ASC, message_id ASC
+                LIMIT ?4
+                ",
+                listed_messages_sql("")
+            ))
+            .map_err(|err| format!("prepare reply query failed: {err}"))?;
+        let rows = stmt
+            .query_map(
+                params![
+                    message_id,
+                    keyset.as_ref().map(|cursor| cursor.timestamp),
+                    keyset.as_ref().map(|cursor| cursor.message_id.as_str()),
+                    (limit + 1) as i64
+                ],
+                |row| indexed_message_from_row(&conn, row),
+            )
+            .map_err(|err| format!("run reply query failed: {err}"))?;
+        let mut items = Vec::new();
+        for result in rows {
+            items.push(result.map_err(|err| format!("parse reply row failed: {err}"))?);
+        }
+
+        let next_cursor = if items.len() > limit {
+            let marker = items.get(limit.saturating_sub(1)).cloned();
+            items.truncate(limit);
+            marker.and_then(|entry| {
+                encode_message_cursor(&MessageCursorKey {
+                    timestamp: entry.timestamp,
+                    message_id: entry.id,
+                })
+            })
+        } else {
+            None
+        };
+
+        serde_json::to_value(CursorResult { items, next_cursor })
+            .map_err(|err| format!("serialize reply query failed: {err}"))
+    }
+}
+
+/// The message `row` replies to, from the app-extensions field (`"16"`).
+pub(super) fn extract_reply_to(row: &MessageRow) -> Option<String> {
+    let extensions = app_extensions(row.fields.as_ref()?)?;
+    ["reply_to", "replyTo", "reply_id"].iter().find_map(|key| {
+        extensions
+            .get(*key)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty() && *value != row.message_id)
+            .map(str::to_string)
+    })
+}
+
+/// The quoted parent of `message_id` and how many replies it has. A parent
+/// that was never received or has been deleted is returned with `missing` set
+/// so the UI can still render a placeholder quote.
+pub(super) fn load_reply_context(
+    conn: &Connection,
+    message_id: &str,
+) -> Result<(Option<QuotedMessage>, usize), String> {
+    let (parent_id, parent, reply_count) = conn
+        .prepare_cached(&format!(
+            "
+            SELECT
+              m.reply_to_message_id,
+              p.source,
+              p.title,
+              p.body,
+              (
+                SELECT COUNT(*)
+                FROM messages c
+                WHERE c.reply_to_message_id = m.message_id AND {}
+              )
+            FROM messages m
+            LEFT JOIN messages p ON p.message_id = m.reply_to_message_id
+            WHERE m.message_id = ?1
+            ",
+            listed_messages_sql("c.")
+        ))
+        .and_then(|mut stmt| {
+            stmt.query_row(params![message_id], |row| {
+                let parent = match row.get::<_, Option<String>>(1)? {
+                    Some(source) => {
+                        Some((source, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+                    }
+                    None => None,
+                };
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    parent,
+                    row.get::<_, i64>(4)?,
+                ))
+            })
+        })
+        .optional()
+        .map_err(|err| format!("read reply context failed: {err}"))?
+        .unwrap_or((None, None, 0));
+
+    let quote = parent_id.map(|parent_id| match parent {
+        Some((source, title, body)) => QuotedMessage {
+            message_id: parent_id,
+            author: Some(source),
+            first_line: quote_line(&body).or_else(|| quote_line(&title)),
+            missing: false,
+        },
+        None => QuotedMessage {
+            message_id: parent_id,
+            author: None,
+            first_line: None,
+            missing: true,
+        },
+    });
+    Ok((quote, reply_count.max(0) as usize))
+}
+
+fn quote_line(text: &str) -> Option<String> {
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty())?;
+    if line.chars().count() <= QUOTE_MAX_CHARS {
+        return Some(line.to_string());
+    }
+    let mut out = line.chars().take(QUOTE_MAX_CHARS - 1).collect::<String>();
+    out.push('…');
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(id: &str, timestamp: i64, parent: &str, content: &str) -> Value {
+        json!({
+            "id": id, "source": "peer-a", "destination": "self", "direction": "in",
+            "timestamp": timestamp, "content": content,
+            "fields": { "16": { "reply_to": parent } },
+        })
+    }
+
+    #[test]
+    fn replies_carry_quotes_and_page_in_time_order() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "root", "source": "self", "destination": "peer-a", "direction": "out",
+                      "timestamp": 1_000, "content": "\n  Convoy leaves at six\nbring radios" },
+                    reply("c1", 2_000, "root", "copy"),
+                    reply("c2", 3_000, "root", "which gate?"),
+                    reply("c3", 4_000, "c2", "north gate"),
+                    reply("orphan", 5_000, "never-seen", "re: earlier"),
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+
+        let first = store
+            .query_message_replies(MessageRepliesQueryParams {
+                message_id: "root".to_string(),
+                limit: Some(1),
+                cursor: None,
+            })
+            .expect("first page");
+        assert_eq!(first["items"][0]["id"], "c1");
+        assert_eq!(
+            first["items"][0]["reply_to"],
+            json!({ "message_id": "root", "author": "self",
+                    "first_line": "Convoy leaves at six", "missing": false })
+        );
+        let second = store
+            .query_message_replies(MessageRepliesQueryParams {
+                message_id: "root".to_string(),
+                limit: Some(1),
+                cursor: first["next_cursor"].as_str().map(str::to_string),
+            })
+            .expect("second page");
+        assert_eq!(second["items"][0]["id"], "c2");
+        assert_eq!(second["items"][0]["reply_count"], 1);
+
+        let thread = store
+            .query_thread_messages(ThreadMessageQueryParams {
+                thread_id: "peer-a".to_string(),
+                query: None,
+                limit: None,
+                cursor: None,
+            })
+            .expect("thread messages");
+        let items = thread["items"].as_array().expect("items");
+        assert_eq!(items[0]["id"], "orphan");
+        assert_eq!(
+            items[0]["reply_to"],
+            json!({ "message_id": "never-seen", "author": null,
+                    "first_line": null, "missing": true })
+        );
+        let root = items
+            .iter()
+            .find(|item| item["id"] == "root")
+            .expect("root");
+        assert_eq!(root["reply_to"], Value::Null);
+        assert_eq!(root["reply_count"], 2);
+    }
+}
@@ -0,0 +1,566 @@
+use super::*;
+
+const GLOBAL_POLICY_KEY: &str = "*";
+
+// Messages and attachments selected for removal by one retention pass.
+#[derive(Default)]
+struct RetentionPlan {
+    messages: BTreeMap<String, Vec<String>>,
+    attachments: Vec<PlannedAttachment>,
+}
+
+struct PlannedAttachment {
+    id: i64,
+    message_id: String,
+    thread_id: String,
+    size_bytes: i64,
+}
+
+impl IndexStore {
+    pub(crate) fn get_retention_policy(&self, thread_id: Option<String>) -> Result<Value, String> {
+        let thread_id = normalize_thread_scope(thread_id.as_deref())?;
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        retention_policy_response(&conn, thread_id)
+    }
+
+    /// Replaces the global policy, or a thread's overrides when `thread_id` is
+    /// set. Unset limits inherit (thread) or are unlimited (global); `0`
+    /// explicitly disables a limit.
+    pub(crate) fn set_retention_policy(
+        &self,
+        params: RetentionPolicyParams,
+    ) -> Result<Value, String> {
+        let thread_id = normalize_thread_scope(params.thread_id.as_deref())?;
+        for (name, value) in [
+            ("max_age_ms", params.max_age_ms),
+            ("max_messages", params.max_messages),
+            ("max_attachment_bytes", params.max_attachment_bytes),
+        ] {
+            if value.is_some_and(|value| value < 0) {
+                return Err(format!("{name} must not be negative"));
+            }
+        }
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let key = thread_id.unwrap_or(GLOBAL_POLICY_KEY);
+        if params.max_age_ms.is_none()
+            && params.max_messages.is_none()
+            && params.max_attachment_bytes.is_none()
+        {
+            conn.execute(
+                "DELETE FROM retention_policies WHERE thread_id = ?1",
+                params![key],
+            )
+            .map_err(|err| format!("clear retention policy failed: {err}"))?;
+        } else {
+            conn.execute(
+                "
+                INSERT INTO retention_policies (
+                  thread_id,
+                  max_age_ms,
+                  max_messages,
+                  max_attachment_bytes,
+                  updated_at_ms
+                ) VALUES (?1, ?2, ?3, ?4, ?5)
+                ON CONFLICT(thread_id) DO UPDATE SET
+                  max_age_ms = excluded.max_age_ms,
+                  max_messages = excluded.max_messages,
+                  max_attachment_bytes = excluded.max_attachment_bytes,
+                  updated_at_ms = excluded.updated_at_ms
+                ",
+                params![
+                    key,
+                    params.max_age_ms,
+                    params.max_messages,
+                    params.max_attachment_bytes,
+                    current_timestamp_ms(),
+                ],
+            )
+            .map_err(|err| format!("update retention policy failed: {err}"))?;
+        }
+        retention_policy_response(&conn, thread_id)
+    }
+
+    /// Evaluates every policy and, unless `dry_run` is set, removes what they
+    /// select. Message removals leave tombstones so sync does not re-import them.
+    pub(crate) fn apply_retention(&self, dry_run: bool) -> Result<RetentionReport, String> {
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let plan = plan_retention(&conn, current_timestamp_ms())?;
+        let report = retention_report(&plan, dry_run);
+        if dry_run || (plan.messages.is_empty() && plan.attachments.is_empty()) {
+            return Ok(report);
+        }
+
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start retention transaction failed: {err}"))?;
+        let mut touched_threads = BTreeSet::new();
+        for message_ids in plan.messages.values() {
+            touched_threads.extend(delete_indexed_messages(&tx, message_ids, "retention")?);
+        }
+        for attachment in &plan.attachments {
+            tx.execute(
+                "DELETE FROM attachments WHERE id = ?1",
+                params![attachment.id],
+            )
+            .map_err(|err| format!("delete retained attachment failed: {err}"))?;
+            tx.execute(
+                "
+                UPDATE messages
+                SET has_attachments = EXISTS (
+                  SELECT 1 FROM attachments a WHERE a.message_id = messages.message_id
+                )
+                WHERE message_id = ?1
+                ",
+                params![&attachment.message_id],
+            )
+            .map_err(|err| format!("update message attachment flag failed: {err}"))?;
+        }
+        tx.execute(
+            "
+            INSERT INTO sync_state (key, value) VALUES ('retention_last_run_ms', ?1)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value
+            ",
+            params![current_timestamp_ms().to_string()],
+        )
+        .map_err(|err| format!("record retention run failed: {err}"))?;
+        tx.commit()
+            .map_err(|err| format!("commit retention failed: {err}"))?;
+
+        blobs::collect_unreferenced_blobs(&conn, self.blobs.as_ref())?;
+        for thread_id in &touched_threads {
+            upsert_thread_summary_for_thread(&conn, thread_id)?;
+        }
+        Ok(report)
+    }
+}
+
+fn normalize_thread_scope(thread_id: Option<&str>) -> Result<Option<&str>, String> {
+    match thread_id.map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(GLOBAL_POLICY_KEY) => Err("thread_id '*' is reserved".to_string()),
+        Some(value) => Ok(Some(value)),
+    }
+}
+
+fn retention_policy_response(conn: &Connection, thread_id: Option<&str>) -> Result<Value, String> {
+    let global = read_retention_policy(conn, GLOBAL_POLICY_KEY)?.unwrap_or_default();
+    let Some(thread_id) = thread_id else {
+        return Ok(json!({ "global": global }));
+    };
+    let thread = read_retention_policy(conn, thread_id)?;
+    let effective = effective_policy(&global, thread.as_ref());
+    Ok(json!({
+        "thread_id": thread_id,
+        "global": global,
+        "thread": thread,
+        "effective": effective,
+    }))
+}
+
+fn read_retention_policy(conn: &Connection, key: &str) -> Result<Option<RetentionPolicy>, String> {
+    conn.query_row(
+        "
+        SELECT max_age_ms, max_messages, max_attachment_bytes
+        FROM retention_policies
+        WHERE thread_id = ?1
+        ",
+        params![key],
+        |row| {
+            Ok(RetentionPolicy {
+                max_age_ms: row.get::<_, Option<i64>>(0)?,
+                max_messages: row.get::<_, Option<i64>>(1)?,
+                max_attachment_bytes: row.get::<_, Option<i64>>(2)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|err| format!("read retention policy failed: {err}"))
+}
+
+// The global attachment limit caps the whole index, so only a thread's own
+// attachment limit applies per thread; age and count limits are inherited.
+fn effective_policy(global: &RetentionPolicy, thread: Option<&RetentionPolicy>) -> RetentionPolicy {
+    let pick =
+        |own: Option<i64>, inherited: Option<i64>| own.or(inherited).filter(|value| *value > 0);
+    RetentionPolicy {
+        max_age_ms: pick(
+            thread.and_then(|policy| policy.max_age_ms),
+            global.max_age_ms,
+        ),
+        max_messages: pick(
+            thread.and_then(|policy| policy.max_messages),
+            global.max_messages,
+        ),
+        max_attachment_bytes: pick(thread.and_then(|policy| policy.max_attachment_bytes), None),
+    }
+}
+
+fn plan_retention(conn: &Connection, now_ms: i64) -> Result<RetentionPlan, String> {
+    let global = read_retention_policy(conn, GLOBAL_POLICY_KEY)?.unwrap_or_default();
+    let mut overrides = HashMap::new();
+    {
+        let mut stmt = conn
+            .prepare(
+                "
+                SELECT thread_id, max_age_ms, max_messages, max_attachment_bytes
+                FROM retention_policies
+                WHERE thread_id != ?1
+                ",
+            )
+            .map_err(|err| format!("prepare retention overrides failed: {err}"))?;
+        let rows = stmt
+            .query_map(params![GLOBAL_POLICY_KEY], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    RetentionPolicy {
+                        max_age_ms: row.get::<_, Option<i64>>(1)?,
+                        max_messages: row.get::<_, Option<i64>>(2)?,
+                        max_attachment_bytes: row.get::<_, Option<i64>>(3)?,
+                    },
+                ))
+            })
+            .map_err(|err| format!("query retention overrides failed: {err}"))?;
+        for row in rows {
+            let (thread_id, policy) =
+                row.map_err(|err| format!("parse retention override failed: {err}"))?;
+            overrides.insert(thread_id, policy);
+        }
+    }
+    let thread_ids = {
+        let mut stmt = conn
+            .prepare("SELECT DISTINCT thread_id FROM messages")
+            .map_err(|err| format!("prepare retention thread scan failed: {err}"))?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(|err| format!("scan retention threads failed: {err}"))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("parse retention thread failed: {err}"))?
+    };
+
+    let mut plan = RetentionPlan::default();
+    let mut removed_messages = BTreeSet::new();
+    let mut pruned_attachments = BTreeSet::new();
+    for thread_id in &thread_ids {
+        let policy = effective_policy(&global, overrides.get(thread_id));
+        if policy.max_age_ms.is_some() || policy.max_messages.is_some() {
+            let cutoff_ms = policy
+                .max_age_ms
+                .map(|max_age_ms| now_ms.saturating_sub(max_age_ms));
+            // The age cutoff covers every row; the count limit ranks only the
+            // messages a thread lists, so reactions and edits don't use it up.
+            let mut stmt = conn
+                .prepare_cached(&format!(
+                    "
+                    SELECT message_id, ts_ms
+                    FROM messages
+                    WHERE thread_id = ?1 AND ?2 IS NOT NULL AND ts_ms < ?2
+                    UNION
+                    SELECT message_id, ts_ms
+                    FROM (
+                      SELECT
+                        message_id,
+                        ts_ms,
+                        ROW_NUMBER() OVER (ORDER BY ts_ms DESC, message_id DESC) AS position
+                      FROM messages
+                      WHERE thread_id = ?1 AND {}
+                    )
+                    WHERE ?3 IS NOT NULL AND position > ?3
+                    ORDER BY ts_ms ASC, message_id ASC
+                    ",
+                    listed_messages_sql("")
+                ))
+                .map_err(|err| format!("prepare retention message scan failed: {err}"))?;
+            let message_ids = stmt
+                .query_map(params![thread_id, cutoff_ms, policy.max_messages], |row| {
+                    row.get::<_, String>(0)
+                })
+                .map_err(|err| format!("scan retention messages failed: {err}"))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("parse retention message failed: {err}"))?;
+            if !message_ids.is_empty() {
+                removed_messages.extend(message_ids.iter().cloned());
+                plan.messages.insert(thread_id.clone(), message_ids);
+            }
+        }
+        if let Some(limit) = policy.max_attachment_bytes {
+            let candidates = attachments_newest_first(conn, Some(thread_id))?;
+            select_attachments_over_limit(
+                candidates,
+                limit,
+                &removed_messages,
+                &mut pruned_attachments,
+                &mut plan,
+            );
+        }
+    }
+    if let Some(limit) = global.max_attachment_bytes.filter(|value| *value > 0) {
+        let candidates = attachments_newest_first(conn, None)?;
+        select_attachments_over_limit(
+            candidates,
+            limit,
+            &removed_messages,
+            &mut pruned_attachments,
+            &mut plan,
+        );
+    }
+    Ok(plan)
+}
+
+fn attachments_newest_first(
+    conn: &Connection,
+    thread_id: Option<&str>,
+) -> Result<Vec<PlannedAttachment>, String> {
+    let mut stmt = conn
+        .prepare_cached(
+            "
+            SELECT a.id, a.message_id, m.thread_id, a.size_bytes
+            FROM attachments a
+            JOIN messages m ON m.message_id = a.message_id
+            WHERE ?1 IS NULL OR m.thread_id = ?1
+            ORDER BY a.created_at_ms DESC, a.id DESC
+            ",
+        )
+        .map_err(|err| format!("prepare retention attachment scan failed: {err}"))?;
+    let rows = stmt
+        .query_map(params![thread_id], |row| {
+            Ok(PlannedAttachment {
+                id: row.get::<_, i64>(0)?,
+                message_id: row.get::<_, String>(1)?,
+                thread_id: row.get::<_, String>(2)?,
+                size_bytes: row.get::<_, i64>(3).unwrap_or(0).max(0),
+            })
+        })
+        .map_err(|err| format!("scan retention attachments failed: {err}"))?;
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("parse retention attachment failed: {err}"))
+}
+
+// Keeps the newest attachments that fit under `limit`; everything older than
+// the first one that overflows is pruned, so an old large file cannot survive
+// while newer ones are dropped.
+fn select_attachments_over_limit(
+    candidates: Vec<PlannedAttachment>,
+    limit: i64,
+    removed_messages: &BTreeSet<String>,
+    pruned: &mut BTreeSet<i64>,
+    plan: &mut RetentionPlan,
+) {
+    let mut total = 0_i64;
+    for attachment in candidates {
+        if removed_messages.contains(&attachment.message_id) || pruned.contains(&attachment.id) {
+            continue;
+        }
+        total = total.saturating_add(attachment.size_bytes);
+        if total > limit {
+            pruned.insert(attachment.id);
+            plan.attachments.push(attachment);
+        }
+    }
+}
+
+fn retention_report(plan: &RetentionPlan, dry_run: bool) -> RetentionReport {
+    let mut threads = BTreeMap::<String, RetentionThreadReport>::new();
+    for (thread_id, message_ids) in &plan.messages {
+        threads
+            .entry(thread_id.clone())
+            .or_insert_with(|| RetentionThreadReport {
+                thread_id: thread_id.clone(),
+                ..RetentionThreadReport::default()
+            })
+            .messages = message_ids.len();
+    }
+    for attachment in &plan.attachments {
+        let entry = threads
+            .entry(attachment.thread_id.clone())
+            .or_insert_with(|| RetentionThreadReport {
+                thread_id: attachment.thread_id.clone(),
+                ..RetentionThreadReport::default()
+            });
+        entry.attachments += 1;
+        entry.attachment_bytes += attachment.size_bytes;
+    }
+    RetentionReport {
+        dry_run,
+        messages: plan.messages.values().map(Vec::len).sum(),
+        attachments: plan.attachments.len(),
+        attachment_bytes: plan
+            .attachments
+            .iter()
+            .map(|attachment| attachment.size_bytes)
+            .sum(),
+        threads: threads.into_values().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(
+        thread_id: Option<&str>,
+        max_age_ms: Option<i64>,
+        max_messages: Option<i64>,
+        max_attachment_bytes: Option<i64>,
+    ) -> RetentionPolicyParams {
+        RetentionPolicyParams {
+            thread_id: thread_id.map(str::to_string),
+            max_age_ms,
+            max_messages,
+            max_attachment_bytes,
+        }
+    }
+
+    #[test]
+    fn retention_applies_global_and_thread_policies_and_survives_resync() {
+        let (_dir, store) = test_store();
+        let now_s = current_timestamp_ms() / 1000;
+        let payload = json!([
+            inbound("a-old", "peer-a", now_s - 10 * 86_400, "body a-old"),
+            with_fields(
+                inbound("a-1", "peer-a", now_s - 300, "body a-1"),
+                attachment_fields("a-1.bin", &[1; 40])
+            ),
+            with_fields(
+                inbound("a-2", "peer-a", now_s - 200, "body a-2"),
+                attachment_fields("a-2.bin", &[2; 40])
+            ),
+            with_fields(
+                inbound("a-3", "peer-a", now_s - 100, "body a-3"),
+                attachment_fields("a-3.bin", &[3; 40])
+            ),
+            inbound("b-old", "peer-b", now_s - 10 * 86_400, "body b-old"),
+            inbound("b-1", "peer-b", now_s - 100, "body b-1"),
+        ]);
+        store
+            .reindex_from_runtime_payloads(&payload, &json!([]))
+            .expect("seed index");
+
+        store
+            .set_retention_policy(policy(None, Some(7 * 86_400_000), None, None))
+            .expect("global policy");
+        let thread = store
+            .set_retention_policy(policy(Some("peer-b"), Some(0), None, None))
+            .expect("thread override");
+        assert_eq!(thread["effective"]["max_age_ms"], Value::Null);
+        store
+            .set_retention_policy(policy(Some("peer-a"), None, Some(2), Some(50)))
+            .expect("thread limits");
+
+        let preview = store.apply_retention(true).expect("dry run");
+        assert!(preview.dry_run);
+        assert_eq!(preview.messages, 2);
+        assert_eq!(preview.attachments, 1);
+        assert_eq!(preview.attachment_bytes, 40);
+        assert_eq!(
+            store.index_status().expect("status").message_count,
+            6,
+            "dry run must not delete"
+        );
+
+        let applied = store.apply_retention(false).expect("apply");
+        assert_eq!(applied.messages, 2);
+        let remaining = store
+            .query_thread_messages(ThreadMessageQueryParams {
+                thread_id: "peer-a".to_string(),
+                limit: None,
+                cursor: None,
+                query: None,
+            })
+            .expect("thread messages");
+        let items = remaining["items"].as_array().expect("items");
+        assert_eq!(items.len(), 2);
+        let files = store
+            .query_files(FilesQueryParams {
+                query: None,
+                kind: None,
+                limit: None,
+                cursor: None,
+                include_bytes: None,
+            })
+            .expect("files");
+        assert_eq!(files["items"].as_array().map(Vec::len), Some(1));
+
+        store
+            .reindex_from_runtime_payloads(&payload, &json!([]))
+            .expect("resync");
+        assert_eq!(store.index_status().expect("status").message_count, 4);
+        assert_eq!(store.apply_retention(true).expect("idle").messages, 0);
+    }
+
+    #[test]
+    fn message_limit_counts_only_listed_messages() {
+        let (_dir, store) = test_store();
+        let now_s = current_timestamp_ms() / 1000;
+        let edit = with_fields(
+            inbound(
+                "c-edit",
+                "peer-c",
+                now_s - 20,
+                &edit_fallback_body("body c-2, revised"),
+            ),
+            json!({ "16": { "edit_of": "c-2" } }),
+        );
+        let reaction = with_fields(
+            inbound("c-like", "peer-c", now_s - 10, ""),
+            json!({ "16": { "reaction_to": "c-3", "emoji": "👍" } }),
+        );
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    inbound("c-1", "peer-c", now_s - 300, "body c-1"),
+                    inbound("c-2", "peer-c", now_s - 200, "body c-2"),
+                    inbound("c-3", "peer-c", now_s - 100, "body c-3"),
+                    edit,
+                    reaction,
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+        store
+            .set_retention_policy(policy(Some("peer-c"), None, Some(2), None))
+            .expect("thread limit");
+
+        let applied = store.apply_retention(false).expect("apply");
+        assert_eq!(applied.messages, 1);
+        let remaining = store
+            .query_thread_messages(ThreadMessageQueryParams {
+                thread_id: "peer-c".to_string(),
+                limit: None,
+                cursor: None,
+                query: None,
+            })
+            .expect("thread messages");
+        let ids = remaining["items"]
+            .as_array()
+            .expect("items")
+            .iter()
+            .filter_map(|item| item["id"].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["c-3", "c-2"]);
+    }
+
+    #[test]
+    fn retention_rejects_invalid_policies() {
+        let (_dir, store) = test_store();
+        assert!(store
+            .set_retention_policy(policy(None, Some(-1), None, None))
+            .is_err());
+        assert!(store
+            .set_retention_policy(policy(Some("*"), Some(1), None, None))
+            .is_err());
+        let cleared = store
+            .set_retention_policy(policy(None, None, None, None))
+            .expect("clear");
+        assert_eq!(cleared["global"]["max_age_ms"], Value::Null);
+    }
+}
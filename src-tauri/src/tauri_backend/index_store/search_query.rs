@@ -0,0 +1,717 @@
+use super::IndexedMessage;
+use rusqlite::types::Value as SqlValue;
+
+const MS_PER_DAY: i64 = 86_400_000;
+const SCAN_SNIPPET_LEAD_CHARS: usize = 40;
+const SCAN_SNIPPET_MAX_CHARS: usize = 160;
+
+/// Parsed form of the `search_messages` query language.
+///
+/// Free text and quoted phrases go to FTS5; operators compile to SQL
+/// predicates over the `messages` table (aliased `m`).
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct SearchQuery {
+    pub terms: Vec<SearchTerm>,
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub in_thread: Vec<String>,
+    pub has_attachment: bool,
+    pub has_location: bool,
+    pub has_paper: bool,
+    pub is_failed: bool,
+    pub direction: Option<&'static str>,
+    pub before_ms: Option<i64>,
+    pub after_ms: Option<i64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum SearchTerm {
+    Word(String),
+    Phrase(String),
+}
+
+#[derive(Debug)]
+struct RawToken {
+    operator: Option<String>,
+    value: String,
+    quoted: bool,
+}
+
+pub(super) fn parse_search_query(input: &str) -> Result<SearchQuery, String> {
+    let mut query = SearchQuery::default();
+    for token in tokenize(input)? {
+        let Some(operator) = token.operator else {
+            let value = token.value.trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+            query.terms.push(if token.quoted {
+                SearchTerm::Phrase(value)
+            } else {
+                SearchTerm::Word(value)
+            });
+            continue;
+        };
+
+        let value = token.value.trim();
+        if value.is_empty() {
+            return Err(format!("search operator '{operator}:' needs a value"));
+        }
+        let lowered = value.to_lowercase();
+        match operator.as_str() {
+            "from" => query.from.push(lowered),
+            "to" => query.to.push(lowered),
+            "in" => query.in_thread.push(lowered),
+            "has" => match lowered.as_str() {
+                "attachment" | "attachments" | "file" | "files" => query.has_attachment = true,
+                "location" | "geo" => query.has_location = true,
+                "paper" => query.has_paper = true,
+                _ => {
+                    return Err(format!(
+                        "unknown value for has: '{value}' (expected attachment, location or paper)"
+                    ))
+                }
+            },
+            "is" => {
+                let direction = match lowered.as_str() {
+                    "failed" => {
+                        query.is_failed = true;
+                        continue;
+                    }
+                    "outbound" | "sent" | "out" => "out",
+                    "inbound" | "received" | "in" => "in",
+                    _ => {
+                        return Err(format!(
+                            "unknown value for is: '{value}' (expected failed, outbound or inbound)"
+                        ))
+                    }
+                };
+                if query.direction.is_some_and(|current| current != direction) {
+                    return Err("is:outbound and is:inbound cannot be combined".to_string());
+                }
+                query.direction = Some(direction);
+            }
+            "before" => {
+                let day_start = parse_search_date(value, "before")?;
+                query.before_ms = Some(query.before_ms.map_or(day_start, |ms| ms.min(day_start)));
+            }
+            "after" => {
+                let day_start = parse_search_date(value, "after")?;
+                query.after_ms = Some(query.after_ms.map_or(day_start, |ms| ms.max(day_start)));
+            }
+            _ => {
+                return Err(format!(
+                    "unknown search operator '{operator}:' (wrap the term in quotes to search for it literally)"
+                ))
+            }
+        }
+    }
+
+    if let (Some(after_ms), Some(before_ms)) = (query.after_ms, query.before_ms) {
+        if after_ms >= before_ms {
+            return Err("after: date must be earlier than before: date".to_string());
+        }
+    }
+    Ok(query)
+}
+
+impl SearchQuery {
+    /// FTS5 table and MATCH expression for the free-text part, or `None` when
+    /// nothing searchable remains and the caller should use substring matching.
+    pub(super) fn fts_match(&self) -> Option<(&'static str, String)> {
+        if self
+            .terms
+            .iter()
+            .any(|term| term_text(term).chars().any(lacks_word_breaks))
+        {
+            return self.trigram_match();
+        }
+        let clauses = self
+            .terms
+            .iter()
+            .filter_map(|term| match term {
+                SearchTerm::Word(word) => {
+                    let token = fts_token(word);
+                    (!token.is_empty()).then(|| format!("\"{token}\"*"))
+                }
+                SearchTerm::Phrase(phrase) => {
+                    let tokens = phrase
+                        .split_whitespace()
+                        .map(fts_token)
+                        .filter(|token| !token.is_empty())
+                        .collect::<Vec<_>>();
+                    (!tokens.is_empty()).then(|| format!("\"{}\"", tokens.join(" ")))
+                }
+            })
+            .collect::<Vec<_>>();
+        if clauses.is_empty() {
+            return None;
+        }
+        Some(("messages_fts", clauses.join(" AND ")))
+    }
+
+    // Trigram queries match substrings, so every term must be at least three
+    // characters long; shorter CJK terms drop to the LIKE path instead.
+    fn trigram_match(&self) -> Option<(&'static str, String)> {
+        let mut clauses = Vec::new();
+        for term in &self.terms {
+            let text = term_text(term).trim();
+            if text.chars().count() < 3 {
+                return None;
+            }
+            clauses.push(format!("\"{}\"", text.replace('"', "\"\"")));
+        }
+        Some(("messages_fts_trigram", clauses.join(" AND ")))
+    }
+
+    /// Substring predicates used when FTS yields nothing (e.g. infix matches).
+    pub(super) fn push_text_filters(&self, clauses: &mut Vec<String>, params: &mut Vec<SqlValue>) {
+        for term in &self.terms {
+            let text = term_text(term);
+            clauses.push(
+                "(LOWER(m.title) LIKE ? ESCAPE '\\' OR LOWER(m.body) LIKE ? ESCAPE '\\')"
+                    .to_string(),
+            );
+            let pattern = format!("%{}%", escape_like(&text.to_lowercase()));
+            params.push(SqlValue::Text(pattern.clone()));
+            params.push(SqlValue::Text(pattern));
+        }
+    }
+
+    pub(super) fn highlight_terms(&self) -> Vec<String> {
+        self.terms
+            .iter()
+            .map(|term| term_text(term).trim().to_string())
+            .filter(|term| !term.is_empty())
+            .collect()
+    }
+
+    pub(super) fn push_filters(&self, clauses: &mut Vec<String>, params: &mut Vec<SqlValue>) {
+        for value in &self.from {
+            if value == "me" {
+                clauses.push("m.direction = 'out'".to_string());
+            } else {
+                push_peer_filter("m.source", value, clauses, params);
+            }
+        }
+        for value in &self.to {
+            if value == "me" {
+                clauses.push("m.direction != 'out'".to_string());
+            } else {
+                push_peer_filter("m.destination", value, clauses, params);
+            }
+        }
+        for value in &self.in_thread {
+            push_peer_filter("m.thread_id", value, clauses, params);
+        }
+        if self.has_attachment {
+            clauses.push("m.has_attachments = 1".to_string());
+        }
+        if self.has_paper {
+            clauses.push("m.has_paper = 1".to_string());
+        }
+        if self.has_location {
+            clauses.push(
+                "EXISTS (SELECT 1 FROM map_points mp WHERE mp.message_id = m.message_id)"
+                    .to_string(),
+            );
+        }
+        if self.is_failed {
+            clauses.push("m.delivery_state = 'failed'".to_string());
+        }
+        match self.direction {
+            Some("out") => clauses.push("m.direction = 'out'".to_string()),
+            Some(_) => clauses.push("m.direction != 'out'".to_string()),
+            None => {}
+        }
+        if let Some(before_ms) = self.before_ms {
+            clauses.push("m.ts_ms < ?".to_string());
+            params.push(SqlValue::Integer(before_ms));
+        }
+        if let Some(after_ms) = self.after_ms {
+            clauses.push("m.ts_ms >= ?".to_string());
+            params.push(SqlValue::Integer(after_ms));
+        }
+    }
+}
+
+// Peers match by hash prefix or by a substring of the thread display name.
+fn push_peer_filter(
+    column: &str,
+    value: &str,
+    clauses: &mut Vec<String>,
+    params: &mut Vec<SqlValue>,
+) {
+    clauses.push(format!(
+        "(LOWER({column}) LIKE ? ESCAPE '\\' OR {column} IN (
+          SELECT thread_id FROM threads WHERE LOWER(display_name) LIKE ? ESCAPE '\\'
+        ))"
+    ));
+    let escaped = escape_like(value);
+    params.push(SqlValue::Text(format!("{escaped}%")));
+    params.push(SqlValue::Text(format!("%{escaped}%")));
+}
+
+fn tokenize(input: &str) -> Result<Vec<RawToken>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            break;
+        };
+
+        if first == '"' {
+            chars.next();
+            tokens.push(RawToken {
+                operator: None,
+                value: read_quoted(&mut chars)?,
+                quoted: true,
+            });
+            continue;
+        }
+
+        let mut operator = None;
+        let mut value = String::new();
+        let mut quoted = false;
+        while let Some(ch) = chars.next_if(|ch| !ch.is_whitespace()) {
+            if ch == ':'
+                && operator.is_none()
+                && !value.is_empty()
+                && value.chars().all(|ch| ch.is_ascii_alphabetic())
+            {
+                operator = Some(value.to_ascii_lowercase());
+                value.clear();
+                if chars.next_if_eq(&'"').is_some() {
+                    value = read_quoted(&mut chars)?;
+                    quoted = true;
+                    if chars.peek().is_some_and(|ch| !ch.is_whitespace()) {
+                        return Err("expected a space after closing quote".to_string());
+                    }
+                    break;
+                }
+                continue;
+            }
+            value.push(ch);
+        }
+        tokens.push(RawToken {
+            operator,
+            value,
+            quoted,
+        });
+    }
+    Ok(tokens)
+}
+
+fn read_quoted(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<String, String> {
+    let mut value = String::new();
+    for ch in chars.by_ref() {
+        if ch == '"' {
+            return Ok(value);
+        }
+        value.push(ch);
+    }
+    Err("unterminated quote in search query".to_string())
+}
+
+fn parse_search_date(value: &str, operator: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid date for {operator}: '{value}' (expected YYYY-MM-DD)");
+    let mut parts = value.splitn(3, '-');
+    let (Some(year), Some(month), Some(day)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return Err(invalid());
+    }
+    let year = year.parse::<i64>().map_err(|_| invalid())?;
+    let month = month.parse::<i64>().map_err(|_| invalid())?;
+    let day = day.parse::<i64>().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    Ok(days_from_civil(year, month, day) * MS_PER_DAY)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date (UTC).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Strips the `\u{1}`/`\u{2}` markers emitted by FTS5 `snippet()` and returns
+/// the highlighted ranges as UTF-16 offsets for the webview.
+pub(super) fn split_snippet_markers(raw: &str) -> (String, Vec<[usize; 2]>) {
+    let mut text = String::with_capacity(raw.len());
+    let mut highlights = Vec::new();
+    let mut position = 0_usize;
+    let mut open = None;
+    for ch in raw.chars() {
+        match ch {
+            '\u{1}' => open = Some(position),
+            '\u{2}' => {
+                if let Some(start) = open.take() {
+                    highlights.push([start, position]);
+                }
+            }
+            _ => {
+                position += ch.len_utf16();
+                text.push(ch);
+            }
+        }
+    }
+    (text, highlights)
+}
+
+/// Builds a snippet for rows found without FTS by windowing the text around
+/// the first case-insensitive term match.
+pub(super) fn scan_snippet(
+    message: &IndexedMessage,
+    terms: &[String],
+) -> (String, Vec<[usize; 2]>) {
+    let source = if message.content.trim().is_empty() {
+        message.title.trim()
+    } else {
+        message.content.trim()
+    };
+    let chars = source.chars().collect::<Vec<_>>();
+    let folded = chars.iter().map(|ch| fold_char(*ch)).collect::<Vec<_>>();
+    let needles = terms
+        .iter()
+        .map(|term| term.chars().map(fold_char).collect::<Vec<_>>())
+        .filter(|needle| !needle.is_empty())
+        .collect::<Vec<_>>();
+
+    let first_match = needles
+        .iter()
+        .filter_map(|needle| find_chars(&folded, needle, 0))
+        .min()
+        .unwrap_or(0);
+    let start = first_match.saturating_sub(SCAN_SNIPPET_LEAD_CHARS);
+    let end = (start + SCAN_SNIPPET_MAX_CHARS).min(chars.len());
+
+    let mut snippet = String::new();
+    let mut offsets = Vec::with_capacity(end - start + 1);
+    if start > 0 {
+        snippet.push('…');
+    }
+    for ch in &chars[start..end] {
+        offsets.push(snippet.encode_utf16().count());
+        snippet.push(*ch);
+    }
+    offsets.push(snippet.encode_utf16().count());
+    if end < chars.len() {
+        snippet.push('…');
+    }
+
+    let mut highlights = Vec::new();
+    for needle in &needles {
+        let mut from = start;
+        while let Some(found) = find_chars(&folded[..end], needle, from) {
+            highlights.push([
+                offsets[found - start],
+                offsets[found + needle.len() - start],
+            ]);
+            from = found + needle.len();
+        }
+    }
+    highlights.sort_unstable();
+    (snippet, highlights)
+}
+
+fn fold_char(ch: char) -> char {
+    let mut lower = ch.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(folded), None) => folded,
+        _ => ch,
+    }
+}
+
+fn find_chars(haystack: &[char], needle: &[char], from: usize) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    (from..=haystack.len() - needle.len())
+        .find(|index| &haystack[*index..*index + needle.len()] == needle)
+}
+
+fn term_text(term: &SearchTerm) -> &str {
+    match term {
+        SearchTerm::Word(word) => word,
+        SearchTerm::Phrase(phrase) => phrase,
+    }
+}
+
+fn fts_token(term: &str) -> String {
+    term.chars()
+        .filter(|ch| ch.is_alphanumeric() || *ch == '_' || *ch == '-')
+        .collect()
+}
+
+// Scripts written without spaces between words; unicode61 would index whole
+// runs as a single token, so these are searched through the trigram index.
+fn lacks_word_breaks(ch: char) -> bool {
+    matches!(
+        u32::from(ch),
+        0x0E00..=0x0EFF // Thai, Lao
+            | 0x1000..=0x109F // Myanmar
+            | 0x1780..=0x17FF // Khmer
+            | 0x3040..=0x30FF // Hiragana, Katakana
+            | 0x3400..=0x4DBF // CJK Extension A
+            | 0x4E00..=0x9FFF // CJK Unified Ideographs
+            | 0xF900..=0xFAFF // CJK Compatibility Ideographs
+            | 0xFF66..=0xFF9F // Halfwidth Katakana
+    )
+}
+
+fn escape_like(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if matches!(ch, '%' | '_' | '\\') {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{IndexStore, SearchQueryParams};
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_operators_phrases_and_dates() {
+        let query = parse_search_query(
+            r#"from:alice to:"Base Camp" has:attachment is:failed after:2024-05-01 before:2024-06-01 "meet at noon" ridge"#,
+        )
+        .expect("parse query");
+        assert_eq!(query.from, vec!["alice".to_string()]);
+        assert_eq!(query.to, vec!["base camp".to_string()]);
+        assert!(query.has_attachment && query.is_failed);
+        assert_eq!(query.after_ms, Some(1_714_521_600_000));
+        assert_eq!(query.before_ms, Some(1_717_200_000_000));
+        assert_eq!(
+            query.terms,
+            vec![
+                SearchTerm::Phrase("meet at noon".to_string()),
+                SearchTerm::Word("ridge".to_string()),
+            ]
+        );
+        assert_eq!(
+            query.fts_match(),
+            Some(("messages_fts", r#""meet at noon" AND "ridge"*"#.to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_syntax_with_clear_errors() {
+        let cases = [
+            ("colour:red", "unknown search operator 'colour:'"),
+            ("has:boat", "unknown value for has: 'boat'"),
+            ("is:outbound is:inbound", "cannot be combined"),
+            ("before:2024-02-30", "invalid date for before: '2024-02-30'"),
+            ("after:2024-06-01 before:2024-05-01", "must be earlier"),
+            ("\"open phrase", "unterminated quote"),
+            ("from:", "'from:' needs a value"),
+        ];
+        for (input, expected) in cases {
+            let err = parse_search_query(input).expect_err(input);
+            assert!(err.contains(expected), "{input}: {err}");
+        }
+    }
+
+    #[test]
+    fn search_applies_operator_filters() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    {
+                        "id": "in-1", "source": "aaaa1111", "destination": "self",
+                        "direction": "in", "timestamp": 1_714_600_000_000_i64,
+                        "content": "ridge camp photos",
+                        "fields": { "attachments": [{ "name": "a.jpg", "size_bytes": 3 }] },
+                    },
+                    {
+                        "id": "out-1", "source": "self", "destination": "aaaa1111",
+                        "direction": "out", "timestamp": 1_714_700_000_000_i64,
+                        "content": "ridge route at geo:46.5,8.0",
+                        "receipt_status": "failed: no route",
+                    },
+                ]),
+                &json!([{ "peer": "aaaa1111", "name": "Alice" }]),
+            )
+            .expect("seed index");
+
+        let search = |query: &str| {
+            let value = store
+                .search_messages(SearchQueryParams {
+                    query: query.to_string(),
+                    thread_id: None,
+                    limit: None,
+                    cursor: None,
+                    sort: None,
+                })
+                .expect(query);
+            value["items"]
+                .as_array()
+                .expect("items")
+                .iter()
+                .map(|item| item["id"].as_str().unwrap_or_default().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(search("ridge from:alice"), vec!["in-1"]);
+        assert_eq!(search("ridge has:attachment"), vec!["in-1"]);
+        assert_eq!(search("has:location"), vec!["out-1"]);
+        assert_eq!(search("is:failed"), vec!["out-1"]);
+        assert_eq!(search("ridge is:outbound"), vec!["out-1"]);
+        assert_eq!(search("\"camp photos\""), vec!["in-1"]);
+        assert_eq!(search("ridge before:2024-05-02"), vec!["in-1"]);
+        assert!(search("ridge after:2024-06-01").is_empty());
+    }
+
+    #[test]
+    fn search_matches_non_ascii_scripts() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "ru", "source": "peer", "destination": "self", "direction": "in",
+                      "timestamp": 1_000, "content": "Встреча у моста завтра" },
+                    { "id": "fr", "source": "peer", "destination": "self", "direction": "in",
+                      "timestamp": 2_000, "content": "Rendez-vous au Café Noël" },
+                    { "id": "el", "source": "peer", "destination": "self", "direction": "in",
+                      "timestamp": 3_000, "content": "Καλημέρα από την Αθήνα" },
+                    { "id": "ja", "source": "peer", "destination": "self", "direction": "in",
+                      "timestamp": 4_000, "content": "東京タワーで会いましょう" },
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+
+        let search = |query: &str| {
+            let value = store
+                .search_messages(SearchQueryParams {
+                    query: query.to_string(),
+                    thread_id: None,
+                    limit: None,
+                    cursor: None,
+                    sort: None,
+                })
+                .expect(query);
+            value["items"]
+                .as_array()
+                .expect("items")
+                .iter()
+                .map(|item| item["id"].as_str().unwrap_or_default().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(search("встреч"), vec!["ru"]);
+        assert_eq!(search("cafe noel"), vec!["fr"]);
+        assert_eq!(search("ΑΘΉΝΑ"), vec!["el"]);
+        assert_eq!(search("タワーで"), vec!["ja"]);
+        assert_eq!(search("東京"), vec!["ja"]);
+    }
+
+    #[test]
+    fn search_returns_snippets_relevance_and_keyset_pages() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "a", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 1_000, "content": "beacon beacon beacon on the ridge" },
+                    { "id": "b", "source": "peer-b", "destination": "self", "direction": "in",
+                      "timestamp": 2_000, "content": "a long day, then the beacon lit up at dusk" },
+                    { "id": "c", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 3_000, "content": "no match here, only lighthouses" },
+                ]),
+                &json!([{ "peer": "peer-a", "name": "Alice" }]),
+            )
+            .expect("seed index");
+
+        let page = |sort: Option<&str>, cursor: Option<String>| {
+            store
+                .search_messages(SearchQueryParams {
+                    query: "beacon".to_string(),
+                    thread_id: None,
+                    limit: Some(1),
+                    cursor,
+                    sort: sort.map(str::to_string),
+                })
+                .expect("search page")
+        };
+
+        let first = page(None, None);
+        let hit = &first["items"][0];
+        assert_eq!(hit["id"], "b");
+        assert_eq!(hit["thread_id"], "peer-b");
+        let snippet = hit["snippet"].as_str().expect("snippet");
+        let [start, end] = [
+            hit["highlights"][0][0].as_u64().expect("start") as usize,
+            hit["highlights"][0][1].as_u64().expect("end") as usize,
+        ];
+        let utf16 = snippet.encode_utf16().collect::<Vec<_>>();
+        assert_eq!(String::from_utf16_lossy(&utf16[start..end]), "beacon");
+
+        let second = page(None, first["next_cursor"].as_str().map(str::to_string));
+        assert_eq!(second["items"][0]["id"], "a");
+        assert_eq!(second["items"][0]["thread_name"], "Alice");
+        assert!(second["next_cursor"].is_null());
+
+        let ranked = page(Some("relevance"), None);
+        assert_eq!(ranked["items"][0]["id"], "a");
+        assert!(ranked["items"][0]["score"].as_f64().is_some());
+        let ranked_next = page(
+            Some("relevance"),
+            ranked["next_cursor"].as_str().map(str::to_string),
+        );
+        assert_eq!(ranked_next["items"][0]["id"], "b");
+        assert!(ranked_next["items"][0]["snippet"]
+            .as_str()
+            .is_some_and(|snippet| snippet.contains("beacon")));
+
+        let err = store
+            .search_messages(SearchQueryParams {
+                query: "beacon".to_string(),
+                thread_id: None,
+                limit: Some(1),
+                cursor: Some("1".to_string()),
+                sort: None,
+            })
+            .expect_err("legacy offset cursor rejected");
+        assert!(err.contains("invalid search cursor"), "{err}");
+
+        let infix = store
+            .search_messages(SearchQueryParams {
+                query: "ighthous".to_string(),
+                thread_id: None,
+                limit: None,
+                cursor: None,
+                sort: None,
+            })
+            .expect("infix search");
+        assert_eq!(infix["items"][0]["id"], "c");
+        assert_eq!(infix["items"][0]["highlights"][0], json!([21, 29]));
+    }
+}
@@ -0,0 +1,370 @@
+use super::*;
+use sha2::{Digest, Sha256};
+
+const SEND_QUEUE_BASE_BACKOFF_MS: i64 = 2_000;
+const SEND_QUEUE_MAX_BACKOFF_MS: i64 = 5 * 60 * 1000;
+// Attempts are only made while the runtime reports running, so this many
+// failures in a row means the send will not go through on its own.
+const SEND_QUEUE_MAX_ATTEMPTS: i64 = 20;
+
+impl IndexStore {
+    /// Persists a send for `profile`/`rpc` until the runtime can take it. A
+    /// request without an id gets one here, so the caller can follow the
+    /// message before and after it is handed over.
+    pub(crate) fn enqueue_send(
+        &self,
+        profile: &str,
+        rpc: &str,
+        mut request: QueuedSend,
+    ) -> Result<QueuedSendEntry, String> {
+        let now_ms = current_timestamp_ms();
+        let message_id = request
+            .id
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| queued_message_id(&request, now_ms));
+        request.id = Some(message_id.clone());
+        let request_json = serde_json::to_string(&request)
+            .map_err(|err| format!("encode queued send failed: {err}"))?;
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        conn.execute(
+            "
+            INSERT INTO send_queue (message_id, profile, rpc, request_json, created_at_ms, next_attempt_ms)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+            ",
+            params![message_id, profile, rpc, request_json, now_ms],
+        )
+        .map_err(|err| format!("enqueue send failed: {err}"))?;
+        Ok(QueuedSendEntry {
+            queue_id: conn.last_insert_rowid(),
+            message_id,
+            profile: profile.to_string(),
+            rpc: rpc.to_string(),
+            destination: request.destination.clone(),
+            title: request.title.clone(),
+            content: request.content.clone(),
+            request,
+            created_at_ms: now_ms,
+            attempts: 0,
+            next_attempt_ms: now_ms,
+            last_error: None,
+            failed_at_ms: None,
+            unconfirmed: false,
+        })
+    }
+
+    /// Queued sends whose next attempt is due, oldest first. Failed sends
+    /// wait to be discarded and are not retried.
+    pub(crate) fn due_queued_sends(&self, limit: usize) -> Result<Vec<QueuedSendEntry>, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        load_queued_sends(
+            &conn,
+            "
+            WHERE failed_at_ms IS NULL AND next_attempt_ms <= ?1
+            ORDER BY next_attempt_ms ASC, id ASC
+            LIMIT ?2
+            ",
+            params![current_timestamp_ms(), limit as i64],
+        )
+    }
+
+    /// Whether a send is still waiting for the runtime. Failed sends don't count.
+    pub(crate) fn has_pending_sends(&self) -> Result<bool, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM send_queue WHERE failed_at_ms IS NULL)",
+            [],
+            |row| row.get::<_, bool>(0),
+        )
+        .map_err(|err| format!("read send queue failed: {err}"))
+    }
+
+    /// Every queued send in the order it was composed, including failed ones.
+    pub(crate) fn list_send_queue(&self) -> Result<Value, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let items = load_queued_sends(&conn, "ORDER BY id ASC", [])?;
+        Ok(json!({ "items": items }))
+    }
+
+    /// Drops a send the runtime has accepted, or one the user gave up on.
+    /// Returns whether it was still queued.
+    pub(crate) fn remove_queued_send(&self, queue_id: i64) -> Result<bool, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        conn.execute("DELETE FROM send_queue WHERE id = ?1", params![queue_id])
+            .map(|removed| removed > 0)
+            .map_err(|err| format!("remove queued send failed: {err}"))
+    }
+
+    /// Records a failed attempt and schedules the next one, doubling the wait
+    /// each time up to five minutes. `unconfirmed` marks an attempt the
+    /// runtime may have accepted without answering, so the next one checks
+    /// before sending again. After `SEND_QUEUE_MAX_ATTEMPTS` the send is
+    /// marked failed instead. Returns the updated entry.
+    pub(crate) fn reschedule_queued_send(
+        &self,
+        queue_id: i64,
+        error: &str,
+        unconfirmed: bool,
+    ) -> Result<Option<QueuedSendEntry>, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let attempts = conn
+            .query_row(
+                "SELECT attempts FROM send_queue WHERE id = ?1",
+                params![queue_id],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+            .map_err(|err| format!("read queued send failed: {err}"))?;
+        let Some(attempts) = attempts else {
+            return Ok(None);
+        };
+        let attempts = attempts.max(0) + 1;
+        let now_ms = current_timestamp_ms();
+        let next_attempt_ms = now_ms.saturating_add(queue_backoff_ms(attempts));
+        let failed_at_ms = (attempts >= SEND_QUEUE_MAX_ATTEMPTS).then_some(now_ms);
+        conn.execute(
+            "
+            UPDATE send_queue
+            SET attempts = ?1, next_attempt_ms = ?2, last_error = ?3, unconfirmed = ?4,
+                failed_at_ms = ?5
+            WHERE id = ?6
+            ",
+            params![
+                attempts,
+                next_attempt_ms,
+                error,
+                unconfirmed,
+                failed_at_ms,
+                queue_id
+            ],
+        )
+        .map_err(|err| format!("reschedule queued send failed: {err}"))?;
+        let mut entries = load_queued_sends(&conn, "WHERE id = ?1", params![queue_id])?;
+        Ok(entries.pop())
+    }
+
+    /// Marks a send the runtime rejected as failed. It stays listed, with the
+    /// error, until it is discarded. Returns the updated entry.
+    pub(crate) fn fail_queued_send(
+        &self,
+        queue_id: i64,
+        error: &str,
+    ) -> Result<Option<QueuedSendEntry>, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        conn.execute(
+            "
+            UPDATE send_queue
+            SET attempts = attempts + 1, last_error = ?1, unconfirmed = 0, failed_at_ms = ?2
+            WHERE id = ?3
+            ",
+            params![error, current_timestamp_ms(), queue_id],
+        )
+        .map_err(|err| format!("fail queued send failed: {err}"))?;
+        let mut entries = load_queued_sends(&conn, "WHERE id = ?1", params![queue_id])?;
+        Ok(entries.pop())
+    }
+}
+
+fn queue_backoff_ms(attempts: i64) -> i64 {
+    let exponent = (attempts - 1).clamp(0, 16) as u32;
+    SEND_QUEUE_BASE_BACKOFF_MS
+        .saturating_mul(1_i64 << exponent)
+        .min(SEND_QUEUE_MAX_BACKOFF_MS)
+}
+
+// LXMF-style 16-byte hex id; the clock and a per-process counter keep two
+// identical messages composed together apart.
+fn queued_message_id(request: &QueuedSend, now_ms: i64) -> String {
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let mut hasher = Sha256::new();
+    hasher.update(request.destination.as_bytes());
+    hasher.update(request.content.as_bytes());
+    hasher.update(now_ms.to_be_bytes());
+    hasher.update(COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    hex::encode(&hasher.finalize()[..16])
+}
+
+fn load_queued_sends(
+    conn: &Connection,
+    clause: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<QueuedSendEntry>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "
+            SELECT id, message_id, profile, rpc, request_json, created_at_ms, attempts,
+              next_attempt_ms, last_error, failed_at_ms, unconfirmed
+            FROM send_queue
+            {clause}
+            "
+        ))
+        .map_err(|err| format!("prepare send queue query failed: {err}"))?;
+    let rows = stmt
+        .query_map(params, |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, i64>(5)?,
+                row.get::<_, i64>(6)?,
+                row.get::<_, i64>(7)?,
+                row.get::<_, Option<String>>(8)?,
+                row.get::<_, Option<i64>>(9)?,
+                row.get::<_, bool>(10)?,
+            ))
+        })
+        .map_err(|err| format!("query send queue failed: {err}"))?;
+    let mut entries = Vec::new();
+    for row in rows {
+        let (
+            queue_id,
+            message_id,
+            profile,
+            rpc,
+            request_json,
+            created_at_ms,
+            attempts,
+            next_attempt_ms,
+            last_error,
+            failed_at_ms,
+            unconfirmed,
+        ) = row.map_err(|err| format!("parse send queue row failed: {err}"))?;
+        let request = serde_json::from_str::<QueuedSend>(&request_json)
+            .map_err(|err| format!("decode queued send {queue_id} failed: {err}"))?;
+        entries.push(QueuedSendEntry {
+            queue_id,
+            message_id,
+            profile,
+            rpc,
+            destination: request.destination.clone(),
+            title: request.title.clone(),
+            content: request.content.clone(),
+            request,
+            created_at_ms,
+            attempts: attempts.clamp(0, u32::MAX as i64) as u32,
+            next_attempt_ms,
+            last_error,
+            failed_at_ms,
+            unconfirmed,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(content: &str) -> QueuedSend {
+        QueuedSend {
+            id: None,
+            source: None,
+            destination: "peer-a".to_string(),
+            title: String::new(),
+            content: content.to_string(),
+            fields: Some(json!({ "16": { "reply_to": "m0" } })),
+            method: Some("direct".to_string()),
+            stamp_cost: None,
+            include_ticket: false,
+        }
+    }
+
+    #[test]
+    fn queued_sends_survive_reopen_and_back_off_on_failure() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("index.sqlite3");
+        let first = {
+            let store = IndexStore::new(path.clone()).expect("open store");
+            let first = store
+                .enqueue_send("default", "127.0.0.1:4243", queued("hello"))
+                .expect("enqueue");
+            let second = store
+                .enqueue_send("default", "127.0.0.1:4243", queued("hello"))
+                .expect("enqueue twice");
+            assert_eq!(first.message_id.len(), 32);
+            assert_ne!(first.message_id, second.message_id);
+            first
+        };
+
+        let store = IndexStore::new(path).expect("reopen store");
+        let due = store.due_queued_sends(10).expect("due sends");
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].queue_id, first.queue_id);
+        assert_eq!(
+            due[0].request.id.as_deref(),
+            Some(first.message_id.as_str())
+        );
+        assert_eq!(
+            due[0].request.fields,
+            Some(json!({ "16": { "reply_to": "m0" } }))
+        );
+
+        let rescheduled = store
+            .reschedule_queued_send(first.queue_id, "runtime worker did not respond", true)
+            .expect("reschedule")
+            .expect("still queued");
+        assert_eq!(rescheduled.attempts, 1);
+        assert!(rescheduled.unconfirmed);
+        assert!(rescheduled.failed_at_ms.is_none());
+        assert!(rescheduled.next_attempt_ms > current_timestamp_ms());
+        assert_eq!(store.due_queued_sends(10).expect("due").len(), 1);
+        assert_eq!(queue_backoff_ms(3), 8_000);
+        assert_eq!(queue_backoff_ms(40), SEND_QUEUE_MAX_BACKOFF_MS);
+
+        let rejected = store
+            .fail_queued_send(due[1].queue_id, "invalid destination")
+            .expect("fail")
+            .expect("still listed");
+        assert!(rejected.failed_at_ms.is_some());
+        assert!(store.due_queued_sends(10).expect("due").is_empty());
+        {
+            let conn = store.conn.lock().expect("lock");
+            conn.execute(
+                "UPDATE send_queue SET attempts = ?1 WHERE id = ?2",
+                params![SEND_QUEUE_MAX_ATTEMPTS - 1, first.queue_id],
+            )
+            .expect("age entry");
+        }
+        let exhausted = store
+            .reschedule_queued_send(first.queue_id, "runtime not started", false)
+            .expect("reschedule")
+            .expect("still listed");
+        assert!(exhausted.failed_at_ms.is_some());
+        assert!(!exhausted.unconfirmed);
+
+        assert!(store.remove_queued_send(first.queue_id).expect("remove"));
+        assert!(!store
+            .remove_queued_send(first.queue_id)
+            .expect("remove again"));
+        let listed = store.list_send_queue().expect("list");
+        assert_eq!(listed["items"].as_array().map(Vec::len), Some(1));
+        assert_eq!(listed["items"][0]["content"], "hello");
+        assert!(listed["items"][0].get("request").is_none());
+    }
+}
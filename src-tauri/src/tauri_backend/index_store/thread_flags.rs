@@ -0,0 +1,261 @@
+use super::*;
+
+impl IndexStore {
+    pub(crate) fn set_thread_flags(&self, params: ThreadFlagParams) -> Result<Value, String> {
+        let thread_id = params.thread_id.trim();
+        if thread_id.is_empty() {
+            return Err("thread_id is required".to_string());
+        }
+        if params.muted_until_ms.is_some() && params.muted != Some(true) {
+            return Err("muted_until_ms requires muted=true".to_string());
+        }
+        if let Some(until) = params.muted_until_ms {
+            if until <= current_timestamp_ms() {
+                return Err("muted_until_ms must be in the future".to_string());
+            }
+        }
+
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| "index lock poisoned".to_string())?;
+        let mut flags = read_thread_flags(&conn, thread_id)?;
+        if let Some(pinned) = params.pinned {
+            flags.pinned = pinned;
+        }
+        if let Some(muted) = params.muted {
+            flags.muted = muted;
+            flags.muted_until_ms = if muted { params.muted_until_ms } else { None };
+        }
+        if let Some(archived) = params.archived {
+            flags.archived = archived;
+        }
+
+        let tx = conn
+            .transaction()
+            .map_err(|err| format!("start thread flags transaction failed: {err}"))?;
+        tx.execute(
+            "
+            INSERT INTO thread_flags (
+              thread_id,
+              pinned,
+              muted,
+              muted_until_ms,
+              archived,
+              updated_at_ms
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT(thread_id) DO UPDATE SET
+              pinned = excluded.pinned,
+              muted = excluded.muted,
+              muted_until_ms = excluded.muted_until_ms,
+              archived = excluded.archived,
+              updated_at_ms = excluded.updated_at_ms
+            ",
+            params![
+                thread_id,
+                if flags.pinned { 1 } else { 0 },
+                if flags.muted { 1 } else { 0 },
+                flags.muted_until_ms,
+                if flags.archived { 1 } else { 0 },
+                current_timestamp_ms(),
+            ],
+        )
+        .map_err(|err| format!("update thread flags failed: {err}"))?;
+        tx.execute(
+            "UPDATE threads SET pinned = ?1, muted = ?2 WHERE thread_id = ?3",
+            params![
+                if flags.pinned { 1 } else { 0 },
+                if flags.muted { 1 } else { 0 },
+                thread_id
+            ],
+        )
+        .map_err(|err| format!("mirror thread flags failed: {err}"))?;
+        tx.commit()
+            .map_err(|err| format!("commit thread flags failed: {err}"))?;
+
+        serde_json::to_value(flags).map_err(|err| format!("serialize thread flags failed: {err}"))
+    }
+}
+
+pub(super) fn read_thread_flags(conn: &Connection, thread_id: &str) -> Result<ThreadFlags, String> {
+    let existing = conn
+        .query_row(
+            "
+            SELECT pinned, muted, muted_until_ms, archived
+            FROM thread_flags
+            WHERE thread_id = ?1
+            ",
+            params![thread_id],
+            |row| {
+                let muted_until_ms = row.get::<_, Option<i64>>(2).ok().flatten();
+                let mute_expired = muted_until_ms
+                    .map(|until| until <= current_timestamp_ms())
+                    .unwrap_or(false);
+                Ok(ThreadFlags {
+                    thread_id: thread_id.to_string(),
+                    pinned: row.get::<_, i64>(0).unwrap_or(0) == 1,
+                    muted: row.get::<_, i64>(1).unwrap_or(0) == 1 && !mute_expired,
+                    muted_until_ms: muted_until_ms.filter(|_| !mute_expired),
+                    archived: row.get::<_, i64>(3).unwrap_or(0) == 1,
+                })
+            },
+        )
+        .optional()
+        .map_err(|err| format!("read thread flags failed: {err}"))?;
+    Ok(existing.unwrap_or_else(|| ThreadFlags {
+        thread_id: thread_id.to_string(),
+        pinned: false,
+        muted: false,
+        muted_until_ms: None,
+        archived: false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(thread_id: &str) -> ThreadFlagParams {
+        ThreadFlagParams {
+            thread_id: thread_id.to_string(),
+            pinned: None,
+            muted: None,
+            muted_until_ms: None,
+            archived: None,
+        }
+    }
+
+    fn threads(store: &IndexStore, pinned_only: bool, archived: Option<bool>) -> Vec<Value> {
+        store
+            .query_threads(ThreadQueryParams {
+                query: None,
+                limit: None,
+                cursor: None,
+                pinned_only: Some(pinned_only),
+                archived,
+            })
+            .expect("threads")["items"]
+            .as_array()
+            .expect("items")
+            .clone()
+    }
+
+    fn ids(items: &[Value]) -> Vec<&str> {
+        items
+            .iter()
+            .map(|item| item["thread_id"].as_str().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn pin_mute_and_archive_shape_the_thread_list() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([
+                    { "id": "a1", "source": "peer-a", "destination": "self", "direction": "in",
+                      "timestamp": 1_000, "content": "older" },
+                    { "id": "b1", "source": "peer-b", "destination": "self", "direction": "in",
+                      "timestamp": 2_000, "content": "newer" },
+                ]),
+                &json!([]),
+            )
+            .expect("seed index");
+        assert_eq!(ids(&threads(&store, false, None)), vec!["peer-b", "peer-a"]);
+
+        store
+            .set_thread_flags(ThreadFlagParams {
+                pinned: Some(true),
+                ..flags("peer-a")
+            })
+            .expect("pin");
+        assert_eq!(ids(&threads(&store, false, None)), vec!["peer-a", "peer-b"]);
+        assert_eq!(ids(&threads(&store, true, None)), vec!["peer-a"]);
+
+        store
+            .set_thread_flags(ThreadFlagParams {
+                archived: Some(true),
+                ..flags("peer-b")
+            })
+            .expect("archive");
+        assert_eq!(ids(&threads(&store, false, Some(false))), vec!["peer-a"]);
+        assert_eq!(ids(&threads(&store, false, Some(true))), vec!["peer-b"]);
+
+        // A new message rebuilds the summary without dropping the flags.
+        store
+            .ingest_event_payload(&json!({
+                "event_type": "inbound",
+                "payload": { "id": "a2", "source": "peer-a", "destination": "self",
+                             "direction": "in", "timestamp": 3_000, "content": "again" },
+            }))
+            .expect("ingest");
+        let listed = threads(&store, false, None);
+        assert_eq!(ids(&listed), vec!["peer-a", "peer-b"]);
+        assert_eq!(listed[0]["pinned"], true);
+        assert_eq!(listed[1]["archived"], true);
+    }
+
+    #[test]
+    fn timed_mutes_expire() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = IndexStore::new(dir.path().join("index.sqlite3")).expect("open store");
+        store
+            .reindex_from_runtime_payloads(
+                &json!([{ "id": "a1", "source": "peer-a", "destination": "self",
+                          "direction": "in", "timestamp": 1_000, "content": "hi" }]),
+                &json!([]),
+            )
+            .expect("seed index");
+
+        let err = store
+            .set_thread_flags(ThreadFlagParams {
+                muted_until_ms: Some(current_timestamp_ms() + 60_000),
+                ..flags("peer-a")
+            })
+            .expect_err("until without muted");
+        assert!(err.contains("requires muted=true"), "{err}");
+        let err = store
+            .set_thread_flags(ThreadFlagParams {
+                muted: Some(true),
+                muted_until_ms: Some(1),
+                ..flags("peer-a")
+            })
+            .expect_err("until in the past");
+        assert!(err.contains("in the future"), "{err}");
+
+        let until = current_timestamp_ms() + 60_000;
+        let muted = store
+            .set_thread_flags(ThreadFlagParams {
+                muted: Some(true),
+                muted_until_ms: Some(until),
+                ..flags("peer-a")
+            })
+            .expect("mute");
+        assert_eq!(muted["muted"], true);
+        assert_eq!(muted["muted_until_ms"], until);
+        assert_eq!(threads(&store, false, None)[0]["muted"], true);
+
+        store
+            .conn
+            .lock()
+            .expect("lock")
+            .execute(
+                "UPDATE thread_flags SET muted_until_ms = ?1 WHERE thread_id = 'peer-a'",
+                params![current_timestamp_ms() - 1],
+            )
+            .expect("expire mute");
+        assert_eq!(threads(&store, false, None)[0]["muted"], false);
+        let pinned = store
+            .set_thread_flags(ThreadFlagParams {
+                pinned: Some(true),
+                ..flags("peer-a")
+            })
+            .expect("pin");
+        assert_eq!(pinned["muted"], false);
+        assert_eq!(pinned["muted_until_ms"], Value::Null);
+        let listed = threads(&store, false, None);
+        assert_eq!(listed[0]["muted"], false);
+        assert_eq!(listed[0]["pinned"], true);
+    }
+}
@@ -1,4 +1,5 @@
 mod actor;
+mod announce_data;
 mod attachment_handles;
 mod commands;
 mod index_store;
@@ -6,7 +7,7 @@ mod selector;
 
 use actor::{ActorCommand, RuntimeActor};
 use attachment_handles::AttachmentHandleManager;
-use index_store::IndexStore;
+use index_store::{IndexStore, IndexStores};
 use selector::{
     auto_daemon_enabled, default_profile, default_rpc, default_transport, RuntimeSelector,
 };
@@ -23,8 +24,13 @@ use tauri_plugin_deep_link::DeepLinkExt;
 
 pub(crate) const LXMF_EVENT_CHANNEL: &str = "weft://lxmf-event";
 pub(crate) const TRAY_ACTION_CHANNEL: &str = "weft://tray-action";
+pub(crate) const SEND_QUEUE_CHANNEL: &str = "weft://send-queue";
 pub(crate) const DEFAULT_EVENT_PUMP_INTERVAL_MS: u64 = 200;
 const INDEX_BACKFILL_FRESHNESS_THRESHOLD_MS: i64 = 15 * 60 * 1000;
+const INDEX_MAINTENANCE_INITIAL_DELAY_MS: u64 = 5 * 60 * 1000;
+const INDEX_MAINTENANCE_INTERVAL_MS: u64 = 60 * 60 * 1000;
+const SEND_QUEUE_POLL_INTERVAL_MS: u64 = 2_000;
+const SEND_QUEUE_BATCH_SIZE: usize = 16;
 const TRAY_ICON_ID: &str = "weft-tray";
 const TRAY_MENU_OPEN: &str = "open";
 const TRAY_MENU_NEW_MESSAGE: &str = "new_message";
@@ -501,6 +507,8 @@ fn setup_status_tray(
     Ok(())
 }
 
+// Base name for the per-profile index databases (see `IndexStores`); builds
+// before those kept a single shared index at exactly this path.
 fn default_index_store_path() -> PathBuf {
     if let Some(explicit_path) = std::env::var_os("WEFT_INDEX_STORE_PATH") {
         return PathBuf::from(explicit_path);
@@ -547,18 +555,27 @@ fn spawn_index_backfill(
             }
         }
 
-        if let Err(err) = commands::indexing::reindex_index_store_from_runtime(
+        let summary = match commands::indexing::reindex_index_store_from_runtime(
             &actor,
             index_store.as_ref(),
             selector,
         ) {
-            log::debug!("index backfill skipped: {err}");
-        } else if let Ok(status) = index_store.index_status() {
+            Ok(summary) => summary,
+            Err(err) => {
+                log::debug!("index backfill skipped: {err}");
+                return;
+            }
+        };
+        if let Ok(status) = index_store.index_status() {
             let freshness_ms = status
                 .last_sync_ms
                 .map(|last_sync_ms| now_epoch_ms().saturating_sub(last_sync_ms));
             log::info!(
-                "index backfill completed message_count={} thread_count={} freshness_ms={}",
+                "index backfill completed upserted={} unchanged={} deleted={} resumed={} message_count={} thread_count={} freshness_ms={}",
+                summary.upserted,
+                summary.unchanged,
+                summary.deleted,
+                summary.resumed,
                 status.message_count,
                 status.thread_count,
                 freshness_ms
@@ -571,12 +588,95 @@ fn spawn_index_backfill(
     });
 }
 
+// Applies retention policies, prunes announce history and reclaims free pages
+// periodically in every open profile index once its first sync has populated it.
+fn spawn_index_maintenance(index_stores: Arc<IndexStores>) {
+    let spawned = thread::Builder::new()
+        .name("weft-index-maintenance".to_string())
+        .spawn(move || {
+            thread::sleep(Duration::from_millis(INDEX_MAINTENANCE_INITIAL_DELAY_MS));
+            loop {
+                for index_store in index_stores.open_stores() {
+                    if !index_store.is_ready() {
+                        continue;
+                    }
+                    match index_store.apply_retention(false) {
+                        Ok(report) if report.messages > 0 || report.attachments > 0 => {
+                            log::info!(
+                                "index retention removed messages={} attachments={} attachment_bytes={}",
+                                report.messages,
+                                report.attachments,
+                                report.attachment_bytes
+                            );
+                        }
+                        Ok(_) => {}
+                        Err(err) => log::warn!("index retention failed: {err}"),
+                    }
+                    match index_store.prune_announces() {
+                        Ok(0) => {}
+                        Ok(pruned) => log::info!("announce history pruned entries={pruned}"),
+                        Err(err) => log::warn!("announce history prune failed: {err}"),
+                    }
+                    match index_store.reclaim_free_pages() {
+                        Ok(true) => log::info!("index vacuumed to reclaim free pages"),
+                        Ok(false) => {}
+                        Err(err) => log::warn!("index vacuum failed: {err}"),
+                    }
+                }
+                thread::sleep(Duration::from_millis(INDEX_MAINTENANCE_INTERVAL_MS));
+            }
+        });
+    if let Err(err) = spawned {
+        log::warn!("index maintenance task failed to start: {err}");
+    }
+}
+
+// Flushes the offline send queue of every open profile index. Entries only go
+// out while the runtime reports running, so a stopped or restarting runtime
+// just leaves them queued.
+fn spawn_send_queue_worker(
+    app: tauri::AppHandle,
+    actor: RuntimeActor,
+    index_stores: Arc<IndexStores>,
+) {
+    let spawned = thread::Builder::new()
+        .name("weft-send-queue".to_string())
+        .spawn(move || {
+            let emit = |event: serde_json::Value| {
+                let _ = app.emit(SEND_QUEUE_CHANNEL, event);
+            };
+            for profile in index_stores.open_profiles_with_pending_sends() {
+                log::info!("send queue resumed for profile '{profile}'");
+            }
+            loop {
+                for index_store in index_stores.open_stores() {
+                    commands::flush_send_queue(
+                        &actor,
+                        index_store.as_ref(),
+                        SEND_QUEUE_BATCH_SIZE,
+                        &emit,
+                    );
+                }
+                thread::sleep(Duration::from_millis(SEND_QUEUE_POLL_INTERVAL_MS));
+            }
+        });
+    if let Err(err) = spawned {
+        log::warn!("send queue worker failed to start: {err}");
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let actor = RuntimeActor::spawn();
-    let index_store = Arc::new(
-        IndexStore::new(default_index_store_path()).expect("failed to initialize index store"),
-    );
+    let startup_selector = RuntimeSelector::load(default_profile(), default_rpc());
+    let startup_profile = startup_selector
+        .as_ref()
+        .map(|selector| selector.profile_name.clone())
+        .unwrap_or_else(|_| "default".to_string());
+    let index_stores = Arc::new(IndexStores::open(
+        default_index_store_path(),
+        &startup_profile,
+    ));
     let attachment_handles = Arc::new(AttachmentHandleManager::default());
     let app = tauri::Builder::default()
         .plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
@@ -602,7 +702,7 @@ pub fn run() {
         }))
         .plugin(tauri_plugin_deep_link::init())
         .manage(actor.clone())
-        .manage(index_store.clone())
+        .manage(index_stores.clone())
         .manage(attachment_handles.clone())
         .manage(EventPumpControl::default())
         .manage(DesktopShellState::default())
@@ -627,7 +727,7 @@ pub fn run() {
             }
 
             if auto_daemon_enabled() {
-                match RuntimeSelector::load(default_profile(), default_rpc()) {
+                match startup_selector.clone() {
                     Ok(selector) => {
                         if let Err(err) = actor.request(ActorCommand::Start {
                             selector,
@@ -642,7 +742,10 @@ pub fn run() {
                 }
             }
 
-            if let Ok(selector) = RuntimeSelector::load(default_profile(), default_rpc()) {
+            spawn_index_maintenance(index_stores.clone());
+            spawn_send_queue_worker(app.handle().clone(), actor.clone(), index_stores.clone());
+            if let Ok(selector) = startup_selector.clone() {
+                let index_store = index_stores.for_profile(&selector.profile_name);
                 spawn_index_backfill(actor.clone(), index_store.clone(), selector.clone());
                 if let Some(control) = app.try_state::<EventPumpControl>() {
                     if let Err(err) = control.start(
@@ -697,15 +800,44 @@ pub fn run() {
             commands::indexing::query_threads_page,
             commands::indexing::lxmf_query_thread_messages,
             commands::indexing::query_thread_messages_page,
+            commands::indexing::lxmf_query_message_replies,
+            commands::indexing::lxmf_query_message_revisions,
+            commands::indexing::lxmf_mark_thread_read,
+            commands::indexing::lxmf_mark_all_threads_read,
+            commands::indexing::lxmf_mark_thread_unread,
+            commands::indexing::lxmf_set_thread_pinned,
+            commands::indexing::lxmf_set_thread_muted,
+            commands::indexing::lxmf_set_thread_archived,
+            commands::indexing::lxmf_message_delivery_timeline,
+            commands::indexing::lxmf_query_outbox,
+            commands::indexing::lxmf_outbox_cancel,
+            commands::indexing::lxmf_list_send_queue,
+            commands::indexing::lxmf_discard_queued_send,
+            commands::indexing::lxmf_query_announces,
+            commands::indexing::lxmf_mark_announces_viewed,
+            commands::indexing::lxmf_query_contacts,
+            commands::indexing::lxmf_get_contact,
+            commands::indexing::lxmf_update_contact,
+            commands::indexing::lxmf_delete_contact,
+            commands::indexing::lxmf_delete_messages,
+            commands::indexing::lxmf_get_retention_policy,
+            commands::indexing::lxmf_set_retention_policy,
+            commands::indexing::lxmf_apply_retention,
+            commands::indexing::lxmf_export_messages,
+            commands::indexing::lxmf_backup_index,
+            commands::indexing::lxmf_restore_index,
             commands::indexing::lxmf_search_messages,
             commands::indexing::lxmf_query_files,
             commands::indexing::query_files_page,
             commands::indexing::lxmf_query_map_points,
+            commands::indexing::lxmf_query_peer_positions,
+            commands::indexing::lxmf_query_peer_track,
             commands::indexing::lxmf_get_attachment_blob,
             commands::indexing::get_attachment_bytes,
             commands::indexing::open_attachment_handle,
             commands::indexing::close_attachment_handle,
             commands::indexing::lxmf_force_reindex,
+            commands::indexing::lxmf_verify_and_repair_index,
             commands::indexing::rebuild_thread_summaries,
             commands::lxmf_list_messages,
             commands::lxmf_list_peers,
@@ -731,6 +863,8 @@ pub fn run() {
             commands::lxmf_get_outbound_propagation_node,
             commands::lxmf_set_outbound_propagation_node,
             commands::lxmf_message_delivery_trace,
+            commands::lxmf_outbox_retry,
+            commands::lxmf_outbox_reroute,
             commands::lxmf_announce_now,
             commands::lxmf_paper_ingest_uri,
             commands::lxmf_poll_event,
@@ -742,6 +876,8 @@ pub fn run() {
             commands::lxmf_send_message,
             commands::lxmf_send_rich_message,
             commands::lxmf_send_rich_message_refs,
+            commands::lxmf_send_message_edit,
+            commands::lxmf_send_message_delete,
             commands::lxmf_send_command,
             commands::desktop_get_shell_preferences,
             commands::desktop_set_shell_preferences
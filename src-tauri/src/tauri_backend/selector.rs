@@ -27,7 +27,7 @@ impl RuntimeSelector {
 
         let profile_name = match resolve_runtime_profile_name(&requested_profile) {
             Ok(name) => name,
-            Err(err) if requested_profile == "default" => {
+            Err(_) if requested_profile == "default" => {
                 let init_rpc = requested_rpc
                     .clone()
                     .unwrap_or_else(|| DEFAULT_AUTOCREATE_RPC.to_string());